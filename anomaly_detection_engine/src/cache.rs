@@ -0,0 +1,118 @@
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+
+use crate::env_or;
+
+// ----------------------------------------------------------------------------
+// Detection Cache
+// ----------------------------------------------------------------------------
+
+const CACHE_CAPACITY_ENV: &str = "PORTKULLIS_CACHE_CAPACITY";
+const CACHE_TTL_SECS_ENV: &str = "PORTKULLIS_CACHE_TTL_SECS";
+
+// the number of lookups between log lines reporting the cache's hit rate
+const STATS_LOG_INTERVAL: u64 = 1000;
+
+// Controls the cache of detection results. Results expire after the TTL so
+// that changes to the collection of normal traffic are picked up, and a
+// capacity of 0 disables the cache.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CacheConfig {
+    pub capacity: usize,
+    pub ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            ttl: Duration::from_secs(300),
+        }
+    }
+}
+
+impl CacheConfig {
+    // Reads the configuration from the PORTKULLIS_CACHE_CAPACITY and
+    // PORTKULLIS_CACHE_TTL_SECS environment variables, falling back to the
+    // defaults for any that are unset.
+    pub fn from_env() -> Result<Self, anyhow::Error> {
+        let default = Self::default();
+        Ok(Self {
+            capacity: env_or(CACHE_CAPACITY_ENV, default.capacity)?,
+            ttl: env_or(CACHE_TTL_SECS_ENV, default.ttl.as_secs()).map(Duration::from_secs)?,
+        })
+    }
+}
+
+// A least-recently-used cache of detection results, keyed by the text which
+// was embedded, so that repeated requests with identical headers skip both
+// the embedding and the vector search. It's shared by every request the
+// server handles, so it's behind a mutex which is never held across an await.
+#[derive(Debug)]
+pub struct DetectionCache<T> {
+    entries: Option<Mutex<LruCache<String, (Instant, T)>>>,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<T: Clone> DetectionCache<T> {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            entries: NonZeroUsize::new(config.capacity)
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+            ttl: config.ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<T> {
+        let entries = self.entries.as_ref()?;
+        let cached = {
+            let mut entries = entries.lock().unwrap_or_else(|e| e.into_inner());
+            match entries.get(key) {
+                Some((inserted, value)) if inserted.elapsed() < self.ttl => Some(value.clone()),
+                Some(_) => {
+                    entries.pop(key);
+                    None
+                }
+                None => None,
+            }
+        };
+
+        match cached {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        self.log_stats();
+        cached
+    }
+
+    pub fn insert(&self, key: String, value: T) {
+        if let Some(entries) = &self.entries {
+            entries
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .put(key, (Instant::now(), value));
+        }
+    }
+
+    fn log_stats(&self) {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        if lookups.is_multiple_of(STATS_LOG_INTERVAL) {
+            println!(
+                "detection cache: {} hits, {} misses ({:.1}% hit rate)",
+                hits,
+                misses,
+                hits as f64 * 100.0 / lookups as f64
+            );
+        }
+    }
+}
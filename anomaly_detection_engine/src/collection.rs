@@ -0,0 +1,91 @@
+use qdrant_client::qdrant::SearchPoints;
+
+// ----------------------------------------------------------------------------
+// Vector Collection
+// ----------------------------------------------------------------------------
+
+// The collection of embeddings of known normal traffic which requests are
+// compared against. These are shared with xtask, so that the collections it
+// creates and the searches it runs match what the engine does. The server
+// can override them with environment variables, see CollectionConfig.
+
+pub const VECTOR_DATABASE_URL: &str = "http://localhost:6334";
+pub const COLLECTION_NAME: &str = "normal_headers";
+// The default threshold, which suits the sample collection. A collection of
+// real traffic should be calibrated with "cargo xtask calibrate-threshold" and
+// the server started with the threshold it recommends.
+pub const SCORE_THRESHOLD: f32 = 0.79;
+
+// The search the engine runs for a request's embedding: only the nearest
+// neighbour, whatever its score, so that the similarity of an anomalous
+// request is still reported and the threshold is applied by the caller. An
+// empty result means the collection is empty.
+pub fn anomaly_search(collection_name: &str, embedding: Vec<f32>) -> SearchPoints {
+    SearchPoints {
+        collection_name: collection_name.to_string(),
+        vector: embedding,
+        limit: 1,
+        with_payload: Some(false.into()),
+        ..Default::default()
+    }
+}
+
+// Headers whose values differ from one request to the next whatever the
+// client, so that they'd only add noise to the similarity of requests.
+pub const DEFAULT_IGNORED_HEADERS: &[&str] = &[
+    "date",
+    "x-request-id",
+    "x-correlation-id",
+    "traceparent",
+    "tracestate",
+    "x-b3-traceid",
+    "x-b3-spanid",
+    "x-b3-parentspanid",
+    "x-b3-sampled",
+    "x-amzn-trace-id",
+    "x-cloud-trace-context",
+];
+
+// A comma separated list of the headers to leave out of embeddings instead of
+// the defaults, e.g. "date,x-request-id", or empty to keep every header.
+pub const IGNORED_HEADERS_ENV: &str = "PORTKULLIS_IGNORED_HEADERS";
+
+// The headers left out of embeddings, from PORTKULLIS_IGNORED_HEADERS or the
+// defaults. The server and xtask both read it, so that the embeddings of
+// requests and of the normal traffic they're compared against agree.
+pub fn ignored_headers_from_env() -> Vec<String> {
+    match std::env::var(IGNORED_HEADERS_ENV) {
+        Ok(ignored_headers) => ignored_headers
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect(),
+        Err(_) => DEFAULT_IGNORED_HEADERS
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+    }
+}
+
+// Formats headers as "name: value | name: value" to be embedded. Names are
+// lowercased and the headers sorted, so that requests which only differ in the
+// order or case of their headers have the same embedding, and the ignored
+// headers (see ignored_headers_from_env) are left out. Changing how headers
+// are formatted requires the collection to be populated again.
+pub fn format_headers_for_embedding(
+    headers: &[(String, String)],
+    ignored_headers: &[String],
+) -> String {
+    let mut headers: Vec<(String, &str)> = headers
+        .iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), value.as_str()))
+        .filter(|(name, _)| !ignored_headers.contains(name))
+        .collect();
+    headers.sort();
+
+    headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
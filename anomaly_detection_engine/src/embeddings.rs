@@ -1,3 +1,4 @@
+use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
 use std::time::Instant;
 
@@ -5,7 +6,7 @@ use candle_core::{Device, Tensor};
 use candle_nn::VarBuilder;
 use candle_transformers::models::bert::{BertModel, Config, DTYPE, HiddenAct};
 use hf_hub::{Repo, RepoType, api::sync::Api};
-use tokenizers::Tokenizer;
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
 
 use anyhow::{Error as E, Result};
 
@@ -13,11 +14,141 @@ use anyhow::{Error as E, Result};
 // Embeddings Generator
 // ----------------------------------------------------------------------------
 
+// The size of the embeddings produced by the default model, and of the
+// vectors stored in the Qdrant collection.
+pub const DIMENSIONS: usize = 384;
+
+const DEFAULT_MODEL_ID: &str = "sentence-transformers/all-MiniLM-L6-v2";
+const DEFAULT_REVISION: &str = "main";
+
+const MODEL_ID_ENV: &str = "PORTKULLIS_EMBEDDING_MODEL";
+const REVISION_ENV: &str = "PORTKULLIS_EMBEDDING_REVISION";
+const MODEL_DIR_ENV: &str = "PORTKULLIS_MODEL_DIR";
+const USE_GPU_ENV: &str = "PORTKULLIS_USE_GPU";
+const MAX_SEQUENCE_LENGTH_ENV: &str = "PORTKULLIS_MAX_SEQUENCE_LENGTH";
+
+// the files the model is loaded from, whether downloaded or local
+const CONFIG_FILE: &str = "config.json";
+const TOKENIZER_FILE: &str = "tokenizer.json";
+const WEIGHTS_FILE: &str = "model.safetensors";
+
+// Identifies the Hugging Face model used to generate embeddings. Pinning the
+// revision to a commit hash keeps embeddings reproducible between ingestion
+// and query time.
+//
+// For offline deployments the model can instead be loaded from a local
+// directory holding its config.json, tokenizer.json and model.safetensors, in
+// which case nothing is downloaded and the model id and revision are unused.
+//
+// The model runs on the CPU unless use_gpu is set, in which case the first
+// CUDA device is used when there is one.
+//
+// Texts longer than the max sequence length, in tokens, are truncated to it,
+// keeping their start. It defaults to the longest sequence the model supports
+// (512 tokens for BERT models), and can't be set any higher. It has to be the
+// same when populating the collection and when querying it, or long texts
+// won't be embedded the same way.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmbeddingsConfig {
+    pub model_id: String,
+    pub revision: String,
+    pub model_dir: Option<PathBuf>,
+    pub use_gpu: bool,
+    pub max_sequence_length: Option<usize>,
+}
+
+impl Default for EmbeddingsConfig {
+    fn default() -> Self {
+        Self {
+            model_id: DEFAULT_MODEL_ID.to_string(),
+            revision: DEFAULT_REVISION.to_string(),
+            model_dir: None,
+            use_gpu: false,
+            max_sequence_length: None,
+        }
+    }
+}
+
+impl EmbeddingsConfig {
+    // Reads the model configuration from the PORTKULLIS_EMBEDDING_MODEL,
+    // PORTKULLIS_EMBEDDING_REVISION, PORTKULLIS_MODEL_DIR, PORTKULLIS_USE_GPU
+    // ("1" or "true") and PORTKULLIS_MAX_SEQUENCE_LENGTH environment
+    // variables, falling back to the defaults for any that are unset.
+    pub fn from_env() -> Result<Self> {
+        let default = Self::default();
+        let max_sequence_length = match std::env::var(MAX_SEQUENCE_LENGTH_ENV) {
+            Ok(value) => Some(value.parse::<usize>().map_err(|_| {
+                anyhow::anyhow!("invalid value for {}: '{}'", MAX_SEQUENCE_LENGTH_ENV, value)
+            })?),
+            Err(_) => default.max_sequence_length,
+        };
+        Ok(Self {
+            model_id: std::env::var(MODEL_ID_ENV).unwrap_or(default.model_id),
+            revision: std::env::var(REVISION_ENV).unwrap_or(default.revision),
+            model_dir: std::env::var_os(MODEL_DIR_ENV)
+                .map(PathBuf::from)
+                .or(default.model_dir),
+            use_gpu: std::env::var(USE_GPU_ENV)
+                .map(|use_gpu| matches!(use_gpu.to_lowercase().as_str(), "1" | "true"))
+                .unwrap_or(default.use_gpu),
+            max_sequence_length,
+        })
+    }
+}
+
+impl std::fmt::Display for EmbeddingsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.model_dir {
+            Some(model_dir) => write!(f, "embedding model in {}", model_dir.display()),
+            None => write!(
+                f,
+                "embedding model {} (revision {})",
+                self.model_id, self.revision
+            ),
+        }
+    }
+}
+
 static EMBEDDINGS_GENERATOR: OnceLock<Result<EmbeddingsGenerator, anyhow::Error>> = OnceLock::new();
 
+// Loads the embeddings model, returning the size of the embeddings it
+// produces. The model is only loaded once per process, so the configuration
+// of the first call (or of the first generate_embeddings call, which reads it
+// from the environment) is the one that's used.
+pub fn init_embeddings(config: &EmbeddingsConfig) -> Result<usize> {
+    let generator_result = EMBEDDINGS_GENERATOR.get_or_init(|| EmbeddingsGenerator::new(config));
+
+    match generator_result {
+        Ok(generator) => Ok(generator.hidden_size),
+        Err(e) => Err(anyhow::anyhow!("embeddings engine failed: {}", e)),
+    }
+}
+
+// Checks that the configured model produces embeddings of exactly the given
+// size, both according to its config and by embedding a probe string, so that
+// a model which doesn't fit the collection fails loudly instead of having its
+// embeddings padded or truncated.
+pub fn validate_dimensions(config: &EmbeddingsConfig, dimensions: usize) -> Result<()> {
+    let hidden_size = init_embeddings(config)?;
+    let output_size = generate_embeddings("dimension check", None)?.len();
+
+    if hidden_size != dimensions || output_size != dimensions {
+        return Err(anyhow::anyhow!(
+            "{} produces {}-dimensional vectors, expected {}",
+            config,
+            output_size,
+            dimensions
+        ));
+    }
+
+    Ok(())
+}
+
+// Generates the embeddings for the text. The embeddings are only resized
+// (truncated, or padded with zeros) when a dimensions override is given.
 pub fn generate_embeddings(text: &str, dimensions: Option<usize>) -> Result<Vec<f32>> {
     let generator_result = EMBEDDINGS_GENERATOR.get_or_init(|| {
-        EmbeddingsGenerator::new("sentence-transformers/all-MiniLM-L6-v2", "main", true)
+        EmbeddingsConfig::from_env().and_then(|config| EmbeddingsGenerator::new(&config))
     });
 
     match generator_result {
@@ -26,34 +157,75 @@ pub fn generate_embeddings(text: &str, dimensions: Option<usize>) -> Result<Vec<
     }
 }
 
+// Generates the embeddings for several texts with a single forward pass
+// through the model, which is much faster than embedding them one at a time.
+pub fn generate_embeddings_batch(
+    texts: &[&str],
+    dimensions: Option<usize>,
+) -> Result<Vec<Vec<f32>>> {
+    let generator_result = EMBEDDINGS_GENERATOR.get_or_init(|| {
+        EmbeddingsConfig::from_env().and_then(|config| EmbeddingsGenerator::new(&config))
+    });
+
+    match generator_result {
+        Ok(generator) => generator.generate_batch(texts, dimensions),
+        Err(e) => Err(anyhow::anyhow!("embeddings engine failed: {}", e)),
+    }
+}
+
 struct EmbeddingsGenerator {
     model: BertModel,
     tokenizer: Tokenizer,
     device: Device,
+    hidden_size: usize,
 }
 
 impl EmbeddingsGenerator {
-    fn new(model_id: &str, revision: &str, cpu: bool) -> Result<Self> {
-        let device = if cpu {
-            Device::Cpu
-        } else {
-            Device::cuda_if_available(0)?
-        };
+    fn new(config: &EmbeddingsConfig) -> Result<Self> {
+        let device = select_device(config.use_gpu);
 
-        let repo = Repo::with_revision(model_id.to_string(), RepoType::Model, revision.to_string());
-        let (config_filename, tokenizer_filename, weights_filename) = {
-            let api = Api::new()?;
-            let api = api.repo(repo);
-            let config = api.get("config.json")?;
-            let tokenizer = api.get("tokenizer.json")?;
-            let weights = api.get("model.safetensors")?;
-            (config, tokenizer, weights)
+        let (config_filename, tokenizer_filename, weights_filename) = match &config.model_dir {
+            Some(model_dir) => local_model_files(model_dir)?,
+            None => {
+                let repo = Repo::with_revision(
+                    config.model_id.clone(),
+                    RepoType::Model,
+                    config.revision.clone(),
+                );
+                let api = Api::new()?;
+                let api = api.repo(repo);
+                let config = api.get(CONFIG_FILE)?;
+                let tokenizer = api.get(TOKENIZER_FILE)?;
+                let weights = api.get(WEIGHTS_FILE)?;
+                (config, tokenizer, weights)
+            }
         };
 
         let config_str = std::fs::read_to_string(config_filename)?;
         let mut bert_config: Config = serde_json::from_str(&config_str)?;
         bert_config.hidden_act = HiddenAct::GeluApproximate;
-        let tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+        let max_sequence_length = match config.max_sequence_length {
+            Some(length) if length == 0 || length > bert_config.max_position_embeddings => {
+                return Err(anyhow::anyhow!(
+                    "max sequence length {} must be between 1 and {}, the longest the model supports",
+                    length,
+                    bert_config.max_position_embeddings
+                ));
+            }
+            Some(length) => length,
+            None => bert_config.max_position_embeddings,
+        };
+
+        // batches are padded to their longest text, and the padding is masked
+        // out of both attention and pooling
+        let mut tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+        tokenizer.with_padding(Some(PaddingParams::default()));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: max_sequence_length,
+                ..TruncationParams::default()
+            }))
+            .map_err(E::msg)?;
         let vb =
             unsafe { VarBuilder::from_mmaped_safetensors(&[weights_filename], DTYPE, &device)? };
 
@@ -63,47 +235,126 @@ impl EmbeddingsGenerator {
             model,
             tokenizer,
             device,
+            hidden_size: bert_config.hidden_size,
         })
     }
 
     fn generate(&self, text: &str, dimensions: Option<usize>) -> Result<Vec<f32>> {
+        self.generate_batch(&[text], dimensions)?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("no embeddings generated"))
+    }
+
+    fn generate_batch(&self, texts: &[&str], dimensions: Option<usize>) -> Result<Vec<Vec<f32>>> {
         let start_time = Instant::now();
 
-        let tokens = self
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let encodings = self
             .tokenizer
-            .encode(text, true)
-            .map_err(E::msg)?
-            .get_ids()
-            .to_vec();
+            .encode_batch(texts.to_vec(), true)
+            .map_err(E::msg)?;
 
-        let token_ids = Tensor::new(&tokens[..], &self.device)?.unsqueeze(0)?;
+        let token_ids = encodings
+            .iter()
+            .map(|encoding| Tensor::new(encoding.get_ids(), &self.device))
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        let attention_mask = encodings
+            .iter()
+            .map(|encoding| Tensor::new(encoding.get_attention_mask(), &self.device))
+            .collect::<candle_core::Result<Vec<_>>>()?;
+
+        let token_ids = Tensor::stack(&token_ids, 0)?;
+        let attention_mask = Tensor::stack(&attention_mask, 0)?;
         let token_type_ids = token_ids.zeros_like()?;
 
-        let embeddings = self.model.forward(&token_ids, &token_type_ids, None)?;
-
-        let (_batch_size, n_tokens, _hidden_size) = embeddings.dims3()?;
-        let pooled_embeddings = (embeddings.sum(1)? / (n_tokens as f64))?;
-        let raw_embeddings: Vec<f32> = pooled_embeddings.squeeze(0)?.to_vec1()?;
-
-        let result_embeddings = if let Some(target_dims) = dimensions {
-            if target_dims < raw_embeddings.len() {
-                raw_embeddings[..target_dims].to_vec()
-            } else if target_dims > raw_embeddings.len() {
-                let mut padded = raw_embeddings;
-                padded.resize(target_dims, 0.0);
-                padded
-            } else {
-                raw_embeddings
-            }
-        } else {
-            raw_embeddings
-        };
+        let embeddings = self
+            .model
+            .forward(&token_ids, &token_type_ids, Some(&attention_mask))?;
+
+        // mean pooling over the real tokens of each text, ignoring padding
+        let mask = attention_mask.to_dtype(DTYPE)?.unsqueeze(2)?;
+        let summed = embeddings.broadcast_mul(&mask)?.sum(1)?;
+        let counts = mask.sum(1)?;
+        let pooled_embeddings = summed.broadcast_div(&counts)?;
+        let raw_embeddings: Vec<Vec<f32>> = pooled_embeddings.to_vec2()?;
+
+        let result_embeddings = raw_embeddings
+            .into_iter()
+            .map(|raw_embeddings| resize_embeddings(raw_embeddings, dimensions))
+            .collect();
 
         println!(
-            "embeddings generated in {:.3}s",
+            "{} embeddings generated in {:.3}s",
+            texts.len(),
             start_time.elapsed().as_secs_f64()
         );
 
         Ok(result_embeddings)
     }
 }
+
+// Falls back to the CPU when no GPU is available, or when it fails to
+// initialize, as slower embeddings are better than none.
+fn select_device(use_gpu: bool) -> Device {
+    if !use_gpu {
+        println!("embeddings running on the CPU");
+        return Device::Cpu;
+    }
+
+    match Device::cuda_if_available(0) {
+        Ok(device) if device.is_cuda() => {
+            println!("embeddings running on CUDA device 0");
+            device
+        }
+        Ok(device) => {
+            println!("warning: no CUDA device available, embeddings running on the CPU");
+            device
+        }
+        Err(e) => {
+            println!(
+                "warning: CUDA initialization failed, embeddings running on the CPU: {}",
+                e
+            );
+            Device::Cpu
+        }
+    }
+}
+
+// The model files in a local model directory, which must all be present.
+fn local_model_files(model_dir: &Path) -> Result<(PathBuf, PathBuf, PathBuf)> {
+    let model_file = |name: &str| {
+        let path = model_dir.join(name);
+        match path.is_file() {
+            true => Ok(path),
+            false => Err(anyhow::anyhow!(
+                "model directory {} is missing {}",
+                model_dir.display(),
+                name
+            )),
+        }
+    };
+    Ok((
+        model_file(CONFIG_FILE)?,
+        model_file(TOKENIZER_FILE)?,
+        model_file(WEIGHTS_FILE)?,
+    ))
+}
+
+fn resize_embeddings(raw_embeddings: Vec<f32>, dimensions: Option<usize>) -> Vec<f32> {
+    if let Some(target_dims) = dimensions {
+        if target_dims < raw_embeddings.len() {
+            raw_embeddings[..target_dims].to_vec()
+        } else if target_dims > raw_embeddings.len() {
+            let mut padded = raw_embeddings;
+            padded.resize(target_dims, 0.0);
+            padded
+        } else {
+            raw_embeddings
+        }
+    } else {
+        raw_embeddings
+    }
+}
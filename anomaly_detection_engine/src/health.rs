@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use qdrant_client::Qdrant;
+use qdrant_client::qdrant::vectors_config::Config;
+use tonic_health::ServingStatus;
+use tonic_health::server::HealthReporter;
+
+use crate::AnomalyDetectionEngine;
+use crate::anomaly::anomaly_detection_server::AnomalyDetectionServer;
+
+// ----------------------------------------------------------------------------
+// Health Checks
+// ----------------------------------------------------------------------------
+
+// how often readiness is checked, so that the server stops serving when the
+// vector database goes away and resumes once it's back
+const READINESS_INTERVAL: Duration = Duration::from_secs(10);
+
+// The server can't detect anything until the vector database is reachable and
+// has the collection, with vectors the size of the embeddings the engine
+// produces.
+pub async fn check_readiness(
+    url: &str,
+    collection_name: &str,
+    dimensions: usize,
+) -> Result<(), anyhow::Error> {
+    let client = Qdrant::from_url(url).build()?;
+    if !client.collection_exists(collection_name).await? {
+        return Err(anyhow::anyhow!(
+            "collection '{}' doesn't exist",
+            collection_name
+        ));
+    }
+
+    let collection_dimensions = client
+        .collection_info(collection_name)
+        .await?
+        .result
+        .and_then(|info| info.config)
+        .and_then(|config| config.params)
+        .and_then(|params| params.vectors_config)
+        .and_then(|vectors_config| vectors_config.config)
+        .and_then(|config| match config {
+            Config::Params(params) => Some(params.size),
+            Config::ParamsMap(_) => None,
+        });
+
+    match collection_dimensions {
+        Some(size) if size == dimensions as u64 => Ok(()),
+        Some(size) => Err(anyhow::anyhow!(
+            "collection '{}' has {} dimensions, expected {}",
+            collection_name,
+            size,
+            dimensions
+        )),
+        None => Err(anyhow::anyhow!(
+            "collection '{}' has no single vector configuration",
+            collection_name
+        )),
+    }
+}
+
+// Reports the anomaly detection service, and the server as a whole (the ""
+// service, which is what probes without a service name check), as
+// NOT_SERVING until it's ready, and keeps checking for as long as the server
+// runs.
+pub async fn report_readiness(
+    mut reporter: HealthReporter,
+    url: String,
+    collection_name: String,
+    dimensions: usize,
+) {
+    let mut ready = None;
+    loop {
+        let status = match check_readiness(&url, &collection_name, dimensions).await {
+            Ok(()) => {
+                if ready != Some(true) {
+                    println!("anomaly detection is ready");
+                }
+                ready = Some(true);
+                ServingStatus::Serving
+            }
+            Err(e) => {
+                if ready != Some(false) {
+                    println!("anomaly detection is not ready: {}", e);
+                }
+                ready = Some(false);
+                ServingStatus::NotServing
+            }
+        };
+
+        reporter.set_service_status("", status).await;
+        match status {
+            ServingStatus::Serving => {
+                reporter
+                    .set_serving::<AnomalyDetectionServer<AnomalyDetectionEngine>>()
+                    .await
+            }
+            _ => {
+                reporter
+                    .set_not_serving::<AnomalyDetectionServer<AnomalyDetectionEngine>>()
+                    .await
+            }
+        }
+
+        tokio::time::sleep(READINESS_INTERVAL).await;
+    }
+}
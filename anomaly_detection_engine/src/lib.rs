@@ -1 +1,2 @@
+pub mod collection;
 pub mod embeddings;
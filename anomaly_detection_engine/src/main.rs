@@ -1,10 +1,30 @@
+pub mod cache;
+pub mod collection;
 pub mod embeddings;
+pub mod health;
+pub mod metrics;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use cache::{CacheConfig, DetectionCache};
+use collection::{
+    COLLECTION_NAME, DEFAULT_IGNORED_HEADERS, SCORE_THRESHOLD, VECTOR_DATABASE_URL, anomaly_search,
+    format_headers_for_embedding, ignored_headers_from_env,
+};
+use embeddings::{DIMENSIONS, EmbeddingsConfig};
+use metrics::Metrics;
 
 use anomaly::anomaly_detection_server::{AnomalyDetection, AnomalyDetectionServer};
 use anomaly::{Detection, HeaderDetectionRequest, HeaderDetectionResponse};
 
 use qdrant_client::Qdrant;
-use tonic::{Request, Response, Status, transport::Server};
+use qdrant_client::qdrant::{ScoredPoint, SearchBatchPoints, SearchBatchResponse};
+use tokio::sync::mpsc;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming, transport::Server};
 use tonic_reflection::server::Builder;
 
 // ----------------------------------------------------------------------------
@@ -24,8 +44,38 @@ pub mod anomaly {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let addr = "127.0.0.1:10764".parse()?;
-    let anomaly_service = AnomalyDetectionEngine::default();
+    let addr: SocketAddr = env_or(GRPC_ADDR_ENV, DEFAULT_GRPC_ADDR.parse()?)?;
+    let collection_config = CollectionConfig::from_env()?;
+    let anomaly_service = AnomalyDetectionEngine::new(
+        &EmbeddingsConfig::from_env()?,
+        collection_config.clone(),
+        SearchPolicy::from_env()?,
+        &CacheConfig::from_env()?,
+    )?;
+
+    // the health service reports NOT_SERVING until the vector database is
+    // ready, so that traffic isn't routed to the server before it can be served
+    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+    health_reporter
+        .set_not_serving::<AnomalyDetectionServer<AnomalyDetectionEngine>>()
+        .await;
+    health_reporter
+        .set_service_status("", tonic_health::ServingStatus::NotServing)
+        .await;
+    tokio::spawn(health::report_readiness(
+        health_reporter,
+        collection_config.qdrant_url,
+        collection_config.collection_name,
+        DIMENSIONS,
+    ));
+
+    let metrics_addr: SocketAddr = env_or(METRICS_ADDR_ENV, DEFAULT_METRICS_ADDR.parse()?)?;
+    let metrics = anomaly_service.metrics();
+    tokio::spawn(async move {
+        if let Err(e) = metrics::serve(metrics_addr, metrics).await {
+            println!("metrics endpoint failed: {}", e);
+        }
+    });
 
     let reflection_service = Builder::configure()
         .register_encoded_file_descriptor_set(anomaly::FILE_DESCRIPTOR_SET)
@@ -33,8 +83,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("AnomalyDetectionServer listening on {}", addr);
     println!("gRPC reflection enabled");
+    println!("gRPC health checks enabled");
+    println!("metrics served on http://{}/metrics", metrics_addr);
 
     Server::builder()
+        .add_service(health_service)
         .add_service(AnomalyDetectionServer::new(anomaly_service))
         .add_service(reflection_service)
         .serve(addr)
@@ -47,16 +100,159 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 // Anomaly Detection Engine
 // ----------------------------------------------------------------------------
 
-const VECTOR_DATABASE_URL: &str = "http://localhost:6334";
-const COLLECTION_NAME: &str = "normal_headers";
-const DIMENSIONS: usize = 386;
-const SCORE_THRESHOLD: f32 = 0.79;
-const SEARCH_COUNT: u64 = 100;
-
 const ANOMALY_DETECTED_MESSAGE: &str = "anomaly detected: no similar patterns found";
 
-#[derive(Debug, Default)]
-pub struct AnomalyDetectionEngine {}
+// Streamed requests are detected in batches of up to this many, or however
+// many arrived within the timeout, whichever comes first.
+const STREAM_BATCH_SIZE: usize = 64;
+const STREAM_BATCH_TIMEOUT: Duration = Duration::from_millis(10);
+
+const GRPC_ADDR_ENV: &str = "PORTKULLIS_GRPC_ADDR";
+const METRICS_ADDR_ENV: &str = "PORTKULLIS_METRICS_ADDR";
+const QDRANT_URL_ENV: &str = "PORTKULLIS_QDRANT_URL";
+const COLLECTION_ENV: &str = "PORTKULLIS_COLLECTION";
+const SCORE_THRESHOLD_ENV: &str = "PORTKULLIS_SCORE_THRESHOLD";
+const SEARCH_RETRIES_ENV: &str = "PORTKULLIS_SEARCH_RETRIES";
+const SEARCH_BACKOFF_MS_ENV: &str = "PORTKULLIS_SEARCH_BACKOFF_MS";
+const SEARCH_TIMEOUT_MS_ENV: &str = "PORTKULLIS_SEARCH_TIMEOUT_MS";
+
+// The address the server listens on. Containerized deployments need to listen
+// on all interfaces (e.g. "0.0.0.0:10764") to be reachable from other pods.
+const DEFAULT_GRPC_ADDR: &str = "127.0.0.1:10764";
+
+// The address Prometheus metrics are served on, over HTTP (see metrics::serve).
+const DEFAULT_METRICS_ADDR: &str = "127.0.0.1:10765";
+
+// The vector database and collection of normal traffic which requests are
+// compared against, the similarity score a request needs to reach to be
+// considered normal, and the headers left out of the comparison.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CollectionConfig {
+    pub qdrant_url: String,
+    pub collection_name: String,
+    pub score_threshold: f32,
+    pub ignored_headers: Vec<String>,
+}
+
+impl Default for CollectionConfig {
+    fn default() -> Self {
+        Self {
+            qdrant_url: VECTOR_DATABASE_URL.to_string(),
+            collection_name: COLLECTION_NAME.to_string(),
+            score_threshold: SCORE_THRESHOLD,
+            ignored_headers: DEFAULT_IGNORED_HEADERS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl CollectionConfig {
+    // Reads the configuration from the PORTKULLIS_QDRANT_URL,
+    // PORTKULLIS_COLLECTION, PORTKULLIS_SCORE_THRESHOLD and
+    // PORTKULLIS_IGNORED_HEADERS environment variables, falling back to the
+    // defaults for any that are unset.
+    pub fn from_env() -> Result<Self, anyhow::Error> {
+        let default = Self::default();
+        Ok(Self {
+            qdrant_url: env_or(QDRANT_URL_ENV, default.qdrant_url)?,
+            collection_name: env_or(COLLECTION_ENV, default.collection_name)?,
+            score_threshold: env_or(SCORE_THRESHOLD_ENV, default.score_threshold)?,
+            ignored_headers: ignored_headers_from_env(),
+        })
+    }
+}
+
+// Controls how hard a vector search is retried before giving up. Failed
+// attempts are retried with an exponential backoff (backoff, 2 * backoff, ...)
+// and the timeout bounds the whole search, retries included, so that a
+// struggling vector database can't hold requests up indefinitely.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchPolicy {
+    pub retries: u32,
+    pub backoff: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for SearchPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            backoff: Duration::from_millis(50),
+            timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+impl SearchPolicy {
+    // Reads the policy from the PORTKULLIS_SEARCH_RETRIES,
+    // PORTKULLIS_SEARCH_BACKOFF_MS and PORTKULLIS_SEARCH_TIMEOUT_MS environment
+    // variables, falling back to the defaults for any that are unset.
+    pub fn from_env() -> Result<Self, anyhow::Error> {
+        let default = Self::default();
+        Ok(Self {
+            retries: env_or(SEARCH_RETRIES_ENV, default.retries)?,
+            backoff: env_or(SEARCH_BACKOFF_MS_ENV, default.backoff.as_millis() as u64)
+                .map(Duration::from_millis)?,
+            timeout: env_or(SEARCH_TIMEOUT_MS_ENV, default.timeout.as_millis() as u64)
+                .map(Duration::from_millis)?,
+        })
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> Result<T, anyhow::Error> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid value for {}: '{}'", name, value)),
+        Err(_) => Ok(default),
+    }
+}
+
+// Detection can fail either because the input couldn't be embedded, or
+// because the vector database couldn't be searched. The latter is an
+// infrastructure problem rather than a signal about the request, so it's
+// reported as unavailable and left to the caller's failure policy instead of
+// being treated as an anomaly.
+#[derive(Debug)]
+enum DetectionError {
+    Embeddings(String),
+    SearchFailed(String),
+}
+
+impl std::fmt::Display for DetectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DetectionError::Embeddings(e) => write!(f, "failed to generate embeddings: {}", e),
+            DetectionError::SearchFailed(e) => write!(f, "vector search failed: {}", e),
+        }
+    }
+}
+
+impl From<DetectionError> for Status {
+    fn from(error: DetectionError) -> Self {
+        match error {
+            DetectionError::Embeddings(_) => {
+                Status::internal(format!("anomaly detection error: {}", error))
+            }
+            DetectionError::SearchFailed(_) => {
+                Status::unavailable(format!("anomaly detection error: {}", error))
+            }
+        }
+    }
+}
+
+// whether the request is anomalous, the top similarity score, and a message
+type DetectionResult = (bool, f32, String);
+
+#[derive(Clone, Debug)]
+pub struct AnomalyDetectionEngine {
+    collection: CollectionConfig,
+    search_policy: SearchPolicy,
+    cache: Arc<DetectionCache<DetectionResult>>,
+    metrics: Arc<Metrics>,
+}
 
 #[tonic::async_trait]
 impl AnomalyDetection for AnomalyDetectionEngine {
@@ -64,79 +260,287 @@ impl AnomalyDetection for AnomalyDetectionEngine {
         &self,
         request: Request<HeaderDetectionRequest>,
     ) -> Result<Response<HeaderDetectionResponse>, Status> {
-        let headers = &request.get_ref().headers;
+        let request = request.into_inner();
+        let header_text = self.header_text(&request);
+
+        match self.detect_anomalies(&[header_text]).await {
+            Ok(detections) => {
+                let response = detections
+                    .into_iter()
+                    .next()
+                    .map(|detection| self.detection_response(&request.request_id, detection))
+                    .ok_or_else(|| Status::internal("anomaly detection error: no detection"))?;
+                Ok(Response::new(response))
+            }
+            Err(e) => {
+                println!("request {}: {}", request.request_id, e);
+                Err(e.into())
+            }
+        }
+    }
+
+    type RunHeaderDetectionStreamStream = ReceiverStream<Result<HeaderDetectionResponse, Status>>;
+
+    // The responses are sent from a task of their own, so that requests keep
+    // being read while a batch is detected. The stream ends at the first
+    // error, as a failed search would most likely fail the rest too.
+    async fn run_header_detection_stream(
+        &self,
+        request: Request<Streaming<HeaderDetectionRequest>>,
+    ) -> Result<Response<Self::RunHeaderDetectionStreamStream>, Status> {
+        let requests = request
+            .into_inner()
+            .chunks_timeout(STREAM_BATCH_SIZE, STREAM_BATCH_TIMEOUT);
+        let (sender, receiver) = mpsc::channel(STREAM_BATCH_SIZE);
+        let engine = self.clone();
+
+        tokio::spawn(async move {
+            tokio::pin!(requests);
+            while let Some(batch) = requests.next().await {
+                let batch = match batch.into_iter().collect::<Result<Vec<_>, _>>() {
+                    Ok(batch) => batch,
+                    Err(status) => {
+                        let _ = sender.send(Err(status)).await;
+                        return;
+                    }
+                };
+                let header_texts = batch
+                    .iter()
+                    .map(|request| engine.header_text(request))
+                    .collect::<Vec<_>>();
+
+                let detections = match engine.detect_anomalies(&header_texts).await {
+                    Ok(detections) => detections,
+                    Err(e) => {
+                        println!("{}", e);
+                        let _ = sender.send(Err(e.into())).await;
+                        return;
+                    }
+                };
+                for (request, detection) in batch.iter().zip(detections) {
+                    let response = engine.detection_response(&request.request_id, detection);
+                    // the client has gone away
+                    if sender.send(Ok(response)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+impl AnomalyDetectionEngine {
+    // Loads the embeddings model up front, so that a misconfigured model is
+    // reported at startup rather than on the first request.
+    pub fn new(
+        embeddings_config: &EmbeddingsConfig,
+        collection: CollectionConfig,
+        search_policy: SearchPolicy,
+        cache_config: &CacheConfig,
+    ) -> Result<Self, anyhow::Error> {
+        embeddings::validate_dimensions(embeddings_config, DIMENSIONS).map_err(|e| {
+            anyhow::anyhow!(
+                "{} collection is incompatible: {}",
+                collection.collection_name,
+                e
+            )
+        })?;
+
+        Ok(Self {
+            collection,
+            search_policy,
+            cache: Arc::new(DetectionCache::new(cache_config)),
+            metrics: Arc::new(Metrics::default()),
+        })
+    }
 
-        let header_pairs: Vec<(String, String)> = headers
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    fn header_text(&self, request: &HeaderDetectionRequest) -> String {
+        let header_pairs: Vec<(String, String)> = request
+            .headers
             .iter()
             .map(|h| (h.name.clone(), h.value.clone()))
             .collect();
 
-        let header_text = format_headers_for_embedding(&header_pairs);
+        format_headers_for_embedding(&header_pairs, &self.collection.ignored_headers)
+    }
 
-        match self.detect_anomaly_with_vectors(&header_text).await {
-            Ok((is_anomaly, score, message)) => {
-                let detection = Detection {
-                    anomaly_detected: is_anomaly,
-                    message: format!("{} (similarity score: {:.4})", message, score),
-                };
+    // Anomalies are logged along with the ID of the request, which the proxy
+    // logs too, so that the two can be correlated.
+    fn detection_response(
+        &self,
+        request_id: &str,
+        detection: DetectionResult,
+    ) -> HeaderDetectionResponse {
+        let (is_anomaly, score, message) = detection;
+        if is_anomaly {
+            println!(
+                "request {}: {} (similarity score: {:.4})",
+                request_id, message, score
+            );
+        }
+        HeaderDetectionResponse {
+            detection: Some(Detection {
+                anomaly_detected: is_anomaly,
+                message: format!("{} (similarity score: {:.4})", message, score),
+                score,
+                threshold: self.collection.score_threshold,
+            }),
+        }
+    }
 
-                let response = HeaderDetectionResponse {
-                    detection: Some(detection),
-                };
+    // Detects anomalies in each of the header texts, in order, recording the
+    // detections in the metrics.
+    async fn detect_anomalies(
+        &self,
+        header_texts: &[String],
+    ) -> Result<Vec<DetectionResult>, DetectionError> {
+        self.metrics.record_requests(header_texts.len());
+        let detections = self.detect_anomalies_with_cache(header_texts).await;
+        match &detections {
+            Ok(detections) => self.metrics.record_anomalies(
+                detections
+                    .iter()
+                    .filter(|(is_anomaly, _, _)| *is_anomaly)
+                    .count(),
+            ),
+            Err(_) => self.metrics.record_errors(header_texts.len()),
+        }
+        detections
+    }
 
-                Ok(Response::new(response))
-            }
-            Err(e) => {
-                return Err(Status::internal(format!("anomaly detection error: {}", e)));
+    // Cached detections are reused, and the rest are embedded and searched as
+    // a single batch. Only successful detections are cached, so that a failed
+    // search is retried by the next identical request.
+    async fn detect_anomalies_with_cache(
+        &self,
+        header_texts: &[String],
+    ) -> Result<Vec<DetectionResult>, DetectionError> {
+        let mut detections: Vec<Option<DetectionResult>> = header_texts
+            .iter()
+            .map(|header_text| self.cache.get(header_text))
+            .collect();
+        let uncached: Vec<&str> = header_texts
+            .iter()
+            .zip(&detections)
+            .filter(|(_, detection)| detection.is_none())
+            .map(|(header_text, _)| header_text.as_str())
+            .collect();
+
+        if !uncached.is_empty() {
+            let mut uncached_detections = self
+                .detect_anomalies_with_vectors(&uncached)
+                .await?
+                .into_iter();
+            for (header_text, detection) in header_texts.iter().zip(&mut detections) {
+                if detection.is_none() {
+                    let uncached_detection = uncached_detections.next().ok_or_else(|| {
+                        DetectionError::SearchFailed("missing search result".to_string())
+                    })?;
+                    self.cache
+                        .insert(header_text.clone(), uncached_detection.clone());
+                    *detection = Some(uncached_detection);
+                }
             }
         }
+
+        Ok(detections.into_iter().flatten().collect())
     }
-}
 
-impl AnomalyDetectionEngine {
-    async fn detect_anomaly_with_vectors(
+    async fn detect_anomalies_with_vectors(
         &self,
-        header_text: &str,
-    ) -> Result<(bool, f32, String), Box<dyn std::error::Error + Send + Sync>> {
-        let client = Qdrant::from_url(VECTOR_DATABASE_URL).build()?;
-        let collection_name = COLLECTION_NAME;
-
-        let embedding = crate::embeddings::generate_embeddings(header_text, Some(DIMENSIONS))?;
-        let search_result = client
-            .search_points(qdrant_client::qdrant::SearchPoints {
-                collection_name: collection_name.to_string(),
-                vector: embedding,
-                limit: SEARCH_COUNT,
-                with_payload: Some(false.into()),
-                score_threshold: Some(SCORE_THRESHOLD),
+        header_texts: &[&str],
+    ) -> Result<Vec<DetectionResult>, DetectionError> {
+        let started = Instant::now();
+        let embeddings = crate::embeddings::generate_embeddings_batch(header_texts, None)
+            .map_err(|e| DetectionError::Embeddings(e.to_string()))?;
+        self.metrics.record_embedding_latency(started.elapsed());
+
+        let started = Instant::now();
+        let search_result = self
+            .search_with_retries(SearchBatchPoints {
+                collection_name: self.collection.collection_name.clone(),
+                search_points: embeddings
+                    .into_iter()
+                    .map(|embedding| anomaly_search(&self.collection.collection_name, embedding))
+                    .collect(),
                 ..Default::default()
             })
-            .await?;
+            .await;
+        self.metrics.record_search_latency(started.elapsed());
+        let search_result = search_result?;
 
-        if search_result.result.is_empty() {
-            return Ok((true, 0.0, ANOMALY_DETECTED_MESSAGE.to_string()));
+        if search_result.result.len() != header_texts.len() {
+            return Err(DetectionError::SearchFailed(format!(
+                "expected {} search results, got {}",
+                header_texts.len(),
+                search_result.result.len()
+            )));
         }
 
-        let top_score = search_result
+        Ok(search_result
             .result
             .iter()
-            .map(|point| point.score)
-            .fold(f32::NEG_INFINITY, f32::max);
+            .map(|batch_result| self.detection(&batch_result.result))
+            .collect())
+    }
 
-        let (is_anomaly, message) = if top_score >= SCORE_THRESHOLD {
+    fn detection(&self, points: &[ScoredPoint]) -> DetectionResult {
+        // a successful search with no results means the collection is empty,
+        // so nothing in it looks like this request
+        let Some(top_score) = points.first().map(|point| point.score) else {
+            return (true, 0.0, ANOMALY_DETECTED_MESSAGE.to_string());
+        };
+
+        let (is_anomaly, message) = if top_score >= self.collection.score_threshold {
             (false, "normal traffic match".to_string())
         } else {
             (true, ANOMALY_DETECTED_MESSAGE.to_string())
         };
 
-        Ok((is_anomaly, top_score, message))
+        (is_anomaly, top_score, message)
     }
-}
 
-fn format_headers_for_embedding(headers: &[(String, String)]) -> String {
-    headers
-        .iter()
-        .map(|(name, value)| format!("{}: {}", name, value))
-        .collect::<Vec<_>>()
-        .join(" | ")
+    async fn search_with_retries(
+        &self,
+        search_points: SearchBatchPoints,
+    ) -> Result<SearchBatchResponse, DetectionError> {
+        let search = async {
+            let client = Qdrant::from_url(&self.collection.qdrant_url)
+                .build()
+                .map_err(|e| DetectionError::SearchFailed(e.to_string()))?;
+
+            let mut backoff = self.search_policy.backoff;
+            let mut attempt = 0;
+            loop {
+                match client.search_batch_points(search_points.clone()).await {
+                    Ok(search_result) => return Ok(search_result),
+                    Err(e) if attempt < self.search_policy.retries => {
+                        attempt += 1;
+                        println!(
+                            "vector search failed, retrying in {:?} ({}/{}): {}",
+                            backoff, attempt, self.search_policy.retries, e
+                        );
+                        tokio::time::sleep(backoff).await;
+                        backoff *= 2;
+                    }
+                    Err(e) => return Err(DetectionError::SearchFailed(e.to_string())),
+                }
+            }
+        };
+
+        tokio::time::timeout(self.search_policy.timeout, search)
+            .await
+            .map_err(|_| {
+                DetectionError::SearchFailed(format!(
+                    "timed out after {:?}",
+                    self.search_policy.timeout
+                ))
+            })?
+    }
 }
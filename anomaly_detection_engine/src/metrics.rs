@@ -0,0 +1,180 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// ----------------------------------------------------------------------------
+// Metrics
+// ----------------------------------------------------------------------------
+
+// The upper bounds, in seconds, of the latency histograms' buckets, from the
+// embeddings of a single request on a GPU to a search which is being retried.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+// The metrics of the anomaly detection path, exposed in the Prometheus text
+// format (see serve). The anomaly rate is the rate of anomalies_detected over
+// the rate of requests.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    // header sets detected, whether by a unary or streamed request
+    requests: AtomicU64,
+    anomalies_detected: AtomicU64,
+    // requests which failed to be detected, e.g. when the search timed out
+    errors: AtomicU64,
+    embedding_latency: Histogram,
+    search_latency: Histogram,
+}
+
+impl Metrics {
+    pub fn record_requests(&self, count: usize) {
+        self.requests.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_anomalies(&self, count: usize) {
+        self.anomalies_detected
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_errors(&self, count: usize) {
+        self.errors.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    // the time to generate the embeddings of a batch of requests
+    pub fn record_embedding_latency(&self, latency: Duration) {
+        self.embedding_latency.observe(latency);
+    }
+
+    // the time to search the vector database for a batch of requests,
+    // including retries
+    pub fn record_search_latency(&self, latency: Duration) {
+        self.search_latency.observe(latency);
+    }
+
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        for (name, help, counter) in [
+            (
+                "portkullis_anomaly_requests_total",
+                "Header sets received for anomaly detection.",
+                &self.requests,
+            ),
+            (
+                "portkullis_anomaly_detected_total",
+                "Header sets detected as anomalous.",
+                &self.anomalies_detected,
+            ),
+            (
+                "portkullis_anomaly_errors_total",
+                "Header sets which failed to be detected.",
+                &self.errors,
+            ),
+        ] {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} counter", name);
+            let _ = writeln!(output, "{} {}", name, counter.load(Ordering::Relaxed));
+        }
+        self.embedding_latency.render(
+            &mut output,
+            "portkullis_anomaly_embedding_seconds",
+            "Time to generate the embeddings of a batch of header sets.",
+        );
+        self.search_latency.render(
+            &mut output,
+            "portkullis_anomaly_search_seconds",
+            "Time to search the vector database for a batch of header sets.",
+        );
+        output
+    }
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // the observations in each bucket, with the last for those above every
+    // bound, which are made cumulative when rendered
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, output: &mut String, name: &str, help: &str) {
+        let _ = writeln!(output, "# HELP {} {}", name, help);
+        let _ = writeln!(output, "# TYPE {} histogram", name);
+        let mut count = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            let bound = match LATENCY_BUCKETS.get(index) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(output, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(output, "{}_sum {}", name, sum);
+        let _ = writeln!(output, "{}_count {}", name, count);
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Metrics Endpoint
+// ----------------------------------------------------------------------------
+
+// The largest request read from a scraper, which is plenty for a GET.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+// Serves the metrics at GET /metrics on a port of their own, separate from the
+// gRPC server, as Prometheus scrapes over plain HTTP.
+pub async fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> Result<(), anyhow::Error> {
+    let listener = TcpListener::bind(addr).await?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, &metrics).await {
+                println!("metrics request failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream, metrics: &Metrics) -> Result<(), anyhow::Error> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST_SIZE {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let (status, body) = match request.starts_with(b"GET /metrics ") {
+        true => ("200 OK", metrics.render()),
+        false => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,157 @@
+// Measures the throughput of the request header and body phases against a
+// ruleset of a few hundred rules shaped like the OWASP CRS, for:
+//
+//   - no match, a benign request, which is the common path
+//   - match, a malicious request which is blocked
+//   - worst case, a large benign request which every rule has to scan
+//
+//   cargo bench -p signature_detection_engine --bench engine
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+
+use signature_detection_engine::SignatureBasedDetectionEngine;
+use signature_detection_engine::transaction::Transaction;
+
+// Scanner signatures and header checks in phase 1, and injection signatures
+// in phase 2, with the transformations such rules usually carry.
+fn conf() -> String {
+    let mut rules = Vec::new();
+    for id in 0..100 {
+        rules.push(format!(
+            r#"SecRule REQUEST_HEADERS:User-Agent "@rx (?i)\bscanner-{id}\b" "id:{},phase:1,deny,t:lowercase,tag:'attack-reputation-scanner'""#,
+            1000 + id
+        ));
+    }
+    for id in 0..50 {
+        rules.push(format!(
+            r#"SecRule REQUEST_HEADERS|!REQUEST_HEADERS:Referer "@contains evil-{id}" "id:{},phase:1,deny,t:urlDecodeUni""#,
+            2000 + id
+        ));
+    }
+    rules.push(
+        r#"SecRule REQUEST_HEADERS|!REQUEST_HEADERS:User-Agent "@detectSQLi" "id:3000,phase:1,deny,t:urlDecodeUni,t:removeComments,tag:'attack-sqli'""#.to_string(),
+    );
+    rules.push(
+        r#"SecRule REQUEST_HEADERS "@detectXSS" "id:3001,phase:1,deny,t:urlDecodeUni,t:htmlEntityDecode,tag:'attack-xss'""#.to_string(),
+    );
+    for id in 0..200 {
+        rules.push(format!(
+            r#"SecRule REQUEST_BODY "@rx (?i)(?:union|select|insert)\s+(?:all\s+)?payload{id}\b" "id:{},phase:2,deny,t:urlDecode,t:removeComments,tag:'attack-sqli'""#,
+            4000 + id
+        ));
+    }
+    for id in 0..50 {
+        rules.push(format!(
+            r#"SecRule REQUEST_BODY "@contains attack-{id}" "id:{},phase:2,deny,t:lowercase""#,
+            5000 + id
+        ));
+    }
+    rules.push(
+        r#"SecRule REQUEST_BODY "@detectSQLi" "id:6000,phase:2,deny,t:urlDecode,t:htmlEntityDecode,tag:'attack-sqli'""#.to_string(),
+    );
+    rules.push(
+        r#"SecRule REQUEST_BODY "@detectXSS" "id:6001,phase:2,deny,t:urlDecode,t:htmlEntityDecode,tag:'attack-xss'""#.to_string(),
+    );
+    rules.join("\n")
+}
+
+fn headers(user_agent: &str, extra: usize) -> Vec<(String, String)> {
+    let mut headers = vec![
+        (":method".to_string(), "POST".to_string()),
+        (":path".to_string(), "/api/orders?page=2".to_string()),
+        ("host".to_string(), "shop.example.com".to_string()),
+        ("user-agent".to_string(), user_agent.to_string()),
+        ("accept".to_string(), "application/json".to_string()),
+        ("content-type".to_string(), "application/json".to_string()),
+        (
+            "referer".to_string(),
+            "https://shop.example.com/cart".to_string(),
+        ),
+    ];
+    headers.extend((0..extra).map(|index| {
+        (
+            format!("x-custom-{}", index),
+            format!("value-{}-{}", index, "abcdefghij".repeat(8)),
+        )
+    }));
+    headers
+}
+
+fn benign_body(len: usize) -> Vec<u8> {
+    let item = r#"{"sku":"A-1042","quantity":2,"note":"please leave at the door"},"#;
+    let mut body = String::from(r#"{"items":["#);
+    while body.len() + item.len() < len {
+        body.push_str(item);
+    }
+    body.push_str("{}]}");
+    body.into_bytes()
+}
+
+fn bench_header_phase(c: &mut Criterion) {
+    let conf = conf();
+    let engine = SignatureBasedDetectionEngine::from_conf_str(&conf).unwrap();
+    let linear = SignatureBasedDetectionEngine::from_conf_str(&conf)
+        .unwrap()
+        .without_rule_index();
+
+    let cases = [
+        (
+            "no match",
+            headers("Mozilla/5.0 (X11; Linux x86_64) Firefox/131.0", 0),
+        ),
+        ("match", headers("scanner-99/1.0", 0)),
+        (
+            "worst case",
+            headers("Mozilla/5.0 (X11; Linux x86_64) Firefox/131.0", 64),
+        ),
+    ];
+
+    let mut group = c.benchmark_group("header phase");
+    for (name, headers) in &cases {
+        group.bench_with_input(BenchmarkId::new("indexed", name), headers, |b, headers| {
+            b.iter(|| {
+                engine
+                    .run_header_phase(&mut Transaction::default(), black_box(headers.clone()))
+                    .unwrap()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("linear", name), headers, |b, headers| {
+            b.iter(|| {
+                linear
+                    .run_header_phase(&mut Transaction::default(), black_box(headers.clone()))
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_body_phase(c: &mut Criterion) {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(&conf()).unwrap();
+
+    let cases = [
+        ("no match", benign_body(1024)),
+        (
+            "match",
+            br#"{"search":"x' UNION SELECT password FROM users --"}"#.to_vec(),
+        ),
+        ("worst case", benign_body(64 * 1024)),
+    ];
+
+    let mut group = c.benchmark_group("body phase");
+    for (name, body) in &cases {
+        group.throughput(Throughput::Bytes(body.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), body, |b, body| {
+            b.iter(|| {
+                engine
+                    .run_body_phase_bytes(&mut Transaction::default(), black_box(body))
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_header_phase, bench_body_phase);
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+// Compares evaluating the request header phase of a 1000 rule set with the
+// rule index against a linear scan of every rule in the phase.
+//
+//   cargo bench -p signature_detection_engine --bench rule_index
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use signature_detection_engine::SignatureBasedDetectionEngine;
+use signature_detection_engine::transaction::Transaction;
+
+const RULES: usize = 1000;
+const ITERATIONS: u32 = 10_000;
+
+// A phase 1 rule set in which only one rule in ten selects a request header,
+// the rest selecting ARGS, which the header phase never evaluates.
+fn conf() -> String {
+    (0..RULES)
+        .map(|id| match id % 10 {
+            0 => format!(
+                r#"SecRule REQUEST_HEADERS:User-Agent "@contains scanner-{}" "id:{},phase:1,deny""#,
+                id, id
+            ),
+            _ => format!(
+                r#"SecRule ARGS "@contains payload-{}" "id:{},phase:1,deny""#,
+                id, id
+            ),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn headers() -> Vec<(String, String)> {
+    vec![
+        (":method".to_string(), "GET".to_string()),
+        (":path".to_string(), "/".to_string()),
+        ("user-agent".to_string(), "curl/8.0".to_string()),
+        ("accept".to_string(), "*/*".to_string()),
+    ]
+}
+
+fn time_header_phase(engine: &SignatureBasedDetectionEngine) -> Duration {
+    let headers = headers();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let result = engine
+            .run_header_phase(&mut Transaction::default(), black_box(headers.clone()))
+            .unwrap();
+        black_box(result);
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let conf = conf();
+    let indexed = SignatureBasedDetectionEngine::from_conf_str(&conf).unwrap();
+    let linear = SignatureBasedDetectionEngine::from_conf_str(&conf)
+        .unwrap()
+        .without_rule_index();
+
+    let linear_time = time_header_phase(&linear);
+    let indexed_time = time_header_phase(&indexed);
+    println!("{} rules, request header phase:", RULES);
+    println!("  linear scan: {:?} per request", linear_time);
+    println!("  indexed:     {:?} per request", indexed_time);
+}
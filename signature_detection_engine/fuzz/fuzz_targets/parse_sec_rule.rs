@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use signature_detection_engine::SecRule;
+
+// -----------------------------------------------------------------------------
+// Fuzzing - SecRule Parser
+// -----------------------------------------------------------------------------
+
+// Rules files may come from outside the project (e.g. a vendor ruleset), so
+// parsing a SecRule directive has to return either a rule or a
+// ValidationErrors for any input, and never panic. Inputs which aren't valid
+// UTF-8 are parsed lossily, as rules files are read as strings. A parsed rule
+// is printed back too, as Display walks every part the parser filled in.
+//
+//   cd signature_detection_engine
+//   cargo +nightly fuzz run parse_sec_rule fuzz/corpus/parse_sec_rule fuzz/seeds/parse_sec_rule
+fuzz_target!(|data: &[u8]| {
+    let raw = String::from_utf8_lossy(data).into_owned();
+    if let Ok(sec_rule) = SecRule::try_from(raw) {
+        let _ = sec_rule.to_string();
+    }
+});
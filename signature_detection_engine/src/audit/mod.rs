@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use crate::macros::expand_macros;
+use crate::transaction::Transaction;
+use crate::{MatchResult, Severity};
+
+// -----------------------------------------------------------------------------
+// Signature-Based Detection Engine - Audit Log
+// -----------------------------------------------------------------------------
+
+// A record of a single rule firing, for incident response. Every matched rule
+// produces a record, including pass and allow rules which don't block, unless
+// the rule has nolog or noauditlog. The message and log data have their
+// macros expanded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditRecord {
+    pub request_id: Option<String>,
+    pub rule_id: u32,
+    pub message: Option<String>,
+    pub log_data: Option<String>,
+    pub matched_variable: String,
+    pub matched_value: String,
+    // the rule's tags and severity, by which events are usually categorized
+    // (e.g. "attack-xss")
+    pub tags: Vec<String>,
+    pub severity: Option<Severity>,
+    // whether the transaction had been blocked when the rule fired, which is
+    // its final disposition for rules in the logging phase
+    pub blocked: bool,
+}
+
+impl AuditRecord {
+    pub fn new(match_result: &MatchResult, transaction: &Transaction) -> Self {
+        let expand = |template: &String| expand_macros(template, transaction, match_result);
+        Self {
+            request_id: transaction.request_id.clone(),
+            rule_id: match_result.rule.id,
+            message: match_result.rule.message.as_ref().map(expand),
+            log_data: match_result.rule.log_data.as_ref().map(expand),
+            matched_variable: match_result.matched_var.clone(),
+            matched_value: match_result.matched_value.clone(),
+            tags: match_result.rule.tags.clone(),
+            severity: match_result.rule.severity,
+            blocked: transaction.blocked,
+        }
+    }
+}
+
+// Called by the engine with the audit record of every rule that fires.
+#[derive(Clone)]
+pub struct AuditHook(Arc<dyn Fn(&AuditRecord) + Send + Sync>);
+
+impl AuditHook {
+    pub fn new(hook: impl Fn(&AuditRecord) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    pub fn emit(&self, record: &AuditRecord) {
+        (self.0)(record)
+    }
+}
+
+impl std::fmt::Debug for AuditHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AuditHook")
+    }
+}
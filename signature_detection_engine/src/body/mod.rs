@@ -0,0 +1,167 @@
+use serde_json::Value;
+
+use crate::body::multipart::parse_multipart;
+use crate::compatibility::modsecurity::transformations::url_decode;
+
+mod multipart;
+
+// -----------------------------------------------------------------------------
+// Signature-Based Detection Engine - Body Processors
+// -----------------------------------------------------------------------------
+
+// How a request body is parsed into arguments, like ModSecurity's
+// ctl:requestBodyProcessor. Whatever the processor, rules see the body as it
+// was received as REQUEST_BODY, and the arguments parsed from it as ARGS.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BodyProcessor {
+    // "a=1&b=%27", parsed like a query string
+    UrlEncoded,
+    // a JSON document, whose values are named by their path from the root,
+    // e.g. "json.user.name" or "json.items.0"
+    Json,
+    // multipart/form-data, whose form fields are parsed into arguments, and
+    // whose uploaded files are seen as FILES, FILES_NAMES and FILES_SIZES
+    Multipart,
+    // any other body, which isn't parsed
+    #[default]
+    Raw,
+}
+
+// A request body along with the arguments and files its processor parsed
+// from it. The error is why the body couldn't be parsed, or could only be
+// parsed in part (e.g. invalid JSON), in which case the arguments are those
+// parsed before the error.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcessedBody<'a> {
+    pub processor: BodyProcessor,
+    pub args: Vec<(String, Vec<u8>)>,
+    pub files: Vec<UploadedFile>,
+    pub error: Option<String>,
+    pub raw: &'a [u8],
+}
+
+impl<'a> ProcessedBody<'a> {
+    // a body which isn't parsed, see BodyProcessor::Raw
+    pub fn raw(body: &'a [u8]) -> Self {
+        Self {
+            processor: BodyProcessor::Raw,
+            args: Vec::new(),
+            files: Vec::new(),
+            error: None,
+            raw: body,
+        }
+    }
+}
+
+// A file uploaded in a multipart body, by the form field it was uploaded with
+// (e.g. "avatar") and the name the client gave it (e.g. "shell.php"). Rules
+// see the file name as FILES, the field name as FILES_NAMES and the size in
+// bytes as FILES_SIZES, each keyed by the field name. The content type is the
+// one the client declared for the file, which isn't checked against its
+// content.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UploadedFile {
+    pub name: String,
+    pub file_name: String,
+    pub content_type: Option<String>,
+    pub size: usize,
+}
+
+impl BodyProcessor {
+    // Selects the processor for a request's content-type, e.g.
+    // "application/json; charset=utf-8". Media types are compared without
+    // their parameters and regardless of case, and JSON based media types
+    // (e.g. "application/vnd.api+json") are processed as JSON. Requests with
+    // any other content-type, or without one, are processed as Raw.
+    pub fn for_content_type(content_type: Option<&str>) -> Self {
+        let media_type = content_type
+            .and_then(|content_type| content_type.split(';').next())
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match media_type.as_str() {
+            "application/x-www-form-urlencoded" => BodyProcessor::UrlEncoded,
+            "multipart/form-data" => BodyProcessor::Multipart,
+            "application/json" => BodyProcessor::Json,
+            media_type if media_type.ends_with("+json") => BodyProcessor::Json,
+            _ => BodyProcessor::Raw,
+        }
+    }
+
+    // Parses a body into its arguments, where the content-type provides the
+    // boundary of a multipart body. A body which fails to parse is still seen
+    // as REQUEST_BODY.
+    pub fn process<'a>(self, content_type: Option<&str>, body: &'a [u8]) -> ProcessedBody<'a> {
+        let mut processed = ProcessedBody {
+            processor: self,
+            ..ProcessedBody::raw(body)
+        };
+        match self {
+            BodyProcessor::UrlEncoded => processed.args = parse_urlencoded(body).collect(),
+            BodyProcessor::Json => match serde_json::from_slice::<Value>(body) {
+                Ok(value) => {
+                    flatten_json(JSON_ARGS_PREFIX.to_string(), &value, &mut processed.args)
+                }
+                Err(error) => processed.error = Some(format!("invalid JSON body: {}", error)),
+            },
+            BodyProcessor::Multipart => {
+                let multipart = parse_multipart(content_type, body);
+                processed.args = multipart.args;
+                processed.files = multipart.files;
+                processed.error = multipart.error;
+            }
+            BodyProcessor::Raw => {}
+        }
+        processed
+    }
+}
+
+// Processes a request body with the processor for its content-type, see
+// BodyProcessor::for_content_type.
+pub fn process_body<'a>(content_type: Option<&str>, body: &'a [u8]) -> ProcessedBody<'a> {
+    BodyProcessor::for_content_type(content_type).process(content_type, body)
+}
+
+// Splits a query string or urlencoded body into its arguments, URL decoding
+// their names and values as ModSecurity does. An argument without a '=' has an
+// empty value. The arguments are parsed as they're iterated, so that a caller
+// which only takes some of them doesn't pay for the rest.
+pub(crate) fn parse_urlencoded(encoded: &[u8]) -> impl Iterator<Item = (String, Vec<u8>)> {
+    encoded
+        .split(|byte| *byte == b'&')
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| {
+            let (name, value) = match arg.iter().position(|byte| *byte == b'=') {
+                Some(position) => (&arg[..position], &arg[position + 1..]),
+                None => (arg, &[][..]),
+            };
+            let name = url_decode(name, false);
+            (
+                String::from_utf8_lossy(&name).into_owned(),
+                url_decode(value, false),
+            )
+        })
+}
+
+// The prefix of the names of the arguments of a JSON body, as in ModSecurity.
+const JSON_ARGS_PREFIX: &str = "json";
+
+// Adds an argument for every string, number, boolean and null in the value,
+// named by its path, where null is an empty value.
+fn flatten_json(name: String, value: &Value, args: &mut Vec<(String, Vec<u8>)>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                flatten_json(format!("{}.{}", name, key), value, args);
+            }
+        }
+        Value::Array(array) => {
+            for (index, value) in array.iter().enumerate() {
+                flatten_json(format!("{}.{}", name, index), value, args);
+            }
+        }
+        Value::String(string) => args.push((name, string.clone().into_bytes())),
+        Value::Null => args.push((name, Vec::new())),
+        Value::Bool(_) | Value::Number(_) => args.push((name, value.to_string().into_bytes())),
+    }
+}
@@ -0,0 +1,214 @@
+use crate::body::UploadedFile;
+
+// -----------------------------------------------------------------------------
+// Signature-Based Detection Engine - Multipart Body Processor
+// -----------------------------------------------------------------------------
+
+// The fields and files of a multipart/form-data body, and the reason parsing
+// stopped early, if it did.
+#[derive(Debug, Default)]
+pub(crate) struct Multipart {
+    pub(crate) args: Vec<(String, Vec<u8>)>,
+    pub(crate) files: Vec<UploadedFile>,
+    pub(crate) error: Option<String>,
+}
+
+// Parses a multipart/form-data body into its fields, where parts with a
+// filename are uploaded files and the others are form fields. Parts are
+// delimited by the boundary of the request's content-type, on lines of their
+// own, with either CRLF or LF line endings.
+//
+// A malformed body (e.g. without a boundary, or missing its closing
+// boundary) is parsed as far as it can be, and the fields found up to that
+// point are kept, so that an attacker can't hide a field from rules by
+// breaking the body after it. Likewise a malformed part (e.g. without a name)
+// is skipped rather than ending the body, so it can't hide the parts after it.
+pub(crate) fn parse_multipart(content_type: Option<&str>, body: &[u8]) -> Multipart {
+    let mut multipart = Multipart::default();
+    let boundary = match content_type.and_then(boundary) {
+        Some(boundary) => boundary,
+        None => {
+            multipart.error = Some("multipart content-type without a boundary".to_string());
+            return multipart;
+        }
+    };
+    let delimiter = format!("--{}", boundary).into_bytes();
+
+    let mut position = match find(body, &delimiter) {
+        Some(position) => position + delimiter.len(),
+        None => {
+            multipart.error = Some("multipart body without its boundary".to_string());
+            return multipart;
+        }
+    };
+    loop {
+        let rest = &body[position..];
+        if rest.starts_with(b"--") {
+            return multipart;
+        }
+        let rest = match strip_line_ending(rest) {
+            Some(rest) => rest,
+            None => {
+                multipart.error = Some("multipart boundary not followed by a newline".to_string());
+                return multipart;
+            }
+        };
+        position = body.len() - rest.len();
+
+        // the part ends at the line ending before the next delimiter
+        let (part, next) = match find_delimiter(rest, &delimiter) {
+            Some((end, next)) => (&rest[..end], position + next),
+            None => {
+                multipart.error = Some("multipart body without its closing boundary".to_string());
+                (rest, body.len())
+            }
+        };
+        if let Err(error) = multipart.add_part(part) {
+            multipart.error = Some(error);
+        }
+        if next == body.len() {
+            return multipart;
+        }
+        position = next;
+    }
+}
+
+impl Multipart {
+    fn add_part(&mut self, part: &[u8]) -> Result<(), String> {
+        let (headers, content) = split_headers(part)
+            .ok_or_else(|| "multipart part without a blank line after its headers".to_string())?;
+
+        let mut name = None;
+        let mut file_name = None;
+        let mut content_type = None;
+        for line in String::from_utf8_lossy(headers).lines() {
+            let (header, value) = match line.split_once(':') {
+                Some((header, value)) => (header.trim(), value.trim()),
+                None => continue,
+            };
+            if header.eq_ignore_ascii_case("content-disposition") {
+                for (param, value) in disposition_params(value) {
+                    match param.to_ascii_lowercase().as_str() {
+                        "name" => name = Some(value),
+                        "filename" => file_name = Some(value),
+                        _ => {}
+                    }
+                }
+            } else if header.eq_ignore_ascii_case("content-type") {
+                content_type = Some(value.to_string());
+            }
+        }
+
+        let name =
+            name.ok_or_else(|| "multipart part without a content-disposition name".to_string())?;
+        match file_name {
+            Some(file_name) => self.files.push(UploadedFile {
+                name,
+                file_name,
+                content_type,
+                size: content.len(),
+            }),
+            None => self.args.push((name, content.to_vec())),
+        }
+        Ok(())
+    }
+}
+
+// The boundary parameter of a content-type, e.g. "----abc" for
+// "multipart/form-data; boundary=----abc", which may be quoted.
+fn boundary(content_type: &str) -> Option<String> {
+    disposition_params(content_type)
+        .into_iter()
+        .find(|(param, _)| param.eq_ignore_ascii_case("boundary"))
+        .map(|(_, boundary)| boundary)
+        .filter(|boundary| !boundary.is_empty())
+}
+
+// The parameters after the first ';' of a header value, e.g.
+// `form-data; name="upload"; filename="a;b.php"`, where quoted values may
+// contain ';' and backslash-escaped quotes.
+fn disposition_params(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = value.chars().skip_while(|ch| *ch != ';').peekable();
+    while chars.next().is_some() {
+        let mut param = String::new();
+        while let Some(ch) = chars.next_if(|ch| *ch != '=' && *ch != ';') {
+            param.push(ch);
+        }
+        // a parameter without a value
+        if chars.next_if_eq(&'=').is_none() {
+            continue;
+        }
+        let param = param.trim().to_string();
+        while chars.next_if(|ch| ch.is_whitespace()).is_some() {}
+        let mut param_value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(ch) = chars.next() {
+                match ch {
+                    '\\' => param_value.extend(chars.next()),
+                    '"' => break,
+                    ch => param_value.push(ch),
+                }
+            }
+            while chars.next_if(|ch| *ch != ';').is_some() {}
+        } else {
+            while let Some(ch) = chars.next_if(|ch| *ch != ';') {
+                param_value.push(ch);
+            }
+            param_value = param_value.trim().to_string();
+        }
+        if !param.is_empty() {
+            params.push((param, param_value));
+        }
+    }
+    params
+}
+
+// Finds the next delimiter which starts a line, returning where the part
+// before it ends (before the line ending) and where the delimiter ends.
+fn find_delimiter(bytes: &[u8], delimiter: &[u8]) -> Option<(usize, usize)> {
+    let mut start = 0;
+    while let Some(found) = find(&bytes[start..], delimiter) {
+        let position = start + found;
+        let end = match &bytes[..position] {
+            [.., b'\r', b'\n'] => Some(position - 2),
+            [.., b'\n'] => Some(position - 1),
+            // a part may be empty, in which case the delimiter follows the
+            // previous one's line ending directly
+            [] => Some(0),
+            _ => None,
+        };
+        if let Some(end) = end {
+            return Some((end, position + delimiter.len()));
+        }
+        start = position + 1;
+    }
+    None
+}
+
+// Splits a part at the blank line ending its headers.
+fn split_headers(part: &[u8]) -> Option<(&[u8], &[u8])> {
+    if let Some(rest) = strip_line_ending(part) {
+        // a part without headers
+        return Some((&[], rest));
+    }
+    let crlf = find(part, b"\r\n\r\n").map(|position| (position, 4));
+    let lf = find(part, b"\n\n").map(|position| (position, 2));
+    let (position, length) = match (crlf, lf) {
+        (Some(crlf), Some(lf)) => crlf.min(lf),
+        (crlf, lf) => crlf.or(lf)?,
+    };
+    Some((&part[..position], &part[position + length..]))
+}
+
+fn strip_line_ending(bytes: &[u8]) -> Option<&[u8]> {
+    bytes
+        .strip_prefix(b"\r\n")
+        .or_else(|| bytes.strip_prefix(b"\n"))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
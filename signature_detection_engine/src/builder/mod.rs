@@ -0,0 +1,94 @@
+use crate::SignatureBasedDetectionEngine;
+use crate::compatibility::modsecurity::directives::{Directive, sec_rule::SecRule};
+use crate::compatibility::modsecurity::rulesets::{RuleGroup, RuleSet, sort_rules_by_id};
+
+// -----------------------------------------------------------------------------
+// Signature-Based Detection Engine - Builder
+// -----------------------------------------------------------------------------
+
+// Builds an engine from rules added one at a time, bucketing them into phases
+// by each rule's phase, for library users who don't load rules files:
+//
+//   let engine = EngineBuilder::new()
+//       .add_rule(SecRule::try_from(rule.to_string())?)
+//       .build();
+//
+// Within a phase, rules are evaluated in the order they were added, unless
+// they're sorted by ID (see sorted_by_id).
+#[derive(Clone, Debug, Default)]
+pub struct EngineBuilder {
+    rule_group: RuleGroup,
+    sorted_by_id: bool,
+}
+
+impl EngineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The rule is added to the phase's last ruleset when that is unnamed, and
+    // to a new unnamed ruleset otherwise.
+    pub fn add_rule(mut self, sec_rule: SecRule) -> Self {
+        let rulesets = self.rule_group.entry(sec_rule.phase).or_default();
+        match rulesets.last_mut() {
+            Some(ruleset) if ruleset.name.is_none() => {
+                ruleset.directives.push(Directive::SecRule(sec_rule));
+            }
+            _ => rulesets.push(RuleSet {
+                name: None,
+                description: None,
+                directives: vec![Directive::SecRule(sec_rule)],
+                version: None,
+            }),
+        }
+        self
+    }
+
+    // A ruleset with rules for several phases is split into one ruleset per
+    // phase, each keeping the name, description and version of the original.
+    // Markers aren't specific to a phase, so they're kept in each of them.
+    pub fn add_ruleset(mut self, ruleset: RuleSet) -> Self {
+        let mut phases = Vec::new();
+        for directive in &ruleset.directives {
+            if let Directive::SecRule(sec_rule) = directive
+                && !phases.contains(&sec_rule.phase)
+            {
+                phases.push(sec_rule.phase);
+            }
+        }
+
+        for phase in phases {
+            let directives = ruleset
+                .directives
+                .iter()
+                .filter(|directive| match directive {
+                    Directive::SecRule(sec_rule) => sec_rule.phase == phase,
+                    Directive::SecMarker(_) => true,
+                })
+                .cloned()
+                .collect();
+            self.rule_group.entry(phase).or_default().push(RuleSet {
+                name: ruleset.name.clone(),
+                description: ruleset.description.clone(),
+                directives,
+                version: ruleset.version.clone(),
+            });
+        }
+        self
+    }
+
+    // Evaluates the rules of each phase in order of ID rather than in the
+    // order they were added, for deterministic behavior when rules from
+    // several files are combined. Off by default.
+    pub fn sorted_by_id(mut self, sorted_by_id: bool) -> Self {
+        self.sorted_by_id = sorted_by_id;
+        self
+    }
+
+    pub fn build(mut self) -> SignatureBasedDetectionEngine {
+        if self.sorted_by_id {
+            sort_rules_by_id(&mut self.rule_group);
+        }
+        SignatureBasedDetectionEngine::new(self.rule_group)
+    }
+}
@@ -1 +1 @@
-pub mod modsecurity;
\ No newline at end of file
+pub mod modsecurity;
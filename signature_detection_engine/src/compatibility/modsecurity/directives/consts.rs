@@ -3,5 +3,24 @@
 // -----------------------------------------------------------------------------
 
 pub const REQUEST_HEADERS: &str = "REQUEST_HEADERS";
+pub const REQUEST_COOKIES: &str = "REQUEST_COOKIES";
+pub const RESPONSE_HEADERS: &str = "RESPONSE_HEADERS";
+pub const RESPONSE_STATUS: &str = "RESPONSE_STATUS";
 pub const REQUEST_BODY: &str = "REQUEST_BODY";
+pub const RESPONSE_BODY: &str = "RESPONSE_BODY";
+pub const REQUEST_METHOD: &str = "REQUEST_METHOD";
+pub const REQUEST_FILENAME: &str = "REQUEST_FILENAME";
+pub const REQUEST_BASENAME: &str = "REQUEST_BASENAME";
+pub const REMOTE_ADDR: &str = "REMOTE_ADDR";
 pub const ARGS: &str = "ARGS";
+pub const QUERY_STRING: &str = "QUERY_STRING";
+pub const FILES: &str = "FILES";
+pub const FILES_NAMES: &str = "FILES_NAMES";
+pub const FILES_SIZES: &str = "FILES_SIZES";
+pub const REQBODY_ERROR: &str = "REQBODY_ERROR";
+pub const REQBODY_ERROR_MSG: &str = "REQBODY_ERROR_MSG";
+pub const TX: &str = "TX";
+
+// the tag the OWASP CRS uses to group rules by paranoia level, e.g.
+// "paranoia-level/2"
+pub const PARANOIA_LEVEL_TAG_PREFIX: &str = "paranoia-level/";
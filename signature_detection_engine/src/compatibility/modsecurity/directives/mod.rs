@@ -1,9 +1,16 @@
+use serde::{Deserialize, Serialize};
+
 pub mod consts;
 pub mod parsers;
+pub mod rule_exclusion;
+pub mod sec_default_action;
 pub mod sec_marker;
 pub mod sec_rule;
 
-#[derive(Clone, Debug, PartialEq)]
+// nearly every directive is a SecRule, so boxing it to shrink the enum would
+// only add an allocation per rule
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub enum Directive {
     SecRule(sec_rule::SecRule),
     SecMarker(sec_marker::SecMarker),
@@ -0,0 +1,377 @@
+use std::path::{Path, PathBuf};
+
+use crate::compatibility::modsecurity::directives::{
+    Directive,
+    parsers::{
+        rule_exclusion::{REMOVE_BY_ID_DIRECTIVE, REMOVE_BY_TAG_DIRECTIVE, parse_rule_exclusion},
+        sec_default_action::{DEFAULT_ACTION_DIRECTIVE, parse_sec_default_action},
+        sec_rule::{ParseOptions, parse_sec_rule_with_defaults, tokenize_directive},
+    },
+    rule_exclusion::RuleExclusion,
+    sec_default_action::SecDefaultAction,
+    sec_rule::Phase,
+};
+use crate::compatibility::modsecurity::rulesets::{RuleGroup, RuleSet, remove_rules};
+use crate::errors::{LoadErrors, ValidationErrors};
+
+// -----------------------------------------------------------------------------
+// ModSecurity - Configuration Parser
+// -----------------------------------------------------------------------------
+
+// Directives which are recognized but have no effect on the engine, so they're
+// skipped rather than rejected: SecComponentSignature only describes the
+// ruleset, and SecMarker only marks a target for skipAfter, which isn't
+// supported. Any other directive besides SecRule, SecDefaultAction and the
+// rule exclusions is an error, as ignoring it could change which requests the
+// rules match (e.g. SecAction).
+const SKIPPED_DIRECTIVES: [&str; 2] = ["SecComponentSignature", "SecMarker"];
+
+// Directives which load the rules of other files in their place, resolved
+// relative to the directory of the including file. Include fails when nothing
+// matches, while IncludeOptional doesn't.
+const INCLUDE_DIRECTIVE: &str = "Include";
+const INCLUDE_OPTIONAL_DIRECTIVE: &str = "IncludeOptional";
+
+// A directive which was skipped while parsing a rules file, and the line it
+// starts on, so that loaders can report what didn't take effect. The file is
+// that of an included file, or None for the file being loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedDirective {
+    pub file: Option<String>,
+    pub line: usize,
+    pub name: String,
+}
+
+// The rules parsed so far, across a rules file and the files it includes.
+struct ConfParser {
+    name: Option<String>,
+    options: ParseOptions,
+    rule_group: RuleGroup,
+    skipped_directives: Vec<SkippedDirective>,
+    // the latest SecDefaultAction of each phase, which the rules after it
+    // start from, including those of files included after it, ordered by when
+    // they were given so that the latest of all comes last
+    default_actions: Vec<SecDefaultAction>,
+    // the phase of the chain the previous rule started or continued, if it
+    // has the chain action, which the next rule continues
+    chain_phase: Option<Phase>,
+    // the directives which failed, which don't stop the rest from being
+    // parsed, so that every failure is reported at once
+    errors: Vec<LoadErrors>,
+    // the canonical paths of the files currently being parsed, outermost
+    // first, to detect files which include themselves
+    including: Vec<PathBuf>,
+}
+
+// Parses the contents of a ModSecurity rules file into a RuleGroup, with one
+// RuleSet per phase holding that phase's rules in file order, along with the
+// directives which were skipped (see SKIPPED_DIRECTIVES). The path is that of
+// the file, if the rules were read from one.
+//
+// Blank lines and '#' comments are skipped, and lines ending in a backslash
+// are joined with the line that follows. Errors report the line on which the
+// failing directive starts, and when more than one directive fails they're
+// all reported (see LoadErrors::Multiple). A rule continuing a chain is stored
+// in the phase of the rule starting it, directly after it, and the engine
+// only evaluates it as part of that chain.
+//
+// Rule exclusions (SecRuleRemoveById, SecRuleRemoveByTag) remove the rules
+// loaded before them, as in ModSecurity, so rules which follow an exclusion
+// aren't affected by it. Likewise a SecDefaultAction only applies to the rules
+// of its phase which follow it, up to the next SecDefaultAction for that phase,
+// and to the rules without a phase which follow it, up to the next
+// SecDefaultAction of any phase.
+pub(crate) fn parse_conf(
+    conf: &str,
+    path: Option<&Path>,
+    options: ParseOptions,
+) -> Result<(RuleGroup, Vec<SkippedDirective>), LoadErrors> {
+    let mut parser = ConfParser {
+        name: path.map(|path| path.display().to_string()),
+        options,
+        rule_group: RuleGroup::new(),
+        skipped_directives: Vec::new(),
+        default_actions: Vec::new(),
+        chain_phase: None,
+        errors: Vec::new(),
+        including: path
+            .and_then(|path| path.canonicalize().ok())
+            .into_iter()
+            .collect(),
+    };
+    parser.parse(conf, None, path.and_then(Path::parent));
+    collect_errors(parser.errors)?;
+    Ok((parser.rule_group, parser.skipped_directives))
+}
+
+impl ConfParser {
+    fn parse(&mut self, conf: &str, file: Option<&str>, base_dir: Option<&Path>) {
+        for (line, directive) in logical_lines(conf) {
+            let name = directive.split_whitespace().next().unwrap_or_default();
+            if SKIPPED_DIRECTIVES.contains(&name) {
+                self.skipped_directives.push(SkippedDirective {
+                    file: file.map(str::to_string),
+                    line,
+                    name: name.to_string(),
+                });
+                continue;
+            }
+
+            if name == INCLUDE_DIRECTIVE || name == INCLUDE_OPTIONAL_DIRECTIVE {
+                if let Err(error) = self.include(&directive, line, base_dir) {
+                    self.errors.push(error);
+                }
+                continue;
+            }
+
+            if is_rule_exclusion(&directive) {
+                match parse_rule_exclusion(&directive) {
+                    Ok(exclusion) => {
+                        remove_rules(&mut self.rule_group, &exclusion);
+                    }
+                    Err(error) => self.errors.push(LoadErrors::InvalidRule { line, error }),
+                }
+                continue;
+            }
+
+            if name == DEFAULT_ACTION_DIRECTIVE {
+                match parse_sec_default_action(&directive, self.options) {
+                    Ok(default_action) => {
+                        self.default_actions
+                            .retain(|existing| existing.phase != default_action.phase);
+                        self.default_actions.push(default_action);
+                    }
+                    Err(error) => self.errors.push(LoadErrors::InvalidRule { line, error }),
+                }
+                continue;
+            }
+
+            let sec_rule = match parse_sec_rule_with_defaults(
+                directive,
+                base_dir,
+                self.options,
+                &self.default_actions,
+                self.chain_phase.take(),
+            ) {
+                Ok(sec_rule) => sec_rule,
+                Err(error) => {
+                    self.errors.push(LoadErrors::InvalidRule { line, error });
+                    continue;
+                }
+            };
+            if sec_rule.chain {
+                self.chain_phase = Some(sec_rule.phase);
+            }
+
+            let rulesets = self.rule_group.entry(sec_rule.phase).or_default();
+            if rulesets.is_empty() {
+                rulesets.push(RuleSet {
+                    name: self.name.clone(),
+                    description: None,
+                    directives: Vec::new(),
+                    version: None,
+                });
+            }
+            rulesets[0].directives.push(Directive::SecRule(sec_rule));
+        }
+    }
+
+    // Parses the files matched by an Include or IncludeOptional directive, in
+    // sorted order, as if their rules were written in place of the directive.
+    // The errors of the included files are attributed to them.
+    fn include(
+        &mut self,
+        directive: &str,
+        line: usize,
+        base_dir: Option<&Path>,
+    ) -> Result<(), LoadErrors> {
+        let invalid_rule = |error| LoadErrors::InvalidRule { line, error };
+        let mut arguments = tokenize_directive(directive)
+            .map_err(invalid_rule)?
+            .into_iter();
+        let name = arguments.next().unwrap_or_default();
+        let pattern = arguments.next().ok_or_else(|| {
+            invalid_rule(ValidationErrors::MissingArgument {
+                directive: name.clone(),
+            })
+        })?;
+        if let Some(unexpected) = arguments.next() {
+            return Err(invalid_rule(ValidationErrors::UnexpectedArgument {
+                found: unexpected,
+            }));
+        }
+
+        let pattern = match base_dir {
+            Some(base_dir) => base_dir.join(&pattern),
+            None => PathBuf::from(&pattern),
+        };
+        let paths = include_paths(&pattern);
+        if paths.is_empty() && name == INCLUDE_DIRECTIVE {
+            return Err(LoadErrors::MissingInclude {
+                line,
+                path: pattern.display().to_string(),
+            });
+        }
+
+        for path in paths {
+            let display_path = path.display().to_string();
+            let canonical_path = path.canonicalize().unwrap_or_else(|_| path.clone());
+            if self.including.contains(&canonical_path) {
+                return Err(LoadErrors::IncludeCycle {
+                    line,
+                    path: display_path,
+                });
+            }
+            let conf = std::fs::read_to_string(&path).map_err(|e| LoadErrors::Io {
+                path: display_path.clone(),
+                reason: e.to_string(),
+            })?;
+
+            self.including.push(canonical_path);
+            let outer_errors = std::mem::take(&mut self.errors);
+            self.parse(&conf, Some(&display_path), path.parent());
+            self.including.pop();
+            let included_errors = std::mem::replace(&mut self.errors, outer_errors);
+            self.errors
+                .extend(included_errors.into_iter().map(|error| match error {
+                    // errors already attributed to a file are reported as they are
+                    LoadErrors::Io { .. } | LoadErrors::InIncludedFile { .. } => error,
+                    error => LoadErrors::InIncludedFile {
+                        path: display_path.clone(),
+                        error: Box::new(error),
+                    },
+                }));
+        }
+
+        Ok(())
+    }
+}
+
+// The files matched by an include's path, in sorted order. Wildcards ('*' and
+// '?') are supported in the file name, e.g. "rules/REQUEST-*.conf", as the
+// OWASP CRS is included, but not in the directories leading up to it.
+fn include_paths(pattern: &Path) -> Vec<PathBuf> {
+    let file_name = pattern
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if !file_name.contains(['*', '?']) {
+        return match pattern.is_file() {
+            true => vec![pattern.to_path_buf()],
+            false => Vec::new(),
+        };
+    }
+
+    let dir = match pattern.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| wildcard_match(&file_name, &entry.file_name().to_string_lossy()))
+        .map(|entry| dir.join(entry.file_name()))
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    paths
+}
+
+// Matches a file name against a pattern where '*' matches any number of
+// characters and '?' any single character.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // the position after the last '*', and of the name when it was reached,
+    // to backtrack to when the rest of the pattern fails to match
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&ch) if ch == '?' || ch == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    backtrack = Some((star_p, star_n + 1));
+                    p = star_p;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&ch| ch == '*')
+}
+
+// Parses a file of rule exclusions, e.g. the CRS's
+// REQUEST-900-EXCLUSION-RULES-BEFORE-CRS.conf, to be applied to rules which
+// were already loaded. Directives other than rule exclusions are rejected.
+pub(crate) fn parse_exclusions(conf: &str) -> Result<Vec<RuleExclusion>, LoadErrors> {
+    let mut exclusions = Vec::new();
+    let mut errors = Vec::new();
+    for (line, directive) in logical_lines(conf) {
+        match parse_rule_exclusion(&directive) {
+            Ok(exclusion) => exclusions.push(exclusion),
+            Err(error) => errors.push(LoadErrors::InvalidRule { line, error }),
+        }
+    }
+    collect_errors(errors)?;
+    Ok(exclusions)
+}
+
+// A single error is reported as it is, and several together.
+fn collect_errors(mut errors: Vec<LoadErrors>) -> Result<(), LoadErrors> {
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(LoadErrors::Multiple { errors }),
+    }
+}
+
+fn is_rule_exclusion(directive: &str) -> bool {
+    matches!(
+        directive.split_whitespace().next(),
+        Some(REMOVE_BY_ID_DIRECTIVE | REMOVE_BY_TAG_DIRECTIVE)
+    )
+}
+
+// Joins continuation lines, returning each directive along with the (1-based)
+// line number it starts on.
+fn logical_lines(conf: &str) -> Vec<(usize, String)> {
+    let mut directives = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+
+    for (index, line) in conf.lines().enumerate() {
+        let line = line.trim();
+
+        if current.is_none() && (line.is_empty() || line.starts_with('#')) {
+            continue;
+        }
+
+        let (start, directive) = current.get_or_insert_with(|| (index + 1, String::new()));
+        match line.strip_suffix('\\') {
+            Some(continued) => {
+                directive.push_str(continued);
+                directive.push(' ');
+            }
+            None => {
+                directive.push_str(line);
+                directives.push((*start, std::mem::take(directive)));
+                current = None;
+            }
+        }
+    }
+
+    if let Some(directive) = current {
+        directives.push(directive);
+    }
+
+    directives
+}
@@ -1 +1,4 @@
+pub mod conf;
+pub mod rule_exclusion;
+pub mod sec_default_action;
 pub mod sec_rule;
@@ -0,0 +1,70 @@
+use crate::compatibility::modsecurity::directives::{
+    parsers::sec_rule::{ParseOptions, compile_regex, tokenize_directive},
+    rule_exclusion::RuleExclusion,
+};
+use crate::errors::ValidationErrors;
+
+// -----------------------------------------------------------------------------
+// ModSecurity - Rule Exclusion Parser
+// -----------------------------------------------------------------------------
+
+pub(crate) const REMOVE_BY_ID_DIRECTIVE: &str = "SecRuleRemoveById";
+pub(crate) const REMOVE_BY_TAG_DIRECTIVE: &str = "SecRuleRemoveByTag";
+
+impl TryFrom<&str> for RuleExclusion {
+    type Error = ValidationErrors;
+
+    fn try_from(raw_directive: &str) -> Result<Self, Self::Error> {
+        parse_rule_exclusion(raw_directive)
+    }
+}
+
+pub(crate) fn parse_rule_exclusion(raw_directive: &str) -> Result<RuleExclusion, ValidationErrors> {
+    let mut arguments = tokenize_directive(raw_directive)?.into_iter();
+    let directive = arguments.next().ok_or(ValidationErrors::EmptyRule)?;
+    let arguments: Vec<String> = arguments.collect();
+    if arguments.is_empty() {
+        return Err(ValidationErrors::MissingArgument { directive });
+    }
+
+    match directive.as_str() {
+        REMOVE_BY_ID_DIRECTIVE => {
+            let ranges = arguments
+                .iter()
+                .map(|argument| parse_id_range(argument))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(RuleExclusion::ById(ranges))
+        }
+        REMOVE_BY_TAG_DIRECTIVE => {
+            // the tag pattern is a single argument, so "attack xss" has to be
+            // quoted
+            if let Some(unexpected) = arguments.get(1) {
+                return Err(ValidationErrors::UnexpectedArgument {
+                    found: unexpected.clone(),
+                });
+            }
+            let pattern = &arguments[0];
+            let regex = compile_regex(pattern, ParseOptions::default().regex_size_limit)?;
+            Ok(RuleExclusion::ByTag(regex))
+        }
+        _ => Err(ValidationErrors::InvalidDirective { found: directive }),
+    }
+}
+
+// e.g. "941100", or "942100-942999" for every ID from 942100 to 942999
+fn parse_id_range(argument: &str) -> Result<(u32, u32), ValidationErrors> {
+    let invalid_range = || ValidationErrors::InvalidRuleIdRange {
+        value: argument.to_string(),
+    };
+
+    let (start, end) = match argument.split_once('-') {
+        Some((start, end)) => (start, end),
+        None => (argument, argument),
+    };
+    let start = start.trim().parse::<u32>().map_err(|_| invalid_range())?;
+    let end = end.trim().parse::<u32>().map_err(|_| invalid_range())?;
+    match start <= end {
+        true => Ok((start, end)),
+        false => Err(invalid_range()),
+    }
+}
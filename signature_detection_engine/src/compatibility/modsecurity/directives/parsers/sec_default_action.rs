@@ -0,0 +1,73 @@
+use crate::compatibility::modsecurity::directives::parsers::sec_rule::{
+    ParseOptions, apply_actions, split_actions, tokenize_directive,
+};
+use crate::compatibility::modsecurity::directives::sec_default_action::SecDefaultAction;
+use crate::compatibility::modsecurity::directives::sec_rule::SecRule;
+use crate::errors::ValidationErrors;
+
+// -----------------------------------------------------------------------------
+// ModSecurity - SecDefaultAction Parser
+// -----------------------------------------------------------------------------
+
+pub(crate) const DEFAULT_ACTION_DIRECTIVE: &str = "SecDefaultAction";
+
+// Actions which describe a single rule, which ModSecurity doesn't allow as
+// defaults, along with chain, which only makes sense for the rule it's on.
+const RULE_ONLY_ACTIONS: [&str; 10] = [
+    "id", "msg", "logdata", "tag", "severity", "rev", "ver", "maturity", "accuracy", "chain",
+];
+
+// Parses a SecDefaultAction directive, which must name the phase whose rules
+// it applies to. The other actions are checked as they would be on a rule, so
+// that an invalid default is reported on its own line rather than on every
+// rule which follows it.
+//
+// Reference: https://github.com/owasp-modsecurity/ModSecurity/wiki/Reference-Manual-(v2.x)#user-content-SecDefaultAction
+pub(crate) fn parse_sec_default_action(
+    raw_directive: &str,
+    options: ParseOptions,
+) -> Result<SecDefaultAction, ValidationErrors> {
+    let mut arguments = tokenize_directive(raw_directive)?.into_iter();
+    let name = arguments.next().unwrap_or_default();
+    if name != DEFAULT_ACTION_DIRECTIVE {
+        return Err(ValidationErrors::InvalidDirective { found: name });
+    }
+    let actions_str = arguments
+        .next()
+        .ok_or_else(|| ValidationErrors::MissingArgument {
+            directive: name.clone(),
+        })?;
+    if let Some(unexpected) = arguments.next() {
+        return Err(ValidationErrors::UnexpectedArgument { found: unexpected });
+    }
+
+    let mut phase = None;
+    let mut actions = Vec::new();
+    for action in split_actions(&actions_str) {
+        let action = action.trim();
+        let key = action.split_once(':').map_or(action, |(key, _)| key);
+        match key {
+            "" => {}
+            "phase" => phase = Some(action),
+            key if RULE_ONLY_ACTIONS.contains(&key) => {
+                return Err(ValidationErrors::InvalidDefaultAction {
+                    action: action.to_string(),
+                });
+            }
+            _ => actions.push(action.to_string()),
+        }
+    }
+    let phase = phase.ok_or(ValidationErrors::MissingDefaultPhase)?;
+
+    let mut sec_rule = SecRule::default();
+    apply_actions(
+        &mut sec_rule,
+        std::iter::once(phase).chain(actions.iter().map(String::as_str)),
+        options,
+    )?;
+
+    Ok(SecDefaultAction {
+        phase: sec_rule.phase,
+        actions,
+    })
+}
@@ -1,28 +1,144 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::compatibility::modsecurity::directives::sec_default_action::SecDefaultAction;
 use crate::compatibility::modsecurity::directives::sec_rule::{
-    Operator, Phase, SecRule, Severity, Variable,
+    CompiledOperator, Ctl, DisruptiveAction, Operator, Phase, SecRule, SetVar, Severity,
+    VariableSpec,
 };
+use crate::compatibility::modsecurity::operators::ip_match::IpMatchSet;
+use crate::compatibility::modsecurity::operators::validate_byte_range::ByteRangeSet;
+use crate::compatibility::modsecurity::transformations::{NO_TRANSFORMATION, is_transformation};
 use crate::errors::ValidationErrors;
 
 // -----------------------------------------------------------------------------
 // ModSecurity - SecRule Parser
 // -----------------------------------------------------------------------------
 
+// Controls how strictly rules are parsed.
+//
+// Strict parsing (the default) rejects any action the engine doesn't support,
+// which is what's wanted for hand-authored rules. Lenient parsing instead
+// records unsupported actions in the rule's unknown_actions, so that upstream
+// rule sets such as the OWASP CRS can be loaded before every action they use
+// is implemented.
+//
+// The regex size limit caps the memory a compiled @rx pattern may use (see
+// compile_regex), so that a rule can't exhaust the proxy's memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseOptions {
+    pub strict: bool,
+    pub regex_size_limit: usize,
+}
+
+// 1 MiB, a tenth of the regex crate's default, which is ample for the OWASP
+// CRS's largest patterns.
+const DEFAULT_REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            strict: true,
+            regex_size_limit: DEFAULT_REGEX_SIZE_LIMIT,
+        }
+    }
+}
+
 // Parser to convert a ModSecurity SecRule string into a structured SecRule Object.
 //
 // Reference: https://github.com/owasp-modsecurity/ModSecurity/wiki/Reference-Manual-(v2.x)#user-content-SecRule
 pub(crate) fn parse_sec_rule(raw_sec_rule: String) -> Result<SecRule, ValidationErrors> {
+    parse_sec_rule_in(raw_sec_rule, None, ParseOptions::default())
+}
+
+// Parses a SecRule which was read from a rules file, so that file arguments
+// to operators such as @ipMatchFromFile are resolved relative to the
+// directory containing that file rather than the working directory.
+pub(crate) fn parse_sec_rule_in(
+    raw_sec_rule: String,
+    base_dir: Option<&Path>,
+    options: ParseOptions,
+) -> Result<SecRule, ValidationErrors> {
+    parse_sec_rule_with_defaults(raw_sec_rule, base_dir, options, &[], None)
+}
+
+// Parses a SecRule which follows SecDefaultAction directives, the latest of
+// which comes last. A rule with a phase starts from the default actions of
+// that phase, if there are any, and a rule without one takes both the phase
+// and the actions of the latest SecDefaultAction. The rule's own actions are
+// applied after the defaults, so that they override them (e.g. a rule's deny
+// replaces a default pass, and t:none clears the default transformations).
+//
+// A rule continuing a chain is given the phase of the rule starting the chain,
+// as continuations don't give a phase of their own, and doesn't take any
+// defaults, which only apply to the rule starting it.
+pub(crate) fn parse_sec_rule_with_defaults(
+    raw_sec_rule: String,
+    base_dir: Option<&Path>,
+    options: ParseOptions,
+    default_actions: &[SecDefaultAction],
+    chain_phase: Option<Phase>,
+) -> Result<SecRule, ValidationErrors> {
     let sec_rule_components = validate_sec_rule(raw_sec_rule)?;
-    let (operator, operator_target) = parse_operator_string(&sec_rule_components.operator)?;
+    let (operator, operator_target, negated) =
+        parse_operator_string(&sec_rule_components.operator)?;
+    let compiled_operator =
+        compile_operator(&operator, operator_target.as_deref(), base_dir, options)?;
     let mut sec_rule = SecRule {
-        variable: sec_rule_components.variable,
-        variable_target: sec_rule_components.variable_target,
+        variables: sec_rule_components.variables,
         operator,
         operator_target,
+        compiled_operator,
+        negated,
         pattern: String::new(),
         ..SecRule::default()
     };
 
-    for action_part in sec_rule_components.actions_str.split(',') {
+    let actions = split_actions(&sec_rule_components.actions_str);
+    // the rule's phase, which picks its defaults, is only known from its actions
+    let mut without_defaults = sec_rule.clone();
+    apply_actions(&mut without_defaults, actions.iter().copied(), options)?;
+    if let Some(chain_phase) = chain_phase {
+        without_defaults.phase = chain_phase;
+        return Ok(without_defaults);
+    }
+    let has_phase = actions.iter().any(|action| {
+        action
+            .trim()
+            .split_once(':')
+            .is_some_and(|(key, _)| key == "phase")
+    });
+    let default_action = match has_phase {
+        true => default_actions
+            .iter()
+            .find(|default_action| default_action.phase == without_defaults.phase),
+        false => default_actions.last(),
+    };
+    match default_action {
+        Some(default_action) => {
+            sec_rule.phase = default_action.phase;
+            apply_actions(
+                &mut sec_rule,
+                default_action.actions.iter().map(String::as_str),
+                options,
+            )?;
+            apply_actions(&mut sec_rule, actions, options)?;
+            Ok(sec_rule)
+        }
+        None => Ok(without_defaults),
+    }
+}
+
+// Applies actions to a rule in order, so that later actions override earlier
+// ones, except for those which accumulate (tag, t and setvar).
+pub(super) fn apply_actions<'a>(
+    sec_rule: &mut SecRule,
+    actions: impl IntoIterator<Item = &'a str>,
+    options: ParseOptions,
+) -> Result<(), ValidationErrors> {
+    for action_part in actions {
         let action_part = action_part.trim();
 
         if let Some((key, value)) = action_part.split_once(':') {
@@ -49,7 +165,10 @@ pub(crate) fn parse_sec_rule(raw_sec_rule: String) -> Result<SecRule, Validation
                     })?;
                 }
                 "msg" => {
-                    sec_rule.message = Some(value.trim_matches('\'').to_string());
+                    sec_rule.message = Some(unquote_action_value(value));
+                }
+                "logdata" => {
+                    sec_rule.log_data = Some(unquote_action_value(value));
                 }
                 "severity" => {
                     let parsed_severity =
@@ -66,25 +185,107 @@ pub(crate) fn parse_sec_rule(raw_sec_rule: String) -> Result<SecRule, Validation
                         })?);
                 }
                 "tag" => {
-                    sec_rule.tags.push(value.trim_matches('\'').to_string());
+                    sec_rule.tags.push(unquote_action_value(value));
+                }
+                // t:none clears the transformations before it, which CRS
+                // rules start with so that none are inherited from
+                // SecDefaultAction
+                "t" if value.eq_ignore_ascii_case(NO_TRANSFORMATION) => {
+                    sec_rule.transformations.clear();
                 }
                 "t" => {
+                    if !is_transformation(value) {
+                        return Err(ValidationErrors::InvalidTransformation {
+                            value: value.to_string(),
+                        });
+                    }
                     sec_rule.transformations.push(value.to_string());
                 }
-                unknown_key => {
+                "setvar" => {
+                    sec_rule.set_vars.push(SetVar::try_from(value)?);
+                }
+                "rev" => {
+                    sec_rule.rev = Some(unquote_action_value(value));
+                }
+                "ver" => {
+                    sec_rule.ver = Some(unquote_action_value(value));
+                }
+                "maturity" => {
+                    sec_rule.maturity = Some(parse_rating(value).ok_or_else(|| {
+                        ValidationErrors::InvalidMaturity {
+                            value: value.to_string(),
+                        }
+                    })?);
+                }
+                "accuracy" => {
+                    sec_rule.accuracy = Some(parse_rating(value).ok_or_else(|| {
+                        ValidationErrors::InvalidAccuracy {
+                            value: value.to_string(),
+                        }
+                    })?);
+                }
+                "redirect" => {
+                    sec_rule.redirect = Some(parse_redirect_url(value)?);
+                }
+                "status" => {
+                    sec_rule.status = Some(
+                        value
+                            .parse::<u16>()
+                            .ok()
+                            .filter(|status| (100..=599).contains(status))
+                            .ok_or_else(|| ValidationErrors::InvalidStatus {
+                                value: value.to_string(),
+                            })?,
+                    );
+                }
+                "ctl" => match Ctl::try_from(unquote_action_value(value).as_str()) {
+                    Ok(ctl) => sec_rule.ctls.push(ctl),
+                    Err(ValidationErrors::UnsupportedCtl { .. }) if !options.strict => {
+                        sec_rule
+                            .unknown_actions
+                            .push(("ctl".to_string(), value.to_string()));
+                    }
+                    Err(error) => return Err(error),
+                },
+                unknown_key if options.strict => {
                     return Err(ValidationErrors::InvalidDirective {
                         found: unknown_key.to_string(),
                     });
                 }
+                unknown_key => {
+                    sec_rule
+                        .unknown_actions
+                        .push((unknown_key.to_string(), value.to_string()));
+                }
             }
         } else {
-            if !action_part.is_empty() {
-                sec_rule.action = action_part.to_string();
+            match action_part {
+                "" => {}
+                "chain" => sec_rule.chain = true,
+                "capture" => sec_rule.capture = true,
+                "multiMatch" => sec_rule.multi_match = true,
+                "log" => sec_rule.log = Some(true),
+                "nolog" => sec_rule.log = Some(false),
+                "auditlog" => sec_rule.audit_log = Some(true),
+                "noauditlog" => sec_rule.audit_log = Some(false),
+                action => match DisruptiveAction::try_from(action) {
+                    Ok(disruptive_action) => sec_rule.action = Some(disruptive_action),
+                    Err(_) if options.strict => {
+                        return Err(ValidationErrors::InvalidDirective {
+                            found: action.to_string(),
+                        });
+                    }
+                    Err(_) => {
+                        sec_rule
+                            .unknown_actions
+                            .push((action.to_string(), String::new()));
+                    }
+                },
             }
         }
     }
 
-    Ok(sec_rule)
+    Ok(())
 }
 
 // -----------------------------------------------------------------------------
@@ -93,8 +294,7 @@ pub(crate) fn parse_sec_rule(raw_sec_rule: String) -> Result<SecRule, Validation
 
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct ValidatedSecRuleComponents {
-    pub variable: Variable,
-    pub variable_target: Option<String>,
+    pub variables: Vec<VariableSpec>,
     pub operator: String,
     pub actions_str: String,
 }
@@ -106,107 +306,150 @@ pub(crate) fn validate_sec_rule(
         return Err(ValidationErrors::EmptyRule);
     }
 
-    let sec_rule = raw_sec_rule
-        .replace("\\\n", " ")
-        .replace("\\", "")
-        .split_whitespace()
-        .collect::<Vec<_>>()
-        .join(" ");
-
-    let mut parts = Vec::new();
-    let mut current_part = String::new();
-    let mut in_quotes = false;
-    let mut chars = sec_rule.chars().peekable();
-
-    while let Some(ch) = chars.next() {
-        match ch {
-            '"' => {
-                if in_quotes {
-                    parts.push(current_part.clone());
-                    current_part.clear();
-                    in_quotes = false;
-                } else {
-                    if !current_part.is_empty() {
-                        parts.push(current_part.clone());
-                        current_part.clear();
-                    }
-                    in_quotes = true;
-                }
-            }
-            ' ' if !in_quotes => {
-                if !current_part.is_empty() {
-                    parts.push(current_part.clone());
-                    current_part.clear();
-                }
-            }
-            _ => {
-                current_part.push(ch);
-            }
-        }
+    // SecRule VARIABLES OPERATOR [ACTIONS], where the actions are optional
+    let mut arguments = tokenize_directive(&raw_sec_rule)?.into_iter();
+    match arguments.next() {
+        Some(directive) if directive == "SecRule" => {}
+        Some(directive) => return Err(ValidationErrors::InvalidDirective { found: directive }),
+        None => return Err(ValidationErrors::EmptyRule),
     }
-
-    if !current_part.is_empty() {
-        parts.push(current_part);
+    let variable_str = arguments.next().ok_or(ValidationErrors::MissingVariable)?;
+    let operator = arguments.next().ok_or(ValidationErrors::MissingOperator)?;
+    let actions_str = arguments.next();
+    if let Some(unexpected) = arguments.next() {
+        return Err(ValidationErrors::UnexpectedArgument { found: unexpected });
     }
 
-    if parts.len() != 4 {
-        return Err(ValidationErrors::InvalidFormat {
-            expected: 4,
-            found: parts.len(),
-        });
+    if variable_str.is_empty() {
+        return Err(ValidationErrors::EmptyVariable);
     }
 
-    if parts[0] != "SecRule" {
-        return Err(ValidationErrors::InvalidDirective {
-            found: parts[0].to_string(),
+    // e.g. "REQUEST_HEADERS|ARGS|!REQUEST_HEADERS:Referer", where negated
+    // variables only exclude values, so at least one mustn't be negated
+    let variables = variable_str
+        .split('|')
+        .map(VariableSpec::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+    if variables.iter().all(|variable| variable.negated) {
+        return Err(ValidationErrors::InvalidVariable {
+            value: variable_str.to_string(),
         });
     }
 
-    let variable_str = &parts[1];
-    if variable_str.is_empty() {
-        return Err(ValidationErrors::EmptyVariable);
+    if operator.is_empty() {
+        return Err(ValidationErrors::EmptyOperator);
     }
 
-    let (variable, variable_target) = match variable_str.split_once(':') {
-        Some((var_type, target)) => {
-            let variable =
-                Variable::try_from(var_type).map_err(|_| ValidationErrors::InvalidVariable {
-                    value: var_type.to_string(),
-                })?;
-            (variable, Some(target.to_string()))
-        }
-        None => {
-            let variable = Variable::try_from(variable_str.as_str()).map_err(|_| {
-                ValidationErrors::InvalidVariable {
-                    value: variable_str.to_string(),
-                }
-            })?;
-            (variable, None)
+    // rules without actions take the defaults, but actions given as an empty
+    // string are most likely a mistake
+    let actions_str = match actions_str {
+        Some(actions_str) if actions_str.is_empty() => {
+            return Err(ValidationErrors::EmptyActions);
         }
+        Some(actions_str) => actions_str,
+        None => String::new(),
     };
 
-    let operator_str = &parts[2];
-    if operator_str.is_empty() {
-        return Err(ValidationErrors::EmptyOperator);
-    }
-    let operator = operator_str.to_string();
-
-    let actions_str = &parts[3];
-    if actions_str.is_empty() {
-        return Err(ValidationErrors::EmptyActions);
-    }
-
     Ok(ValidatedSecRuleComponents {
-        variable,
-        variable_target,
+        variables,
         operator,
-        actions_str: actions_str.to_string(),
+        actions_str,
     })
 }
 
+// Splits a directive into its arguments the way Apache's configuration parser,
+// which ModSecurity relies on, does: arguments are separated by whitespace,
+// unless they're wrapped in double quotes, and lines ending in a backslash are
+// continued on the next line.
+//
+// Inside a quoted argument an escaped double quote (\") is a literal quote,
+// and any other backslash is kept as is, so that regular expressions such as
+// "@rx user-agent:\s*\"sqlmap\"" reach the operator intact. Quotes only
+// start a quoted argument at its beginning, elsewhere they're literal.
+pub(crate) fn tokenize_directive(raw_directive: &str) -> Result<Vec<String>, ValidationErrors> {
+    let directive = raw_directive
+        .lines()
+        .map(|line| {
+            let line = line.trim();
+            line.strip_suffix('\\').unwrap_or(line)
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut arguments = Vec::new();
+    let mut chars = directive.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch.is_whitespace() {
+            continue;
+        }
+
+        let mut argument = String::new();
+        if ch == '"' {
+            loop {
+                match chars.next() {
+                    Some('\\') if chars.peek() == Some(&'"') => {
+                        argument.push('"');
+                        chars.next();
+                    }
+                    Some('"') => break,
+                    Some(ch) => argument.push(ch),
+                    None => return Err(ValidationErrors::UnterminatedQuote { value: argument }),
+                }
+            }
+        } else {
+            argument.push(ch);
+            while let Some(ch) = chars.next_if(|ch| !ch.is_whitespace()) {
+                argument.push(ch);
+            }
+        }
+        arguments.push(argument);
+    }
+
+    Ok(arguments)
+}
+
+// Splits the actions on commas, except for commas inside single-quoted values
+// such as "logdata:'Matched %{MATCHED_VAR}, in %{MATCHED_VAR_NAME}'", where an
+// escaped single quote (\') doesn't end the value.
+pub(super) fn split_actions(actions_str: &str) -> Vec<&str> {
+    let mut actions = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (position, ch) in actions_str.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match ch {
+            '\\' if in_quotes => escaped = true,
+            '\'' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                actions.push(&actions_str[start..position]);
+                start = position + 1;
+            }
+            _ => {}
+        }
+    }
+    actions.push(&actions_str[start..]);
+    actions
+}
+
+// Splits an operator string such as "!@rx ^GET$" into the operator, its target
+// and whether the operator was negated with a leading '!'. An operator string
+// without an operator (e.g. "^GET$") is a regular expression.
 fn parse_operator_string(
     operator_str: &str,
-) -> Result<(Operator, Option<String>), ValidationErrors> {
+) -> Result<(Operator, Option<String>, bool), ValidationErrors> {
+    let (negated, operator_str) = match operator_str.strip_prefix('!') {
+        Some(operator_str) => (true, operator_str.trim_start()),
+        None => (false, operator_str),
+    };
+
+    if !operator_str.starts_with('@') {
+        return Ok((Operator::Rx, Some(operator_str.to_string()), negated));
+    }
+
     if let Some((op_part, target_part)) = operator_str.split_once(' ') {
         let operator =
             Operator::try_from(op_part).map_err(|_| ValidationErrors::InvalidOperator {
@@ -217,12 +460,134 @@ fn parse_operator_string(
         } else {
             Some(target_part.trim().to_string())
         };
-        Ok((operator, target))
+        Ok((operator, target, negated))
     } else {
         let operator =
             Operator::try_from(operator_str).map_err(|_| ValidationErrors::InvalidOperator {
                 value: operator_str.to_string(),
             })?;
-        Ok((operator, None))
+        Ok((operator, None, negated))
+    }
+}
+
+// Action values may be wrapped in single quotes, inside which a single quote
+// is escaped with a backslash (e.g. msg:'it\'s a trap').
+fn unquote_action_value(value: &str) -> String {
+    let value = value.trim();
+    value
+        .strip_prefix('\'')
+        .and_then(|value| value.strip_suffix('\''))
+        .unwrap_or(value)
+        .replace("\\'", "'")
+}
+
+// Maturity and accuracy are rated from 1 to 9, with 9 being the highest.
+fn parse_rating(value: &str) -> Option<u8> {
+    value
+        .trim_matches('\'')
+        .parse::<u8>()
+        .ok()
+        .filter(|rating| (1..=9).contains(rating))
+}
+
+// The redirect location ends up in a response header, so it must be either an
+// absolute http(s) URL or an absolute path, and can't contain whitespace or
+// control characters.
+fn parse_redirect_url(value: &str) -> Result<String, ValidationErrors> {
+    let url = value.trim_matches('\'');
+    let invalid_redirect = || ValidationErrors::InvalidRedirect {
+        value: value.to_string(),
+    };
+
+    if url.chars().any(|ch| ch.is_whitespace() || ch.is_control()) {
+        return Err(invalid_redirect());
+    }
+
+    let host = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .map(|rest| rest.split(['/', '?', '#']).next().unwrap_or_default());
+
+    match host {
+        Some(host) if !host.is_empty() => Ok(url.to_string()),
+        Some(_) => Err(invalid_redirect()),
+        None if url.starts_with('/') && !url.starts_with("//") => Ok(url.to_string()),
+        None => Err(invalid_redirect()),
+    }
+}
+
+// Compiles an @rx pattern. Patterns are matched by the regex crate, which
+// runs in time linear in the size of the input whatever the pattern, so a rule
+// can't hang the proxy with catastrophic backtracking (ReDoS). The price is
+// that backreferences (e.g. "(['\"]).*\1") and lookaround aren't supported,
+// and CRS rules which depend on them fail to compile. Those rules can usually
+// be rewritten as an alternation (e.g. "'.*'|\".*\""), or split into chained
+// rules.
+//
+// The size of the compiled pattern is limited instead, as large counted
+// repetitions (e.g. "(?:\w{100}){100}") compile to very large automata.
+pub(crate) fn compile_regex(pattern: &str, size_limit: usize) -> Result<Regex, ValidationErrors> {
+    RegexBuilder::new(pattern)
+        .size_limit(size_limit)
+        .build()
+        .map_err(|e| match e {
+            regex::Error::CompiledTooBig(limit) => ValidationErrors::RegexTooComplex {
+                pattern: pattern.to_string(),
+                limit,
+            },
+            e => ValidationErrors::InvalidRegex {
+                pattern: pattern.to_string(),
+                reason: e.to_string(),
+            },
+        })
+}
+
+pub(crate) fn compile_operator(
+    operator: &Operator,
+    operator_target: Option<&str>,
+    base_dir: Option<&Path>,
+    options: ParseOptions,
+) -> Result<Option<CompiledOperator>, ValidationErrors> {
+    match operator {
+        Operator::Rx | Operator::RxGlobal => {
+            let pattern = operator_target.ok_or(ValidationErrors::EmptyOperator)?;
+            let regex = compile_regex(pattern, options.regex_size_limit)?;
+            Ok(Some(CompiledOperator::Regex(regex)))
+        }
+        Operator::IpMatch => {
+            let networks = operator_target.ok_or(ValidationErrors::EmptyOperator)?;
+            Ok(Some(CompiledOperator::IpMatch(IpMatchSet::try_from(
+                networks,
+            )?)))
+        }
+        Operator::IpMatchFromFile => {
+            let file = Path::new(operator_target.ok_or(ValidationErrors::EmptyOperator)?);
+            let path = match base_dir {
+                Some(base_dir) => base_dir.join(file),
+                None => file.to_path_buf(),
+            };
+            Ok(Some(CompiledOperator::IpMatch(IpMatchSet::from_file(
+                &path,
+            )?)))
+        }
+        Operator::ValidateByteRange => {
+            let ranges = operator_target.ok_or(ValidationErrors::EmptyOperator)?;
+            Ok(Some(CompiledOperator::ByteRange(ByteRangeSet::try_from(
+                ranges,
+            )?)))
+        }
+        Operator::Within => {
+            let members = operator_target.ok_or(ValidationErrors::EmptyOperator)?;
+            let members: HashSet<String> = members.split_whitespace().map(str::to_string).collect();
+            match members.is_empty() {
+                true => Err(ValidationErrors::EmptyOperator),
+                false => Ok(Some(CompiledOperator::Within(members))),
+            }
+        }
+        Operator::Contains
+        | Operator::DetectSqli
+        | Operator::DetectXss
+        | Operator::Streq
+        | Operator::ValidateUtf8Encoding => Ok(None),
     }
 }
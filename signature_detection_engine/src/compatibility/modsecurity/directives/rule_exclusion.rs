@@ -0,0 +1,54 @@
+use regex::Regex;
+
+use crate::compatibility::modsecurity::directives::sec_rule::SecRule;
+
+// -----------------------------------------------------------------------------
+// ModSecurity - Rule Exclusions
+// -----------------------------------------------------------------------------
+
+// A directive which removes rules that were loaded before it, which is how
+// rules from a vendor ruleset (e.g. the OWASP CRS) are tuned without editing
+// the vendor's files.
+#[derive(Clone, Debug)]
+pub enum RuleExclusion {
+    // "SecRuleRemoveById 941100 942100-942999", as inclusive ranges of IDs
+    ById(Vec<(u32, u32)>),
+    // "SecRuleRemoveByTag attack-xss", where the argument is a regular
+    // expression matched against each of the rule's tags
+    ByTag(Regex),
+}
+
+impl RuleExclusion {
+    pub fn excludes(&self, sec_rule: &SecRule) -> bool {
+        match self {
+            RuleExclusion::ById(ranges) => ranges
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&sec_rule.id)),
+            RuleExclusion::ByTag(regex) => sec_rule.tags.iter().any(|tag| regex.is_match(tag)),
+        }
+    }
+}
+
+impl std::fmt::Display for RuleExclusion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleExclusion::ById(ranges) => {
+                f.write_str("SecRuleRemoveById")?;
+                for (start, end) in ranges {
+                    match start == end {
+                        true => write!(f, " {}", start)?,
+                        false => write!(f, " {}-{}", start, end)?,
+                    }
+                }
+                Ok(())
+            }
+            RuleExclusion::ByTag(regex) => {
+                write!(
+                    f,
+                    "SecRuleRemoveByTag \"{}\"",
+                    regex.as_str().replace('"', "\\\"")
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,11 @@
+use crate::compatibility::modsecurity::directives::sec_rule::Phase;
+
+// The actions which the rules of a phase start from when they follow a
+// SecDefaultAction directive for that phase, e.g.
+// SecDefaultAction "phase:2,log,auditlog,pass". The actions are those of the
+// directive other than its phase, in the order they were written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecDefaultAction {
+    pub phase: Phase,
+    pub actions: Vec<String>,
+}
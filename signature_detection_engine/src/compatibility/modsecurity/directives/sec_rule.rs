@@ -1,45 +1,86 @@
+use std::collections::HashSet;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
 use super::consts::*;
 use crate::compatibility::modsecurity::directives::parsers::sec_rule::parse_sec_rule;
+use crate::compatibility::modsecurity::operators::ip_match::IpMatchSet;
+use crate::compatibility::modsecurity::operators::validate_byte_range::ByteRangeSet;
 use crate::errors::ValidationErrors;
 
 // -----------------------------------------------------------------------------
 // ModSecurity - SecRule
 // -----------------------------------------------------------------------------
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct SecRule {
     pub id: u32,
     pub phase: Phase,
-    pub action: String,
+    pub action: Option<DisruptiveAction>,
     pub operator: Operator,
     pub operator_target: Option<String>,
-    pub variable: Variable,
-    pub variable_target: Option<String>,
+    // rebuilt from the operator and its target when deserialized
+    #[serde(skip)]
+    pub compiled_operator: Option<CompiledOperator>,
+    pub negated: bool,
+    pub variables: Vec<VariableSpec>,
     pub pattern: String,
     pub transformations: Vec<String>,
+    pub set_vars: Vec<SetVar>,
     pub tags: Vec<String>,
     pub message: Option<String>,
+    pub log_data: Option<String>,
     pub severity: Option<Severity>,
+    pub redirect: Option<String>,
+    pub status: Option<u16>,
     pub chain: bool,
+    pub capture: bool,
+    // evaluate the operator against the value before and after each
+    // transformation, rather than only after the last one
+    pub multi_match: bool,
+    // the log/nolog and auditlog/noauditlog actions, see logs and audit_logs
+    pub log: Option<bool>,
+    pub audit_log: Option<bool>,
+    // ctl actions, which change how the rest of the transaction is evaluated
+    // when the rule fires
+    pub ctls: Vec<Ctl>,
+    // metadata, which doesn't affect matching
+    pub rev: Option<String>,
+    pub ver: Option<String>,
+    pub maturity: Option<u8>,
+    pub accuracy: Option<u8>,
+    // actions which aren't supported, as (key, value) pairs, which are only
+    // collected when parsing leniently (see ParseOptions)
+    pub unknown_actions: Vec<(String, String)>,
 }
 
-impl Default for SecRule {
-    fn default() -> Self {
-        SecRule {
-            id: 0,
-            phase: Phase::default(),
-            action: String::new(),
-            operator: Operator::default(),
-            operator_target: None,
-            variable: Variable::default(),
-            variable_target: None,
-            pattern: String::new(),
-            transformations: Vec::new(),
-            tags: Vec::new(),
-            message: None,
-            severity: None,
-            chain: false,
-        }
+impl SecRule {
+    // Rules without an explicit disruptive action block the request when they
+    // match, which has always been the engine's behavior.
+    pub fn disruptive_action(&self) -> DisruptiveAction {
+        self.action.unwrap_or(DisruptiveAction::Deny)
+    }
+
+    // Whether a match is logged, which it is unless the rule has nolog.
+    pub fn logs(&self) -> bool {
+        self.log.unwrap_or(true)
+    }
+
+    // Whether a match produces an audit record. As in ModSecurity, nolog also
+    // turns off audit logging unless the rule explicitly has auditlog.
+    pub fn audit_logs(&self) -> bool {
+        self.audit_log.unwrap_or(self.logs())
+    }
+
+    // The paranoia level of the rule, from a CRS "paranoia-level/N" tag. When
+    // a rule is tagged with several levels the highest one applies.
+    pub fn paranoia_level(&self) -> Option<u8> {
+        self.tags
+            .iter()
+            .filter_map(|tag| tag.strip_prefix(PARANOIA_LEVEL_TAG_PREFIX))
+            .filter_map(|level| level.parse::<u8>().ok())
+            .max()
     }
 }
 
@@ -51,12 +92,233 @@ impl TryFrom<String> for SecRule {
     }
 }
 
+// Prints the rule back in canonical SecRule syntax, with the actions in a
+// fixed order and quotes escaped, such that parsing the output gives an
+// equivalent rule.
+impl std::fmt::Display for SecRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let variables = self
+            .variables
+            .iter()
+            .map(VariableSpec::to_string)
+            .collect::<Vec<_>>()
+            .join("|");
+        let negated = if self.negated { "!" } else { "" };
+        let operator = match &self.operator_target {
+            Some(operator_target) => format!("{}{} {}", negated, self.operator, operator_target),
+            None => format!("{}{}", negated, self.operator),
+        };
+
+        let mut actions = vec![
+            format!("id:{}", self.id),
+            format!("phase:{}", u8::from(self.phase)),
+        ];
+        if let Some(action) = self.action {
+            actions.push(action.to_string());
+        }
+        for transformation in &self.transformations {
+            actions.push(format!("t:{}", transformation));
+        }
+        if let Some(message) = &self.message {
+            actions.push(format!("msg:'{}'", escape_single_quotes(message)));
+        }
+        if let Some(log_data) = &self.log_data {
+            actions.push(format!("logdata:'{}'", escape_single_quotes(log_data)));
+        }
+        if let Some(severity) = self.severity {
+            actions.push(format!("severity:{}", u8::from(severity)));
+        }
+        for tag in &self.tags {
+            actions.push(format!("tag:'{}'", escape_single_quotes(tag)));
+        }
+        if let Some(rev) = &self.rev {
+            actions.push(format!("rev:'{}'", escape_single_quotes(rev)));
+        }
+        if let Some(ver) = &self.ver {
+            actions.push(format!("ver:'{}'", escape_single_quotes(ver)));
+        }
+        if let Some(maturity) = self.maturity {
+            actions.push(format!("maturity:{}", maturity));
+        }
+        if let Some(accuracy) = self.accuracy {
+            actions.push(format!("accuracy:{}", accuracy));
+        }
+        for set_var in &self.set_vars {
+            actions.push(format!("setvar:'{}'", set_var));
+        }
+        if let Some(redirect) = &self.redirect {
+            actions.push(format!("redirect:'{}'", redirect));
+        }
+        if let Some(status) = self.status {
+            actions.push(format!("status:{}", status));
+        }
+        if self.capture {
+            actions.push("capture".to_string());
+        }
+        if self.multi_match {
+            actions.push("multiMatch".to_string());
+        }
+        match self.log {
+            Some(true) => actions.push("log".to_string()),
+            Some(false) => actions.push("nolog".to_string()),
+            None => {}
+        }
+        match self.audit_log {
+            Some(true) => actions.push("auditlog".to_string()),
+            Some(false) => actions.push("noauditlog".to_string()),
+            None => {}
+        }
+        for ctl in &self.ctls {
+            actions.push(format!("ctl:{}", ctl));
+        }
+        if self.chain {
+            actions.push("chain".to_string());
+        }
+        for (key, value) in &self.unknown_actions {
+            match value.is_empty() {
+                true => actions.push(key.clone()),
+                false => actions.push(format!("{}:{}", key, value)),
+            }
+        }
+
+        write!(
+            f,
+            "SecRule {} \"{}\" \"{}\"",
+            variables,
+            operator.replace('"', "\\\""),
+            actions.join(",").replace('"', "\\\"")
+        )
+    }
+}
+
+fn escape_single_quotes(value: &str) -> String {
+    value.replace('\'', "\\'")
+}
+
+// -----------------------------------------------------------------------------
+// ModSecurity - Disruptive Action
+// -----------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DisruptiveAction {
+    // Block the request.
+    Deny,
+    // Stop evaluating the remaining rules in the phase and let the request
+    // through, e.g. to exempt health-check paths.
+    Allow,
+    // Record the match, but continue evaluating without blocking.
+    Pass,
+    // Block the request by resetting its stream without responding, so that
+    // the client isn't told a firewall blocked it.
+    Drop,
+}
+
+impl TryFrom<&str> for DisruptiveAction {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "deny" => Ok(DisruptiveAction::Deny),
+            "allow" => Ok(DisruptiveAction::Allow),
+            "pass" => Ok(DisruptiveAction::Pass),
+            "drop" => Ok(DisruptiveAction::Drop),
+            _ => Err(format!("unknown disruptive action: '{}'", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for DisruptiveAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisruptiveAction::Deny => f.write_str("deny"),
+            DisruptiveAction::Allow => f.write_str("allow"),
+            DisruptiveAction::Pass => f.write_str("pass"),
+            DisruptiveAction::Drop => f.write_str("drop"),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+// ModSecurity - Ctl
+// -----------------------------------------------------------------------------
+
+// "ctl:ruleEngine=DetectionOnly", which changes the engine's configuration
+// for the rest of the transaction when the rule fires, e.g. for a CRS
+// exclusion rule to stop enforcing on a path which is known to be noisy. Of
+// ModSecurity's ctl targets only ruleEngine is supported, and the others are
+// rejected when parsing strictly (see ParseOptions).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Ctl {
+    RuleEngine(RuleEngineMode),
+}
+
+// How the rules after a ctl:ruleEngine action are evaluated, for the rest of
+// the transaction including its later phases. Rules already evaluated aren't
+// affected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RuleEngineMode {
+    // Rules are evaluated and block the request, as without a ctl action.
+    #[default]
+    On,
+    // No more rules are evaluated.
+    Off,
+    // Rules are still evaluated, and fire (e.g. running their setvar actions
+    // and producing audit records), but don't block the request.
+    DetectionOnly,
+}
+
+impl TryFrom<&str> for Ctl {
+    type Error = ValidationErrors;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let (target, value) = s
+            .split_once('=')
+            .ok_or_else(|| ValidationErrors::InvalidCtl {
+                value: s.to_string(),
+            })?;
+        match target.trim() {
+            target if target.eq_ignore_ascii_case("ruleEngine") => {
+                match value.trim().to_ascii_lowercase().as_str() {
+                    "on" => Ok(Ctl::RuleEngine(RuleEngineMode::On)),
+                    "off" => Ok(Ctl::RuleEngine(RuleEngineMode::Off)),
+                    "detectiononly" => Ok(Ctl::RuleEngine(RuleEngineMode::DetectionOnly)),
+                    _ => Err(ValidationErrors::InvalidCtl {
+                        value: s.to_string(),
+                    }),
+                }
+            }
+            target => Err(ValidationErrors::UnsupportedCtl {
+                target: target.to_string(),
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for Ctl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ctl::RuleEngine(mode) => write!(f, "ruleEngine={}", mode),
+        }
+    }
+}
+
+impl std::fmt::Display for RuleEngineMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleEngineMode::On => f.write_str("On"),
+            RuleEngineMode::Off => f.write_str("Off"),
+            RuleEngineMode::DetectionOnly => f.write_str("DetectionOnly"),
+        }
+    }
+}
+
 // -----------------------------------------------------------------------------
 // ModSecurity - Phase
 // -----------------------------------------------------------------------------
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum Phase {
+    #[default]
     RequestHeaders = 1,
     RequestBody = 2,
     ResponseHeaders = 3,
@@ -64,12 +326,6 @@ pub enum Phase {
     Logging = 5,
 }
 
-impl Default for Phase {
-    fn default() -> Self {
-        Phase::RequestHeaders
-    }
-}
-
 impl TryFrom<u8> for Phase {
     type Error = String;
 
@@ -85,9 +341,9 @@ impl TryFrom<u8> for Phase {
     }
 }
 
-impl Into<u8> for Phase {
-    fn into(self) -> u8 {
-        self as u8
+impl From<Phase> for u8 {
+    fn from(phase: Phase) -> u8 {
+        phase as u8
     }
 }
 
@@ -95,8 +351,14 @@ impl Into<u8> for Phase {
 // ModSecurity - Severity
 // -----------------------------------------------------------------------------
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+// Severities are numbered as in syslog, where the lower the number the more
+// severe the severity, from 0 (Emergency) to 7 (Debug). They're ordered by how
+// severe they are rather than by number, so Severity::Emergency is the
+// greatest and Severity::Critical > Severity::Warning, while comparing their
+// numbers (see u8::from) gives the reverse.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub enum Severity {
+    #[default]
     Emergency = 0,
     Alert = 1,
     Critical = 2,
@@ -107,12 +369,6 @@ pub enum Severity {
     Debug = 7,
 }
 
-impl Default for Severity {
-    fn default() -> Self {
-        Severity::Emergency
-    }
-}
-
 impl TryFrom<u8> for Severity {
     type Error = String;
 
@@ -131,9 +387,53 @@ impl TryFrom<u8> for Severity {
     }
 }
 
-impl Into<u8> for Severity {
-    fn into(self) -> u8 {
-        self as u8
+impl From<Severity> for u8 {
+    fn from(severity: Severity) -> u8 {
+        severity as u8
+    }
+}
+
+impl Ord for Severity {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // reversed, as the more severe a severity the lower its number
+        u8::from(*other).cmp(&u8::from(*self))
+    }
+}
+
+impl PartialOrd for Severity {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// The names used by ModSecurity's logs, e.g. "CRITICAL".
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Emergency => f.write_str("EMERGENCY"),
+            Severity::Alert => f.write_str("ALERT"),
+            Severity::Critical => f.write_str("CRITICAL"),
+            Severity::Error => f.write_str("ERROR"),
+            Severity::Warning => f.write_str("WARNING"),
+            Severity::Notice => f.write_str("NOTICE"),
+            Severity::Info => f.write_str("INFO"),
+            Severity::Debug => f.write_str("DEBUG"),
+        }
+    }
+}
+
+impl Severity {
+    // The anomaly score contributed by a matched rule of this severity, using
+    // the same weights as the OWASP Core Rule Set. Emergency and alert are
+    // more severe than critical, so they score as critical rather than zero.
+    pub fn anomaly_score(&self) -> u32 {
+        match self {
+            Severity::Emergency | Severity::Alert | Severity::Critical => 5,
+            Severity::Error => 4,
+            Severity::Warning => 3,
+            Severity::Notice => 2,
+            Severity::Info | Severity::Debug => 0,
+        }
     }
 }
 
@@ -141,16 +441,48 @@ impl Into<u8> for Severity {
 // ModSecurity - Operator
 // -----------------------------------------------------------------------------
 
-#[derive(Clone, Debug, PartialEq)]
+// Operators compare values as they are after the rule's transformations, so
+// whether a rule is case sensitive is up to the rule (e.g. with t:lowercase,
+// or "(?i)" in an @rx pattern), except for @contains. @detectSQLi and
+// @detectXSS recognize attacks whatever their case.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub enum Operator {
     // TODO: implement more operators
-    Contains,
-}
 
-impl Default for Operator {
-    fn default() -> Self {
-        Operator::Contains
-    }
+    // "@contains bot", which matches when the target appears anywhere in the
+    // value, compared case insensitively (unlike ModSecurity's @contains) as
+    // the engine has always done.
+    #[default]
+    Contains,
+    DetectSqli,
+    DetectXss,
+    IpMatch,
+    IpMatchFromFile,
+    // "@rx", matched in linear time, so without backreferences or lookaround
+    // (see compile_regex). As in ModSecurity the pattern matches anywhere in
+    // the value unless it's anchored with ^ and $, and is case sensitive
+    // unless it starts with "(?i)" or the rule lowercases the value.
+    Rx,
+    // "@rxGlobal", which matches like @rx but finds every non-overlapping
+    // match in the value rather than only the first. The number of matches is
+    // available to setvar as %{MATCHED_COUNT} (e.g. to count SQL keywords),
+    // and with the capture action TX.0 to TX.9 hold the whole match and
+    // groups of each match in turn, up to ten in all. Unlike ModSecurity's
+    // &VARIABLE, which counts a collection's values, this counts the matches
+    // within the one value that matched. With multiMatch the matches are those
+    // of the first transformation stage which matched.
+    RxGlobal,
+    // "@streq GET", which matches when the value is exactly the target. It's
+    // case sensitive, so a case insensitive comparison lowercases the value
+    // with t:lowercase and gives the target in lowercase.
+    Streq,
+    ValidateByteRange,
+    ValidateUtf8Encoding,
+    // "@within GET POST HEAD", which matches when the value is exactly one of
+    // the space separated members. Members are compared case sensitively
+    // against the transformed value, so a rule using t:lowercase must list
+    // its members in lowercase or they'll never match.
+    Within,
 }
 
 impl TryFrom<&str> for Operator {
@@ -160,28 +492,103 @@ impl TryFrom<&str> for Operator {
         let op_str = s.strip_prefix('@').unwrap_or(s);
         match op_str.to_lowercase().as_str() {
             "contains" => Ok(Operator::Contains),
+            "detectsqli" => Ok(Operator::DetectSqli),
+            "detectxss" => Ok(Operator::DetectXss),
+            "ipmatch" => Ok(Operator::IpMatch),
+            "ipmatchfromfile" | "ipmatchf" => Ok(Operator::IpMatchFromFile),
+            "rx" => Ok(Operator::Rx),
+            "rxglobal" => Ok(Operator::RxGlobal),
+            "streq" => Ok(Operator::Streq),
+            "validatebyterange" => Ok(Operator::ValidateByteRange),
+            "validateutf8encoding" => Ok(Operator::ValidateUtf8Encoding),
+            "within" => Ok(Operator::Within),
             _ => Err(format!("operator type unknown (or unimplemented): '{}'", s)),
         }
     }
 }
 
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operator::Contains => f.write_str("@contains"),
+            Operator::DetectSqli => f.write_str("@detectSQLi"),
+            Operator::DetectXss => f.write_str("@detectXSS"),
+            Operator::IpMatch => f.write_str("@ipMatch"),
+            Operator::IpMatchFromFile => f.write_str("@ipMatchFromFile"),
+            Operator::Rx => f.write_str("@rx"),
+            Operator::RxGlobal => f.write_str("@rxGlobal"),
+            Operator::Streq => f.write_str("@streq"),
+            Operator::ValidateByteRange => f.write_str("@validateByteRange"),
+            Operator::ValidateUtf8Encoding => f.write_str("@validateUtf8Encoding"),
+            Operator::Within => f.write_str("@within"),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+// ModSecurity - Compiled Operator
+// -----------------------------------------------------------------------------
+
+// Operator arguments which are compiled once when the rule is parsed, rather
+// than for every request.
+#[derive(Clone, Debug)]
+pub enum CompiledOperator {
+    ByteRange(ByteRangeSet),
+    IpMatch(IpMatchSet),
+    Regex(Regex),
+    // the space separated members of a @within list
+    Within(HashSet<String>),
+}
+
+impl PartialEq for CompiledOperator {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (CompiledOperator::ByteRange(a), CompiledOperator::ByteRange(b)) => a == b,
+            (CompiledOperator::IpMatch(a), CompiledOperator::IpMatch(b)) => a == b,
+            (CompiledOperator::Regex(a), CompiledOperator::Regex(b)) => a.as_str() == b.as_str(),
+            (CompiledOperator::Within(a), CompiledOperator::Within(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
 // -----------------------------------------------------------------------------
 // ModSecurity - Variable
 // -----------------------------------------------------------------------------
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum Variable {
     // TODO: implement more variables
+    #[default]
     RequestHeaders,
+    // the cookies of the Cookie request header, by name, e.g. "session" for
+    // "Cookie: session=abc; theme=dark"
+    RequestCookies,
     ResponseHeaders,
+    ResponseStatus,
     RequestBody,
+    ResponseBody,
+    RequestMethod,
+    // the request's path without its query string, e.g. "/blog/wp-login.php"
+    RequestFilename,
+    // the last segment of the request's path, e.g. "wp-login.php"
+    RequestBasename,
+    RemoteAddr,
     Args,
-}
-
-impl Default for Variable {
-    fn default() -> Self {
-        Variable::RequestHeaders
-    }
+    // the raw query string, e.g. "a=1&b=%27", where ARGS holds its decoded
+    // arguments
+    QueryString,
+    // the names, form field names and sizes of the files uploaded in a
+    // multipart request body, each keyed by the form field name, e.g.
+    // FILES:avatar is "shell.php" for a file uploaded as "avatar"
+    Files,
+    FilesNames,
+    FilesSizes,
+    // whether the request body processor failed, "1" if it did and "0"
+    // otherwise, along with why it failed, which is empty if it didn't
+    ReqbodyError,
+    ReqbodyErrorMsg,
+    Tx,
 }
 
 impl TryFrom<&str> for Variable {
@@ -190,9 +597,209 @@ impl TryFrom<&str> for Variable {
     fn try_from(s: &str) -> Result<Self, Self::Error> {
         match s.to_uppercase().as_str() {
             REQUEST_HEADERS => Ok(Variable::RequestHeaders),
+            REQUEST_COOKIES => Ok(Variable::RequestCookies),
+            RESPONSE_HEADERS => Ok(Variable::ResponseHeaders),
+            RESPONSE_STATUS => Ok(Variable::ResponseStatus),
             REQUEST_BODY => Ok(Variable::RequestBody),
+            RESPONSE_BODY => Ok(Variable::ResponseBody),
+            REQUEST_METHOD => Ok(Variable::RequestMethod),
+            REQUEST_FILENAME => Ok(Variable::RequestFilename),
+            REQUEST_BASENAME => Ok(Variable::RequestBasename),
+            REMOTE_ADDR => Ok(Variable::RemoteAddr),
             ARGS => Ok(Variable::Args),
+            QUERY_STRING => Ok(Variable::QueryString),
+            FILES => Ok(Variable::Files),
+            FILES_NAMES => Ok(Variable::FilesNames),
+            FILES_SIZES => Ok(Variable::FilesSizes),
+            REQBODY_ERROR => Ok(Variable::ReqbodyError),
+            REQBODY_ERROR_MSG => Ok(Variable::ReqbodyErrorMsg),
+            TX => Ok(Variable::Tx),
             _ => Err(format!("unknown variable type: '{}'", s)),
         }
     }
 }
+
+impl Variable {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variable::RequestHeaders => REQUEST_HEADERS,
+            Variable::RequestCookies => REQUEST_COOKIES,
+            Variable::ResponseHeaders => RESPONSE_HEADERS,
+            Variable::ResponseStatus => RESPONSE_STATUS,
+            Variable::RequestBody => REQUEST_BODY,
+            Variable::ResponseBody => RESPONSE_BODY,
+            Variable::RequestMethod => REQUEST_METHOD,
+            Variable::RequestFilename => REQUEST_FILENAME,
+            Variable::RequestBasename => REQUEST_BASENAME,
+            Variable::RemoteAddr => REMOTE_ADDR,
+            Variable::Args => ARGS,
+            Variable::QueryString => QUERY_STRING,
+            Variable::Files => FILES,
+            Variable::FilesNames => FILES_NAMES,
+            Variable::FilesSizes => FILES_SIZES,
+            Variable::ReqbodyError => REQBODY_ERROR,
+            Variable::ReqbodyErrorMsg => REQBODY_ERROR_MSG,
+            Variable::Tx => TX,
+        }
+    }
+}
+
+// One of the "|" separated variables of a rule, e.g. "REQUEST_HEADERS:Referer".
+// A negated variable (e.g. "!REQUEST_HEADERS:Referer") excludes the values it
+// names from the other variables of the rule instead of adding to them.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct VariableSpec {
+    pub variable: Variable,
+    pub target: Option<String>,
+    pub negated: bool,
+}
+
+impl VariableSpec {
+    // Whether the spec names the given value of a variable, where a spec
+    // without a target names every value of the variable.
+    pub(crate) fn includes(&self, variable: &Variable, key: Option<&str>) -> bool {
+        if self.variable != *variable {
+            return false;
+        }
+        match (&self.target, key) {
+            (None, _) => true,
+            (Some(target), Some(key)) => target.eq_ignore_ascii_case(key),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+impl TryFrom<&str> for VariableSpec {
+    type Error = ValidationErrors;
+
+    fn try_from(variable_str: &str) -> Result<Self, Self::Error> {
+        let (negated, variable_str) = match variable_str.strip_prefix('!') {
+            Some(variable_str) => (true, variable_str),
+            None => (false, variable_str),
+        };
+        if variable_str.is_empty() {
+            return Err(ValidationErrors::EmptyVariable);
+        }
+
+        let (variable_name, target) = match variable_str.split_once(':') {
+            Some((variable_name, target)) => (variable_name, Some(target.to_string())),
+            None => (variable_str, None),
+        };
+        let variable =
+            Variable::try_from(variable_name).map_err(|_| ValidationErrors::InvalidVariable {
+                value: variable_name.to_string(),
+            })?;
+
+        Ok(VariableSpec {
+            variable,
+            target,
+            negated,
+        })
+    }
+}
+
+impl std::fmt::Display for VariableSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.negated {
+            f.write_str("!")?;
+        }
+        f.write_str(self.variable.name())?;
+        match &self.target {
+            Some(target) => write!(f, ":{}", target),
+            None => Ok(()),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+// ModSecurity - SetVar
+// -----------------------------------------------------------------------------
+
+// A setvar action, which changes a variable in the TX collection when the rule
+// matches, e.g. "setvar:tx.anomaly_score=+5".
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SetVar {
+    pub name: String,
+    pub operation: SetVarOperation,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum SetVarOperation {
+    // "tx.name=value", or "tx.name" which sets the variable to 1
+    Set(String),
+    // "tx.name=+value"
+    Increment(String),
+    // "tx.name=-value"
+    Decrement(String),
+    // "!tx.name"
+    Delete,
+}
+
+impl SetVar {
+    // Returns a copy of the setvar with its value mapped, e.g. to expand the
+    // macros in it.
+    pub(crate) fn map_value(&self, f: impl Fn(&str) -> String) -> SetVar {
+        let operation = match &self.operation {
+            SetVarOperation::Set(value) => SetVarOperation::Set(f(value)),
+            SetVarOperation::Increment(value) => SetVarOperation::Increment(f(value)),
+            SetVarOperation::Decrement(value) => SetVarOperation::Decrement(f(value)),
+            SetVarOperation::Delete => SetVarOperation::Delete,
+        };
+        SetVar {
+            name: self.name.clone(),
+            operation,
+        }
+    }
+}
+
+impl TryFrom<&str> for SetVar {
+    type Error = ValidationErrors;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let invalid_set_var = || ValidationErrors::InvalidSetVar {
+            value: s.to_string(),
+        };
+
+        let set_var = s.trim_matches('\'');
+        let (delete, set_var) = match set_var.strip_prefix('!') {
+            Some(set_var) => (true, set_var),
+            None => (false, set_var),
+        };
+        let (variable, value) = match set_var.split_once('=') {
+            Some((variable, value)) => (variable, Some(value)),
+            None => (set_var, None),
+        };
+
+        // only the TX collection is supported
+        let name = match variable.split_once('.') {
+            Some((collection, name)) if collection.eq_ignore_ascii_case(TX) && !name.is_empty() => {
+                name.to_string()
+            }
+            _ => return Err(invalid_set_var()),
+        };
+
+        let operation = match (delete, value) {
+            (true, None) => SetVarOperation::Delete,
+            (true, Some(_)) => return Err(invalid_set_var()),
+            (false, None) => SetVarOperation::Set("1".to_string()),
+            (false, Some(value)) => match value.split_at_checked(1) {
+                Some(("+", value)) => SetVarOperation::Increment(value.to_string()),
+                Some(("-", value)) => SetVarOperation::Decrement(value.to_string()),
+                _ => SetVarOperation::Set(value.to_string()),
+            },
+        };
+
+        Ok(SetVar { name, operation })
+    }
+}
+
+impl std::fmt::Display for SetVar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.operation {
+            SetVarOperation::Set(value) => write!(f, "tx.{}={}", self.name, value),
+            SetVarOperation::Increment(value) => write!(f, "tx.{}=+{}", self.name, value),
+            SetVarOperation::Decrement(value) => write!(f, "tx.{}=-{}", self.name, value),
+            SetVarOperation::Delete => write!(f, "!tx.{}", self.name),
+        }
+    }
+}
@@ -1,2 +1,4 @@
 pub mod directives;
+pub mod operators;
 pub mod rulesets;
+pub mod transformations;
@@ -0,0 +1,273 @@
+// -----------------------------------------------------------------------------
+// ModSecurity - detectSQLi Operator
+// -----------------------------------------------------------------------------
+
+// A tokenizing SQL injection detector in the spirit of libinjection. The input
+// is tokenized as SQL and reduced to a fingerprint of token kinds, which is
+// then checked for the structures injections need (tautologies, UNION SELECT,
+// stacked queries, string breaks followed by SQL, time-based functions).
+//
+// Injections usually start inside a quoted value in the query they target, so
+// the input is also tokenized as if it followed a single and a double quote.
+//
+// Token kinds, as they appear in fingerprints:
+//
+//   s  string          n  number          v  bareword / variable
+//   k  keyword         U  UNION           &  logical operator (AND, OR, ...)
+//   o  operator        f  function call   c  comment
+//   ;  semicolon       (  )  ,  punctuation
+
+// Returns the fingerprint of the input when it looks like SQL injection.
+pub fn detect_sqli(input: &str) -> Option<String> {
+    ["", "'", "\""].iter().find_map(|quote| {
+        let tokens = tokenize(&format!("{}{}", quote, input));
+        is_injection(&tokens, !quote.is_empty()).then(|| fingerprint(&tokens))
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Token {
+    kind: char,
+    value: String,
+}
+
+const KEYWORDS: &[&str] = &[
+    "all", "alter", "between", "by", "case", "create", "declare", "delay", "delete", "distinct",
+    "drop", "else", "end", "exec", "execute", "from", "group", "having", "in", "insert", "into",
+    "is", "limit", "not", "null", "order", "select", "shutdown", "table", "then", "truncate",
+    "update", "values", "waitfor", "when", "where",
+];
+
+const LOGICAL_OPERATORS: &[&str] = &["and", "or", "xor", "&&", "||"];
+
+// Statements which make a query after a ';' a stacked query.
+const STACKED_KEYWORDS: &[&str] = &[
+    "alter", "create", "declare", "delete", "drop", "exec", "execute", "insert", "select",
+    "shutdown", "truncate", "update", "waitfor",
+];
+
+// Functions used for blind, time-based and error-based injection.
+const DANGEROUS_FUNCTIONS: &[&str] = &[
+    "benchmark",
+    "extractvalue",
+    "load_file",
+    "pg_sleep",
+    "sleep",
+    "updatexml",
+];
+
+fn tokenize(input: &str) -> Vec<Token> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        let start = i;
+
+        let kind = match ch {
+            _ if ch.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '\'' | '"' => {
+                i = skip_string(&chars, i);
+                's'
+            }
+            '#' => {
+                i = skip_line(&chars, i);
+                'c'
+            }
+            '-' if chars.get(i + 1) == Some(&'-') => {
+                i = skip_line(&chars, i);
+                'c'
+            }
+            // MySQL executes the contents of "/*! ... */" comments, so only
+            // the markers are skipped and the contents are tokenized.
+            '/' if chars.get(i + 1) == Some(&'*') && chars.get(i + 2) == Some(&'!') => {
+                i += 3;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                continue;
+            }
+            '*' if chars.get(i + 1) == Some(&'/') => {
+                i += 2;
+                continue;
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                i = skip_block_comment(&chars, i);
+                'c'
+            }
+            ';' | '(' | ')' | ',' => {
+                i += 1;
+                ch
+            }
+            _ if ch.is_ascii_digit() => {
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                    i += 1;
+                }
+                'n'
+            }
+            _ if is_word_char(ch) || ch == '@' || ch == '`' => {
+                i = skip_word(&chars, i);
+                'v'
+            }
+            _ if "=<>!+-*/%|&^~:".contains(ch) => {
+                while i < chars.len() && "=<>!|&:".contains(chars[i]) && i - start < 3 {
+                    i += 1;
+                }
+                i = i.max(start + 1);
+                'o'
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+
+        let value: String = chars[start..i].iter().collect::<String>().to_lowercase();
+        tokens.push(classify(kind, value, &chars, i));
+    }
+
+    tokens
+}
+
+fn classify(kind: char, value: String, chars: &[char], end: usize) -> Token {
+    let kind = match kind {
+        'v' | 'o' if LOGICAL_OPERATORS.contains(&value.as_str()) => '&',
+        'v' if value == "like" => 'o',
+        'v' if value == "union" => 'U',
+        'v' if KEYWORDS.contains(&value.as_str()) => 'k',
+        'v' if next_non_whitespace(chars, end) == Some('(') => 'f',
+        kind => kind,
+    };
+    Token { kind, value }
+}
+
+fn is_injection(tokens: &[Token], quoted: bool) -> bool {
+    // comments are only meaningful directly after a string break, elsewhere
+    // they're just used to separate tokens (e.g. "UNION/**/SELECT")
+    let code: Vec<&Token> = tokens.iter().filter(|token| token.kind != 'c').collect();
+    let kinds: Vec<char> = code.iter().map(|token| token.kind).collect();
+
+    let union_select = code.iter().enumerate().any(|(i, token)| {
+        let next = match code.get(i + 1) {
+            Some(next) if next.kind == 'k' && matches!(next.value.as_str(), "all" | "distinct") => {
+                code.get(i + 2)
+            }
+            next => next,
+        };
+        token.kind == 'U' && next.is_some_and(|next| is_keyword(next, "select") || next.kind == '(')
+    });
+
+    let stacked_query = code.windows(2).any(|window| {
+        window[0].kind == ';'
+            && window[1].kind == 'k'
+            && STACKED_KEYWORDS.contains(&window[1].value.as_str())
+    });
+
+    let tautology = (0..kinds.len()).any(|i| {
+        let rest = &kinds[i..];
+        let rest = match rest {
+            ['&', '(', rest @ ..] | ['&', rest @ ..] => rest,
+            _ => return false,
+        };
+        matches!(rest, ['s' | 'n', 'o', 's' | 'n', ..])
+    });
+
+    let dangerous_function = code.iter().enumerate().any(|(i, token)| {
+        token.kind == 'f'
+            && DANGEROUS_FUNCTIONS.contains(&token.value.as_str())
+            && i > 0
+            && (matches!(code[i - 1].kind, '&' | ';' | 'o' | '(' | ',' | 's')
+                || is_keyword(code[i - 1], "select"))
+    });
+
+    // when the input is treated as quoted, a leading string token means the
+    // input closed the quote, and whatever follows it is SQL
+    let string_break = quoted
+        && (matches!(
+            tokens
+                .iter()
+                .map(|token| token.kind)
+                .collect::<Vec<_>>()
+                .as_slice(),
+            ['s', 'c'] | ['s', ';', 'c'] | ['s', ')', 'c']
+        ) || matches!(
+            kinds.as_slice(),
+            ['s', '&', 's' | 'n' | 'f' | '(', ..]
+                | ['s', '&', 'v', 'o', ..]
+                | ['s', ')', '&', ..]
+                | ['s', 'U', ..]
+        ));
+
+    union_select || stacked_query || tautology || dangerous_function || string_break
+}
+
+fn fingerprint(tokens: &[Token]) -> String {
+    tokens.iter().map(|token| token.kind).collect()
+}
+
+fn is_keyword(token: &Token, keyword: &str) -> bool {
+    token.kind == 'k' && token.value == keyword
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_' || ch == '$' || ch == '.'
+}
+
+fn next_non_whitespace(chars: &[char], from: usize) -> Option<char> {
+    chars[from..].iter().copied().find(|ch| !ch.is_whitespace())
+}
+
+// Skips a quoted string starting at `start`, honouring backslash escapes and
+// doubled quotes. Unterminated strings run to the end of the input.
+fn skip_string(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            ch if ch == quote && chars.get(i + 1) == Some(&quote) => i += 2,
+            ch if ch == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+fn skip_line(chars: &[char], start: usize) -> usize {
+    chars[start..]
+        .iter()
+        .position(|&ch| ch == '\n')
+        .map_or(chars.len(), |offset| start + offset)
+}
+
+fn skip_block_comment(chars: &[char], start: usize) -> usize {
+    let mut i = start + 2;
+    while i + 1 < chars.len() {
+        if chars[i] == '*' && chars[i + 1] == '/' {
+            return i + 2;
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+fn skip_word(chars: &[char], start: usize) -> usize {
+    let mut i = start;
+    if chars[i] == '`' {
+        return chars[i + 1..]
+            .iter()
+            .position(|&ch| ch == '`')
+            .map_or(chars.len(), |offset| i + offset + 2);
+    }
+    while i < chars.len() && chars[i] == '@' {
+        i += 1;
+    }
+    while i < chars.len() && is_word_char(chars[i]) {
+        i += 1;
+    }
+    i.max(start + 1)
+}
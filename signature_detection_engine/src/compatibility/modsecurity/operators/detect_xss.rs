@@ -0,0 +1,329 @@
+// -----------------------------------------------------------------------------
+// ModSecurity - detectXSS Operator
+// -----------------------------------------------------------------------------
+
+// A structural XSS detector in the spirit of libinjection. Rather than looking
+// for particular strings, the input is tokenized the way a browser's HTML
+// parser would, and flagged when it produces something that can run script:
+// a dangerous tag, an event handler attribute, a style attribute, a URL
+// attribute with a script-capable scheme, or an IE conditional comment.
+//
+// A payload may be injected into an attribute value rather than into the page
+// body, so the input is also parsed as if it started inside an unquoted,
+// single-quoted, double-quoted and backtick-quoted attribute value. The rest
+// of the input is only parsed as markup once it breaks out of that value (see
+// HtmlParser::break_out), as otherwise ordinary prose would be parsed as
+// attribute names, and any word starting with "on" taken for an event handler.
+
+// Returns true when the input looks like XSS.
+pub fn detect_xss(input: &str) -> bool {
+    let chars: Vec<char> = input.chars().collect();
+
+    let mut contexts = vec![Context::Data];
+    contexts.extend([None, Some('\''), Some('"'), Some('`')].map(Context::AttributeValue));
+
+    contexts
+        .into_iter()
+        .any(|context| HtmlParser::new(&chars, context).any(|event| is_dangerous(&event)))
+}
+
+// Tags which execute script or load active content.
+const DANGEROUS_TAGS: &[&str] = &[
+    "applet", "base", "comment", "embed", "frame", "frameset", "handler", "iframe", "import",
+    "isindex", "link", "listener", "meta", "noscript", "object", "script", "style", "vmlframe",
+    "xml", "xss",
+];
+
+// Attributes whose value is loaded as a URL.
+const URL_ATTRIBUTES: &[&str] = &[
+    "action",
+    "background",
+    "by",
+    "dynsrc",
+    "folder",
+    "formaction",
+    "from",
+    "handler",
+    "href",
+    "lowsrc",
+    "poster",
+    "src",
+    "to",
+    "values",
+    "xlink:href",
+];
+
+// Attributes which are dangerous regardless of their value.
+const DANGEROUS_ATTRIBUTES: &[&str] = &[
+    "attributename",
+    "dataformatas",
+    "datasrc",
+    "filter",
+    "style",
+];
+
+// URL schemes which run script or render attacker-controlled documents.
+const DANGEROUS_SCHEMES: &[&str] = &["data:", "javascript:", "vbscript:", "view-source:"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Context {
+    Data,
+    // the quote character of the value, if it's quoted
+    AttributeValue(Option<char>),
+}
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Tag(String),
+    // the name is None for the attribute value the input started inside
+    Attribute(Option<String>, String),
+    Comment(String),
+}
+
+fn is_dangerous(event: &Event) -> bool {
+    match event {
+        Event::Tag(name) => {
+            DANGEROUS_TAGS.contains(&name.as_str())
+                || name.starts_with("svg")
+                || name.starts_with("xsl")
+        }
+        Event::Attribute(Some(name), value) => {
+            (name.len() > 2 && name.starts_with("on"))
+                || name.starts_with("xmlns")
+                || name.starts_with("xlink")
+                || DANGEROUS_ATTRIBUTES.contains(&name.as_str())
+                || (URL_ATTRIBUTES.contains(&name.as_str()) && is_dangerous_url(value))
+        }
+        // the attribute the input was injected into is unknown, so assume it
+        // takes a URL, but require the scheme to be followed by script so that
+        // prose like "javascript: the good parts" isn't flagged
+        Event::Attribute(None, value) => dangerous_scheme(value)
+            .is_some_and(|rest| rest.chars().next().is_some_and(|ch| !ch.is_whitespace())),
+        Event::Comment(text) => {
+            let text = text.to_ascii_lowercase();
+            text.contains("[if") || text.contains("xml") || text.contains('`')
+        }
+    }
+}
+
+fn is_dangerous_url(value: &str) -> bool {
+    dangerous_scheme(value).is_some()
+}
+
+// Browsers strip leading spaces and control characters from URLs, and remove
+// tabs and newlines anywhere in them, so "jav&#x09;ascript:" (once entity
+// decoded) still runs script. Returns what follows the scheme when the URL
+// has a dangerous one.
+fn dangerous_scheme(value: &str) -> Option<String> {
+    let url: String = value
+        .trim_start_matches(|ch: char| ch == ' ' || ch.is_control())
+        .chars()
+        .filter(|ch| !matches!(ch, '\t' | '\n' | '\r'))
+        .collect();
+    DANGEROUS_SCHEMES.iter().find_map(|scheme| {
+        url.get(..scheme.len())
+            .filter(|prefix| prefix.eq_ignore_ascii_case(scheme))
+            .map(|_| url[scheme.len()..].to_string())
+    })
+}
+
+// The characters of the attribute names a break out is recognized by, which
+// are narrower than what browsers accept so that punctuated prose isn't.
+fn is_attribute_name_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | ':')
+}
+
+// -----------------------------------------------------------------------------
+// ModSecurity - detectXSS HTML Parser
+// -----------------------------------------------------------------------------
+
+// A small subset of the HTML5 tokenizer, which yields the tags, attributes and
+// comments in the input.
+struct HtmlParser<'a> {
+    chars: &'a [char],
+    position: usize,
+    in_tag: bool,
+    initial_value: Option<Option<char>>,
+}
+
+impl<'a> HtmlParser<'a> {
+    fn new(chars: &'a [char], context: Context) -> Self {
+        let (in_tag, initial_value) = match context {
+            Context::Data => (false, None),
+            Context::AttributeValue(quote) => (true, Some(quote)),
+        };
+        HtmlParser {
+            chars,
+            position: 0,
+            in_tag,
+            initial_value,
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(offset, ch)| self.peek(offset) == Some(ch))
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let start = self.position;
+        while self.peek(0).is_some_and(&predicate) {
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().collect()
+    }
+
+    fn take_until(&mut self, end: &str) -> String {
+        let start = self.position;
+        while self.position < self.chars.len() && !self.starts_with(end) {
+            self.position += 1;
+        }
+        let text = self.chars[start..self.position].iter().collect();
+        self.position = (self.position + end.chars().count()).min(self.chars.len());
+        text
+    }
+
+    // Leaves the attribute value the input started inside, returning whether
+    // the input broke out of it: a quoted value must be closed by its quote,
+    // and either value must then be followed by a '>', a tag, or attributes
+    // leading up to one with a value (e.g. " autofocus onfocus="). Prose
+    // which merely contains a quote or a space is left inside the value.
+    fn break_out(&mut self, quote: Option<char>) -> bool {
+        if let Some(quote) = quote
+            && (self.position == 0 || self.chars[self.position - 1] != quote)
+        {
+            // the value ran to the end of the input without being closed
+            return false;
+        }
+        self.take_while(char::is_whitespace);
+        match self.peek(0) {
+            Some('>') => return true,
+            Some('<') => {
+                self.in_tag = false;
+                return true;
+            }
+            _ => {}
+        }
+
+        let start = self.position;
+        let broke_out = loop {
+            self.take_while(|ch| ch.is_whitespace() || ch == '/');
+            let name = self.take_while(|ch| !ch.is_whitespace() && !matches!(ch, '=' | '>' | '/'));
+            if name.is_empty() || !name.chars().all(is_attribute_name_char) {
+                break false;
+            }
+            self.take_while(char::is_whitespace);
+            if self.peek(0) == Some('=') {
+                break true;
+            }
+        };
+        self.position = start;
+        broke_out
+    }
+
+    fn attribute_value(&mut self, quote: Option<char>) -> String {
+        match quote {
+            Some(quote) => {
+                let value = self.take_while(|ch| ch != quote);
+                self.position = (self.position + 1).min(self.chars.len());
+                value
+            }
+            None => self.take_while(|ch| !ch.is_whitespace() && ch != '>'),
+        }
+    }
+
+    // Parses the next attribute of the current tag, or ends the tag.
+    fn next_in_tag(&mut self) -> Option<Event> {
+        self.take_while(|ch| ch.is_whitespace() || ch == '/');
+        match self.peek(0)? {
+            '>' => {
+                self.position += 1;
+                self.in_tag = false;
+                None
+            }
+            _ => {
+                let name =
+                    self.take_while(|ch| !ch.is_whitespace() && !matches!(ch, '=' | '>' | '/'));
+                if name.is_empty() {
+                    // a lone '=' can't start an attribute name, skip it
+                    self.position += 1;
+                    return None;
+                }
+                let name = name.to_lowercase();
+
+                self.take_while(char::is_whitespace);
+                let value = if self.peek(0) == Some('=') {
+                    self.position += 1;
+                    self.take_while(char::is_whitespace);
+                    let quote = self.peek(0).filter(|ch| matches!(ch, '"' | '\'' | '`'));
+                    if quote.is_some() {
+                        self.position += 1;
+                    }
+                    self.attribute_value(quote)
+                } else {
+                    String::new()
+                };
+
+                Some(Event::Attribute(Some(name), value))
+            }
+        }
+    }
+
+    // Scans the page body for the start of the next tag or comment.
+    fn next_in_data(&mut self) -> Option<Event> {
+        self.take_while(|ch| ch != '<');
+        self.peek(0)?;
+        self.position += 1;
+
+        if self.starts_with("!--") {
+            self.position += 3;
+            return Some(Event::Comment(self.take_until("-->")));
+        }
+        if matches!(self.peek(0), Some('!' | '?')) {
+            self.position += 1;
+            return Some(Event::Comment(self.take_until(">")));
+        }
+
+        if self.peek(0) == Some('/') {
+            self.position += 1;
+        }
+        if !self.peek(0).is_some_and(|ch| ch.is_ascii_alphabetic()) {
+            return None;
+        }
+
+        let name = self.take_while(|ch| !ch.is_whitespace() && !matches!(ch, '/' | '>'));
+        self.in_tag = true;
+        Some(Event::Tag(name.to_lowercase()))
+    }
+}
+
+impl Iterator for HtmlParser<'_> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        if let Some(quote) = self.initial_value.take() {
+            let value = self.attribute_value(quote);
+            if !self.break_out(quote) {
+                self.position = self.chars.len();
+            }
+            return Some(Event::Attribute(None, value));
+        }
+
+        while self.position < self.chars.len() {
+            let event = match self.in_tag {
+                true => self.next_in_tag(),
+                false => self.next_in_data(),
+            };
+            if event.is_some() {
+                return event;
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::{BTreeMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+
+use ipnet::IpNet;
+
+use crate::errors::ValidationErrors;
+
+// -----------------------------------------------------------------------------
+// ModSecurity - ipMatch Operator
+// -----------------------------------------------------------------------------
+
+// A set of IPv4 and IPv6 addresses and networks, parsed from the argument of
+// an @ipMatch operator (e.g. "10.0.0.0/8,192.168.1.5,2001:db8::/32") or from
+// the file named by an @ipMatchFromFile operator.
+//
+// Networks are bucketed by prefix length, so a lookup costs one hash probe per
+// distinct prefix length in the set (at most 33 for IPv4 and 129 for IPv6)
+// regardless of how many networks the set holds.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IpMatchSet {
+    v4: BTreeMap<u8, HashSet<u32>>,
+    v6: BTreeMap<u8, HashSet<u128>>,
+}
+
+impl IpMatchSet {
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        match address {
+            IpAddr::V4(address) => {
+                let address = u32::from(*address);
+                self.v4
+                    .iter()
+                    .any(|(prefix_len, networks)| networks.contains(&mask_v4(address, *prefix_len)))
+            }
+            IpAddr::V6(address) => {
+                let address = u128::from(*address);
+                self.v6
+                    .iter()
+                    .any(|(prefix_len, networks)| networks.contains(&mask_v6(address, *prefix_len)))
+            }
+        }
+    }
+
+    // Loads a set from a file containing one address or CIDR per line. Blank
+    // lines and '#' comments are ignored.
+    pub fn from_file(path: &Path) -> Result<Self, ValidationErrors> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ValidationErrors::InvalidFile {
+                path: path.display().to_string(),
+                reason: e.to_string(),
+            })?;
+
+        let mut set = IpMatchSet::default();
+        for line in contents.lines() {
+            let entry = match line.split_once('#') {
+                Some((entry, _comment)) => entry,
+                None => line,
+            };
+            let entry = entry.trim();
+            if !entry.is_empty() {
+                set.insert(parse_network(entry)?);
+            }
+        }
+        Ok(set)
+    }
+
+    fn insert(&mut self, network: IpNet) {
+        match network {
+            IpNet::V4(network) => {
+                self.v4
+                    .entry(network.prefix_len())
+                    .or_default()
+                    .insert(u32::from(network.network()));
+            }
+            IpNet::V6(network) => {
+                self.v6
+                    .entry(network.prefix_len())
+                    .or_default()
+                    .insert(u128::from(network.network()));
+            }
+        }
+    }
+}
+
+impl TryFrom<&str> for IpMatchSet {
+    type Error = ValidationErrors;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let mut set = IpMatchSet::default();
+        for entry in s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            set.insert(parse_network(entry)?);
+        }
+        Ok(set)
+    }
+}
+
+// Parses a single address or CIDR, treating bare addresses as host networks.
+fn parse_network(entry: &str) -> Result<IpNet, ValidationErrors> {
+    entry
+        .parse::<IpNet>()
+        .map(|network| network.trunc())
+        .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| ValidationErrors::InvalidNetwork {
+            value: entry.to_string(),
+        })
+}
+
+fn mask_v4(address: u32, prefix_len: u8) -> u32 {
+    address
+        & u32::MAX
+            .checked_shl(32 - u32::from(prefix_len))
+            .unwrap_or(0)
+}
+
+fn mask_v6(address: u128, prefix_len: u8) -> u128 {
+    address
+        & u128::MAX
+            .checked_shl(128 - u32::from(prefix_len))
+            .unwrap_or(0)
+}
@@ -0,0 +1,5 @@
+pub mod detect_sqli;
+pub mod detect_xss;
+pub mod ip_match;
+pub mod validate_byte_range;
+pub mod validate_utf8_encoding;
@@ -0,0 +1,61 @@
+use crate::errors::ValidationErrors;
+
+// -----------------------------------------------------------------------------
+// ModSecurity - validateByteRange Operator
+// -----------------------------------------------------------------------------
+
+// The bytes allowed by a @validateByteRange operator, parsed from a list of
+// single byte values and inclusive ranges (e.g. "10,13,32-126"). The operator
+// matches when the value contains any byte outside of the allowed set.
+//
+// The allowed bytes are stored as a 256-bit bitmap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ByteRangeSet {
+    allowed: [u64; 4],
+}
+
+impl ByteRangeSet {
+    // Returns true when any of the bytes isn't allowed.
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        bytes.iter().any(|&byte| !self.allows(byte))
+    }
+
+    fn allows(&self, byte: u8) -> bool {
+        self.allowed[byte as usize / 64] & (1 << (byte % 64)) != 0
+    }
+
+    fn allow(&mut self, byte: u8) {
+        self.allowed[byte as usize / 64] |= 1 << (byte % 64);
+    }
+}
+
+impl TryFrom<&str> for ByteRangeSet {
+    type Error = ValidationErrors;
+
+    fn try_from(ranges: &str) -> Result<Self, Self::Error> {
+        let mut set = ByteRangeSet { allowed: [0; 4] };
+        for range in ranges.split(',') {
+            let (low, high) = parse_range(range.trim())?;
+            (low..=high).for_each(|byte| set.allow(byte));
+        }
+        Ok(set)
+    }
+}
+
+fn parse_range(range: &str) -> Result<(u8, u8), ValidationErrors> {
+    let invalid_byte_range = || ValidationErrors::InvalidByteRange {
+        value: range.to_string(),
+    };
+
+    let (low, high) = match range.split_once('-') {
+        Some((low, high)) => (low.trim(), high.trim()),
+        None => (range, range),
+    };
+    let low = low.parse::<u8>().map_err(|_| invalid_byte_range())?;
+    let high = high.parse::<u8>().map_err(|_| invalid_byte_range())?;
+
+    if low > high {
+        return Err(invalid_byte_range());
+    }
+    Ok((low, high))
+}
@@ -0,0 +1,12 @@
+// -----------------------------------------------------------------------------
+// ModSecurity - validateUtf8Encoding Operator
+// -----------------------------------------------------------------------------
+
+// Returns true when the bytes aren't valid UTF-8. Besides truncated sequences
+// and stray continuation bytes, this rejects overlong encodings (e.g. 0xC0 0xAF
+// for '/', a classic path traversal evasion), UTF-16 surrogates and code
+// points above U+10FFFF, none of which a conforming decoder will accept but
+// which a lenient backend might still decode.
+pub fn validate_utf8_encoding(bytes: &[u8]) -> bool {
+    std::str::from_utf8(bytes).is_err()
+}
@@ -0,0 +1,117 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::compatibility::modsecurity::directives::{
+    Directive,
+    sec_rule::{Operator, Phase, SecRule, Variable},
+};
+use crate::compatibility::modsecurity::rulesets::{RuleGroup, index::RulePosition};
+
+// -----------------------------------------------------------------------------
+// ModSecurity - Header Denylist
+// -----------------------------------------------------------------------------
+
+// Maps exact header values to the phase 1 rules which match them, so that a
+// large static blocklist of @streq rules (e.g. of known bad User-Agents) costs
+// a lookup per header rather than an evaluation per rule.
+//
+// Only rules whose match is decided by the value alone are denylisted: a
+// non-negated @streq against a single named request header, without chaining,
+// and without transformations other than a single t:lowercase, which is how
+// a rule compares a value case insensitively. Rules with multiMatch are left
+// out, as they also compare the value before it's lowercased. Those rules are
+// still fired in the order they were declared, so a denylisted rule is only
+// reached after the rules before it, but they're looked up rather than
+// evaluated. Rules are identified by their position (see RuleIndex), as rules
+// without an ID, such as the rules continuing a chain, share the ID 0.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct HeaderDenylist {
+    // keyed by the lowercased header name and the exact value
+    rules: HashMap<(String, String), Vec<RulePosition>>,
+    // the rules with t:lowercase, keyed by the lowercased header name and
+    // the lowercased value
+    lowercase_rules: HashMap<(String, String), Vec<RulePosition>>,
+    positions: HashSet<RulePosition>,
+}
+
+impl HeaderDenylist {
+    pub(crate) fn new(rule_group: &RuleGroup) -> Self {
+        let mut denylist = Self::default();
+        let rulesets = rule_group.get(&Phase::RequestHeaders).into_iter().flatten();
+        for (ruleset_index, ruleset) in rulesets.enumerate() {
+            for (directive_index, directive) in ruleset.directives.iter().enumerate() {
+                let sec_rule = match directive {
+                    Directive::SecRule(sec_rule) => sec_rule,
+                    _ => continue,
+                };
+                let rules = match sec_rule.transformations.as_slice() {
+                    [] => &mut denylist.rules,
+                    _ => &mut denylist.lowercase_rules,
+                };
+                if let Some(key) = denylist_key(sec_rule) {
+                    let position = (ruleset_index, directive_index);
+                    rules.entry(key).or_default().push(position);
+                    denylist.positions.insert(position);
+                }
+            }
+        }
+        denylist
+    }
+
+    pub(crate) fn contains(&self, position: RulePosition) -> bool {
+        self.positions.contains(&position)
+    }
+
+    // The denylisted rules which match any of the headers, along with the
+    // first of the headers each one matched, as (name, value).
+    pub(crate) fn matches<'a>(
+        &self,
+        headers: &'a [(String, String)],
+    ) -> HashMap<RulePosition, &'a (String, String)> {
+        let mut matches = HashMap::new();
+        if self.rules.is_empty() && self.lowercase_rules.is_empty() {
+            return matches;
+        }
+        for header in headers {
+            let name = header.0.to_ascii_lowercase();
+            let positions = self
+                .rules
+                .get(&(name.clone(), header.1.clone()))
+                .into_iter()
+                .chain(
+                    self.lowercase_rules
+                        .get(&(name, header.1.to_ascii_lowercase())),
+                )
+                .flatten();
+            for position in positions {
+                matches.entry(*position).or_insert(header);
+            }
+        }
+        matches
+    }
+}
+
+fn denylist_key(sec_rule: &SecRule) -> Option<(String, String)> {
+    let other_transformations = match sec_rule.transformations.as_slice() {
+        [] => false,
+        [transformation] => !transformation.eq_ignore_ascii_case("lowercase"),
+        _ => true,
+    };
+    if sec_rule.operator != Operator::Streq
+        || sec_rule.negated
+        || sec_rule.chain
+        || sec_rule.multi_match
+        || other_transformations
+    {
+        return None;
+    }
+    let header_name = match sec_rule.variables.as_slice() {
+        [variable] if variable.variable == Variable::RequestHeaders && !variable.negated => {
+            variable.target.as_ref()?
+        }
+        _ => return None,
+    };
+    Some((
+        header_name.to_ascii_lowercase(),
+        sec_rule.operator_target.clone()?,
+    ))
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+use crate::compatibility::modsecurity::directives::{
+    Directive,
+    sec_rule::{Phase, Variable},
+};
+use crate::compatibility::modsecurity::rulesets::RuleGroup;
+
+// -----------------------------------------------------------------------------
+// ModSecurity - RuleSet Index
+// -----------------------------------------------------------------------------
+
+// The position of a rule within its phase, as the index of its ruleset and the
+// index of the rule's directive within that ruleset.
+pub(crate) type RulePosition = (usize, usize);
+
+// Maps each phase and variable to the rules of that phase which select the
+// variable, so that evaluating a phase only visits the rules which can match
+// in it. Negated variables only exclude values, so rules aren't indexed by
+// them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct RuleIndex {
+    positions: HashMap<(Phase, Variable), Vec<RulePosition>>,
+}
+
+impl RuleIndex {
+    pub(crate) fn new(rule_group: &RuleGroup) -> Self {
+        let mut positions: HashMap<(Phase, Variable), Vec<RulePosition>> = HashMap::new();
+        for (phase, rulesets) in rule_group {
+            for (ruleset_index, ruleset) in rulesets.iter().enumerate() {
+                for (directive_index, directive) in ruleset.directives.iter().enumerate() {
+                    let sec_rule = match directive {
+                        Directive::SecRule(sec_rule) => sec_rule,
+                        _ => continue,
+                    };
+                    for variable in sec_rule.variables.iter().filter(|v| !v.negated) {
+                        let rule_positions =
+                            positions.entry((*phase, variable.variable)).or_default();
+                        // a rule selecting a variable more than once (e.g.
+                        // "ARGS:a|ARGS:b") is only indexed once
+                        if rule_positions.last() != Some(&(ruleset_index, directive_index)) {
+                            rule_positions.push((ruleset_index, directive_index));
+                        }
+                    }
+                }
+            }
+        }
+        Self { positions }
+    }
+
+    // The positions of the phase's rules which select any of the variables, in
+    // the order the rules were declared.
+    pub(crate) fn positions(&self, phase: Phase, variables: &[Variable]) -> Vec<RulePosition> {
+        let mut positions: Vec<RulePosition> = variables
+            .iter()
+            .filter_map(|variable| self.positions.get(&(phase, *variable)))
+            .flatten()
+            .copied()
+            .collect();
+        positions.sort_unstable();
+        positions.dedup();
+        positions
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
+
+use crate::compatibility::modsecurity::directives::{
+    Directive,
+    sec_rule::{Phase, SecRule},
+};
+use crate::compatibility::modsecurity::rulesets::{RuleGroup, index::RulePosition};
+
+// -----------------------------------------------------------------------------
+// ModSecurity - Rule Lookup
+// -----------------------------------------------------------------------------
+
+// Maps rule IDs and tags to the rules which have them, so that rules can be
+// looked up at runtime (e.g. by an introspection endpoint) without scanning
+// every rule. Rules without an ID, such as the rules continuing a chain, can
+// only be found by their tags. When an ID is used by more than one rule the
+// first is found, in the order of RuleGroup iteration by phase.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct RuleLookup {
+    ids: BTreeMap<u32, (Phase, RulePosition)>,
+    tags: HashMap<String, Vec<(Phase, RulePosition)>>,
+}
+
+impl RuleLookup {
+    pub(crate) fn new(rule_group: &RuleGroup) -> Self {
+        let mut lookup = Self::default();
+        let mut phases: Vec<&Phase> = rule_group.keys().collect();
+        phases.sort_by_key(|phase| u8::from(**phase));
+        for phase in phases {
+            for (ruleset_index, ruleset) in rule_group[phase].iter().enumerate() {
+                for (directive_index, directive) in ruleset.directives.iter().enumerate() {
+                    let sec_rule = match directive {
+                        Directive::SecRule(sec_rule) => sec_rule,
+                        _ => continue,
+                    };
+                    let position = (*phase, (ruleset_index, directive_index));
+                    if sec_rule.id != 0 {
+                        lookup.ids.entry(sec_rule.id).or_insert(position);
+                    }
+                    for tag in &sec_rule.tags {
+                        let positions = lookup.tags.entry(tag.clone()).or_default();
+                        // a rule with the same tag more than once is only
+                        // found once
+                        if positions.last() != Some(&position) {
+                            positions.push(position);
+                        }
+                    }
+                }
+            }
+        }
+        lookup
+    }
+
+    pub(crate) fn by_id<'a>(&self, rule_group: &'a RuleGroup, id: u32) -> Option<&'a SecRule> {
+        self.ids
+            .get(&id)
+            .and_then(|position| rule_at(rule_group, *position))
+    }
+
+    // in order of ID
+    pub(crate) fn by_id_range<'a>(
+        &self,
+        rule_group: &'a RuleGroup,
+        ids: RangeInclusive<u32>,
+    ) -> Vec<&'a SecRule> {
+        self.ids
+            .range(ids)
+            .filter_map(|(_, position)| rule_at(rule_group, *position))
+            .collect()
+    }
+
+    // in phase order and then in the order the rules are evaluated
+    pub(crate) fn by_tag<'a>(&self, rule_group: &'a RuleGroup, tag: &str) -> Vec<&'a SecRule> {
+        self.tags
+            .get(tag)
+            .into_iter()
+            .flatten()
+            .filter_map(|position| rule_at(rule_group, *position))
+            .collect()
+    }
+}
+
+fn rule_at(
+    rule_group: &RuleGroup,
+    (phase, (ruleset_index, directive_index)): (Phase, RulePosition),
+) -> Option<&SecRule> {
+    match rule_group
+        .get(&phase)?
+        .get(ruleset_index)?
+        .directives
+        .get(directive_index)?
+    {
+        Directive::SecRule(sec_rule) => Some(sec_rule),
+        _ => None,
+    }
+}
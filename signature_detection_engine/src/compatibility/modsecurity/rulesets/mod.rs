@@ -1,6 +1,16 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
-use crate::compatibility::modsecurity::directives::{Directive, sec_rule::Phase};
+use serde::{Deserialize, Serialize};
+
+pub(crate) mod denylist;
+pub(crate) mod index;
+pub(crate) mod lookup;
+
+use crate::compatibility::modsecurity::directives::{
+    Directive,
+    rule_exclusion::RuleExclusion,
+    sec_rule::{Phase, SecRule},
+};
 
 // -----------------------------------------------------------------------------
 // ModSecurity - RuleSet
@@ -10,7 +20,7 @@ pub type RuleGroup = HashMap<Phase, RuleSets>;
 
 pub type RuleSets = Vec<RuleSet>;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct RuleSet {
     pub name: Option<String>,
     pub description: Option<String>,
@@ -28,8 +38,145 @@ impl RuleSet {
         RuleSet {
             name: Some(name),
             description: Some(description),
-            directives: directives,
+            directives,
             version: Some(version),
         }
     }
 }
+
+// Every rule of the rule group along with its phase, in phase order and then
+// in the order the rules are evaluated within the phase. The rules continuing
+// a chain are included, after the rule starting it.
+pub(crate) fn iter_rules(rule_group: &RuleGroup) -> impl Iterator<Item = (Phase, &SecRule)> {
+    let mut phases: Vec<(&Phase, &RuleSets)> = rule_group.iter().collect();
+    phases.sort_by_key(|(phase, _)| u8::from(**phase));
+    phases.into_iter().flat_map(|(phase, rulesets)| {
+        rulesets
+            .iter()
+            .flat_map(|ruleset| &ruleset.directives)
+            .filter_map(move |directive| match directive {
+                Directive::SecRule(sec_rule) => Some((*phase, sec_rule)),
+                _ => None,
+            })
+    })
+}
+
+// The IDs used by more than one rule, in ascending order. A duplicate ID is
+// usually an authoring mistake, and makes it ambiguous which rule matched.
+// Rules without an ID, such as the rules continuing a chain, are ignored.
+pub(crate) fn duplicate_rule_ids(rule_group: &RuleGroup) -> Vec<u32> {
+    let mut seen = BTreeSet::new();
+    let mut duplicates = BTreeSet::new();
+    for ruleset in rule_group.values().flatten() {
+        for directive in &ruleset.directives {
+            if let Directive::SecRule(sec_rule) = directive
+                && sec_rule.id != 0
+                && !seen.insert(sec_rule.id)
+            {
+                duplicates.insert(sec_rule.id);
+            }
+        }
+    }
+    duplicates.into_iter().collect()
+}
+
+// Appends the rulesets of another rule group to each phase, after the rules
+// already there. Fails with the IDs used by both rule groups, leaving the rule
+// group unchanged, as which of the two rules matched would be ambiguous.
+pub(crate) fn merge_rule_groups(
+    rule_group: &mut RuleGroup,
+    other: RuleGroup,
+) -> Result<(), Vec<u32>> {
+    let ids = rule_ids(rule_group);
+    let conflicts: Vec<u32> = rule_ids(&other).intersection(&ids).copied().collect();
+    if !conflicts.is_empty() {
+        return Err(conflicts);
+    }
+    for (phase, rulesets) in other {
+        rule_group.entry(phase).or_default().extend(rulesets);
+    }
+    Ok(())
+}
+
+fn rule_ids(rule_group: &RuleGroup) -> BTreeSet<u32> {
+    rule_group
+        .values()
+        .flatten()
+        .flat_map(|ruleset| &ruleset.directives)
+        .filter_map(|directive| match directive {
+            Directive::SecRule(sec_rule) if sec_rule.id != 0 => Some(sec_rule.id),
+            _ => None,
+        })
+        .collect()
+}
+
+// Removes the rules matched by an exclusion from every phase, returning the
+// number of rules removed. The rules continuing a removed rule's chain are
+// removed along with it, as they'd otherwise be left as rules of their own.
+pub(crate) fn remove_rules(rule_group: &mut RuleGroup, exclusion: &RuleExclusion) -> usize {
+    let mut removed = 0;
+    for ruleset in rule_group.values_mut().flatten() {
+        let mut removing_chain = false;
+        ruleset.directives.retain(|directive| {
+            let sec_rule = match directive {
+                Directive::SecRule(sec_rule) => sec_rule,
+                _ => return true,
+            };
+            let remove = removing_chain || exclusion.excludes(sec_rule);
+            removing_chain = remove && sec_rule.chain;
+            if remove {
+                removed += 1;
+            }
+            !remove
+        });
+    }
+    removed
+}
+
+// Sorts the rules of each phase by ID, so that rules combined from several
+// files are evaluated in numeric order. A chained rule keeps its place after
+// the rule starting the chain, and markers stay in front of the rule which
+// followed them. Order across rulesets can only be expressed by merging them,
+// so a phase with more than one ruleset ends up with a single unnamed one.
+pub(crate) fn sort_rules_by_id(rule_group: &mut RuleGroup) {
+    for rulesets in rule_group.values_mut() {
+        let mut units: Vec<(u32, Vec<Directive>)> = Vec::new();
+        let mut pending: Vec<Directive> = Vec::new();
+        let mut continues_chain = false;
+        for directive in rulesets
+            .iter()
+            .flat_map(|ruleset| ruleset.directives.iter())
+        {
+            match directive {
+                Directive::SecRule(sec_rule) if continues_chain => {
+                    continues_chain = sec_rule.chain;
+                    if let Some((_, unit)) = units.last_mut() {
+                        unit.push(directive.clone());
+                    }
+                }
+                Directive::SecRule(sec_rule) => {
+                    continues_chain = sec_rule.chain;
+                    pending.push(directive.clone());
+                    units.push((sec_rule.id, std::mem::take(&mut pending)));
+                }
+                Directive::SecMarker(_) => pending.push(directive.clone()),
+            }
+        }
+        // the sort is stable, so rules sharing an ID keep their order
+        units.sort_by_key(|(id, _)| *id);
+
+        let mut directives: Vec<Directive> = units.into_iter().flat_map(|(_, unit)| unit).collect();
+        directives.append(&mut pending);
+        match rulesets.as_mut_slice() {
+            [ruleset] => ruleset.directives = directives,
+            _ => {
+                *rulesets = vec![RuleSet {
+                    name: None,
+                    description: None,
+                    directives,
+                    version: None,
+                }]
+            }
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use std::borrow::Cow;
+
+// -----------------------------------------------------------------------------
+// ModSecurity - Transformations
+// -----------------------------------------------------------------------------
+
+// Transformations normalize a value before a rule's operator is evaluated
+// against it (e.g. "t:urlDecode,t:lowercase"), so that rules don't have to
+// account for every way an attacker could encode the same payload. They're
+// applied in the order they were declared.
+//
+// Like ModSecurity, transformations work on bytes rather than text, so that
+// decoding "%C0%AF" yields the overlong sequence itself for byte-oriented
+// operators such as @validateUtf8Encoding to inspect.
+//
+// Reference: https://github.com/owasp-modsecurity/ModSecurity/wiki/Reference-Manual-(v2.x)#user-content-Transformation_functions
+
+pub const TRANSFORMATIONS: &[&str] = &[
+    "cssDecode",
+    "hexDecode",
+    "hexEncode",
+    "htmlEntityDecode",
+    "jsDecode",
+    "lowercase",
+    "normalizePath",
+    "normalizePathWin",
+    "removeComments",
+    "removeNulls",
+    "replaceNulls",
+    "urlDecode",
+    "urlDecodeUni",
+];
+
+// Not a transformation itself, but a reset of those declared before it.
+pub const NO_TRANSFORMATION: &str = "none";
+
+pub fn is_transformation(name: &str) -> bool {
+    TRANSFORMATIONS
+        .iter()
+        .any(|transformation| transformation.eq_ignore_ascii_case(name))
+}
+
+pub fn apply_transformations<'a>(
+    transformations: &[String],
+    value: &'a [u8],
+) -> Result<Cow<'a, [u8]>, String> {
+    let mut value = Cow::Borrowed(value);
+    for transformation in transformations {
+        value = Cow::Owned(apply_transformation(transformation, &value)?);
+    }
+    Ok(value)
+}
+
+// The value before any transformation and after each successive one, for the
+// multiMatch action. Transformations which leave the value unchanged don't
+// add a stage, as evaluating the same value again can't change the result.
+pub fn transformation_stages<'a>(
+    transformations: &[String],
+    value: &'a [u8],
+) -> Result<Vec<Cow<'a, [u8]>>, String> {
+    let mut stages = vec![Cow::Borrowed(value)];
+    for transformation in transformations {
+        let previous = stages.last().map(Cow::as_ref).unwrap_or(value);
+        let transformed = apply_transformation(transformation, previous)?;
+        if transformed != previous {
+            stages.push(Cow::Owned(transformed));
+        }
+    }
+    Ok(stages)
+}
+
+fn apply_transformation(transformation: &str, value: &[u8]) -> Result<Vec<u8>, String> {
+    match transformation.to_ascii_lowercase().as_str() {
+        "cssdecode" => Ok(css_decode(value)),
+        "hexdecode" => Ok(hex_decode(value)),
+        "hexencode" => Ok(hex_encode(value)),
+        "htmlentitydecode" => Ok(html_entity_decode(value)),
+        "jsdecode" => Ok(js_decode(value)),
+        "lowercase" => Ok(value.to_ascii_lowercase()),
+        "normalizepath" => Ok(normalize_path(value)),
+        "normalizepathwin" => Ok(normalize_path(
+            &value
+                .iter()
+                .map(|&byte| if byte == b'\\' { b'/' } else { byte })
+                .collect::<Vec<u8>>(),
+        )),
+        "removecomments" => Ok(remove_comments(value)),
+        "removenulls" => Ok(value.iter().copied().filter(|&byte| byte != 0).collect()),
+        "replacenulls" => Ok(value
+            .iter()
+            .map(|&byte| if byte == 0 { b' ' } else { byte })
+            .collect()),
+        "urldecode" => Ok(url_decode(value, false)),
+        "urldecodeuni" => Ok(url_decode(value, true)),
+        _ => Err(format!("unknown transformation: '{}'", transformation)),
+    }
+}
+
+// Removes "/* ... */" and "<!-- ... -->" comments, so that "DR/*x*/OP" becomes
+// "DROP", and everything from a "--" or "#" comment to the end of the value.
+// Like ModSecurity, an unterminated block comment runs to the end of the value.
+fn remove_comments(value: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(value.len());
+    let mut rest = value;
+
+    loop {
+        let start = [&b"/*"[..], b"<!--", b"--", b"#"]
+            .iter()
+            .filter_map(|marker| find(rest, marker).map(|position| (position, *marker)))
+            .min_by_key(|(position, _)| *position);
+
+        let (position, marker) = match start {
+            Some(start) => start,
+            None => {
+                result.extend_from_slice(rest);
+                return result;
+            }
+        };
+
+        result.extend_from_slice(&rest[..position]);
+        let comment = &rest[position + marker.len()..];
+        rest = match marker {
+            b"/*" => find(comment, b"*/").map_or(&[][..], |end| &comment[end + 2..]),
+            b"<!--" => find(comment, b"-->").map_or(&[][..], |end| &comment[end + 3..]),
+            _ => return result,
+        };
+    }
+}
+
+// Decodes %XX escapes and '+' as a space. With `unicode`, IIS-style %uXXXX
+// escapes are decoded as well. Invalid escapes are left as they are.
+pub(crate) fn url_decode(bytes: &[u8], unicode: bool) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if unicode && matches!(bytes.get(i + 1), Some(b'u' | b'U')) => {
+                match bytes.get(i + 2..i + 6).and_then(parse_hex) {
+                    Some(code_point) => {
+                        let ch = char::from_u32(code_point).unwrap_or(char::REPLACEMENT_CHARACTER);
+                        decoded.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+                        i += 6;
+                    }
+                    None => {
+                        decoded.push(b'%');
+                        i += 1;
+                    }
+                }
+            }
+            b'%' => match bytes.get(i + 1..i + 3).and_then(parse_hex) {
+                Some(byte) => {
+                    decoded.push(byte as u8);
+                    i += 3;
+                }
+                None => {
+                    decoded.push(b'%');
+                    i += 1;
+                }
+            },
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    decoded
+}
+
+// Decodes each pair of hex digits into a byte, so that "414243" becomes "ABC".
+// Bytes which aren't part of a pair of hex digits, such as the last digit of
+// an odd number of them, are left as they are, like invalid URL escapes.
+fn hex_decode(value: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(value.len() / 2 + 1);
+    let mut i = 0;
+
+    while i < value.len() {
+        match value.get(i..i + 2).and_then(parse_hex) {
+            Some(byte) => {
+                decoded.push(byte as u8);
+                i += 2;
+            }
+            None => {
+                decoded.push(value[i]);
+                i += 1;
+            }
+        }
+    }
+
+    decoded
+}
+
+fn hex_encode(value: &[u8]) -> Vec<u8> {
+    value
+        .iter()
+        .flat_map(|byte| format!("{:02x}", byte).into_bytes())
+        .collect()
+}
+
+// Decodes CSS escapes, so that "\3c script\3e" and "\00003Cscript>" become
+// "<script>": a backslash followed by up to 6 hex digits is the code point
+// they name, with a single whitespace after them ending the escape. A
+// backslash before a newline is a line continuation, and is removed along
+// with it, while before any other character it's removed to leave the
+// character itself, e.g. "java\script" is "javascript".
+fn css_decode(value: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut i = 0;
+
+    while i < value.len() {
+        if value[i] != b'\\' {
+            decoded.push(value[i]);
+            i += 1;
+            continue;
+        }
+
+        let digits_length = value[i + 1..]
+            .iter()
+            .take(6)
+            .take_while(|byte| byte.is_ascii_hexdigit())
+            .count();
+        if digits_length > 0 {
+            let code_point = parse_hex(&value[i + 1..i + 1 + digits_length]).unwrap_or_default();
+            push_code_point(&mut decoded, code_point);
+            i += 1 + digits_length;
+            if matches!(value.get(i), Some(b' ' | b'\t' | b'\n' | b'\r' | b'\x0c')) {
+                i += 1;
+            }
+            continue;
+        }
+
+        match value.get(i + 1) {
+            Some(b'\n') | None => {}
+            Some(&byte) => decoded.push(byte),
+        }
+        i += 2;
+    }
+
+    decoded
+}
+
+// Decodes JavaScript string escapes, so that "\x3cscript\u003e" becomes
+// "<script>": \xHH, \uHHHH and \u{H...} as the code point they name, octal
+// escapes of up to 3 digits (\74), and the single character escapes (\n, \t,
+// etc.). A backslash before any other character is removed to leave the
+// character itself, as in JavaScript, while \x and \u escapes without valid
+// hex digits are left as they are.
+fn js_decode(value: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut i = 0;
+
+    while i < value.len() {
+        if value[i] != b'\\' {
+            decoded.push(value[i]);
+            i += 1;
+            continue;
+        }
+
+        let escape = &value[i + 1..];
+        let (code_point, length) = match escape {
+            [b'x', rest @ ..] => match rest.get(..2).and_then(parse_hex) {
+                Some(code_point) => (code_point, 4),
+                None => (u32::from(b'\\'), 1),
+            },
+            [b'u', b'{', rest @ ..] => {
+                let digits_length = rest.iter().position(|&byte| byte == b'}');
+                match digits_length
+                    .filter(|&length| (1..=6).contains(&length))
+                    .and_then(|length| parse_hex(&rest[..length]).map(|code| (code, length)))
+                {
+                    Some((code_point, digits_length)) => (code_point, digits_length + 4),
+                    None => (u32::from(b'\\'), 1),
+                }
+            }
+            [b'u', rest @ ..] => match rest.get(..4).and_then(parse_hex) {
+                Some(code_point) => (code_point, 6),
+                None => (u32::from(b'\\'), 1),
+            },
+            [first @ b'0'..=b'7', ..] => {
+                // three digits only fit in a byte when the first is at most 3
+                let max_digits = if *first <= b'3' { 3 } else { 2 };
+                let digits_length = escape
+                    .iter()
+                    .take(max_digits)
+                    .take_while(|byte| (b'0'..=b'7').contains(*byte))
+                    .count();
+                let code_point = escape[..digits_length].iter().fold(0, |code_point, digit| {
+                    code_point * 8 + u32::from(digit - b'0')
+                });
+                (code_point, 1 + digits_length)
+            }
+            [b'b', ..] => (0x08, 2),
+            [b'f', ..] => (0x0c, 2),
+            [b'n', ..] => (u32::from(b'\n'), 2),
+            [b'r', ..] => (u32::from(b'\r'), 2),
+            [b't', ..] => (u32::from(b'\t'), 2),
+            [b'v', ..] => (0x0b, 2),
+            [byte, ..] => {
+                decoded.push(*byte);
+                i += 2;
+                continue;
+            }
+            [] => (u32::from(b'\\'), 1),
+        };
+        push_code_point(&mut decoded, code_point);
+        i += length;
+    }
+
+    decoded
+}
+
+// Code points which aren't characters, such as surrogates, are replaced.
+fn push_code_point(decoded: &mut Vec<u8>, code_point: u32) {
+    let ch = char::from_u32(code_point).unwrap_or(char::REPLACEMENT_CHARACTER);
+    decoded.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+// Resolves "." and ".." segments and collapses repeated slashes, so that
+// "/foo/../admin" and "//admin/./" become "/admin" and "/admin/". A ".." can't
+// climb above the root of an absolute path, while leading ".." segments of a
+// relative path are kept. A path which ends in a directory (with a slash, "."
+// or "..") keeps its trailing slash.
+fn normalize_path(value: &[u8]) -> Vec<u8> {
+    let absolute = value.starts_with(b"/");
+    let mut segments: Vec<&[u8]> = Vec::new();
+    let mut directory = false;
+
+    for segment in value.split(|&byte| byte == b'/') {
+        directory = matches!(segment, b"" | b"." | b"..");
+        match segment {
+            b"" | b"." => {}
+            b".." => match segments.last() {
+                Some(&last) if last != b".." => {
+                    segments.pop();
+                }
+                _ if absolute => {}
+                _ => segments.push(segment),
+            },
+            _ => segments.push(segment),
+        }
+    }
+
+    let mut normalized = Vec::with_capacity(value.len());
+    if absolute {
+        normalized.push(b'/');
+    }
+    normalized.extend_from_slice(&segments.join(&b'/'));
+    if directory && !segments.is_empty() {
+        normalized.push(b'/');
+    }
+    normalized
+}
+
+// Decodes numeric (&#DDD; and &#xHH;) and the common named (&quot; &amp;
+// &lt; &gt; &apos; &nbsp;) HTML entities. Like browsers, the terminating ';'
+// is optional.
+fn html_entity_decode(value: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(position) = rest.iter().position(|&byte| byte == b'&') {
+        decoded.extend_from_slice(&rest[..position]);
+        rest = &rest[position..];
+
+        match decode_entity(rest) {
+            Some((ch, length)) => {
+                decoded.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+                rest = &rest[length..];
+            }
+            None => {
+                decoded.push(b'&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.extend_from_slice(rest);
+    decoded
+}
+
+// Decodes the entity at the start of `value` (which starts with '&'),
+// returning the character and the length of the entity.
+fn decode_entity(value: &[u8]) -> Option<(char, usize)> {
+    let body = &value[1..];
+
+    let (ch, length) = if let Some(number) = body.strip_prefix(b"#") {
+        let (digits, radix, prefix_length) = match number {
+            [b'x' | b'X', hex @ ..] => (hex, 16, 2),
+            _ => (number, 10, 1),
+        };
+        let digits_length = digits
+            .iter()
+            .position(|&byte| !(byte as char).is_digit(radix))
+            .unwrap_or(digits.len());
+        if digits_length == 0 {
+            return None;
+        }
+        let digits = std::str::from_utf8(&digits[..digits_length]).ok()?;
+        let code_point = u32::from_str_radix(digits, radix).ok()?;
+        (
+            char::from_u32(code_point).unwrap_or(char::REPLACEMENT_CHARACTER),
+            1 + prefix_length + digits_length,
+        )
+    } else {
+        let name_length = body
+            .iter()
+            .position(|byte| !byte.is_ascii_alphanumeric())
+            .unwrap_or(body.len());
+        let ch = match body[..name_length].to_ascii_lowercase().as_slice() {
+            b"quot" => '"',
+            b"amp" => '&',
+            b"lt" => '<',
+            b"gt" => '>',
+            b"apos" => '\'',
+            b"nbsp" => '\u{a0}',
+            _ => return None,
+        };
+        (ch, 1 + name_length)
+    };
+
+    match value.get(length) {
+        Some(b';') => Some((ch, length + 1)),
+        _ => Some((ch, length)),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn parse_hex(digits: &[u8]) -> Option<u32> {
+    if !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+}
@@ -5,16 +5,37 @@
 #[derive(Debug, Clone, PartialEq)]
 pub enum ValidationErrors {
     EmptyRule,
-    InvalidFormat { expected: usize, found: usize },
+    MissingVariable,
+    MissingOperator,
+    UnexpectedArgument { found: String },
+    UnterminatedQuote { value: String },
+    MissingArgument { directive: String },
     InvalidDirective { found: String },
     InvalidRuleId { value: String },
+    InvalidRuleIdRange { value: String },
     InvalidPhase { value: String },
     InvalidSeverity { value: String },
     InvalidVariable { value: String },
     InvalidOperator { value: String },
+    InvalidRegex { pattern: String, reason: String },
+    RegexTooComplex { pattern: String, limit: usize },
+    InvalidRedirect { value: String },
+    InvalidNetwork { value: String },
+    InvalidStatus { value: String },
+    InvalidCtl { value: String },
+    UnsupportedCtl { target: String },
+    InvalidFile { path: String, reason: String },
+    InvalidTransformation { value: String },
+    InvalidByteRange { value: String },
+    InvalidMaturity { value: String },
+    InvalidAccuracy { value: String },
+    InvalidSetVar { value: String },
+    InvalidDefaultAction { action: String },
+    MissingDefaultPhase,
     EmptyVariable,
     EmptyOperator,
     EmptyActions,
+    DuplicateRuleId { id: u32 },
 }
 
 impl std::fmt::Display for ValidationErrors {
@@ -23,13 +44,25 @@ impl std::fmt::Display for ValidationErrors {
             ValidationErrors::EmptyRule => {
                 write!(f, "Rule string is empty or contains only whitespace")
             }
-            ValidationErrors::InvalidFormat { expected, found } => {
+            ValidationErrors::MissingVariable => {
+                write!(f, "Invalid rule format: missing variable")
+            }
+            ValidationErrors::MissingOperator => {
+                write!(f, "Invalid rule format: missing operator")
+            }
+            ValidationErrors::UnexpectedArgument { found } => {
                 write!(
                     f,
-                    "Invalid rule format: expected {} parts, found {}",
-                    expected, found
+                    "Invalid rule format: unexpected argument '{}' after the actions",
+                    found
                 )
             }
+            ValidationErrors::UnterminatedQuote { value } => {
+                write!(f, "Invalid rule format: unterminated quote in '{}'", value)
+            }
+            ValidationErrors::MissingArgument { directive } => {
+                write!(f, "Invalid directive: '{}' requires an argument", directive)
+            }
             ValidationErrors::InvalidDirective { found } => {
                 write!(
                     f,
@@ -40,6 +73,13 @@ impl std::fmt::Display for ValidationErrors {
             ValidationErrors::InvalidRuleId { value } => {
                 write!(f, "Invalid rule ID: '{}' is not a valid number", value)
             }
+            ValidationErrors::InvalidRuleIdRange { value } => {
+                write!(
+                    f,
+                    "Invalid rule ID range: '{}' is not a rule ID or range of rule IDs",
+                    value
+                )
+            }
             ValidationErrors::InvalidPhase { value } => {
                 write!(f, "Invalid phase: '{}' is not a valid phase", value)
             }
@@ -56,9 +96,194 @@ impl std::fmt::Display for ValidationErrors {
             ValidationErrors::InvalidOperator { value } => {
                 write!(f, "Invalid operator: '{}' is not a valid operator", value)
             }
+            ValidationErrors::InvalidRegex { pattern, reason } => {
+                write!(
+                    f,
+                    "Invalid regex: '{}' failed to compile: {}",
+                    pattern, reason
+                )
+            }
+            ValidationErrors::RegexTooComplex { pattern, limit } => {
+                write!(
+                    f,
+                    "Regex too complex: '{}' compiles to more than {} bytes",
+                    pattern, limit
+                )
+            }
+            ValidationErrors::InvalidRedirect { value } => {
+                write!(
+                    f,
+                    "Invalid redirect: '{}' is not an absolute http(s) URL or path",
+                    value
+                )
+            }
+            ValidationErrors::InvalidNetwork { value } => {
+                write!(
+                    f,
+                    "Invalid network: '{}' is not a valid IP address or CIDR",
+                    value
+                )
+            }
+            ValidationErrors::InvalidStatus { value } => {
+                write!(f, "Invalid status: '{}' is not a valid HTTP status", value)
+            }
+            ValidationErrors::InvalidCtl { value } => {
+                write!(
+                    f,
+                    "Invalid ctl: '{}' is not of the form ruleEngine=On|Off|DetectionOnly",
+                    value
+                )
+            }
+            ValidationErrors::UnsupportedCtl { target } => {
+                write!(
+                    f,
+                    "Unsupported ctl: '{}' isn't supported, only ruleEngine is",
+                    target
+                )
+            }
+            ValidationErrors::InvalidFile { path, reason } => {
+                write!(f, "Invalid file: '{}' could not be read: {}", path, reason)
+            }
+            ValidationErrors::InvalidTransformation { value } => {
+                write!(
+                    f,
+                    "Invalid transformation: '{}' is not a supported transformation",
+                    value
+                )
+            }
+            ValidationErrors::InvalidByteRange { value } => {
+                write!(
+                    f,
+                    "Invalid byte range: '{}' is not a byte value or range (0-255)",
+                    value
+                )
+            }
+            ValidationErrors::InvalidMaturity { value } => {
+                write!(
+                    f,
+                    "Invalid maturity: '{}' is not a valid maturity (1-9)",
+                    value
+                )
+            }
+            ValidationErrors::InvalidAccuracy { value } => {
+                write!(
+                    f,
+                    "Invalid accuracy: '{}' is not a valid accuracy (1-9)",
+                    value
+                )
+            }
+            ValidationErrors::InvalidSetVar { value } => {
+                write!(
+                    f,
+                    "Invalid setvar: '{}' is not a valid TX variable assignment",
+                    value
+                )
+            }
+            ValidationErrors::InvalidDefaultAction { action } => {
+                write!(
+                    f,
+                    "Invalid SecDefaultAction: '{}' can only be given on a rule",
+                    action
+                )
+            }
+            ValidationErrors::MissingDefaultPhase => {
+                write!(f, "Invalid SecDefaultAction: a phase is required")
+            }
             ValidationErrors::EmptyVariable => write!(f, "Variable cannot be empty"),
             ValidationErrors::EmptyOperator => write!(f, "Operator cannot be empty"),
             ValidationErrors::EmptyActions => write!(f, "Actions cannot be empty"),
+            ValidationErrors::DuplicateRuleId { id } => {
+                write!(f, "Duplicate rule ID: {} is used by more than one rule", id)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadErrors {
+    Io {
+        path: String,
+        reason: String,
+    },
+    InvalidRule {
+        line: usize,
+        error: ValidationErrors,
+    },
+    InvalidJson {
+        reason: String,
+    },
+    InvalidJsonRule {
+        id: u32,
+        error: ValidationErrors,
+    },
+    DuplicateRuleIds {
+        ids: Vec<u32>,
+    },
+    MissingInclude {
+        line: usize,
+        path: String,
+    },
+    IncludeCycle {
+        line: usize,
+        path: String,
+    },
+    InIncludedFile {
+        path: String,
+        error: Box<LoadErrors>,
+    },
+    // every directive which failed, in the order they were loaded
+    Multiple {
+        errors: Vec<LoadErrors>,
+    },
+}
+
+impl std::fmt::Display for LoadErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadErrors::Io { path, reason } => {
+                write!(f, "Failed to read rules file '{}': {}", path, reason)
+            }
+            LoadErrors::InvalidRule { line, error } => {
+                write!(f, "Invalid rule on line {}: {}", line, error)
+            }
+            LoadErrors::InvalidJson { reason } => {
+                write!(f, "Invalid JSON ruleset: {}", reason)
+            }
+            LoadErrors::InvalidJsonRule { id, error } => {
+                write!(f, "Invalid rule {} in JSON ruleset: {}", id, error)
+            }
+            LoadErrors::DuplicateRuleIds { ids } => {
+                let ids = ids
+                    .iter()
+                    .map(u32::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "Duplicate rule IDs: {}", ids)
+            }
+            LoadErrors::MissingInclude { line, path } => {
+                write!(
+                    f,
+                    "Invalid include on line {}: no files match '{}'",
+                    line, path
+                )
+            }
+            LoadErrors::IncludeCycle { line, path } => {
+                write!(
+                    f,
+                    "Invalid include on line {}: '{}' is already being included",
+                    line, path
+                )
+            }
+            LoadErrors::InIncludedFile { path, error } => {
+                write!(f, "In included file '{}': {}", path, error)
+            }
+            LoadErrors::Multiple { errors } => {
+                write!(f, "{} errors loading rules:", errors.len())?;
+                for error in errors {
+                    write!(f, "\n  {}", error)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -1,15 +1,164 @@
+pub mod audit;
+pub mod body;
+pub mod builder;
 mod compatibility;
 pub mod errors;
+pub mod macros;
+pub mod scoring;
+pub mod timing;
+pub mod transaction;
 
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+use std::path::Path;
 use std::sync::Mutex;
 
+use crate::audit::{AuditHook, AuditRecord};
+use crate::body::{ProcessedBody, UploadedFile, parse_urlencoded};
 use crate::compatibility::modsecurity::directives::{
-    Directive,
-    parsers::sec_rule::parse_sec_rule,
-    sec_rule::{Operator, Phase, SecRule, Variable},
+    parsers::{
+        conf::{parse_conf, parse_exclusions},
+        sec_rule::compile_operator,
+    },
+    sec_rule::{CompiledOperator, Operator, Variable, VariableSpec},
 };
-use crate::compatibility::modsecurity::rulesets::{RuleGroup, RuleSet};
+use crate::compatibility::modsecurity::operators::{
+    detect_sqli::detect_sqli, detect_xss::detect_xss,
+    validate_utf8_encoding::validate_utf8_encoding,
+};
+use crate::compatibility::modsecurity::rulesets::index::RulePosition;
+use crate::compatibility::modsecurity::rulesets::{
+    RuleGroup, denylist::HeaderDenylist, duplicate_rule_ids, index::RuleIndex, iter_rules,
+    lookup::RuleLookup, merge_rule_groups, remove_rules,
+};
+use crate::compatibility::modsecurity::transformations::{
+    apply_transformations, transformation_stages,
+};
+use crate::errors::{LoadErrors, ValidationErrors};
+use crate::macros::expand_macros;
+use crate::timing::{Clock, RuleTiming, RuleTimings};
+use crate::transaction::Transaction;
+
+pub use crate::compatibility::modsecurity::directives::Directive;
+pub use crate::compatibility::modsecurity::directives::parsers::conf::SkippedDirective;
+pub use crate::compatibility::modsecurity::directives::parsers::sec_rule::ParseOptions;
+pub use crate::compatibility::modsecurity::directives::rule_exclusion::RuleExclusion;
+pub use crate::compatibility::modsecurity::directives::sec_rule::{
+    Ctl, DisruptiveAction, Phase, RuleEngineMode, SecRule, Severity,
+};
+pub use crate::compatibility::modsecurity::operators::ip_match::IpMatchSet;
+pub use crate::compatibility::modsecurity::rulesets::RuleSet;
+
+// -----------------------------------------------------------------------------
+// Signature-Based Detection Engine - Mode
+// -----------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EngineMode {
+    // Block the request on the first matching rule.
+    #[default]
+    FirstMatch,
+    // Sum the severity-derived scores of every matching rule across the
+    // request (see scoring::AnomalyScorer), and only block once the total
+    // reaches the threshold. This is how the OWASP CRS avoids blocking on a
+    // single false positive.
+    AnomalyScore {
+        threshold: u32,
+    },
+}
+
+// -----------------------------------------------------------------------------
+// Signature-Based Detection Engine - Match Result
+// -----------------------------------------------------------------------------
+
+// A matched rule, along with the variable it matched (e.g. "ARGS:search" or
+// "REQUEST_HEADERS:User-Agent") and the value of that variable before the
+// rule's transformations were applied.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchResult {
+    pub rule: SecRule,
+    pub matched_var: String,
+    pub matched_value: String,
+    // the whole match and capture groups of an @rx operator, when the rule has
+    // the capture action, which are stored in TX.0 to TX.9
+    pub captures: Vec<String>,
+    // the number of times the operator matched the value, which is only ever
+    // more than 1 for @rxGlobal
+    pub match_count: usize,
+}
+
+const MAX_CAPTURES: usize = 10;
+
+const EXAMPLE_RULES: &str = r#"
+# curl -H "User-Agent: malicious-bot" http://127.0.0.1
+SecRule REQUEST_HEADERS:User-Agent \
+    "@contains bot" \
+    "id:1001,\
+    phase:1,\
+    deny,\
+    msg:'bot detected',\
+    severity:3,\
+    tag:'attack/bot'"
+
+# curl "http://127.0.0.1/test?search=<script>alert('xss')</script>"
+# curl "http://127.0.0.1/test?search=%3Cscript%3E" (URL-encoded XSS)
+SecRule ARGS \
+    "@detectXSS" \
+    "id:1002,\
+    phase:2,\
+    deny,\
+    t:urlDecode,\
+    t:htmlEntityDecode,\
+    msg:'XSS attempt detected',\
+    severity:2,\
+    tag:'attack/xss'"
+
+# curl -X POST -H "Content-Type: application/json" -d '{"user": "Robert');DROP TABLE users;--"}' http://127.0.0.1
+SecRule REQUEST_BODY \
+    "@contains DROP TABLE" \
+    "id:1003,\
+    phase:2,\
+    deny,\
+    msg:'SQL injection attempt detected in request body',\
+    severity:2,\
+    tag:'attack/sqli'"
+"#;
+
+// the phases whose ARGS and QUERY_STRING rules are evaluated by the args phase
+const ARGS_PHASES: [Phase; 2] = [Phase::RequestHeaders, Phase::RequestBody];
+
+// the variables evaluated by each phase, which must match those handled by the
+// corresponding check_rule_against_* function
+const HEADER_VARIABLES: [Variable; 7] = [
+    Variable::RequestHeaders,
+    Variable::RequestCookies,
+    Variable::RequestMethod,
+    Variable::RequestFilename,
+    Variable::RequestBasename,
+    Variable::RemoteAddr,
+    Variable::Tx,
+];
+const ARGS_VARIABLES: [Variable; 2] = [Variable::Args, Variable::QueryString];
+const BODY_VARIABLES: [Variable; 8] = [
+    Variable::RequestBody,
+    Variable::Args,
+    Variable::Files,
+    Variable::FilesNames,
+    Variable::FilesSizes,
+    Variable::ReqbodyError,
+    Variable::ReqbodyErrorMsg,
+    Variable::Tx,
+];
+const RESPONSE_HEADER_VARIABLES: [Variable; 3] = [
+    Variable::ResponseStatus,
+    Variable::ResponseHeaders,
+    Variable::Tx,
+];
+const RESPONSE_BODY_VARIABLES: [Variable; 2] = [Variable::ResponseBody, Variable::Tx];
+const LOGGING_VARIABLES: [Variable; 3] =
+    [Variable::ResponseStatus, Variable::RemoteAddr, Variable::Tx];
 
 // -----------------------------------------------------------------------------
 // Signature-Based Detection Engine
@@ -18,217 +167,1019 @@ use crate::compatibility::modsecurity::rulesets::{RuleGroup, RuleSet};
 #[derive(Debug)]
 pub struct SignatureBasedDetectionEngine {
     pub counter: Mutex<u64>,
+    pub mode: EngineMode,
     pub rule_group: RuleGroup,
+    audit_hook: Option<AuditHook>,
+    // rules tagged with a higher paranoia level are skipped, see
+    // set_paranoia_level
+    paranoia_level: Option<u8>,
+    // rules less severe than this are skipped, see set_min_severity
+    min_severity: Option<Severity>,
+    // the most arguments evaluated per request, see set_max_args
+    max_args: Option<usize>,
+    // built from the rule group when the engine is created, so changes made
+    // to the rule group afterwards aren't seen by it
+    rule_index: Option<RuleIndex>,
+    // built along with the rule index, see HeaderDenylist
+    header_denylist: HeaderDenylist,
+    // built along with the rule index, see get_rule_by_id and rules_by_tag
+    rule_lookup: RuleLookup,
+    // the directives skipped when the rules were loaded from a rules file
+    skipped_directives: Vec<SkippedDirective>,
+    // only present when enabled, see with_rule_timings
+    rule_timings: Option<RuleTimings>,
 }
 
 impl SignatureBasedDetectionEngine {
     pub fn new(rule_group: RuleGroup) -> Self {
         Self {
+            rule_index: Some(RuleIndex::new(&rule_group)),
+            header_denylist: HeaderDenylist::new(&rule_group),
+            rule_lookup: RuleLookup::new(&rule_group),
             rule_group,
+            mode: EngineMode::default(),
             counter: Mutex::new(0),
+            audit_hook: None,
+            paranoia_level: None,
+            min_severity: None,
+            max_args: None,
+            skipped_directives: Vec::new(),
+            rule_timings: None,
         }
     }
 
-    // some example rules, for testing purposes
-    pub fn new_example() -> Self {
-        // curl -H "User-Agent: malicious-bot" http://127.0.0.1
-        let rule1 = r#"SecRule REQUEST_HEADERS:User-Agent \
-        "@contains bot" \
-        "id:1001,\
-        phase:1,\
-        deny,\
-        msg:'bot detected',\
-        severity:3,\
-        tag:'attack/bot'""#;
-
-        // curl -X POST -d "input=<script>alert('xss')</script>" http://127.0.0.1
-        let rule2 = r#"SecRule ARGS \
-        "@contains <script" \
-        "id:1002,\
-        phase:2,\
-        deny,\
-        msg:'XSS attempt detected',\
-        severity:2,\
-        tag:'attack/xss'""#;
-
-        // curl "http://127.0.0.1/test?search=%3Cscript%3E" (URL-encoded XSS)
-        let rule2b = r#"SecRule ARGS \
-        "@contains %3Cscript" \
-        "id:1004,\
-        phase:2,\
-        deny,\
-        msg:'URL-encoded XSS attempt detected',\
-        severity:2,\
-        tag:'attack/xss'""#;
-
-        // curl -X POST -H "Content-Type: application/json" -d '{"user": "Robert');DROP TABLE users;--"}' http://127.0.0.1
-        let rule3 = r#"SecRule REQUEST_BODY \
-        "@contains DROP TABLE" \
-        "id:1003,\
-        phase:2,\
-        deny,\
-        msg:'SQL injection attempt detected in request body',\
-        severity:2,\
-        tag:'attack/sqli'""#;
-
-        let sec_rule_header_1 = parse_sec_rule(rule1.to_string()).unwrap();
-        let sec_rule_body_1 = parse_sec_rule(rule2.to_string()).unwrap();
-        let sec_rule_body_1b = parse_sec_rule(rule2b.to_string()).unwrap();
-        let sec_rule_body_2 = parse_sec_rule(rule3.to_string()).unwrap();
-
-        let mut rule_group = HashMap::new();
-
-        let phase1_directives = vec![Directive::SecRule(sec_rule_header_1)];
-        let phase1_ruleset = RuleSet::new(
-            "Request Header Phase Rules".to_string(),
-            "Request header processing rules".to_string(),
-            "0.0.1".to_string(),
-            phase1_directives,
-        );
-        rule_group.insert(Phase::RequestHeaders, vec![phase1_ruleset]);
-
-        let phase2_directives = vec![
-            Directive::SecRule(sec_rule_body_1),
-            Directive::SecRule(sec_rule_body_1b),
-            Directive::SecRule(sec_rule_body_2),
-        ];
-        let phase2_ruleset = RuleSet::new(
-            "Request Body Phase Rules".to_string(),
-            "Request body processing rules".to_string(),
-            "0.0.1".to_string(),
-            phase2_directives,
-        );
-        rule_group.insert(Phase::RequestBody, vec![phase2_ruleset]);
+    pub fn with_mode(mut self, mode: EngineMode) -> Self {
+        self.mode = mode;
+        self
+    }
 
-        Self {
-            rule_group,
-            counter: Mutex::new(0),
+    // Evaluates every rule of a phase in order instead of only the rules
+    // indexed under the phase's variables, which is the baseline the index is
+    // benchmarked against.
+    pub fn without_rule_index(mut self) -> Self {
+        self.rule_index = None;
+        self
+    }
+
+    // Records how long each rule takes to evaluate, to find the rules which
+    // dominate latency (see take_slowest_rules). The clock is provided by the
+    // caller, as the WASM host has no std::time::Instant; elsewhere use
+    // timing::monotonic_clock. Without this the clock is never read.
+    pub fn with_rule_timings(mut self, clock: Clock) -> Self {
+        self.rule_timings = Some(RuleTimings::new(clock));
+        self
+    }
+
+    // The given number of rules which took the most time to evaluate since
+    // the last call, slowest first, or nothing if rule timings aren't enabled.
+    pub fn take_slowest_rules(&self, count: usize) -> Vec<RuleTiming> {
+        match &self.rule_timings {
+            Some(rule_timings) => rule_timings.take_slowest(count),
+            None => Vec::new(),
+        }
+    }
+
+    // Skips rules tagged with a paranoia level above the given level (e.g.
+    // "paranoia-level/3" at level 2), which is how the OWASP CRS trades
+    // detection coverage for fewer false positives. Rules without a paranoia
+    // level tag always run, and every rule runs until a level is set.
+    pub fn set_paranoia_level(&mut self, paranoia_level: u8) {
+        self.paranoia_level = Some(paranoia_level);
+    }
+
+    // Skips rules which are less severe than the given severity, e.g. at
+    // Severity::Critical only Critical, Alert and Emergency rules run. Note
+    // that "less severe" means a greater severity number, as ModSecurity
+    // numbers severities from 0 (Emergency) down to 7 (Debug), see Severity.
+    // Rules without a severity always run, and every rule runs until a
+    // minimum is set.
+    pub fn set_min_severity(&mut self, min_severity: Severity) {
+        self.min_severity = Some(min_severity);
+    }
+
+    // Caps the number of a request's arguments which are evaluated, of its
+    // query string and then its body, so that a request with tens of
+    // thousands of arguments can't make every ARGS rule do as much work, like
+    // the CRS's tx.max_num_args. The arguments past the maximum aren't parsed
+    // (for the query string) or evaluated, and the transaction's
+    // too_many_args is set, for the caller to block the request if that's its
+    // policy. Until a maximum is set every argument is evaluated.
+    pub fn set_max_args(&mut self, max_args: usize) {
+        self.max_args = Some(max_args);
+    }
+
+    // Registers a hook which is called with an audit record whenever a rule
+    // fires, e.g. to write a structured audit log.
+    pub fn with_audit_hook(mut self, hook: impl Fn(&AuditRecord) + Send + Sync + 'static) -> Self {
+        self.audit_hook = Some(AuditHook::new(hook));
+        self
+    }
+
+    // Loads the rules from a ModSecurity rules file, along with the files it
+    // includes (Include, IncludeOptional). Files referenced by the rules
+    // (e.g. @ipMatchFromFile) are resolved relative to the directory of the
+    // rules file and read immediately, so missing files are reported here
+    // rather than when requests are processed.
+    pub fn from_conf_file(path: impl AsRef<Path>) -> Result<Self, LoadErrors> {
+        Self::from_conf_file_with_options(path, ParseOptions::default())
+    }
+
+    pub fn from_conf_file_with_options(
+        path: impl AsRef<Path>,
+        options: ParseOptions,
+    ) -> Result<Self, LoadErrors> {
+        let path = path.as_ref();
+        let conf = std::fs::read_to_string(path).map_err(|e| LoadErrors::Io {
+            path: path.display().to_string(),
+            reason: e.to_string(),
+        })?;
+        let (rule_group, skipped_directives) = parse_conf(&conf, Some(path), options)?;
+        check_unique_ids(&rule_group)?;
+        Ok(Self::new(rule_group).with_skipped_directives(skipped_directives))
+    }
+
+    // Loads the rules from the contents of a ModSecurity rules file. Relative
+    // file arguments are resolved against the working directory.
+    pub fn from_conf_str(conf: &str) -> Result<Self, LoadErrors> {
+        Self::from_conf_str_with_options(conf, ParseOptions::default())
+    }
+
+    pub fn from_conf_str_with_options(
+        conf: &str,
+        options: ParseOptions,
+    ) -> Result<Self, LoadErrors> {
+        let (rule_group, skipped_directives) = parse_conf(conf, None, options)?;
+        check_unique_ids(&rule_group)?;
+        Ok(Self::new(rule_group).with_skipped_directives(skipped_directives))
+    }
+
+    fn with_skipped_directives(mut self, skipped_directives: Vec<SkippedDirective>) -> Self {
+        self.skipped_directives = skipped_directives;
+        self
+    }
+
+    // The directives which were recognized but skipped when the rules were
+    // loaded (e.g. SecComponentSignature), which have no effect on the engine
+    // and are worth logging so that it's clear they didn't take effect.
+    pub fn skipped_directives(&self) -> &[SkippedDirective] {
+        &self.skipped_directives
+    }
+
+    // Loads rules which were previously serialized with to_json, which is
+    // cheaper than parsing SecRule directives. Compiled operator arguments
+    // aren't serialized, so they are rebuilt here, and @ipMatchFromFile files
+    // are read again relative to the working directory.
+    pub fn from_json(json: &str) -> Result<Self, LoadErrors> {
+        let mut rule_group: RuleGroup =
+            serde_json::from_str(json).map_err(|e| LoadErrors::InvalidJson {
+                reason: e.to_string(),
+            })?;
+
+        for ruleset in rule_group.values_mut().flatten() {
+            for directive in &mut ruleset.directives {
+                if let Directive::SecRule(sec_rule) = directive {
+                    sec_rule.compiled_operator = compile_operator(
+                        &sec_rule.operator,
+                        sec_rule.operator_target.as_deref(),
+                        None,
+                        ParseOptions::default(),
+                    )
+                    .map_err(|error| LoadErrors::InvalidJsonRule {
+                        id: sec_rule.id,
+                        error,
+                    })?;
+                }
+            }
+        }
+
+        check_unique_ids(&rule_group)?;
+        Ok(Self::new(rule_group))
+    }
+
+    // Checks that no two rules share an ID. Rules files and JSON rulesets are
+    // checked when they're loaded, while engines built from a rule group (e.g.
+    // with the EngineBuilder) aren't, so this is for those.
+    pub fn validate_unique_ids(&self) -> Result<(), ValidationErrors> {
+        match duplicate_rule_ids(&self.rule_group).first() {
+            Some(id) => Err(ValidationErrors::DuplicateRuleId { id: *id }),
+            None => Ok(()),
+        }
+    }
+
+    // Applies rule exclusions (SecRuleRemoveById, SecRuleRemoveByTag) from a
+    // separate file after the base rules are loaded, which is how noisy rules
+    // of a vendor ruleset are disabled without editing the vendor's files.
+    // Returns the number of rules removed.
+    pub fn apply_exclusions_file(&mut self, path: impl AsRef<Path>) -> Result<usize, LoadErrors> {
+        let path = path.as_ref();
+        let conf = std::fs::read_to_string(path).map_err(|e| LoadErrors::Io {
+            path: path.display().to_string(),
+            reason: e.to_string(),
+        })?;
+        self.apply_exclusions(&conf)
+    }
+
+    pub fn apply_exclusions(&mut self, conf: &str) -> Result<usize, LoadErrors> {
+        let exclusions = parse_exclusions(conf)?;
+        Ok(exclusions
+            .iter()
+            .map(|exclusion| self.remove_rules(exclusion))
+            .sum())
+    }
+
+    // Removes the rules matched by an exclusion from every phase, returning
+    // the number of rules removed.
+    pub fn remove_rules(&mut self, exclusion: &RuleExclusion) -> usize {
+        let removed = remove_rules(&mut self.rule_group, exclusion);
+        // the positions of the remaining rules have moved
+        if removed > 0 {
+            self.rebuild_rule_index();
         }
+        removed
+    }
+
+    // Appends the rules of another engine after this engine's own, phase by
+    // phase, e.g. to layer local rules over a vendor ruleset which was loaded
+    // separately, rather than concatenating their rules files. This engine's
+    // mode, paranoia level and hooks are kept. Fails with the IDs used by both
+    // engines, leaving this engine unchanged.
+    pub fn extend(&mut self, other: SignatureBasedDetectionEngine) -> Result<(), LoadErrors> {
+        merge_rule_groups(&mut self.rule_group, other.rule_group)
+            .map_err(|ids| LoadErrors::DuplicateRuleIds { ids })?;
+        self.skipped_directives.extend(other.skipped_directives);
+        self.rebuild_rule_index();
+        Ok(())
+    }
+
+    fn rebuild_rule_index(&mut self) {
+        if self.rule_index.is_some() {
+            self.rule_index = Some(RuleIndex::new(&self.rule_group));
+        }
+        self.header_denylist = HeaderDenylist::new(&self.rule_group);
+        self.rule_lookup = RuleLookup::new(&self.rule_group);
+    }
+
+    // Every rule of the engine along with its phase, in phase order and then in
+    // the order they're evaluated, e.g. to lint or document the rules. The
+    // rules continuing a chain are included, after the rule starting it.
+    pub fn iter_rules(&self) -> impl Iterator<Item = (Phase, &SecRule)> {
+        iter_rules(&self.rule_group)
+    }
+
+    // The number of rules of the engine, counted as iter_rules does.
+    pub fn rule_count(&self) -> usize {
+        self.iter_rules().count()
+    }
+
+    // The rule with the given ID, e.g. to show rule 942100 while debugging.
+    // Like the rule index, the lookups are built when the engine is created,
+    // so changes made to rule_group directly afterwards aren't seen by them.
+    pub fn get_rule_by_id(&self, id: u32) -> Option<&SecRule> {
+        self.rule_lookup.by_id(&self.rule_group, id)
     }
 
+    // The rules with IDs in the range, in order of ID, e.g. 942000..=942999
+    // for the CRS's SQL injection rules.
+    pub fn rules_by_id_range(&self, ids: RangeInclusive<u32>) -> Vec<&SecRule> {
+        self.rule_lookup.by_id_range(&self.rule_group, ids)
+    }
+
+    // The rules with exactly the given tag (e.g. "attack/xss"), in the order
+    // of iter_rules.
+    pub fn rules_by_tag(&self, tag: &str) -> Vec<&SecRule> {
+        self.rule_lookup.by_tag(&self.rule_group, tag)
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string(&self.rule_group).map_err(|e| e.to_string())
+    }
+
+    // some example rules, for testing purposes, see EXAMPLE_RULES
+    pub fn new_example() -> Result<Self, LoadErrors> {
+        Self::from_conf_str(EXAMPLE_RULES)
+    }
+
+    // Each phase of a request is evaluated against the same transaction, which
+    // holds the request's state (e.g. its TX collection) between phases.
+    //
+    // A matched pass rule fires and evaluation continues, while any other
+    // matched rule ends the phase.
+    //
+    // A rule which fires with ctl:ruleEngine changes how the rest of the
+    // transaction is evaluated: with Off no more rules are evaluated, and with
+    // DetectionOnly matched rules still fire but no longer block the request,
+    // so evaluation continues past them.
     pub fn run_header_phase(
         &self,
+        transaction: &mut Transaction,
         headers: Vec<(String, String)>,
-    ) -> Result<Option<SecRule>, String> {
-        let header_rulesets = match self.rule_group.get(&Phase::RequestHeaders) {
-            Some(rulesets) => rulesets,
-            None => return Ok(None),
-        };
+    ) -> Result<Option<MatchResult>, String> {
+        let denylisted = self.header_denylist.matches(&headers);
+        for chained_rule in self.phase_rules(Phase::RequestHeaders, &HEADER_VARIABLES) {
+            if transaction.rule_engine == Some(RuleEngineMode::Off) {
+                break;
+            }
+            let sec_rule = chained_rule.sec_rule;
+            if let Some(chain_match) = self.time_rule(sec_rule, || {
+                chained_rule.check(|position, sec_rule| {
+                    self.check_header_rule(position, sec_rule, transaction, &headers, &denylisted)
+                })
+            })? {
+                let match_result = self.rule_fired(transaction, chain_match);
+                if ends_phase(sec_rule, transaction) {
+                    return Ok(block_unless_allowed(match_result));
+                }
+            }
+        }
+
+        Ok(None)
+    }
 
-        for ruleset in header_rulesets {
-            if let Some(matched_rule) = check_ruleset_against_headers(ruleset, &headers)? {
-                return Ok(Some(matched_rule));
+    // The query string is available from the start of the request, so ARGS
+    // and QUERY_STRING rules are evaluated whether they were declared in
+    // phase 1 or phase 2.
+    pub fn run_args_phase(
+        &self,
+        transaction: &mut Transaction,
+        query_string: &str,
+    ) -> Result<Option<MatchResult>, String> {
+        let args = self.query_args(transaction, query_string);
+        for chained_rule in self.args_rules() {
+            if transaction.rule_engine == Some(RuleEngineMode::Off) {
+                break;
+            }
+            let sec_rule = chained_rule.sec_rule;
+            if let Some(chain_match) = self.time_rule(sec_rule, || {
+                chained_rule
+                    .check(|_, sec_rule| check_rule_against_args(sec_rule, query_string, &args))
+            })? {
+                let match_result = self.rule_fired(transaction, chain_match);
+                if ends_phase(sec_rule, transaction) {
+                    return Ok(block_unless_allowed(match_result));
+                }
             }
         }
 
         Ok(None)
     }
 
-    pub fn run_args_phase(&self, query_string: &str) -> Result<Option<SecRule>, String> {
-        let header_rulesets = match self.rule_group.get(&Phase::RequestBody) {
-            Some(rulesets) => rulesets,
-            None => return Ok(None),
-        };
+    pub fn run_body_phase(
+        &self,
+        transaction: &mut Transaction,
+        body: &str,
+    ) -> Result<Option<MatchResult>, String> {
+        self.run_body_phase_bytes(transaction, body.as_bytes())
+    }
 
-        for ruleset in header_rulesets {
-            if let Some(matched_rule) = check_ruleset_against_args(ruleset, query_string)? {
-                return Ok(Some(matched_rule));
+    // The body is evaluated as the raw bytes received from the client, so
+    // that invalid UTF-8 reaches byte-oriented operators such as
+    // @validateUtf8Encoding instead of being replaced before they see it. The
+    // body isn't parsed into arguments, see run_processed_body_phase.
+    pub fn run_body_phase_bytes(
+        &self,
+        transaction: &mut Transaction,
+        body: &[u8],
+    ) -> Result<Option<MatchResult>, String> {
+        self.run_processed_body_phase(transaction, &ProcessedBody::raw(body))
+    }
+
+    // Evaluates a body which was parsed by its body processor (see
+    // body::process_body), where REQUEST_BODY is the raw body and the phase 2
+    // rules selecting ARGS see the arguments parsed from the body. The query
+    // string's arguments are evaluated by the args phase instead.
+    pub fn run_processed_body_phase(
+        &self,
+        transaction: &mut Transaction,
+        body: &ProcessedBody,
+    ) -> Result<Option<MatchResult>, String> {
+        let args = self.body_args(transaction, body);
+        for chained_rule in self.phase_rules(Phase::RequestBody, &BODY_VARIABLES) {
+            if transaction.rule_engine == Some(RuleEngineMode::Off) {
+                break;
+            }
+            let sec_rule = chained_rule.sec_rule;
+            if let Some(chain_match) = self.time_rule(sec_rule, || {
+                chained_rule
+                    .check(|_, sec_rule| check_rule_against_body(sec_rule, transaction, body, args))
+            })? {
+                let match_result = self.rule_fired(transaction, chain_match);
+                if ends_phase(sec_rule, transaction) {
+                    return Ok(block_unless_allowed(match_result));
+                }
             }
         }
 
         Ok(None)
     }
 
-    pub fn run_body_phase(&self, body: &str) -> Result<Option<SecRule>, String> {
-        let body_rulesets = match self.rule_group.get(&Phase::RequestBody) {
-            Some(rulesets) => rulesets,
-            None => return Ok(None),
-        };
+    // Evaluates the phase 3 rules against the backend's response status and
+    // headers. The request has already been forwarded by then, so blocking in
+    // this phase replaces the response rather than the request.
+    pub fn run_response_header_phase(
+        &self,
+        transaction: &mut Transaction,
+        status: u32,
+        headers: Vec<(String, String)>,
+    ) -> Result<Option<MatchResult>, String> {
+        transaction.response_status = Some(status);
+        for chained_rule in self.phase_rules(Phase::ResponseHeaders, &RESPONSE_HEADER_VARIABLES) {
+            if transaction.rule_engine == Some(RuleEngineMode::Off) {
+                break;
+            }
+            let sec_rule = chained_rule.sec_rule;
+            if let Some(chain_match) = self.time_rule(sec_rule, || {
+                chained_rule.check(|_, sec_rule| {
+                    check_rule_against_response_headers(sec_rule, transaction, status, &headers)
+                })
+            })? {
+                let match_result = self.rule_fired(transaction, chain_match);
+                if ends_phase(sec_rule, transaction) {
+                    return Ok(block_unless_allowed(match_result));
+                }
+            }
+        }
+
+        Ok(None)
+    }
 
-        for ruleset in body_rulesets {
-            if let Some(matched_rule) = check_ruleset_against_body(ruleset, body)? {
-                return Ok(Some(matched_rule));
+    // Evaluates the phase 4 rules against the backend's response body, e.g.
+    // to catch leaked secrets or error pages. As with the request body, the
+    // body is evaluated as raw bytes, and only as much of it as was buffered.
+    pub fn run_response_body_phase(
+        &self,
+        transaction: &mut Transaction,
+        body: &[u8],
+    ) -> Result<Option<MatchResult>, String> {
+        for chained_rule in self.phase_rules(Phase::ResponseBody, &RESPONSE_BODY_VARIABLES) {
+            if transaction.rule_engine == Some(RuleEngineMode::Off) {
+                break;
+            }
+            let sec_rule = chained_rule.sec_rule;
+            if let Some(chain_match) = self.time_rule(sec_rule, || {
+                chained_rule.check(|_, sec_rule| {
+                    check_rule_against_response_body(sec_rule, transaction, body)
+                })
+            })? {
+                let match_result = self.rule_fired(transaction, chain_match);
+                if ends_phase(sec_rule, transaction) {
+                    return Ok(block_unless_allowed(match_result));
+                }
             }
         }
 
         Ok(None)
     }
+
+    // Evaluates the phase 5 rules once the transaction is complete, whether or
+    // not it was blocked, e.g. to write a summary of the anomaly score. The
+    // request and response themselves are gone by then, so the rules only see
+    // the state kept in the transaction: TX, REMOTE_ADDR, and RESPONSE_STATUS
+    // if the response headers were evaluated. The transaction's blocked flag
+    // holds the final disposition.
+    //
+    // Nothing can be blocked at this point, so every rule is evaluated and
+    // the matched rules are returned, for their audit records.
+    pub fn run_logging_phase(
+        &self,
+        transaction: &mut Transaction,
+    ) -> Result<Vec<MatchResult>, String> {
+        let mut matched_rules = Vec::new();
+        for chained_rule in self.phase_rules(Phase::Logging, &LOGGING_VARIABLES) {
+            if transaction.rule_engine == Some(RuleEngineMode::Off) {
+                break;
+            }
+            let sec_rule = chained_rule.sec_rule;
+            if let Some(chain_match) = self.time_rule(sec_rule, || {
+                chained_rule
+                    .check(|_, sec_rule| check_rule_against_transaction(sec_rule, transaction))
+            })? {
+                let match_result = self.rule_fired(transaction, chain_match);
+                matched_rules.push(match_result);
+            }
+        }
+        Ok(matched_rules)
+    }
+
+    // The run_*_phase_all variants evaluate every rule in the phase instead of
+    // stopping at the first match, and return all of the matched rules so that
+    // they can be fed into an AnomalyScorer. Matched pass rules are included,
+    // as they still contribute to the score, while a matched allow rule ends
+    // the phase with no matches. After ctl:ruleEngine=DetectionOnly the
+    // matched rules are still returned, and it's up to the caller not to
+    // block on them (see Transaction::rule_engine).
+
+    pub fn run_header_phase_all(
+        &self,
+        transaction: &mut Transaction,
+        headers: Vec<(String, String)>,
+    ) -> Result<Vec<MatchResult>, String> {
+        let denylisted = self.header_denylist.matches(&headers);
+        let mut matched_rules = Vec::new();
+        for chained_rule in self.phase_rules(Phase::RequestHeaders, &HEADER_VARIABLES) {
+            if transaction.rule_engine == Some(RuleEngineMode::Off) {
+                break;
+            }
+            let sec_rule = chained_rule.sec_rule;
+            if let Some(chain_match) = self.time_rule(sec_rule, || {
+                chained_rule.check(|position, sec_rule| {
+                    self.check_header_rule(position, sec_rule, transaction, &headers, &denylisted)
+                })
+            })? {
+                let match_result = self.rule_fired(transaction, chain_match);
+                if sec_rule.disruptive_action() == DisruptiveAction::Allow {
+                    return Ok(Vec::new());
+                }
+                matched_rules.push(match_result);
+            }
+        }
+        Ok(matched_rules)
+    }
+
+    pub fn run_args_phase_all(
+        &self,
+        transaction: &mut Transaction,
+        query_string: &str,
+    ) -> Result<Vec<MatchResult>, String> {
+        let args = self.query_args(transaction, query_string);
+        let mut matched_rules = Vec::new();
+        for chained_rule in self.args_rules() {
+            if transaction.rule_engine == Some(RuleEngineMode::Off) {
+                break;
+            }
+            let sec_rule = chained_rule.sec_rule;
+            if let Some(chain_match) = self.time_rule(sec_rule, || {
+                chained_rule
+                    .check(|_, sec_rule| check_rule_against_args(sec_rule, query_string, &args))
+            })? {
+                let match_result = self.rule_fired(transaction, chain_match);
+                if sec_rule.disruptive_action() == DisruptiveAction::Allow {
+                    return Ok(Vec::new());
+                }
+                matched_rules.push(match_result);
+            }
+        }
+        Ok(matched_rules)
+    }
+
+    pub fn run_body_phase_all(
+        &self,
+        transaction: &mut Transaction,
+        body: &str,
+    ) -> Result<Vec<MatchResult>, String> {
+        self.run_body_phase_all_bytes(transaction, body.as_bytes())
+    }
+
+    pub fn run_body_phase_all_bytes(
+        &self,
+        transaction: &mut Transaction,
+        body: &[u8],
+    ) -> Result<Vec<MatchResult>, String> {
+        self.run_processed_body_phase_all(transaction, &ProcessedBody::raw(body))
+    }
+
+    pub fn run_processed_body_phase_all(
+        &self,
+        transaction: &mut Transaction,
+        body: &ProcessedBody,
+    ) -> Result<Vec<MatchResult>, String> {
+        let args = self.body_args(transaction, body);
+        let mut matched_rules = Vec::new();
+        for chained_rule in self.phase_rules(Phase::RequestBody, &BODY_VARIABLES) {
+            if transaction.rule_engine == Some(RuleEngineMode::Off) {
+                break;
+            }
+            let sec_rule = chained_rule.sec_rule;
+            if let Some(chain_match) = self.time_rule(sec_rule, || {
+                chained_rule
+                    .check(|_, sec_rule| check_rule_against_body(sec_rule, transaction, body, args))
+            })? {
+                let match_result = self.rule_fired(transaction, chain_match);
+                if sec_rule.disruptive_action() == DisruptiveAction::Allow {
+                    return Ok(Vec::new());
+                }
+                matched_rules.push(match_result);
+            }
+        }
+        Ok(matched_rules)
+    }
+
+    pub fn run_response_header_phase_all(
+        &self,
+        transaction: &mut Transaction,
+        status: u32,
+        headers: Vec<(String, String)>,
+    ) -> Result<Vec<MatchResult>, String> {
+        transaction.response_status = Some(status);
+        let mut matched_rules = Vec::new();
+        for chained_rule in self.phase_rules(Phase::ResponseHeaders, &RESPONSE_HEADER_VARIABLES) {
+            if transaction.rule_engine == Some(RuleEngineMode::Off) {
+                break;
+            }
+            let sec_rule = chained_rule.sec_rule;
+            if let Some(chain_match) = self.time_rule(sec_rule, || {
+                chained_rule.check(|_, sec_rule| {
+                    check_rule_against_response_headers(sec_rule, transaction, status, &headers)
+                })
+            })? {
+                let match_result = self.rule_fired(transaction, chain_match);
+                if sec_rule.disruptive_action() == DisruptiveAction::Allow {
+                    return Ok(Vec::new());
+                }
+                matched_rules.push(match_result);
+            }
+        }
+        Ok(matched_rules)
+    }
+
+    pub fn run_response_body_phase_all(
+        &self,
+        transaction: &mut Transaction,
+        body: &[u8],
+    ) -> Result<Vec<MatchResult>, String> {
+        let mut matched_rules = Vec::new();
+        for chained_rule in self.phase_rules(Phase::ResponseBody, &RESPONSE_BODY_VARIABLES) {
+            if transaction.rule_engine == Some(RuleEngineMode::Off) {
+                break;
+            }
+            let sec_rule = chained_rule.sec_rule;
+            if let Some(chain_match) = self.time_rule(sec_rule, || {
+                chained_rule.check(|_, sec_rule| {
+                    check_rule_against_response_body(sec_rule, transaction, body)
+                })
+            })? {
+                let match_result = self.rule_fired(transaction, chain_match);
+                if sec_rule.disruptive_action() == DisruptiveAction::Allow {
+                    return Ok(Vec::new());
+                }
+                matched_rules.push(match_result);
+            }
+        }
+        Ok(matched_rules)
+    }
+
+    // The arguments of the query string, up to max_args.
+    fn query_args(
+        &self,
+        transaction: &mut Transaction,
+        query_string: &str,
+    ) -> Vec<(String, Vec<u8>)> {
+        let allowed = self.allowed_args(transaction);
+        let mut args: Vec<_> = parse_urlencoded(query_string.as_bytes())
+            .take(allowed.saturating_add(1))
+            .collect();
+        let count = self.count_args(transaction, args.len());
+        args.truncate(count);
+        args
+    }
+
+    // The arguments of the body, up to what's left of max_args after the
+    // query string's.
+    fn body_args<'a>(
+        &self,
+        transaction: &mut Transaction,
+        body: &'a ProcessedBody,
+    ) -> &'a [(String, Vec<u8>)] {
+        let count = self.count_args(transaction, body.args.len());
+        &body.args[..count]
+    }
+
+    fn allowed_args(&self, transaction: &Transaction) -> usize {
+        self.max_args.map_or(usize::MAX, |max_args| {
+            max_args.saturating_sub(transaction.args_count)
+        })
+    }
+
+    // Counts the arguments which will be evaluated, out of those available,
+    // flagging the transaction when some of them won't be.
+    fn count_args(&self, transaction: &mut Transaction, available: usize) -> usize {
+        let allowed = self.allowed_args(transaction);
+        if available > allowed {
+            transaction.too_many_args = true;
+        }
+        let count = available.min(allowed);
+        transaction.args_count += count;
+        count
+    }
+
+    // The rules of the phase which select any of the variables, in the order
+    // they were declared, leaving out rules above the paranoia level and
+    // below the minimum severity. Without
+    // the index every rule of the phase is returned, and rules for other
+    // variables are skipped when evaluated. The rules continuing a chain are
+    // returned along with the rule starting it rather than on their own.
+    fn phase_rules(&self, phase: Phase, variables: &[Variable]) -> Vec<ChainedRule<'_>> {
+        let rulesets = self
+            .rule_group
+            .get(&phase)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        let positions: Vec<RulePosition> = match &self.rule_index {
+            Some(rule_index) => rule_index.positions(phase, variables),
+            None => rulesets
+                .iter()
+                .enumerate()
+                .flat_map(|(ruleset_index, ruleset)| {
+                    (0..ruleset.directives.len())
+                        .map(move |directive_index| (ruleset_index, directive_index))
+                })
+                .collect(),
+        };
+        let mut phase_rules: Vec<ChainedRule> = positions
+            .into_iter()
+            .filter_map(|(ruleset_index, directive_index)| {
+                ChainedRule::at(
+                    rulesets.get(ruleset_index)?,
+                    (ruleset_index, directive_index),
+                )
+            })
+            .collect();
+        if let Some(paranoia_level) = self.paranoia_level {
+            phase_rules.retain(|chained_rule| {
+                chained_rule
+                    .sec_rule
+                    .paranoia_level()
+                    .is_none_or(|rule_level| rule_level <= paranoia_level)
+            });
+        }
+        if let Some(min_severity) = self.min_severity {
+            phase_rules.retain(|chained_rule| {
+                chained_rule
+                    .sec_rule
+                    .severity
+                    .is_none_or(|severity| severity >= min_severity)
+            });
+        }
+        phase_rules
+    }
+
+    fn args_rules(&self) -> impl Iterator<Item = ChainedRule<'_>> {
+        ARGS_PHASES
+            .iter()
+            .flat_map(|phase| self.phase_rules(*phase, &ARGS_VARIABLES))
+    }
+
+    // Denylisted rules were already looked up by the headers they match, so
+    // only the others are evaluated.
+    fn check_header_rule(
+        &self,
+        position: RulePosition,
+        sec_rule: &SecRule,
+        transaction: &Transaction,
+        headers: &[(String, String)],
+        denylisted: &HashMap<RulePosition, &(String, String)>,
+    ) -> Result<Option<MatchResult>, String> {
+        if !self.header_denylist.contains(position) {
+            return check_rule_against_headers(sec_rule, transaction, headers);
+        }
+        Ok(denylisted.get(&position).map(|(name, value)| MatchResult {
+            rule: sec_rule.clone(),
+            matched_var: format!("{}:{}", Variable::RequestHeaders.name(), name),
+            matched_value: value.clone(),
+            captures: Vec::new(),
+            match_count: 1,
+        }))
+    }
+
+    // Evaluates a rule, recording how long it took when rule timings are
+    // enabled.
+    fn time_rule<T>(&self, sec_rule: &SecRule, evaluate: impl FnOnce() -> T) -> T {
+        match &self.rule_timings {
+            Some(rule_timings) => rule_timings.time(sec_rule.id, evaluate),
+            None => evaluate(),
+        }
+    }
+
+    // Runs the actions of a matched rule, and of the rules continuing its
+    // chain, which take effect whether or not it blocks the request, and
+    // audits the match unless the rule opted out. Returns the match result of
+    // the rule starting the chain, as its disruptive action is the chain's.
+    fn rule_fired(&self, transaction: &mut Transaction, chain_match: ChainMatch) -> MatchResult {
+        let ChainMatch {
+            match_result,
+            chain,
+        } = chain_match;
+        for match_result in std::iter::once(&match_result).chain(&chain) {
+            run_actions(transaction, match_result);
+        }
+        if let Some(audit_hook) = &self.audit_hook
+            && match_result.rule.audit_logs()
+        {
+            audit_hook.emit(&AuditRecord::new(&match_result, transaction));
+        }
+        match_result
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Signature-Based Detection Engine - Rule Matching
+// -----------------------------------------------------------------------------
+
+// Evaluates a single rule against a single input without building an engine,
+// so that rule authors can unit test their rules:
+//
+//   let sec_rule = SecRule::try_from(rule.to_string())?;
+//   assert!(sec_rule.matches_args("q=1' OR '1'='1")?);
+//
+// Only the rule's variables which are available in the input are evaluated
+// (e.g. a REQUEST_BODY rule never matches headers), and TX and REMOTE_ADDR
+// are evaluated against an empty transaction. The rule's phase isn't checked.
+impl SecRule {
+    pub fn matches_headers(&self, headers: &[(String, String)]) -> Result<bool, String> {
+        check_rule_against_headers(self, &Transaction::default(), headers)
+            .map(|match_result| match_result.is_some())
+    }
+
+    pub fn matches_args(&self, query_string: &str) -> Result<bool, String> {
+        let args: Vec<_> = parse_urlencoded(query_string.as_bytes()).collect();
+        check_rule_against_args(self, query_string, &args)
+            .map(|match_result| match_result.is_some())
+    }
+
+    pub fn matches_body(&self, body: &[u8]) -> Result<bool, String> {
+        check_rule_against_body(
+            self,
+            &Transaction::default(),
+            &ProcessedBody::raw(body),
+            &[],
+        )
+        .map(|match_result| match_result.is_some())
+    }
 }
 
 // -----------------------------------------------------------------------------
 // Private Helper Functions
 // -----------------------------------------------------------------------------
 
-fn check_ruleset_against_headers(
-    ruleset: &RuleSet,
-    headers: &[(String, String)],
-) -> Result<Option<SecRule>, String> {
-    for directive in &ruleset.directives {
-        if let Directive::SecRule(sec_rule) = directive {
-            if let Some(matched_rule) = check_rule_against_headers(sec_rule, headers)? {
-                return Ok(Some(matched_rule));
-            }
-        }
+// Every duplicate ID is reported, so that they can all be fixed at once.
+fn check_unique_ids(rule_group: &RuleGroup) -> Result<(), LoadErrors> {
+    let ids = duplicate_rule_ids(rule_group);
+    match ids.is_empty() {
+        true => Ok(()),
+        false => Err(LoadErrors::DuplicateRuleIds { ids }),
     }
-    Ok(None)
 }
 
-fn check_rule_against_headers(
-    sec_rule: &SecRule,
-    headers: &[(String, String)],
-) -> Result<Option<SecRule>, String> {
-    if sec_rule.variable != Variable::RequestHeaders {
-        return Ok(None);
+// Whether a matched rule ends the phase, which any rule but a pass rule does,
+// unless a ctl:ruleEngine=DetectionOnly action has stopped rules from
+// blocking the request, in which case only an allow rule does.
+fn ends_phase(sec_rule: &SecRule, transaction: &Transaction) -> bool {
+    match sec_rule.disruptive_action() {
+        DisruptiveAction::Pass => false,
+        DisruptiveAction::Allow => true,
+        _ => transaction.rule_engine != Some(RuleEngineMode::DetectionOnly),
     }
+}
 
-    if sec_rule.operator != Operator::Contains {
-        return Err(format!(
-            "{:?} operator is not yet implemented. rule: {}",
-            sec_rule.operator, sec_rule.id
-        ));
+// A matched allow rule ends the phase without blocking the request.
+fn block_unless_allowed(match_result: MatchResult) -> Option<MatchResult> {
+    match match_result.rule.disruptive_action() {
+        DisruptiveAction::Allow => None,
+        _ => Some(match_result),
     }
+}
 
-    if rule_matches_headers(sec_rule, headers) {
-        Ok(Some(sec_rule.clone()))
-    } else {
-        Ok(None)
+// Runs the actions of a matched rule which don't decide whether the request is
+// blocked.
+fn run_actions(transaction: &mut Transaction, match_result: &MatchResult) {
+    transaction.set_captures(&match_result.captures);
+    for ctl in &match_result.rule.ctls {
+        match ctl {
+            Ctl::RuleEngine(mode) => transaction.rule_engine = Some(*mode),
+        }
+    }
+    for set_var in &match_result.rule.set_vars {
+        let set_var = set_var.map_value(|value| expand_macros(value, transaction, match_result));
+        transaction.apply_set_var(&set_var);
     }
 }
 
-fn rule_matches_headers(sec_rule: &SecRule, headers: &[(String, String)]) -> bool {
-    let variable_target = match &sec_rule.variable_target {
-        Some(target) => target,
-        None => return false,
-    };
+// A rule along with the rules continuing its chain, if it starts one. The
+// rules continuing a chain are only evaluated after the rule starting it has
+// matched, and the chain only fires when every one of its rules matches.
+struct ChainedRule<'a> {
+    position: RulePosition,
+    sec_rule: &'a SecRule,
+    chain: Vec<(RulePosition, &'a SecRule)>,
+}
 
-    let operator_target = match &sec_rule.operator_target {
-        Some(target) => target,
-        None => return false,
-    };
+// The match results of a chain's rules, in order, see ChainedRule::check.
+struct ChainMatch {
+    match_result: MatchResult,
+    chain: Vec<MatchResult>,
+}
 
-    for (name, value) in headers {
-        if name.eq_ignore_ascii_case(variable_target) {
-            if value
-                .to_ascii_lowercase()
-                .contains(&operator_target.to_ascii_lowercase())
-            {
-                return true;
+impl<'a> ChainedRule<'a> {
+    // The rule at the directive index of the ruleset along with its chain,
+    // unless it isn't a rule or continues a chain, as the rules continuing a
+    // chain are stored directly after the rule before them.
+    fn at(ruleset: &'a RuleSet, (ruleset_index, directive_index): RulePosition) -> Option<Self> {
+        let sec_rule = match ruleset.directives.get(directive_index)? {
+            Directive::SecRule(sec_rule) => sec_rule,
+            _ => return None,
+        };
+        if let Some(Directive::SecRule(previous)) = directive_index
+            .checked_sub(1)
+            .and_then(|index| ruleset.directives.get(index))
+            && previous.chain
+        {
+            return None;
+        }
+
+        let mut chain = Vec::new();
+        let mut continues_chain = sec_rule.chain;
+        for (index, directive) in ruleset
+            .directives
+            .iter()
+            .enumerate()
+            .skip(directive_index + 1)
+        {
+            match directive {
+                Directive::SecRule(next) if continues_chain => {
+                    chain.push(((ruleset_index, index), next));
+                    continues_chain = next.chain;
+                }
+                _ => break,
             }
         }
+        Some(Self {
+            position: (ruleset_index, directive_index),
+            sec_rule,
+            chain,
+        })
     }
 
-    false
+    // Evaluates the rule and then each rule continuing its chain, stopping at
+    // the first which doesn't match.
+    fn check(
+        &self,
+        mut check_rule: impl FnMut(RulePosition, &SecRule) -> Result<Option<MatchResult>, String>,
+    ) -> Result<Option<ChainMatch>, String> {
+        let Some(match_result) = check_rule(self.position, self.sec_rule)? else {
+            return Ok(None);
+        };
+        let mut chain = Vec::with_capacity(self.chain.len());
+        for (position, sec_rule) in &self.chain {
+            match check_rule(*position, sec_rule)? {
+                Some(chained_match) => chain.push(chained_match),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(ChainMatch {
+            match_result,
+            chain,
+        }))
+    }
 }
 
-fn check_ruleset_against_args(
-    ruleset: &RuleSet,
-    query_string: &str,
-) -> Result<Option<SecRule>, String> {
-    for directive in &ruleset.directives {
-        if let Directive::SecRule(sec_rule) = directive {
-            if let Some(matched_rule) = check_rule_against_args(sec_rule, query_string)? {
-                return Ok(Some(matched_rule));
+// The check_rule_against_* functions return the match result when the rule
+// matched any of the values of its variables which are available in the
+// phase, less the values excluded by its negated variables.
+//
+// TX rules are evaluated by the header, body, response header and logging
+// phases, which run the phase 1, 2, 3 and 5 rules respectively, so that they
+// see the TX collection as it was left by the rules before them.
+
+// A single value a rule is evaluated against, such as one request header,
+// along with its key within the variable (e.g. the header name) if it has one.
+struct VariableValue<'a> {
+    variable: Variable,
+    key: Option<String>,
+    value: Cow<'a, [u8]>,
+}
+
+impl VariableValue<'_> {
+    // e.g. "REQUEST_HEADERS:User-Agent" or "REQUEST_BODY"
+    fn name(&self) -> String {
+        match &self.key {
+            Some(key) => format!("{}:{}", self.variable.name(), key),
+            None => self.variable.name().to_string(),
+        }
+    }
+}
+
+fn check_rule_against_headers(
+    sec_rule: &SecRule,
+    transaction: &Transaction,
+    headers: &[(String, String)],
+) -> Result<Option<MatchResult>, String> {
+    for variable in included_variables(sec_rule) {
+        let match_result = match variable.variable {
+            Variable::RequestHeaders => check_values(
+                sec_rule,
+                header_values(variable, Variable::RequestHeaders, headers),
+            ),
+            Variable::RequestCookies => check_values(sec_rule, cookie_values(variable, headers)),
+            Variable::RequestMethod => check_values(sec_rule, method_value(headers)),
+            Variable::RequestFilename | Variable::RequestBasename => {
+                check_values(sec_rule, filename_value(variable.variable, headers))
             }
+            Variable::RemoteAddr => check_values(sec_rule, remote_addr_value(transaction)),
+            Variable::Tx => check_values(sec_rule, tx_values(variable, transaction)),
+            _ => Ok(None),
+        }?;
+        if match_result.is_some() {
+            return Ok(match_result);
         }
     }
     Ok(None)
@@ -237,72 +1188,463 @@ fn check_ruleset_against_args(
 fn check_rule_against_args(
     sec_rule: &SecRule,
     query_string: &str,
-) -> Result<Option<SecRule>, String> {
-    if sec_rule.variable != Variable::Args {
-        return Ok(None);
+    args: &[(String, Vec<u8>)],
+) -> Result<Option<MatchResult>, String> {
+    for variable in included_variables(sec_rule) {
+        let match_result = match variable.variable {
+            Variable::Args => check_values(sec_rule, arg_values(variable, args)),
+            // matched verbatim, so that attacks in the structure of the query
+            // string itself (e.g. its encoding) aren't decoded away
+            Variable::QueryString => check_values(
+                sec_rule,
+                Some(VariableValue {
+                    variable: Variable::QueryString,
+                    key: None,
+                    value: Cow::Borrowed(query_string.as_bytes()),
+                }),
+            ),
+            _ => Ok(None),
+        }?;
+        if match_result.is_some() {
+            return Ok(match_result);
+        }
     }
+    Ok(None)
+}
+
+fn arg_values<'a>(
+    variable: &'a VariableSpec,
+    args: &'a [(String, Vec<u8>)],
+) -> impl Iterator<Item = VariableValue<'a>> {
+    args.iter()
+        .filter(move |(name, _)| variable.includes(&Variable::Args, Some(name)))
+        .map(|(name, value)| VariableValue {
+            variable: Variable::Args,
+            key: Some(name.clone()),
+            value: Cow::Borrowed(value.as_slice()),
+        })
+}
 
-    if sec_rule.operator != Operator::Contains {
-        return Err(format!(
-            "{:?} operator is not yet implemented. rule: {}",
-            sec_rule.operator, sec_rule.id
-        ));
+// The arguments are those of the body which are evaluated, see max_args.
+fn check_rule_against_body(
+    sec_rule: &SecRule,
+    transaction: &Transaction,
+    body: &ProcessedBody,
+    args: &[(String, Vec<u8>)],
+) -> Result<Option<MatchResult>, String> {
+    for variable in included_variables(sec_rule) {
+        let match_result = match variable.variable {
+            Variable::RequestBody => check_values(
+                sec_rule,
+                Some(VariableValue {
+                    variable: Variable::RequestBody,
+                    key: None,
+                    value: Cow::Borrowed(body.raw),
+                }),
+            ),
+            Variable::Args => check_values(sec_rule, arg_values(variable, args)),
+            Variable::Files | Variable::FilesNames | Variable::FilesSizes => {
+                check_values(sec_rule, file_values(variable, &body.files))
+            }
+            Variable::ReqbodyError | Variable::ReqbodyErrorMsg => {
+                check_values(sec_rule, reqbody_error_value(variable.variable, body))
+            }
+            Variable::Tx => check_values(sec_rule, tx_values(variable, transaction)),
+            _ => Ok(None),
+        }?;
+        if match_result.is_some() {
+            return Ok(match_result);
+        }
     }
+    Ok(None)
+}
 
-    if rule_matches_args(sec_rule, query_string) {
-        Ok(Some(sec_rule.clone()))
-    } else {
-        Ok(None)
+fn file_values<'a>(
+    variable: &'a VariableSpec,
+    files: &'a [UploadedFile],
+) -> impl Iterator<Item = VariableValue<'a>> {
+    files
+        .iter()
+        .filter(move |file| variable.includes(&variable.variable, Some(&file.name)))
+        .map(move |file| VariableValue {
+            variable: variable.variable,
+            key: Some(file.name.clone()),
+            value: match variable.variable {
+                Variable::FilesNames => Cow::Borrowed(file.name.as_bytes()),
+                Variable::FilesSizes => Cow::Owned(file.size.to_string().into_bytes()),
+                _ => Cow::Borrowed(file.file_name.as_bytes()),
+            },
+        })
+}
+
+// e.g. "1" and "invalid JSON body: ..." for a body which failed to parse, or
+// "0" and "" for one which didn't
+fn reqbody_error_value<'a>(
+    variable: Variable,
+    body: &'a ProcessedBody,
+) -> Option<VariableValue<'a>> {
+    let value = match variable {
+        Variable::ReqbodyError => Cow::Borrowed(if body.error.is_some() {
+            &b"1"[..]
+        } else {
+            &b"0"[..]
+        }),
+        _ => Cow::Borrowed(body.error.as_deref().unwrap_or_default().as_bytes()),
+    };
+    Some(VariableValue {
+        variable,
+        key: None,
+        value,
+    })
+}
+
+fn check_rule_against_response_headers(
+    sec_rule: &SecRule,
+    transaction: &Transaction,
+    status: u32,
+    headers: &[(String, String)],
+) -> Result<Option<MatchResult>, String> {
+    for variable in included_variables(sec_rule) {
+        let match_result = match variable.variable {
+            Variable::ResponseStatus => check_values(
+                sec_rule,
+                Some(VariableValue {
+                    variable: Variable::ResponseStatus,
+                    key: None,
+                    value: Cow::Owned(status.to_string().into_bytes()),
+                }),
+            ),
+            Variable::ResponseHeaders => check_values(
+                sec_rule,
+                header_values(variable, Variable::ResponseHeaders, headers),
+            ),
+            Variable::Tx => check_values(sec_rule, tx_values(variable, transaction)),
+            _ => Ok(None),
+        }?;
+        if match_result.is_some() {
+            return Ok(match_result);
+        }
     }
+    Ok(None)
+}
+
+fn check_rule_against_response_body(
+    sec_rule: &SecRule,
+    transaction: &Transaction,
+    body: &[u8],
+) -> Result<Option<MatchResult>, String> {
+    for variable in included_variables(sec_rule) {
+        let match_result = match variable.variable {
+            Variable::ResponseBody => check_values(
+                sec_rule,
+                Some(VariableValue {
+                    variable: Variable::ResponseBody,
+                    key: None,
+                    value: Cow::Borrowed(body),
+                }),
+            ),
+            Variable::Tx => check_values(sec_rule, tx_values(variable, transaction)),
+            _ => Ok(None),
+        }?;
+        if match_result.is_some() {
+            return Ok(match_result);
+        }
+    }
+    Ok(None)
+}
+
+fn check_rule_against_transaction(
+    sec_rule: &SecRule,
+    transaction: &Transaction,
+) -> Result<Option<MatchResult>, String> {
+    for variable in included_variables(sec_rule) {
+        let match_result = match variable.variable {
+            Variable::ResponseStatus => check_values(
+                sec_rule,
+                transaction.response_status.map(|status| VariableValue {
+                    variable: Variable::ResponseStatus,
+                    key: None,
+                    value: Cow::Owned(status.to_string().into_bytes()),
+                }),
+            ),
+            Variable::RemoteAddr => check_values(sec_rule, remote_addr_value(transaction)),
+            Variable::Tx => check_values(sec_rule, tx_values(variable, transaction)),
+            _ => Ok(None),
+        }?;
+        if match_result.is_some() {
+            return Ok(match_result);
+        }
+    }
+    Ok(None)
 }
 
-fn rule_matches_args(sec_rule: &SecRule, query_string: &str) -> bool {
-    let operator_target = match &sec_rule.operator_target {
-        Some(target) => target,
-        None => return false,
+fn included_variables(sec_rule: &SecRule) -> impl Iterator<Item = &VariableSpec> {
+    sec_rule
+        .variables
+        .iter()
+        .filter(|variable| !variable.negated)
+}
+
+// Request and response headers without a target select every header, except
+// for the pseudo-headers (e.g. ":path" or ":status") which the proxy adds.
+//
+// A header sent more than once (e.g. "X-Forwarded-For" or "Set-Cookie") is
+// evaluated once per instance, so a targeted rule sees every instance of the
+// header rather than only the first. Instances the proxy has already joined
+// into a single comma separated value are evaluated as that one value.
+fn header_values<'a>(
+    variable: &'a VariableSpec,
+    kind: Variable,
+    headers: &'a [(String, String)],
+) -> impl Iterator<Item = VariableValue<'a>> {
+    headers
+        .iter()
+        .filter(move |(name, _)| variable.target.is_some() || !name.starts_with(':'))
+        .filter(move |(name, _)| variable.includes(&kind, Some(name)))
+        .map(move |(name, value)| VariableValue {
+            variable: kind,
+            key: Some(name.clone()),
+            value: Cow::Borrowed(value.as_bytes()),
+        })
+}
+
+// The cookies of every Cookie header, which are separated by ';'. Values are
+// used as the client sent them, as in ModSecurity, so rules which expect them
+// decoded need t:urlDecode. A cookie without a '=' has an empty value.
+fn cookie_values<'a>(
+    variable: &'a VariableSpec,
+    headers: &'a [(String, String)],
+) -> impl Iterator<Item = VariableValue<'a>> {
+    headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("cookie"))
+        .flat_map(|(_, value)| value.split(';'))
+        .map(|cookie| {
+            let cookie = cookie.trim();
+            cookie.split_once('=').unwrap_or((cookie, ""))
+        })
+        .filter(|(name, _)| !name.is_empty())
+        .filter(move |(name, _)| variable.includes(&Variable::RequestCookies, Some(name)))
+        .map(|(name, value)| VariableValue {
+            variable: Variable::RequestCookies,
+            key: Some(name.to_string()),
+            value: Cow::Borrowed(value.as_bytes()),
+        })
+}
+
+// the request method is provided by the proxy as the ":method" pseudo-header
+fn method_value(headers: &[(String, String)]) -> Option<VariableValue<'_>> {
+    headers
+        .iter()
+        .find(|(name, _)| name == ":method")
+        .map(|(_, value)| VariableValue {
+            variable: Variable::RequestMethod,
+            key: None,
+            value: Cow::Borrowed(value.as_bytes()),
+        })
+}
+
+// REQUEST_FILENAME and REQUEST_BASENAME are derived from the ":path"
+// pseudo-header, without its query string. The path is used as the proxy
+// received it, so rules which expect it decoded need t:urlDecode.
+fn filename_value(kind: Variable, headers: &[(String, String)]) -> Option<VariableValue<'_>> {
+    let path = headers
+        .iter()
+        .find(|(name, _)| name == ":path")
+        .map(|(_, path)| path.split_once('?').map_or(path.as_str(), |(path, _)| path))?;
+    let value = match kind {
+        Variable::RequestBasename => path.rsplit('/').next().unwrap_or(path),
+        _ => path,
     };
+    Some(VariableValue {
+        variable: kind,
+        key: None,
+        value: Cow::Borrowed(value.as_bytes()),
+    })
+}
 
-    query_string
-        .to_ascii_lowercase()
-        .contains(&operator_target.to_ascii_lowercase())
+fn remote_addr_value(transaction: &Transaction) -> Option<VariableValue<'_>> {
+    transaction.remote_addr.map(|remote_addr| VariableValue {
+        variable: Variable::RemoteAddr,
+        key: None,
+        value: Cow::Owned(remote_addr.to_string().into_bytes()),
+    })
 }
 
-fn check_ruleset_against_body(ruleset: &RuleSet, body: &str) -> Result<Option<SecRule>, String> {
-    for directive in &ruleset.directives {
-        if let Directive::SecRule(sec_rule) = directive {
-            if let Some(matched_rule) = check_rule_against_body(sec_rule, body)? {
-                return Ok(Some(matched_rule));
-            }
+// TX variables are selected in order of name, so that rules without a target
+// are evaluated deterministically.
+fn tx_values<'a>(variable: &VariableSpec, transaction: &'a Transaction) -> Vec<VariableValue<'a>> {
+    let mut names: Vec<&String> = transaction.tx.keys().collect();
+    names.sort();
+    names
+        .into_iter()
+        .filter(|name| variable.includes(&Variable::Tx, Some(name)))
+        .map(|name| VariableValue {
+            variable: Variable::Tx,
+            key: Some(name.clone()),
+            value: Cow::Borrowed(transaction.tx[name].as_bytes()),
+        })
+        .collect()
+}
+
+// Returns the match result for the first value the rule matched, skipping the
+// values which a negated variable excludes (e.g. "ARGS|!ARGS:csrf_token").
+// Excluding a value which isn't present has no effect.
+//
+// Evaluation stops at the first match: a rule fires at most once per phase,
+// so the values after it can't change whether or how it fires. Variables are
+// evaluated in the order the rule lists them, and each variable's values are
+// only gathered once the previous variable's have failed to match, so a rule
+// with a broad variable list (e.g. "REQUEST_HEADERS|ARGS|REQUEST_BODY") does
+// no more work than it must. multiMatch doesn't need any more values, as it
+// evaluates the transformation stages of one value, and captures are those of
+// the first value which matched.
+fn check_values<'a>(
+    sec_rule: &SecRule,
+    values: impl IntoIterator<Item = VariableValue<'a>>,
+) -> Result<Option<MatchResult>, String> {
+    for value in values {
+        let excluded = sec_rule.variables.iter().any(|variable| {
+            variable.negated && variable.includes(&value.variable, value.key.as_deref())
+        });
+        if excluded {
+            continue;
+        }
+        if let Some(match_result) = check_value(sec_rule, &value.name(), &value.value)? {
+            return Ok(Some(match_result));
         }
     }
     Ok(None)
 }
 
-fn check_rule_against_body(sec_rule: &SecRule, body: &str) -> Result<Option<SecRule>, String> {
-    if sec_rule.variable != Variable::RequestBody {
-        return Ok(None);
+fn check_value(
+    sec_rule: &SecRule,
+    variable_name: &str,
+    value: &[u8],
+) -> Result<Option<MatchResult>, String> {
+    let matched = match operator_match(sec_rule, value)? {
+        Some(matched) => matched,
+        None => return Ok(None),
+    };
+
+    let captures = match sec_rule.capture {
+        true => capture_groups(sec_rule, &matched),
+        false => Vec::new(),
+    };
+    Ok(Some(MatchResult {
+        rule: sec_rule.clone(),
+        matched_var: variable_name.to_string(),
+        matched_value: String::from_utf8_lossy(value).into_owned(),
+        captures,
+        match_count: match_count(sec_rule, &matched),
+    }))
+}
+
+// Returns the whole match and the first nine capture groups of a matched @rx
+// operator, with empty strings for groups which didn't participate. @rxGlobal
+// returns the whole match and groups of every match in turn, up to the same
+// ten in all. Other operators, and negated operators, don't capture anything.
+fn capture_groups(sec_rule: &SecRule, matched: &[u8]) -> Vec<String> {
+    let regex = match (&sec_rule.compiled_operator, sec_rule.negated) {
+        (Some(CompiledOperator::Regex(regex)), false) => regex,
+        _ => return Vec::new(),
+    };
+
+    let text = String::from_utf8_lossy(matched);
+    let matches = match sec_rule.operator {
+        Operator::RxGlobal => regex.captures_iter(&text).collect(),
+        _ => regex.captures(&text).into_iter().collect::<Vec<_>>(),
+    };
+    matches
+        .iter()
+        .flat_map(|captures| captures.iter())
+        .take(MAX_CAPTURES)
+        .map(|group| group.map_or("", |group| group.as_str()).to_string())
+        .collect()
+}
+
+fn match_count(sec_rule: &SecRule, matched: &[u8]) -> usize {
+    match (
+        &sec_rule.operator,
+        &sec_rule.compiled_operator,
+        sec_rule.negated,
+    ) {
+        (Operator::RxGlobal, Some(CompiledOperator::Regex(regex)), false) => {
+            regex.find_iter(&String::from_utf8_lossy(matched)).count()
+        }
+        _ => 1,
     }
+}
 
-    if sec_rule.operator != Operator::Contains {
-        return Err(format!(
-            "{:?} operator is not yet implemented. rule: {}",
-            sec_rule.operator, sec_rule.id
-        ));
+// Evaluates the rule's operator against a single value, after applying the
+// rule's transformations to it, returning the transformed value it matched.
+// With multiMatch the operator is evaluated against the value before and after
+// each transformation instead, and the first of them which matches is
+// returned, which catches payloads that are only malicious part way through
+// decoding.
+fn operator_match<'a>(
+    sec_rule: &SecRule,
+    value: &'a [u8],
+) -> Result<Option<Cow<'a, [u8]>>, String> {
+    if !sec_rule.multi_match {
+        let bytes = apply_transformations(&sec_rule.transformations, value)?;
+        return Ok(evaluate_operator(sec_rule, &bytes)?.then_some(bytes));
     }
 
-    if rule_matches_body(sec_rule, body) {
-        Ok(Some(sec_rule.clone()))
-    } else {
-        Ok(None)
+    for stage in transformation_stages(&sec_rule.transformations, value)? {
+        if evaluate_operator(sec_rule, &stage)? {
+            return Ok(Some(stage));
+        }
     }
+    Ok(None)
 }
 
-fn rule_matches_body(sec_rule: &SecRule, body: &str) -> bool {
-    let operator_target = match &sec_rule.operator_target {
-        Some(target) => target,
-        None => return false,
+// Evaluates the rule's operator against an already transformed value,
+// inverting the result when the operator was negated (e.g. "!@rx ^GET$").
+//
+// Byte-oriented operators see the bytes as they are, while the text operators
+// see them decoded as UTF-8, with invalid sequences replaced.
+fn evaluate_operator(sec_rule: &SecRule, bytes: &[u8]) -> Result<bool, String> {
+    let text = String::from_utf8_lossy(bytes);
+    let value = text.as_ref();
+
+    let matched = match (&sec_rule.operator, &sec_rule.compiled_operator) {
+        (Operator::Contains | Operator::Streq, _) => {
+            let operator_target = match &sec_rule.operator_target {
+                Some(target) => target,
+                None => return Ok(false),
+            };
+            if sec_rule.operator == Operator::Contains {
+                value
+                    .to_ascii_lowercase()
+                    .contains(&operator_target.to_ascii_lowercase())
+            } else {
+                value == operator_target
+            }
+        }
+        (Operator::DetectSqli, _) => detect_sqli(value).is_some(),
+        (Operator::DetectXss, _) => detect_xss(value),
+        (
+            Operator::IpMatch | Operator::IpMatchFromFile,
+            Some(CompiledOperator::IpMatch(networks)),
+        ) => value
+            .parse::<IpAddr>()
+            .is_ok_and(|address| networks.contains(&address)),
+        (Operator::Rx | Operator::RxGlobal, Some(CompiledOperator::Regex(regex))) => {
+            regex.is_match(value)
+        }
+        (Operator::ValidateByteRange, Some(CompiledOperator::ByteRange(allowed))) => {
+            allowed.matches(bytes)
+        }
+        (Operator::ValidateUtf8Encoding, _) => validate_utf8_encoding(bytes),
+        (Operator::Within, Some(CompiledOperator::Within(members))) => members.contains(value),
+        (operator, _) => {
+            return Err(format!(
+                "{:?} operator is missing its compiled argument. rule: {}",
+                operator, sec_rule.id
+            ));
+        }
     };
 
-    body.to_ascii_lowercase()
-        .contains(&operator_target.to_ascii_lowercase())
+    Ok(matched != sec_rule.negated)
 }
@@ -0,0 +1,60 @@
+use crate::MatchResult;
+use crate::transaction::Transaction;
+
+// -----------------------------------------------------------------------------
+// Signature-Based Detection Engine - Macro Expansion
+// -----------------------------------------------------------------------------
+
+// Expands the %{...} macros in msg, logdata and setvar values against the
+// current request and the rule match, e.g. "SQLi in %{MATCHED_VAR_NAME}". The
+// supported macros are:
+//
+//   %{MATCHED_VAR}       the value of the matched variable
+//   %{MATCHED_VAR_NAME}  the name of the matched variable
+//   %{MATCHED_COUNT}     the number of times the operator matched (see @rxGlobal)
+//   %{TX.name}           a variable in the TX collection (e.g. %{TX.0})
+//   %{REQUEST_URI}       the request's path and query string
+//
+// Macro names are case-insensitive. Like ModSecurity, macros which aren't
+// defined expand to an empty string.
+pub fn expand_macros(
+    template: &str,
+    transaction: &Transaction,
+    match_result: &MatchResult,
+) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("%{") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&resolve_macro(
+            &rest[start + 2..end],
+            transaction,
+            match_result,
+        ));
+        rest = &rest[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+fn resolve_macro(name: &str, transaction: &Transaction, match_result: &MatchResult) -> String {
+    let (collection, key) = match name.split_once(['.', ':']) {
+        Some((collection, key)) => (collection, Some(key)),
+        None => (name, None),
+    };
+
+    match (collection.to_ascii_uppercase().as_str(), key) {
+        ("MATCHED_VAR", None) => match_result.matched_value.clone(),
+        ("MATCHED_VAR_NAME", None) => match_result.matched_var.clone(),
+        ("MATCHED_COUNT", None) => match_result.match_count.to_string(),
+        ("REQUEST_URI", None) => transaction.request_uri.clone().unwrap_or_default(),
+        ("TX", Some(key)) => transaction.tx_var(key).unwrap_or_default().to_string(),
+        _ => String::new(),
+    }
+}
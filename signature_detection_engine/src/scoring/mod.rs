@@ -0,0 +1,37 @@
+use crate::MatchResult;
+
+// -----------------------------------------------------------------------------
+// Signature-Based Detection Engine - Anomaly Scoring
+// -----------------------------------------------------------------------------
+
+// Accumulates the severity-derived scores of matched rules over every phase of
+// a single request, for use with EngineMode::AnomalyScore.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AnomalyScorer {
+    pub score: u32,
+    pub matched_rule_ids: Vec<u32>,
+}
+
+impl AnomalyScorer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds the scores of the matched rules, returning the accumulated total.
+    // Rules without a severity don't contribute to the score.
+    pub fn record(&mut self, matched_rules: &[MatchResult]) -> u32 {
+        for matched_rule in matched_rules {
+            self.score += matched_rule
+                .rule
+                .severity
+                .map(|severity| severity.anomaly_score())
+                .unwrap_or(0);
+            self.matched_rule_ids.push(matched_rule.rule.id);
+        }
+        self.score
+    }
+
+    pub fn threshold_reached(&self, threshold: u32) -> bool {
+        self.score >= threshold
+    }
+}
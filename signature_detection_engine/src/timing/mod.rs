@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+// Signature-Based Detection Engine - Rule Timing
+// -----------------------------------------------------------------------------
+
+// The time elapsed since an arbitrary fixed point. The engine doesn't read the
+// clock itself, as std::time::Instant isn't available in every WASM host, so
+// a proxy-wasm plugin provides one backed by the proxy's clock instead.
+pub type Clock = fn() -> Duration;
+
+// A clock backed by std::time::Instant, for hosts which have one.
+pub fn monotonic_clock() -> Duration {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed()
+}
+
+// The time spent evaluating a rule against requests, over every phase and
+// every value of its variables.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RuleTiming {
+    pub rule_id: u32,
+    pub evaluations: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+// Accumulates the evaluation time of each rule, to find the rules (e.g. regexes
+// prone to catastrophic backtracking) which dominate the latency of a large
+// ruleset. See SignatureBasedDetectionEngine::with_rule_timings.
+#[derive(Debug)]
+pub struct RuleTimings {
+    clock: Clock,
+    timings: Mutex<HashMap<u32, RuleTiming>>,
+}
+
+impl RuleTimings {
+    pub fn new(clock: Clock) -> Self {
+        Self {
+            clock,
+            timings: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn time<T>(&self, rule_id: u32, evaluate: impl FnOnce() -> T) -> T {
+        let start = (self.clock)();
+        let result = evaluate();
+        // a clock backed by the wall clock can go backwards
+        let elapsed = (self.clock)().saturating_sub(start);
+
+        // the timings are only informational, so a lock poisoned by an
+        // earlier panic is recovered rather than unwrapped
+        let mut timings = self.timings.lock().unwrap_or_else(PoisonError::into_inner);
+        let timing = timings.entry(rule_id).or_insert(RuleTiming {
+            rule_id,
+            ..RuleTiming::default()
+        });
+        timing.evaluations += 1;
+        timing.total += elapsed;
+        timing.max = timing.max.max(elapsed);
+        result
+    }
+
+    // Returns the timings of the given number of rules which took the most
+    // time in total, slowest first, and starts over, so that each call covers
+    // the window since the one before it.
+    pub fn take_slowest(&self, count: usize) -> Vec<RuleTiming> {
+        let timings =
+            std::mem::take(&mut *self.timings.lock().unwrap_or_else(PoisonError::into_inner));
+        let mut timings: Vec<RuleTiming> = timings.into_values().collect();
+        timings.sort_by(|a, b| b.total.cmp(&a.total).then(a.rule_id.cmp(&b.rule_id)));
+        timings.truncate(count);
+        timings
+    }
+}
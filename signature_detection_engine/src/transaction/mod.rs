@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::compatibility::modsecurity::directives::sec_rule::{
+    RuleEngineMode, SetVar, SetVarOperation,
+};
+
+// -----------------------------------------------------------------------------
+// Signature-Based Detection Engine - Transaction
+// -----------------------------------------------------------------------------
+
+// The state of a single request, carried through each of its phases. The
+// engine itself is shared between requests, so anything a rule changes (e.g.
+// with setvar) lives here instead.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Transaction {
+    // the client address as derived by the proxy, which is what REMOTE_ADDR
+    // rules are evaluated against
+    pub remote_addr: Option<IpAddr>,
+    // the ID the request is known by in the logs (e.g. from x-request-id), like
+    // ModSecurity's UNIQUE_ID, which is included in audit records
+    pub request_id: Option<String>,
+    // the request's path and query string
+    pub request_uri: Option<String>,
+    // the backend's response status, once the response headers have been
+    // evaluated
+    pub response_status: Option<u32>,
+    // set by the caller when it blocks the request, so that the logging phase
+    // knows the final disposition of the transaction
+    pub blocked: bool,
+    // the number of the request's arguments which have been evaluated, of its
+    // query string and body together, and whether there were more than the
+    // engine's max_args, in which case the rest weren't evaluated
+    pub args_count: usize,
+    pub too_many_args: bool,
+    // set by the ctl:ruleEngine action of a rule which fired, changing how the
+    // rest of the transaction's rules are evaluated (see RuleEngineMode). A
+    // caller which blocks requests on matches of its own, e.g. by their
+    // anomaly score, shouldn't block when this is DetectionOnly.
+    pub rule_engine: Option<RuleEngineMode>,
+    // the TX collection, keyed by lowercase name as ModSecurity variable names
+    // are case-insensitive
+    pub tx: HashMap<String, String>,
+}
+
+impl Transaction {
+    pub fn new(remote_addr: Option<IpAddr>) -> Self {
+        Self {
+            remote_addr,
+            ..Self::default()
+        }
+    }
+
+    pub fn tx_var(&self, name: &str) -> Option<&str> {
+        self.tx.get(&name.to_lowercase()).map(String::as_str)
+    }
+
+    // Stores regex capture groups as TX.0 (the whole match) to TX.9.
+    pub(crate) fn set_captures(&mut self, captures: &[String]) {
+        for (index, capture) in captures.iter().enumerate() {
+            self.tx.insert(index.to_string(), capture.clone());
+        }
+    }
+
+    // Increments and decrements treat missing and non-numeric values as 0,
+    // like ModSecurity does, and saturate rather than overflow.
+    pub(crate) fn apply_set_var(&mut self, set_var: &SetVar) {
+        let name = set_var.name.to_lowercase();
+        let numeric = |value: Option<&String>| {
+            value
+                .and_then(|value| value.trim().parse::<i64>().ok())
+                .unwrap_or(0)
+        };
+
+        match &set_var.operation {
+            SetVarOperation::Set(value) => {
+                self.tx.insert(name, value.clone());
+            }
+            SetVarOperation::Increment(value) => {
+                let total = numeric(self.tx.get(&name)).saturating_add(numeric(Some(value)));
+                self.tx.insert(name, total.to_string());
+            }
+            SetVarOperation::Decrement(value) => {
+                let total = numeric(self.tx.get(&name)).saturating_sub(numeric(Some(value)));
+                self.tx.insert(name, total.to_string());
+            }
+            SetVarOperation::Delete => {
+                self.tx.remove(&name);
+            }
+        }
+    }
+}
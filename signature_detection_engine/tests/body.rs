@@ -0,0 +1,289 @@
+use signature_detection_engine::SignatureBasedDetectionEngine;
+use signature_detection_engine::body::{BodyProcessor, UploadedFile, process_body};
+use signature_detection_engine::transaction::Transaction;
+
+fn args(body: &[(&str, &str)]) -> Vec<(String, Vec<u8>)> {
+    body.iter()
+        .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+        .collect()
+}
+
+#[test]
+fn body_processors_are_selected_by_content_type() {
+    for (content_type, processor) in [
+        (
+            Some("application/x-www-form-urlencoded"),
+            BodyProcessor::UrlEncoded,
+        ),
+        (
+            Some("multipart/form-data; boundary=----abc"),
+            BodyProcessor::Multipart,
+        ),
+        (Some("Application/JSON; charset=utf-8"), BodyProcessor::Json),
+        (Some("application/vnd.api+json"), BodyProcessor::Json),
+        (Some("text/plain"), BodyProcessor::Raw),
+        (None, BodyProcessor::Raw),
+    ] {
+        assert_eq!(
+            BodyProcessor::for_content_type(content_type),
+            processor,
+            "{:?}",
+            content_type
+        );
+    }
+}
+
+#[test]
+fn urlencoded_bodies_are_parsed_into_args() {
+    let body = process_body(
+        Some("application/x-www-form-urlencoded"),
+        b"user=robert&comment=a%27+or+1%3D1&flag",
+    );
+    assert_eq!(
+        body.args,
+        args(&[("user", "robert"), ("comment", "a' or 1=1"), ("flag", "")])
+    );
+}
+
+#[test]
+fn json_bodies_are_parsed_into_args_named_by_path() {
+    let body = process_body(
+        Some("application/json"),
+        br#"{"user": {"name": "robert", "admin": false}, "tags": ["a", 1], "note": null}"#,
+    );
+    assert_eq!(
+        body.args,
+        args(&[
+            ("json.note", ""),
+            ("json.tags.0", "a"),
+            ("json.tags.1", "1"),
+            ("json.user.admin", "false"),
+            ("json.user.name", "robert"),
+        ])
+    );
+
+    // a body which fails to parse is still seen as REQUEST_BODY
+    let body = process_body(Some("application/json"), br#"{"user": "#);
+    assert!(body.args.is_empty());
+    assert_eq!(body.raw, br#"{"user": "#);
+}
+
+#[test]
+fn phase_2_args_rules_see_the_body_args() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"
+SecRule ARGS:json.user.name "@contains drop table" "id:1,phase:2,deny,t:lowercase"
+SecRule REQUEST_BODY "@contains <script>" "id:2,phase:2,deny"
+"#,
+    )
+    .unwrap();
+
+    let body = process_body(
+        Some("application/json"),
+        br#"{"user": {"name": "robert'); DROP TABLE users;--"}}"#,
+    );
+    let match_result = engine
+        .run_processed_body_phase(&mut Transaction::default(), &body)
+        .unwrap()
+        .unwrap();
+    assert_eq!(match_result.rule.id, 1);
+    assert_eq!(match_result.matched_var, "ARGS:json.user.name");
+
+    let body = process_body(Some("text/plain"), b"<script>alert(1)</script>");
+    let match_result = engine
+        .run_processed_body_phase(&mut Transaction::default(), &body)
+        .unwrap()
+        .unwrap();
+    assert_eq!(match_result.rule.id, 2);
+}
+
+const MULTIPART_CONTENT_TYPE: &str = "multipart/form-data; boundary=\"----portkullis\"";
+
+const MULTIPART_BODY: &[u8] = b"------portkullis\r\n\
+Content-Disposition: form-data; name=\"comment\"\r\n\
+\r\n\
+first line\r\nsecond line\r\n\
+------portkullis\r\n\
+Content-Disposition: form-data; name=\"avatar\"; filename=\"shell;1.php\"\r\n\
+Content-Type: image/png\r\n\
+\r\n\
+<?php system($_GET['c']); ?>\r\n\
+------portkullis--\r\n";
+
+#[test]
+fn multipart_bodies_are_parsed_into_args_and_files() {
+    let body = process_body(Some(MULTIPART_CONTENT_TYPE), MULTIPART_BODY);
+    assert_eq!(body.error, None);
+    assert_eq!(body.args, args(&[("comment", "first line\r\nsecond line")]));
+    assert_eq!(
+        body.files,
+        vec![UploadedFile {
+            name: "avatar".to_string(),
+            file_name: "shell;1.php".to_string(),
+            content_type: Some("image/png".to_string()),
+            size: 28,
+        }]
+    );
+}
+
+#[test]
+fn rules_see_the_uploaded_files() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"
+SecRule FILES_SIZES "@rx ^[0-9]{7,}$" "id:1,phase:2,deny"
+SecRule FILES "@rx \.php$" "id:2,phase:2,deny"
+"#,
+    )
+    .unwrap();
+
+    let body = process_body(Some(MULTIPART_CONTENT_TYPE), MULTIPART_BODY);
+    let match_result = engine
+        .run_processed_body_phase(&mut Transaction::default(), &body)
+        .unwrap()
+        .unwrap();
+    assert_eq!(match_result.rule.id, 2);
+    assert_eq!(match_result.matched_var, "FILES:avatar");
+    assert_eq!(match_result.matched_value, "shell;1.php");
+
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule FILES_NAMES "@streq avatar" "id:3,phase:2,deny""#,
+    )
+    .unwrap();
+    let match_result = engine
+        .run_processed_body_phase(&mut Transaction::default(), &body)
+        .unwrap()
+        .unwrap();
+    assert_eq!(match_result.rule.id, 3);
+}
+
+#[test]
+fn malformed_multipart_bodies_are_parsed_as_far_as_they_can_be() {
+    // without a boundary nothing can be parsed
+    let body = process_body(Some("multipart/form-data"), MULTIPART_BODY);
+    assert!(body.args.is_empty());
+    assert!(body.error.is_some());
+
+    // without the closing boundary the fields before it are kept
+    let truncated = &MULTIPART_BODY[..MULTIPART_BODY.len() - 22];
+    let body = process_body(Some(MULTIPART_CONTENT_TYPE), truncated);
+    assert_eq!(body.args, args(&[("comment", "first line\r\nsecond line")]));
+    assert_eq!(body.files.len(), 1);
+    assert!(body.error.is_some());
+
+    // LF line endings are accepted
+    let body = process_body(
+        Some("multipart/form-data; boundary=x"),
+        b"--x\nContent-Disposition: form-data; name=a\n\n1\n--x--\n",
+    );
+    assert_eq!(body.args, args(&[("a", "1")]));
+    assert_eq!(body.error, None);
+
+    for malformed in [
+        &b""[..],
+        b"--x",
+        b"--x\r\n",
+        b"--x\r\n\r\n--x--",
+        b"--x\r\nContent-Disposition: form-data\r\n\r\nvalue\r\n--x--",
+        b"--x\r\nContent-Disposition: form-data; name=\"a\r\n--x",
+        b"--xx--x\r\n--x\r\n",
+    ] {
+        let body = process_body(Some("multipart/form-data; boundary=x"), malformed);
+        assert!(
+            body.error.is_some(),
+            "{:?}",
+            String::from_utf8_lossy(malformed)
+        );
+    }
+}
+
+#[test]
+fn malformed_multipart_parts_do_not_hide_the_parts_after_them() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule ARGS:q "@contains or 1=1" "id:1,phase:2,deny""#,
+    )
+    .unwrap();
+
+    let body = process_body(
+        Some("multipart/form-data; boundary=x"),
+        b"--x\r\n\
+Content-Disposition: form-data\r\n\
+\r\n\
+nameless\r\n\
+--x\r\n\
+Content-Disposition: form-data; name=\"q\"\r\n\
+\r\n\
+' or 1=1--\r\n\
+--x--\r\n",
+    );
+    assert_eq!(body.args, args(&[("q", "' or 1=1--")]));
+    assert!(body.error.is_some());
+
+    let match_result = engine
+        .run_processed_body_phase(&mut Transaction::default(), &body)
+        .unwrap()
+        .unwrap();
+    assert_eq!(match_result.rule.id, 1);
+    assert_eq!(match_result.matched_var, "ARGS:q");
+}
+
+#[test]
+fn rules_see_whether_the_body_failed_to_parse() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"
+SecRule REQBODY_ERROR "!@streq 0" "id:1,phase:2,deny,chain"
+    SecRule REQBODY_ERROR_MSG "@contains invalid JSON" "t:none"
+"#,
+    )
+    .unwrap();
+
+    let body = process_body(Some("application/json"), br#"{"user": "#);
+    let match_result = engine
+        .run_processed_body_phase(&mut Transaction::default(), &body)
+        .unwrap()
+        .unwrap();
+    assert_eq!(match_result.rule.id, 1);
+    assert_eq!(match_result.matched_var, "REQBODY_ERROR");
+    assert_eq!(match_result.matched_value, "1");
+
+    let body = process_body(Some("application/json"), br#"{"user": "robert"}"#);
+    let match_result = engine
+        .run_processed_body_phase(&mut Transaction::default(), &body)
+        .unwrap();
+    assert_eq!(match_result, None);
+}
+
+#[test]
+fn args_past_the_maximum_are_not_evaluated() {
+    let mut engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule ARGS "@contains <script>" "id:1,phase:2,deny""#,
+    )
+    .unwrap();
+    engine.set_max_args(3);
+
+    // the query string's arguments count towards the body's
+    let mut transaction = Transaction::default();
+    let match_result = engine.run_args_phase(&mut transaction, "a=1&b=2").unwrap();
+    assert_eq!(match_result, None);
+    assert!(!transaction.too_many_args);
+
+    let body = process_body(
+        Some("application/x-www-form-urlencoded"),
+        b"c=3&d=%3Cscript%3E",
+    );
+    let match_result = engine
+        .run_processed_body_phase(&mut transaction, &body)
+        .unwrap();
+    assert_eq!(match_result, None);
+    assert!(transaction.too_many_args);
+    assert_eq!(transaction.args_count, 3);
+
+    let mut transaction = Transaction::default();
+    let match_result = engine
+        .run_args_phase(&mut transaction, "a=%3Cscript%3E&b=2&c=3&d=4")
+        .unwrap();
+    assert_eq!(
+        match_result.map(|match_result| match_result.rule.id),
+        Some(1)
+    );
+    assert!(transaction.too_many_args);
+}
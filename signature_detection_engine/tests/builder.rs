@@ -0,0 +1,121 @@
+mod common;
+
+use common::{rule, user_agent_headers};
+use signature_detection_engine::builder::EngineBuilder;
+use signature_detection_engine::transaction::Transaction;
+use signature_detection_engine::{Directive, RuleSet};
+
+#[test]
+fn rules_are_bucketed_into_their_phases() {
+    let engine = EngineBuilder::new()
+        .add_rule(rule(
+            r#"SecRule REQUEST_HEADERS:User-Agent "@contains bot" "id:1,phase:1,deny""#,
+        ))
+        .add_rule(rule(
+            r#"SecRule REQUEST_BODY "@contains DROP TABLE" "id:2,phase:2,deny""#,
+        ))
+        .build();
+
+    let mut transaction = Transaction::default();
+    let header_match = engine
+        .run_header_phase(&mut transaction, user_agent_headers("evil-bot"))
+        .unwrap();
+    assert_eq!(
+        header_match.map(|match_result| match_result.rule.id),
+        Some(1)
+    );
+
+    let body_match = engine
+        .run_body_phase(&mut transaction, "DROP TABLE users")
+        .unwrap();
+    assert_eq!(body_match.map(|match_result| match_result.rule.id), Some(2));
+
+    // the body rule isn't evaluated in the header phase
+    let header_match = engine
+        .run_header_phase(&mut transaction, user_agent_headers("DROP TABLE"))
+        .unwrap();
+    assert_eq!(header_match, None);
+}
+
+#[test]
+fn rulesets_are_split_by_phase_and_keep_their_order() {
+    let ruleset = RuleSet::new(
+        "example".to_string(),
+        "example rules".to_string(),
+        "1.0.0".to_string(),
+        vec![
+            Directive::SecRule(rule(
+                r#"SecRule REQUEST_BODY "@contains DROP" "id:3,phase:2,deny""#,
+            )),
+            Directive::SecRule(rule(
+                r#"SecRule REQUEST_HEADERS:User-Agent "@contains bot" "id:4,phase:1,deny""#,
+            )),
+        ],
+    );
+    let engine = EngineBuilder::new()
+        .add_ruleset(ruleset)
+        .add_rule(rule(
+            r#"SecRule REQUEST_BODY "@contains DROP TABLE" "id:5,phase:2,deny""#,
+        ))
+        .build();
+
+    let mut transaction = Transaction::default();
+    let header_match = engine
+        .run_header_phase(&mut transaction, user_agent_headers("evil-bot"))
+        .unwrap();
+    assert_eq!(
+        header_match.map(|match_result| match_result.rule.id),
+        Some(4)
+    );
+
+    let body_matches = engine
+        .run_body_phase_all(&mut transaction, "DROP TABLE users")
+        .unwrap();
+    let ids: Vec<u32> = body_matches
+        .iter()
+        .map(|match_result| match_result.rule.id)
+        .collect();
+    assert_eq!(ids, vec![3, 5]);
+}
+
+#[test]
+fn rules_can_be_sorted_by_id() {
+    let builder = || {
+        EngineBuilder::new()
+            .add_ruleset(RuleSet::new(
+                "custom".to_string(),
+                "custom rules".to_string(),
+                "1.0.0".to_string(),
+                vec![Directive::SecRule(rule(
+                    r#"SecRule REQUEST_BODY "@contains DROP" "id:20,phase:2,pass""#,
+                ))],
+            ))
+            .add_ruleset(RuleSet::new(
+                "vendor".to_string(),
+                "vendor rules".to_string(),
+                "1.0.0".to_string(),
+                vec![
+                    Directive::SecRule(rule(
+                        r#"SecRule REQUEST_BODY "@contains TABLE" "id:30,phase:2,pass""#,
+                    )),
+                    Directive::SecRule(rule(
+                        r#"SecRule REQUEST_BODY "@contains DROP TABLE" "id:10,phase:2,pass""#,
+                    )),
+                ],
+            ))
+    };
+    let matched_ids = |engine: signature_detection_engine::SignatureBasedDetectionEngine| {
+        engine
+            .run_body_phase_all(&mut Transaction::default(), "DROP TABLE users")
+            .unwrap()
+            .iter()
+            .map(|match_result| match_result.rule.id)
+            .collect::<Vec<u32>>()
+    };
+
+    assert_eq!(matched_ids(builder().build()), vec![20, 30, 10]);
+    assert_eq!(
+        matched_ids(builder().sorted_by_id(true).build()),
+        vec![10, 20, 30]
+    );
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use common::headers;
+use signature_detection_engine::SignatureBasedDetectionEngine;
+use signature_detection_engine::transaction::Transaction;
+
+const RULES: &str = r#"
+SecRule REQUEST_HEADERS:X-Method "@streq POST" "id:1,phase:1,deny,chain"
+    SecRule REQUEST_HEADERS:X-Evil "@streq yes" "t:none,setvar:tx.evil=1"
+SecRule REQUEST_HEADERS:X-Score "@streq high" "id:2,phase:1,pass,setvar:tx.score=+1"
+"#;
+
+fn matched_id(
+    engine: &SignatureBasedDetectionEngine,
+    request_headers: &[(&str, &str)],
+) -> Option<u32> {
+    engine
+        .run_header_phase(&mut Transaction::default(), headers(request_headers))
+        .unwrap()
+        .map(|match_result| match_result.rule.id)
+}
+
+#[test]
+fn chained_rules_only_fire_when_every_rule_matches() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(RULES).unwrap();
+
+    assert_eq!(matched_id(&engine, &[("x-method", "POST")]), None);
+    assert_eq!(matched_id(&engine, &[("x-evil", "yes")]), None);
+    assert_eq!(
+        matched_id(&engine, &[("x-method", "POST"), ("x-evil", "yes")]),
+        Some(1)
+    );
+}
+
+#[test]
+fn chained_rules_are_evaluated_together_without_the_rule_index() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(RULES)
+        .unwrap()
+        .without_rule_index();
+
+    assert_eq!(matched_id(&engine, &[("x-evil", "yes")]), None);
+    assert_eq!(
+        matched_id(&engine, &[("x-method", "POST"), ("x-evil", "yes")]),
+        Some(1)
+    );
+}
+
+#[test]
+fn actions_of_chained_rules_only_run_when_the_chain_matches() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(RULES).unwrap();
+
+    let mut transaction = Transaction::default();
+    let matched_rules = engine
+        .run_header_phase_all(
+            &mut transaction,
+            headers(&[("x-evil", "yes"), ("x-score", "high")]),
+        )
+        .unwrap();
+    let rule_ids: Vec<u32> = matched_rules.iter().map(|m| m.rule.id).collect();
+    assert_eq!(rule_ids, vec![2]);
+    assert_eq!(transaction.tx_var("evil"), None);
+
+    let mut transaction = Transaction::default();
+    let matched_rules = engine
+        .run_header_phase_all(
+            &mut transaction,
+            headers(&[("x-method", "POST"), ("x-evil", "yes")]),
+        )
+        .unwrap();
+    let rule_ids: Vec<u32> = matched_rules.iter().map(|m| m.rule.id).collect();
+    assert_eq!(rule_ids, vec![1]);
+    assert_eq!(transaction.tx_var("evil"), Some("1"));
+}
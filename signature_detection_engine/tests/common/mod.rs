@@ -0,0 +1,31 @@
+// Fixtures shared by the integration tests, each of which only uses some.
+#![allow(dead_code)]
+
+use signature_detection_engine::transaction::Transaction;
+use signature_detection_engine::{MatchResult, SecRule, SignatureBasedDetectionEngine};
+
+pub fn rule(raw: &str) -> SecRule {
+    SecRule::try_from(raw.to_string()).unwrap()
+}
+
+pub fn headers(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+pub fn user_agent_headers(user_agent: &str) -> Vec<(String, String)> {
+    headers(&[("user-agent", user_agent)])
+}
+
+// The rule which blocks a request with only the given header, if any.
+pub fn header_match(
+    engine: &SignatureBasedDetectionEngine,
+    name: &str,
+    value: &str,
+) -> Option<MatchResult> {
+    engine
+        .run_header_phase(&mut Transaction::default(), headers(&[(name, value)]))
+        .unwrap()
+}
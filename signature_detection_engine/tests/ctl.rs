@@ -0,0 +1,145 @@
+mod common;
+
+use common::headers;
+use signature_detection_engine::errors::ValidationErrors;
+use signature_detection_engine::transaction::Transaction;
+use signature_detection_engine::{
+    Ctl, ParseOptions, RuleEngineMode, SecRule, SignatureBasedDetectionEngine,
+};
+
+#[test]
+fn ctl_rule_engine_is_parsed() {
+    let sec_rule = SecRule::try_from(
+        r#"SecRule REQUEST_FILENAME "@rx ^/legacy" "id:1,phase:1,pass,ctl:ruleEngine=detectionOnly""#
+            .to_string(),
+    )
+    .unwrap();
+    assert_eq!(
+        sec_rule.ctls,
+        vec![Ctl::RuleEngine(RuleEngineMode::DetectionOnly)]
+    );
+    assert!(
+        sec_rule
+            .to_string()
+            .contains("ctl:ruleEngine=DetectionOnly")
+    );
+    assert_eq!(
+        SecRule::try_from(sec_rule.to_string()).unwrap().ctls,
+        sec_rule.ctls
+    );
+
+    let error = SecRule::try_from(
+        r#"SecRule REQUEST_FILENAME "@rx ^/legacy" "id:1,phase:1,pass,ctl:ruleEngine=Sometimes""#
+            .to_string(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        error,
+        ValidationErrors::InvalidCtl {
+            value: "ruleEngine=Sometimes".to_string()
+        }
+    );
+}
+
+#[test]
+fn unsupported_ctl_targets_are_rejected_when_parsing_strictly() {
+    let conf =
+        r#"SecRule REQUEST_FILENAME "@rx ^/api" "id:1,phase:1,pass,ctl:ruleRemoveById=942100""#;
+    assert!(SignatureBasedDetectionEngine::from_conf_str(conf).is_err());
+
+    let options = ParseOptions {
+        strict: false,
+        ..ParseOptions::default()
+    };
+    let engine = SignatureBasedDetectionEngine::from_conf_str_with_options(conf, options).unwrap();
+    let sec_rule = engine.get_rule_by_id(1).unwrap();
+    assert!(sec_rule.ctls.is_empty());
+    assert_eq!(
+        sec_rule.unknown_actions,
+        vec![("ctl".to_string(), "ruleRemoveById=942100".to_string())]
+    );
+}
+
+#[test]
+fn rules_after_ctl_rule_engine_detection_only_fire_without_blocking() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"
+SecRule REQUEST_FILENAME "@rx ^/legacy" "id:1,phase:1,pass,ctl:ruleEngine=DetectionOnly"
+SecRule REQUEST_HEADERS:User-Agent "@contains sqlmap" "id:2,phase:1,deny,setvar:tx.scanner=1"
+SecRule REQUEST_HEADERS:User-Agent "@contains sqlmap" "id:3,phase:1,pass,setvar:tx.score=+5"
+SecRule ARGS "@contains <script>" "id:4,phase:2,deny"
+"#,
+    )
+    .unwrap();
+
+    let mut transaction = Transaction::default();
+    let match_result = engine
+        .run_header_phase(
+            &mut transaction,
+            headers(&[
+                (":method", "GET"),
+                (":path", "/legacy/search"),
+                ("user-agent", "sqlmap/1.7"),
+            ]),
+        )
+        .unwrap();
+    assert_eq!(match_result, None);
+    assert_eq!(transaction.rule_engine, Some(RuleEngineMode::DetectionOnly));
+    assert_eq!(transaction.tx_var("scanner"), Some("1"));
+    assert_eq!(transaction.tx_var("score"), Some("5"));
+
+    // the mode carries over to the transaction's later phases
+    let match_result = engine
+        .run_args_phase(&mut transaction, "q=%3Cscript%3E")
+        .unwrap();
+    assert_eq!(match_result, None);
+
+    let mut transaction = Transaction::default();
+    let match_result = engine
+        .run_header_phase(
+            &mut transaction,
+            headers(&[
+                (":method", "GET"),
+                (":path", "/search"),
+                ("user-agent", "sqlmap/1.7"),
+            ]),
+        )
+        .unwrap();
+    assert_eq!(
+        match_result.map(|match_result| match_result.rule.id),
+        Some(2)
+    );
+    assert_eq!(transaction.rule_engine, None);
+}
+
+#[test]
+fn no_rules_are_evaluated_after_ctl_rule_engine_off() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"
+SecRule REQUEST_FILENAME "@streq /health" "id:1,phase:1,pass,ctl:ruleEngine=Off"
+SecRule REQUEST_HEADERS:User-Agent "@contains sqlmap" "id:2,phase:1,pass,setvar:tx.score=+5"
+SecRule ARGS "@contains <script>" "id:3,phase:2,deny"
+"#,
+    )
+    .unwrap();
+
+    let mut transaction = Transaction::default();
+    let match_result = engine
+        .run_header_phase(
+            &mut transaction,
+            headers(&[
+                (":method", "GET"),
+                (":path", "/health"),
+                ("user-agent", "sqlmap/1.7"),
+            ]),
+        )
+        .unwrap();
+    assert_eq!(match_result, None);
+    assert_eq!(transaction.rule_engine, Some(RuleEngineMode::Off));
+    assert_eq!(transaction.tx_var("score"), None);
+
+    let matched_rules = engine
+        .run_args_phase_all(&mut transaction, "q=%3Cscript%3E")
+        .unwrap();
+    assert!(matched_rules.is_empty());
+}
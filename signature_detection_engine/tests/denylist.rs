@@ -0,0 +1,198 @@
+mod common;
+
+use common::{headers, user_agent_headers};
+use signature_detection_engine::SignatureBasedDetectionEngine;
+use signature_detection_engine::transaction::Transaction;
+
+// A static blocklist of User-Agents, which the engine looks up rather than
+// evaluating rule by rule.
+fn blocklist(count: u32) -> String {
+    (0..count)
+        .map(|index| {
+            format!(
+                "SecRule REQUEST_HEADERS:User-Agent \"@streq bad-agent-{}\" \"id:{},phase:1,deny,msg:'blocklisted agent'\"\n",
+                index,
+                10_000 + index
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn blocklisted_values_match_like_evaluated_rules() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(&blocklist(1000)).unwrap();
+
+    let match_result = engine
+        .run_header_phase(
+            &mut Transaction::default(),
+            user_agent_headers("bad-agent-742"),
+        )
+        .unwrap()
+        .expect("blocklisted agent");
+    assert_eq!(match_result.rule.id, 10_742);
+    assert_eq!(match_result.matched_var, "REQUEST_HEADERS:user-agent");
+    assert_eq!(match_result.matched_value, "bad-agent-742");
+    assert_eq!(match_result.match_count, 1);
+    assert!(
+        match_result
+            .rule
+            .matches_headers(&user_agent_headers("bad-agent-742"))
+            .unwrap()
+    );
+
+    // @streq is case sensitive, while header names aren't
+    for user_agent in ["BAD-AGENT-742", "bad-agent-7420", "bad-agent-742 "] {
+        let match_result = engine
+            .run_header_phase(&mut Transaction::default(), user_agent_headers(user_agent))
+            .unwrap();
+        assert_eq!(match_result, None, "{}", user_agent);
+    }
+}
+
+#[test]
+fn blocklisted_rules_fire_in_declaration_order() {
+    let rules = format!(
+        r#"SecRule REQUEST_HEADERS:X-Health "@streq 1" "id:1,phase:1,allow"
+SecRule REQUEST_HEADERS:User-Agent "@streq bad-agent-5" "id:2,phase:1,pass,setvar:tx.seen=1"
+{}SecRule REQUEST_HEADERS:User-Agent "@contains bad-agent" "id:3,phase:1,deny""#,
+        blocklist(10)
+    );
+    let engine = SignatureBasedDetectionEngine::from_conf_str(&rules).unwrap();
+
+    // the pass rule fires before the blocklisted rule blocks
+    let mut transaction = Transaction::default();
+    let match_result = engine
+        .run_header_phase(&mut transaction, user_agent_headers("bad-agent-5"))
+        .unwrap();
+    assert_eq!(match_result.map(|m| m.rule.id), Some(10_005));
+    assert_eq!(transaction.tx_var("seen"), Some("1"));
+
+    // an earlier allow rule still lets the request through
+    let allowed = headers(&[("user-agent", "bad-agent-5"), ("x-health", "1")]);
+    let match_result = engine
+        .run_header_phase(&mut Transaction::default(), allowed)
+        .unwrap();
+    assert_eq!(match_result, None);
+
+    let matched_rules = engine
+        .run_header_phase_all(
+            &mut Transaction::default(),
+            user_agent_headers("bad-agent-5"),
+        )
+        .unwrap();
+    let rule_ids: Vec<u32> = matched_rules.iter().map(|m| m.rule.id).collect();
+    assert_eq!(rule_ids, vec![2, 10_005, 3]);
+}
+
+#[test]
+fn blocklisted_rules_without_an_id_are_told_apart() {
+    // both chains continue with a rule without an ID, only one of which can
+    // be blocklisted
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule REQUEST_HEADERS:X-A "@streq a" "id:1,phase:1,deny,chain"
+SecRule REQUEST_HEADERS:X-B "@streq b" "t:none"
+SecRule REQUEST_HEADERS:X-C "@streq c" "id:2,phase:1,deny,chain"
+SecRule REQUEST_HEADERS:X-D "@rx evil" "t:none""#,
+    )
+    .unwrap();
+
+    for (request_headers, rule_id) in [
+        (&[("x-c", "c"), ("x-d", "evil")], Some(2)),
+        (&[("x-a", "a"), ("x-b", "b")], Some(1)),
+        (&[("x-c", "c"), ("x-b", "b")], None),
+    ] {
+        let matched_rules = engine
+            .run_header_phase_all(&mut Transaction::default(), headers(request_headers))
+            .unwrap();
+        assert_eq!(
+            matched_rules.first().map(|m| m.rule.id),
+            rule_id,
+            "{:?}",
+            request_headers
+        );
+        assert_eq!(matched_rules.len(), usize::from(rule_id.is_some()));
+    }
+}
+
+#[test]
+fn excluded_blocklisted_rules_no_longer_match() {
+    let mut engine = SignatureBasedDetectionEngine::from_conf_str(&blocklist(10)).unwrap();
+    engine.apply_exclusions("SecRuleRemoveById 10003").unwrap();
+
+    let match_result = engine
+        .run_header_phase(
+            &mut Transaction::default(),
+            user_agent_headers("bad-agent-3"),
+        )
+        .unwrap();
+    assert_eq!(match_result, None);
+    let match_result = engine
+        .run_header_phase(
+            &mut Transaction::default(),
+            user_agent_headers("bad-agent-4"),
+        )
+        .unwrap();
+    assert_eq!(match_result.map(|m| m.rule.id), Some(10_004));
+}
+
+#[test]
+fn lowercased_values_are_blocklisted_case_insensitively() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule REQUEST_HEADERS:User-Agent "@streq bad-agent" "id:1,phase:1,deny,t:lowercase"
+SecRule REQUEST_HEADERS:User-Agent "@streq Bad-Bot" "id:2,phase:1,deny,t:lowercase""#,
+    )
+    .unwrap();
+
+    for user_agent in ["bad-agent", "BAD-AGENT", "Bad-Agent"] {
+        let match_result = engine
+            .run_header_phase(&mut Transaction::default(), user_agent_headers(user_agent))
+            .unwrap()
+            .expect(user_agent);
+        assert_eq!(match_result.rule.id, 1);
+        assert_eq!(match_result.matched_value, user_agent);
+        assert!(
+            match_result
+                .rule
+                .matches_headers(&user_agent_headers(user_agent))
+                .unwrap()
+        );
+    }
+
+    // the value is lowercased but the target isn't, so this never matches
+    for user_agent in ["Bad-Bot", "bad-bot"] {
+        let match_result = engine
+            .run_header_phase(&mut Transaction::default(), user_agent_headers(user_agent))
+            .unwrap();
+        assert_eq!(match_result, None, "{}", user_agent);
+        let sec_rule = engine.get_rule_by_id(2).unwrap();
+        assert!(
+            !sec_rule
+                .matches_headers(&user_agent_headers(user_agent))
+                .unwrap()
+        );
+    }
+}
+
+#[test]
+fn lowercased_values_with_multi_match_are_evaluated() {
+    // multiMatch also compares the value before it's lowercased
+    let rules = r#"SecRule REQUEST_HEADERS:User-Agent "@streq BadBot" "id:1,phase:1,deny,t:lowercase,multiMatch""#;
+    let engine = SignatureBasedDetectionEngine::from_conf_str(rules).unwrap();
+    assert!(
+        engine
+            .get_rule_by_id(1)
+            .unwrap()
+            .matches_headers(&user_agent_headers("BadBot"))
+            .unwrap()
+    );
+
+    let without_rule_index = SignatureBasedDetectionEngine::from_conf_str(rules)
+        .unwrap()
+        .without_rule_index();
+    for engine in [engine, without_rule_index] {
+        let match_result = engine
+            .run_header_phase(&mut Transaction::default(), user_agent_headers("BadBot"))
+            .unwrap();
+        assert_eq!(match_result.map(|m| m.rule.id), Some(1));
+    }
+}
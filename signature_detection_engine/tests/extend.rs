@@ -0,0 +1,74 @@
+mod common;
+
+use common::user_agent_headers;
+use signature_detection_engine::SignatureBasedDetectionEngine;
+use signature_detection_engine::errors::LoadErrors;
+use signature_detection_engine::transaction::Transaction;
+
+const BASE_RULES: &str = r#"
+SecRule REQUEST_HEADERS:User-Agent "@contains bot" "id:1,phase:1,deny"
+SecRule REQUEST_BODY "@contains DROP TABLE" "id:2,phase:2,deny"
+"#;
+
+#[test]
+fn extended_rules_are_evaluated_after_the_base_rules() {
+    let mut engine = SignatureBasedDetectionEngine::from_conf_str(BASE_RULES).unwrap();
+    let local = SignatureBasedDetectionEngine::from_conf_str(
+        r#"
+SecRule REQUEST_HEADERS:User-Agent "@contains evil" "id:100,phase:1,deny"
+SecRule RESPONSE_STATUS "@streq 500" "id:101,phase:3,deny"
+"#,
+    )
+    .unwrap();
+    engine.extend(local).unwrap();
+
+    let mut transaction = Transaction::default();
+    let match_result = engine
+        .run_header_phase(&mut transaction, user_agent_headers("evil-bot"))
+        .unwrap();
+    assert_eq!(
+        match_result.map(|match_result| match_result.rule.id),
+        Some(1)
+    );
+
+    let match_result = engine
+        .run_header_phase(&mut transaction, user_agent_headers("evil-crawler"))
+        .unwrap();
+    assert_eq!(
+        match_result.map(|match_result| match_result.rule.id),
+        Some(100)
+    );
+
+    let match_result = engine
+        .run_response_header_phase(&mut transaction, 500, Vec::new())
+        .unwrap();
+    assert_eq!(
+        match_result.map(|match_result| match_result.rule.id),
+        Some(101)
+    );
+}
+
+#[test]
+fn extending_with_duplicate_ids_leaves_the_engine_unchanged() {
+    let mut engine = SignatureBasedDetectionEngine::from_conf_str(BASE_RULES).unwrap();
+    let local = SignatureBasedDetectionEngine::from_conf_str(
+        r#"
+SecRule REQUEST_HEADERS:User-Agent "@contains evil" "id:2,phase:1,deny"
+SecRule REQUEST_HEADERS:User-Agent "@contains crawler" "id:3,phase:1,deny"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        engine.extend(local),
+        Err(LoadErrors::DuplicateRuleIds { ids: vec![2] })
+    );
+
+    let match_result = engine
+        .run_header_phase(
+            &mut Transaction::default(),
+            user_agent_headers("evil-crawler"),
+        )
+        .unwrap();
+    assert_eq!(match_result, None);
+}
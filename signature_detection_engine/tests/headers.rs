@@ -0,0 +1,144 @@
+mod common;
+
+use common::headers;
+use signature_detection_engine::transaction::Transaction;
+use signature_detection_engine::{SecRule, SignatureBasedDetectionEngine};
+
+#[test]
+fn targeted_rules_see_every_instance_of_a_header() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule REQUEST_HEADERS:X-Forwarded-For "@ipMatch 10.0.0.0/8" "id:1,phase:1,deny""#,
+    )
+    .unwrap();
+
+    let match_result = engine
+        .run_header_phase(
+            &mut Transaction::default(),
+            headers(&[
+                ("x-forwarded-for", "203.0.113.7"),
+                ("via", "1.1 proxy"),
+                ("X-Forwarded-For", "10.1.2.3"),
+            ]),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(match_result.rule.id, 1);
+    assert_eq!(match_result.matched_var, "REQUEST_HEADERS:X-Forwarded-For");
+    assert_eq!(match_result.matched_value, "10.1.2.3");
+
+    let match_result = engine
+        .run_header_phase(
+            &mut Transaction::default(),
+            headers(&[
+                ("x-forwarded-for", "203.0.113.7"),
+                ("x-forwarded-for", "198.51.100.1"),
+            ]),
+        )
+        .unwrap();
+    assert_eq!(match_result, None);
+}
+
+#[test]
+fn excluded_headers_exclude_every_instance() {
+    let sec_rule = SecRule::try_from(
+        r#"SecRule REQUEST_HEADERS|!REQUEST_HEADERS:Via "@contains evil" "id:2,phase:1,deny""#
+            .to_string(),
+    )
+    .unwrap();
+    assert!(
+        !sec_rule
+            .matches_headers(&headers(&[("via", "1.1 evil"), ("Via", "1.0 evil")]))
+            .unwrap()
+    );
+    assert!(
+        sec_rule
+            .matches_headers(&headers(&[("via", "1.1 evil"), ("x-evil", "evil")]))
+            .unwrap()
+    );
+}
+
+#[test]
+fn repeated_response_headers_are_each_evaluated() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule RESPONSE_HEADERS:Set-Cookie "@rx ^debug=" "id:3,phase:3,deny""#,
+    )
+    .unwrap();
+
+    let match_result = engine
+        .run_response_header_phase(
+            &mut Transaction::default(),
+            200,
+            headers(&[
+                ("set-cookie", "session=abc; Secure; HttpOnly"),
+                ("set-cookie", "debug=1"),
+            ]),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(match_result.rule.id, 3);
+    assert_eq!(match_result.matched_value, "debug=1");
+}
+
+#[test]
+fn encoded_payloads_in_custom_headers_are_decoded_by_url_decode() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"
+SecRule REQUEST_HEADERS:X-Search "@contains <script>" "id:4,phase:1,deny,t:urlDecode"
+SecRule REQUEST_HEADERS:X-Raw-Search "@contains <script>" "id:5,phase:1,deny"
+"#,
+    )
+    .unwrap();
+
+    let match_result = engine
+        .run_header_phase(
+            &mut Transaction::default(),
+            headers(&[("x-search", "%3Cscript%3Ealert(1)%3C/script%3E")]),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(match_result.rule.id, 4);
+    assert_eq!(match_result.matched_var, "REQUEST_HEADERS:x-search");
+    assert_eq!(
+        match_result.matched_value,
+        "%3Cscript%3Ealert(1)%3C/script%3E"
+    );
+
+    // without the transformation the raw value is matched
+    let match_result = engine
+        .run_header_phase(
+            &mut Transaction::default(),
+            headers(&[("x-raw-search", "%3Cscript%3Ealert(1)%3C/script%3E")]),
+        )
+        .unwrap();
+    assert_eq!(match_result, None);
+}
+
+#[test]
+fn cookies_are_matched_by_name_and_decoded_by_url_decode() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule REQUEST_COOKIES|!REQUEST_COOKIES:session "@contains ' or 1=1" "id:6,phase:1,deny,t:urlDecode,t:lowercase""#,
+    )
+    .unwrap();
+
+    let match_result = engine
+        .run_header_phase(
+            &mut Transaction::default(),
+            headers(&[
+                ("cookie", "session=%27%20OR%201=1; theme=dark"),
+                ("cookie", "user=%27%20OR%201%3D1--"),
+            ]),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(match_result.rule.id, 6);
+    assert_eq!(match_result.matched_var, "REQUEST_COOKIES:user");
+    assert_eq!(match_result.matched_value, "%27%20OR%201%3D1--");
+
+    let match_result = engine
+        .run_header_phase(
+            &mut Transaction::default(),
+            headers(&[("cookie", "session=%27%20OR%201=1; theme=dark")]),
+        )
+        .unwrap();
+    assert_eq!(match_result, None);
+}
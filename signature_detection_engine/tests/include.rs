@@ -0,0 +1,136 @@
+use std::fs;
+use std::path::PathBuf;
+
+use signature_detection_engine::SignatureBasedDetectionEngine;
+use signature_detection_engine::errors::{LoadErrors, ValidationErrors};
+use signature_detection_engine::transaction::Transaction;
+
+// Writes the files to a fresh directory, named after the test, and returns its
+// path.
+fn rules_dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("portkullis-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (name, contents) in files {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    dir
+}
+
+fn header_rule_ids(engine: &SignatureBasedDetectionEngine, user_agent: &str) -> Vec<u32> {
+    engine
+        .run_header_phase_all(
+            &mut Transaction::default(),
+            vec![("user-agent".to_string(), user_agent.to_string())],
+        )
+        .unwrap()
+        .iter()
+        .map(|match_result| match_result.rule.id)
+        .collect()
+}
+
+fn header_rule(id: u32, user_agent: &str) -> String {
+    format!(
+        "SecRule REQUEST_HEADERS:User-Agent \"@contains {}\" \"id:{},phase:1,pass\"\n",
+        user_agent, id
+    )
+}
+
+#[test]
+fn included_files_are_loaded_in_sorted_order() {
+    let dir = rules_dir(
+        "sorted",
+        &[
+            (
+                "main.conf",
+                &format!(
+                    "{}Include rules/*.conf\n{}",
+                    header_rule(1, "bot"),
+                    header_rule(4, "bot")
+                ),
+            ),
+            ("rules/REQUEST-920.conf", &header_rule(3, "bot")),
+            ("rules/REQUEST-910.conf", &header_rule(2, "bot")),
+            ("rules/README.md", "not a rules file"),
+        ],
+    );
+
+    let engine = SignatureBasedDetectionEngine::from_conf_file(dir.join("main.conf")).unwrap();
+    assert_eq!(header_rule_ids(&engine, "bot"), vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn includes_are_relative_to_the_including_file() {
+    let dir = rules_dir(
+        "relative",
+        &[
+            ("main.conf", "Include crs/crs.conf\n"),
+            ("crs/crs.conf", "Include \"rules/bots.conf\"\n"),
+            ("crs/rules/bots.conf", &header_rule(1, "bot")),
+        ],
+    );
+
+    let engine = SignatureBasedDetectionEngine::from_conf_file(dir.join("main.conf")).unwrap();
+    assert_eq!(header_rule_ids(&engine, "bot"), vec![1]);
+}
+
+#[test]
+fn missing_includes_fail_unless_optional() {
+    let dir = rules_dir(
+        "missing",
+        &[
+            ("optional.conf", "IncludeOptional local/*.conf\n"),
+            ("required.conf", "\nInclude local/*.conf\n"),
+        ],
+    );
+
+    let engine = SignatureBasedDetectionEngine::from_conf_file(dir.join("optional.conf")).unwrap();
+    assert!(header_rule_ids(&engine, "bot").is_empty());
+    assert!(matches!(
+        SignatureBasedDetectionEngine::from_conf_file(dir.join("required.conf")),
+        Err(LoadErrors::MissingInclude { line: 2, .. })
+    ));
+}
+
+#[test]
+fn include_cycles_are_rejected() {
+    let dir = rules_dir(
+        "cycle",
+        &[
+            ("a.conf", "Include b.conf\n"),
+            ("b.conf", "Include a.conf\n"),
+        ],
+    );
+
+    let error = SignatureBasedDetectionEngine::from_conf_file(dir.join("a.conf")).unwrap_err();
+    let LoadErrors::InIncludedFile { path, error } = error else {
+        panic!("expected an error in b.conf, got {:?}", error);
+    };
+    assert!(path.ends_with("b.conf"));
+    assert!(matches!(*error, LoadErrors::IncludeCycle { line: 1, .. }));
+}
+
+#[test]
+fn errors_in_included_files_name_the_file() {
+    let dir = rules_dir(
+        "invalid",
+        &[
+            ("main.conf", "Include rules.conf\n"),
+            ("rules.conf", "\n\nSecRule ARGS\n"),
+        ],
+    );
+
+    let error = SignatureBasedDetectionEngine::from_conf_file(dir.join("main.conf")).unwrap_err();
+    let LoadErrors::InIncludedFile { path, error } = error else {
+        panic!("expected an error in rules.conf, got {:?}", error);
+    };
+    assert!(path.ends_with("rules.conf"));
+    assert_eq!(
+        *error,
+        LoadErrors::InvalidRule {
+            line: 3,
+            error: ValidationErrors::MissingOperator
+        }
+    );
+}
@@ -0,0 +1,75 @@
+use signature_detection_engine::{Phase, SignatureBasedDetectionEngine};
+
+const RULES: &str = r#"
+SecRule RESPONSE_STATUS "@streq 500" "id:30,phase:3,deny"
+SecRule ARGS "@contains <script>" "id:20,phase:2,deny,tag:'attack/xss'"
+SecMarker END_OF_ARGS
+SecRule REQUEST_HEADERS:User-Agent "@contains bot" "id:10,phase:1,chain,deny,tag:'attack/scanner'"
+SecRule REQUEST_METHOD "@streq POST"
+SecRule REQUEST_HEADERS:X-Payload "@contains <script>" "id:11,phase:1,deny,tag:'attack/xss'"
+"#;
+
+#[test]
+fn rules_are_iterated_in_phase_order() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(RULES).unwrap();
+
+    let rules: Vec<(Phase, u32)> = engine
+        .iter_rules()
+        .map(|(phase, sec_rule)| (phase, sec_rule.id))
+        .collect();
+    assert_eq!(
+        rules,
+        vec![
+            (Phase::RequestHeaders, 10),
+            (Phase::RequestHeaders, 0),
+            (Phase::RequestHeaders, 11),
+            (Phase::RequestBody, 20),
+            (Phase::ResponseHeaders, 30),
+        ]
+    );
+    assert_eq!(engine.rule_count(), 5);
+}
+
+#[test]
+fn rules_are_looked_up_by_id() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(RULES).unwrap();
+
+    let sec_rule = engine.get_rule_by_id(20).unwrap();
+    assert_eq!(sec_rule.phase, Phase::RequestBody);
+    assert_eq!(sec_rule.tags, vec!["attack/xss".to_string()]);
+    assert_eq!(engine.get_rule_by_id(0), None);
+    assert_eq!(engine.get_rule_by_id(40), None);
+
+    let ids: Vec<u32> = engine
+        .rules_by_id_range(11..=30)
+        .iter()
+        .map(|sec_rule| sec_rule.id)
+        .collect();
+    assert_eq!(ids, vec![11, 20, 30]);
+}
+
+#[test]
+fn rules_are_looked_up_by_tag() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(RULES).unwrap();
+
+    let ids: Vec<u32> = engine
+        .rules_by_tag("attack/xss")
+        .iter()
+        .map(|sec_rule| sec_rule.id)
+        .collect();
+    assert_eq!(ids, vec![11, 20]);
+    assert!(engine.rules_by_tag("attack").is_empty());
+}
+
+#[test]
+fn lookups_follow_removed_rules() {
+    let mut engine = SignatureBasedDetectionEngine::from_conf_str(RULES).unwrap();
+    engine.apply_exclusions("SecRuleRemoveById 10").unwrap();
+
+    assert_eq!(engine.get_rule_by_id(10), None);
+    assert_eq!(
+        engine.get_rule_by_id(11).map(|sec_rule| sec_rule.id),
+        Some(11)
+    );
+    assert_eq!(engine.rules_by_tag("attack/scanner"), Vec::<&_>::new());
+}
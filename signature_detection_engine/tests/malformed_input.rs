@@ -0,0 +1,163 @@
+use signature_detection_engine::SignatureBasedDetectionEngine;
+use signature_detection_engine::transaction::Transaction;
+
+// A panic in the engine aborts the proxy's WASM VM, so every phase has to
+// handle arbitrary input by returning a result. These feed malformed input
+// through rules exercising every operator and transformation, where any panic
+// fails the test.
+
+const RULES: &str = r#"
+SecRule REQUEST_HEADERS|REQUEST_METHOD|REQUEST_FILENAME|REQUEST_BASENAME "@detectSQLi" "id:1,phase:1,pass,capture,logdata:'%{MATCHED_VAR} %{TX.0} %{REQUEST_URI}',t:urlDecodeUni,t:htmlEntityDecode,t:removeComments,multiMatch"
+SecRule REQUEST_HEADERS "@detectXSS" "id:2,phase:1,pass,t:urlDecode,t:htmlEntityDecode,t:lowercase"
+SecRule REQUEST_HEADERS "@rx (?i)(a+)+(b*)" "id:3,phase:1,pass,capture,setvar:'tx.captured=%{TX.1}',t:removeNulls,t:replaceNulls"
+SecRule REMOTE_ADDR "@ipMatch 10.0.0.0/8,::1" "id:4,phase:1,pass"
+SecRule ARGS|QUERY_STRING "@validateUtf8Encoding" "id:5,phase:2,pass,t:urlDecode"
+SecRule ARGS "@validateByteRange 32-126" "id:6,phase:2,pass,setvar:tx.score=+%{MATCHED_VAR}"
+SecRule REQUEST_BODY "@contains %{" "id:7,phase:2,pass,capture,msg:'%{MATCHED_VAR_NAME} %{TX.score}',t:urlDecodeUni,multiMatch"
+SecRule REQUEST_BODY "@within a b c" "id:8,phase:2,pass,t:htmlEntityDecode"
+SecRule TX "@streq %{}" "id:9,phase:2,pass,setvar:'tx.%{TX.captured}=-1'"
+SecRule RESPONSE_STATUS|RESPONSE_HEADERS "@rx ^5" "id:10,phase:3,pass,logdata:'%{TX.missing'"
+SecRule RESPONSE_STATUS|REMOTE_ADDR|TX "@rx ." "id:11,phase:5,pass"
+SecRule RESPONSE_BODY "@rxGlobal (?i)secret" "id:12,phase:4,pass,capture,t:hexDecode,t:lowercase"
+"#;
+
+// Truncated and overlong encodings, unterminated comments and entities, and
+// bytes which aren't valid UTF-8.
+fn malformed_inputs() -> Vec<Vec<u8>> {
+    let mut inputs: Vec<Vec<u8>> = [
+        "",
+        "%",
+        "%%",
+        "%2",
+        "%zz",
+        "%u",
+        "%u12",
+        "%uD800",
+        "%C0%AF",
+        "%00",
+        "&",
+        "&#",
+        "&#x",
+        "&#xFFFFFFFFFF;",
+        "&#99999999999;",
+        "&amp",
+        "/*",
+        "<!--",
+        "--",
+        "#",
+        "?",
+        "=",
+        "&&==&&",
+        "a=%",
+        "%{",
+        "%{}",
+        "%{TX.",
+        "'\"\\",
+        "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaac",
+        "/",
+        "//",
+        "/a/b/",
+        "1' OR '1'='1",
+        "<script>",
+        "\u{feff}\u{200b}",
+    ]
+    .iter()
+    .map(|input| input.as_bytes().to_vec())
+    .collect();
+    inputs.extend([
+        vec![0xff, 0xfe, 0xfd],
+        vec![0xc0],
+        vec![0xe2, 0x82],
+        vec![b'%', 0xff],
+        vec![b'&', b'#', 0xf0, b';'],
+        vec![0; 64],
+        b"%".repeat(1_000),
+        b"&#".repeat(1_000),
+        b"/*".repeat(1_000),
+    ]);
+
+    // plus a deterministic sample of random bytes, biased towards the
+    // characters the decoders treat specially
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let alphabet = b"%&#;/*-<!>?=xuX0123456789abcdefABCDEF'\"\\ \x00\xff\xc3";
+    for length in 0..128 {
+        let input = (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                alphabet[(state % alphabet.len() as u64) as usize]
+            })
+            .collect();
+        inputs.push(input);
+    }
+    inputs
+}
+
+fn engine() -> SignatureBasedDetectionEngine {
+    SignatureBasedDetectionEngine::from_conf_str(RULES)
+        .unwrap()
+        .with_audit_hook(|_| {})
+}
+
+#[test]
+fn malformed_input_does_not_panic_any_phase() {
+    let engine = engine();
+    for input in malformed_inputs() {
+        let text = String::from_utf8_lossy(&input).into_owned();
+        let headers = vec![
+            (":method".to_string(), text.clone()),
+            (":path".to_string(), text.clone()),
+            (text.clone(), text.clone()),
+        ];
+        let mut transaction = Transaction {
+            request_uri: Some(text.clone()),
+            ..Transaction::default()
+        };
+
+        let _ = engine.run_header_phase(&mut transaction, headers.clone());
+        let _ = engine.run_header_phase_all(&mut transaction, headers.clone());
+        let _ = engine.run_args_phase(&mut transaction, &text);
+        let _ = engine.run_args_phase_all(&mut transaction, &text);
+        let _ = engine.run_body_phase_bytes(&mut transaction, &input);
+        let _ = engine.run_body_phase_all_bytes(&mut transaction, &input);
+        let _ = engine.run_response_header_phase(&mut transaction, 599, headers.clone());
+        let _ = engine.run_response_header_phase_all(&mut transaction, 0, headers);
+        let _ = engine.run_response_body_phase(&mut transaction, &input);
+        let _ = engine.run_response_body_phase_all(&mut transaction, &input);
+        let _ = engine.run_logging_phase(&mut transaction);
+    }
+}
+
+#[test]
+fn malformed_rules_are_rejected_without_panicking() {
+    for input in malformed_inputs() {
+        let text = String::from_utf8_lossy(&input).into_owned();
+        for conf in [
+            text.clone(),
+            format!("SecRule {}", text),
+            format!("SecRule ARGS {}", text),
+            format!("SecRule ARGS \"@rx {}\" \"id:1,phase:2\"", text),
+            format!("SecRule ARGS \"@contains x\" \"{}\"", text),
+            format!("SecRuleRemoveById {}", text),
+            format!("SecRuleRemoveByTag {}", text),
+        ] {
+            let _ = SignatureBasedDetectionEngine::from_conf_str(&conf);
+        }
+        let _ = SignatureBasedDetectionEngine::from_json(&text);
+    }
+}
+
+#[test]
+fn setvar_saturates_instead_of_overflowing() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule REQUEST_HEADERS:X-Score "@rx ." "id:1,phase:1,pass,setvar:tx.high=9223372036854775807,setvar:tx.high=+%{MATCHED_VAR},setvar:tx.low=-9223372036854775807,setvar:tx.low=-%{MATCHED_VAR}""#,
+    )
+    .unwrap();
+
+    let mut transaction = Transaction::default();
+    let headers = vec![("x-score".to_string(), "9223372036854775807".to_string())];
+    engine.run_header_phase(&mut transaction, headers).unwrap();
+    assert_eq!(transaction.tx_var("high"), Some("9223372036854775807"));
+    assert_eq!(transaction.tx_var("low"), Some("-9223372036854775808"));
+}
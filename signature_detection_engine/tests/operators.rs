@@ -0,0 +1,204 @@
+use signature_detection_engine::transaction::Transaction;
+use signature_detection_engine::{SecRule, SignatureBasedDetectionEngine};
+
+fn query_header(query: &str) -> Vec<(String, String)> {
+    vec![("x-query".to_string(), query.to_string())]
+}
+
+#[test]
+fn rx_global_counts_every_match() {
+    // blocks when three or more SQL keywords appear, where a single keyword
+    // is common in ordinary text
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule REQUEST_HEADERS:X-Query "@rxGlobal \b(?:select|union|from|where)\b" "id:1,phase:1,pass,t:lowercase,setvar:'tx.sql_keywords=%{MATCHED_COUNT}'"
+SecRule TX:sql_keywords "@rx ^(?:[3-9]|[1-9][0-9]+)$" "id:2,phase:1,deny""#,
+    )
+    .unwrap();
+
+    let mut transaction = Transaction::default();
+    let match_result = engine
+        .run_header_phase(
+            &mut transaction,
+            query_header("SELECT name FROM users WHERE id=1"),
+        )
+        .unwrap();
+    assert_eq!(match_result.map(|m| m.rule.id), Some(2));
+    assert_eq!(transaction.tx_var("sql_keywords"), Some("3"));
+
+    let mut transaction = Transaction::default();
+    let match_result = engine
+        .run_header_phase(&mut transaction, query_header("select a size"))
+        .unwrap();
+    assert_eq!(match_result, None);
+    assert_eq!(transaction.tx_var("sql_keywords"), Some("1"));
+}
+
+#[test]
+fn rx_global_captures_every_match() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule REQUEST_HEADERS:X-Query "@rxGlobal (\w+)=(\d+)" "id:1,phase:1,pass,capture""#,
+    )
+    .unwrap();
+
+    let mut transaction = Transaction::default();
+    let matched_rules = engine
+        .run_header_phase_all(&mut transaction, query_header("a=1&b=2&c=x&d=4"))
+        .unwrap();
+    assert_eq!(matched_rules[0].match_count, 3);
+    let captures: Vec<_> = (0..10)
+        .map(|index| transaction.tx_var(&index.to_string()))
+        .collect();
+    assert_eq!(
+        captures,
+        vec![
+            Some("a=1"),
+            Some("a"),
+            Some("1"),
+            Some("b=2"),
+            Some("b"),
+            Some("2"),
+            Some("d=4"),
+            Some("d"),
+            Some("4"),
+            None
+        ]
+    );
+}
+
+#[test]
+fn rx_captures_only_the_first_match() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule REQUEST_HEADERS:X-Query "@rx (\w+)=(\d+)" "id:1,phase:1,pass,capture""#,
+    )
+    .unwrap();
+
+    let mut transaction = Transaction::default();
+    let matched_rules = engine
+        .run_header_phase_all(&mut transaction, query_header("a=1&b=2"))
+        .unwrap();
+    assert_eq!(matched_rules[0].match_count, 1);
+    assert_eq!(transaction.tx_var("0"), Some("a=1"));
+    assert_eq!(transaction.tx_var("3"), None);
+}
+
+#[test]
+fn rx_global_with_multi_match_counts_the_stage_which_matched() {
+    // only the decoded value has the keywords
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule REQUEST_HEADERS:X-Query "@rxGlobal \bor\b" "id:1,phase:1,pass,t:urlDecode,multiMatch""#,
+    )
+    .unwrap();
+
+    let matched_rules = engine
+        .run_header_phase_all(
+            &mut Transaction::default(),
+            query_header("1%20or%201%20or%202"),
+        )
+        .unwrap();
+    assert_eq!(matched_rules[0].match_count, 2);
+}
+
+#[test]
+fn rx_is_case_sensitive_unless_the_rule_says_otherwise() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule REQUEST_HEADERS:X-Query "@rx UNION SELECT" "id:1,phase:1,deny"
+SecRule REQUEST_HEADERS:X-Query "@rx (?i)drop table" "id:2,phase:1,deny"
+SecRule REQUEST_HEADERS:X-Query "@rx insert into" "id:3,phase:1,deny,t:lowercase""#,
+    )
+    .unwrap();
+
+    for (query, id) in [
+        ("1 UNION SELECT 2", Some(1)),
+        ("1 union select 2", None),
+        ("1; DROP TABLE users", Some(2)),
+        ("1; INSERT INTO users", Some(3)),
+    ] {
+        let match_result = engine
+            .run_header_phase(&mut Transaction::default(), query_header(query))
+            .unwrap();
+        assert_eq!(match_result.map(|m| m.rule.id), id, "{}", query);
+    }
+}
+
+#[test]
+fn rx_matches_anywhere_unless_anchored() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule REQUEST_HEADERS:X-Query "@rx admin" "id:1,phase:1,pass,setvar:tx.unanchored=1"
+SecRule REQUEST_HEADERS:X-Query "@rx ^admin$" "id:2,phase:1,pass,setvar:tx.anchored=1""#,
+    )
+    .unwrap();
+
+    let mut transaction = Transaction::default();
+    engine
+        .run_header_phase(&mut transaction, query_header("/superadmin/users"))
+        .unwrap();
+    assert_eq!(transaction.tx_var("unanchored"), Some("1"));
+    assert_eq!(transaction.tx_var("anchored"), None);
+
+    let mut transaction = Transaction::default();
+    engine
+        .run_header_phase(&mut transaction, query_header("admin"))
+        .unwrap();
+    assert_eq!(transaction.tx_var("anchored"), Some("1"));
+}
+
+#[test]
+fn only_contains_compares_case_insensitively() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule REQUEST_HEADERS:X-Query "@contains Select" "id:1,phase:1,pass,setvar:tx.contains=1"
+SecRule REQUEST_HEADERS:X-Query "@streq Select" "id:2,phase:1,pass,setvar:tx.streq=1"
+SecRule REQUEST_HEADERS:X-Query "@streq select" "id:3,phase:1,pass,t:lowercase,setvar:tx.streq_lowercase=1"
+SecRule REQUEST_HEADERS:X-Query "@within Select Union" "id:4,phase:1,pass,setvar:tx.within=1""#,
+    )
+    .unwrap();
+
+    let mut transaction = Transaction::default();
+    engine
+        .run_header_phase(&mut transaction, query_header("SELECT"))
+        .unwrap();
+    assert_eq!(transaction.tx_var("contains"), Some("1"));
+    assert_eq!(transaction.tx_var("streq"), None);
+    assert_eq!(transaction.tx_var("streq_lowercase"), Some("1"));
+    assert_eq!(transaction.tx_var("within"), None);
+}
+
+#[test]
+fn detect_xss_matches_attribute_injections_but_not_prose() {
+    let sec_rule =
+        SecRule::try_from(r#"SecRule REQUEST_BODY "@detectXSS" "id:1,phase:2,deny""#.to_string())
+            .unwrap();
+
+    for attack in [
+        "<script>alert(1)</script>",
+        "<img src=x onerror=alert(1)>",
+        "\" onmouseover=\"alert(1)",
+        "' autofocus onfocus='alert(1)",
+        "x onmouseover=alert(1)",
+        "x><svg onload=alert(1)>",
+        "x <script>alert(1)</script>",
+        "javascript:alert(1)",
+    ] {
+        assert!(
+            sec_rule.matches_body(attack.as_bytes()).unwrap(),
+            "{}",
+            attack
+        );
+    }
+
+    for prose in [
+        "the one and only",
+        "hello online shopping",
+        "Call me once",
+        "it's on the house, isn't it? only once",
+        "\"quoted\" text about onions",
+        "javascript: the good parts",
+        "don't shop online, it's one click too many",
+        "a > b and b < c, so online is one option",
+    ] {
+        assert!(
+            !sec_rule.matches_body(prose.as_bytes()).unwrap(),
+            "{}",
+            prose
+        );
+    }
+}
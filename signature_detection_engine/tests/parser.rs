@@ -0,0 +1,404 @@
+mod common;
+
+use common::{header_match, rule};
+use signature_detection_engine::errors::{LoadErrors, ValidationErrors};
+use signature_detection_engine::transaction::Transaction;
+use signature_detection_engine::{
+    DisruptiveAction, ParseOptions, Phase, SecRule, SignatureBasedDetectionEngine,
+};
+
+#[test]
+fn escaped_quotes_in_the_operator_are_literal_quotes() {
+    let sec_rule = rule(r#"SecRule REQUEST_HEADERS "@rx user-agent:\s*\"sqlmap\"" "id:1,phase:1""#);
+    assert_eq!(
+        sec_rule.operator_target.as_deref(),
+        Some(r#"user-agent:\s*"sqlmap""#)
+    );
+}
+
+#[test]
+fn regex_escapes_reach_the_operator() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule REQUEST_HEADERS:X-Probe "@rx user-agent:\s*\"sqlmap\"" "id:1,phase:1,deny""#,
+    )
+    .unwrap();
+    assert_eq!(
+        header_match(&engine, "X-Probe", r#"user-agent:   "sqlmap""#).map(|m| m.rule.id),
+        Some(1)
+    );
+    assert_eq!(
+        header_match(&engine, "X-Probe", r#"user-agent: sqlmap"#).map(|m| m.rule.id),
+        None
+    );
+
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule REQUEST_HEADERS:X-Id "@rx ^\d+$" "id:2,phase:1,deny""#,
+    )
+    .unwrap();
+    assert_eq!(
+        header_match(&engine, "X-Id", "12345").map(|m| m.rule.id),
+        Some(2)
+    );
+    assert_eq!(
+        header_match(&engine, "X-Id", "ddd").map(|m| m.rule.id),
+        None
+    );
+}
+
+#[test]
+fn whitespace_inside_the_operator_is_kept() {
+    let sec_rule = rule(r#"SecRule REQUEST_BODY "@contains a  b	c" "id:1,phase:2""#);
+    assert_eq!(sec_rule.operator_target.as_deref(), Some("a  b\tc"));
+}
+
+#[test]
+fn escaped_quotes_in_the_actions() {
+    let sec_rule =
+        rule(r#"SecRule ARGS "@contains x" "id:1,phase:2,msg:'say \"hi\", it\'s fine',tag:'a,b'""#);
+    assert_eq!(sec_rule.message.as_deref(), Some(r#"say "hi", it's fine"#));
+    assert_eq!(sec_rule.tags, vec!["a,b".to_string()]);
+}
+
+#[test]
+fn continued_lines_are_joined() {
+    let sec_rule = rule(
+        "SecRule REQUEST_HEADERS:User-Agent \\\n    \"@rx \\bsqlmap\\b\" \\\n    \"id:1,\\\n    phase:1,\\\n    deny\"",
+    );
+    assert_eq!(sec_rule.operator_target.as_deref(), Some(r"\bsqlmap\b"));
+    assert_eq!(sec_rule.id, 1);
+}
+
+#[test]
+fn rules_with_quotes_round_trip() {
+    let sec_rule = rule(
+        r#"SecRule REQUEST_HEADERS "@rx user-agent:\s*\"sqlmap\"" "id:1,phase:1,deny,msg:'it\'s \"sqlmap\"'""#,
+    );
+    assert_eq!(rule(&sec_rule.to_string()), sec_rule);
+}
+
+#[test]
+fn arguments_are_identified_by_structure() {
+    let sec_rule =
+        rule(r#"SecRule "REQUEST_HEADERS:User-Agent" "@contains bad bot" "id:1,phase:1""#);
+    assert_eq!(
+        sec_rule.variables[0].to_string(),
+        "REQUEST_HEADERS:User-Agent"
+    );
+    assert_eq!(sec_rule.operator_target.as_deref(), Some("bad bot"));
+
+    let sec_rule = rule("SecRule REQUEST_METHOD ^TRACE$ id:1,phase:1,deny");
+    assert_eq!(sec_rule.operator_target.as_deref(), Some("^TRACE$"));
+    assert_eq!(sec_rule.id, 1);
+}
+
+#[test]
+fn actions_are_optional() {
+    let sec_rule = rule(r#"SecRule REQUEST_METHOD "@streq TRACE""#);
+    assert_eq!(sec_rule.operator_target.as_deref(), Some("TRACE"));
+    assert_eq!(sec_rule.id, 0);
+}
+
+#[test]
+fn operators_without_an_operator_name_are_regular_expressions() {
+    let sec_rule = rule(r#"SecRule REQUEST_METHOD "!^(GET|POST)$" "id:1,phase:1""#);
+    assert_eq!(sec_rule.operator.to_string(), "@rx");
+    assert_eq!(sec_rule.operator_target.as_deref(), Some("^(GET|POST)$"));
+    assert!(sec_rule.negated);
+}
+
+#[test]
+fn malformed_rules_are_reported() {
+    let error = |rule: &str| SecRule::try_from(rule.to_string()).unwrap_err();
+
+    assert_eq!(error("SecRule"), ValidationErrors::MissingVariable);
+    assert_eq!(error("SecRule ARGS"), ValidationErrors::MissingOperator);
+    assert_eq!(
+        error(r#"SecRule ARGS "@contains x" "id:1" "id:2""#),
+        ValidationErrors::UnexpectedArgument {
+            found: "id:2".to_string()
+        }
+    );
+    assert_eq!(
+        error(r#"SecRule ARGS "@contains x" "id:1"#),
+        ValidationErrors::UnterminatedQuote {
+            value: "id:1".to_string()
+        }
+    );
+    assert_eq!(
+        error(r#"SecAction "id:1""#),
+        ValidationErrors::InvalidDirective {
+            found: "SecAction".to_string()
+        }
+    );
+}
+
+#[test]
+fn metadata_directives_and_comments_are_skipped() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"# ------------------------------------------------------------------------
+# OWASP CRS ver.4.0.0
+# ------------------------------------------------------------------------
+
+SecComponentSignature "OWASP_CRS/4.0.0"
+
+SecRule REQUEST_HEADERS:User-Agent "@contains sqlmap" "id:1,phase:1,deny"
+    # a comment indented under a rule
+SecMarker "END-SCANNER-DETECTION"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        header_match(&engine, "User-Agent", "sqlmap/1.7").map(|m| m.rule.id),
+        Some(1)
+    );
+    let skipped: Vec<_> = engine
+        .skipped_directives()
+        .iter()
+        .map(|skipped| (skipped.line, skipped.name.as_str()))
+        .collect();
+    assert_eq!(
+        skipped,
+        vec![(5, "SecComponentSignature"), (9, "SecMarker")]
+    );
+}
+
+#[test]
+fn unsupported_directives_are_rejected() {
+    let result = SignatureBasedDetectionEngine::from_conf_str(
+        "SecRule ARGS \"@contains x\" \"id:1,phase:2,deny\"\nSecAction \"id:2,phase:2,pass\"",
+    );
+    assert!(matches!(
+        result,
+        Err(LoadErrors::InvalidRule {
+            line: 2,
+            error: ValidationErrors::InvalidDirective { .. }
+        })
+    ));
+}
+
+#[test]
+fn every_invalid_rule_is_reported() {
+    let conf = r#"
+SecRule ARGS "@contains x" "id:1,phase:2,deny"
+SecRule ARGS "@unknown x" "id:2,phase:2,deny"
+SecRule ARGS "@contains x" "id:3,phase:9,deny"
+SecRuleRemoveById abc
+"#;
+    let Err(LoadErrors::Multiple { errors }) = SignatureBasedDetectionEngine::from_conf_str(conf)
+    else {
+        panic!("expected every invalid rule to be reported");
+    };
+    let lines: Vec<usize> = errors
+        .iter()
+        .map(|error| match error {
+            LoadErrors::InvalidRule { line, .. } => *line,
+            error => panic!("unexpected error: {}", error),
+        })
+        .collect();
+    assert_eq!(lines, vec![3, 4, 5]);
+}
+
+#[test]
+fn example_rules_load() {
+    let engine = SignatureBasedDetectionEngine::new_example().unwrap();
+    assert_eq!(
+        header_match(&engine, "user-agent", "malicious-bot").map(|m| m.rule.id),
+        Some(1001)
+    );
+}
+
+#[test]
+fn status_must_be_an_http_status() {
+    let sec_rule = rule(r#"SecRule ARGS "@contains x" "id:1,phase:2,deny,status:429""#);
+    assert_eq!(sec_rule.status, Some(429));
+
+    for status in ["99", "600", "4o3", ""] {
+        let rule = format!(
+            r#"SecRule ARGS "@contains x" "id:1,phase:2,deny,status:{}""#,
+            status
+        );
+        assert_eq!(
+            SecRule::try_from(rule),
+            Err(ValidationErrors::InvalidStatus {
+                value: status.to_string()
+            })
+        );
+    }
+}
+
+#[test]
+fn regexes_which_compile_too_large_are_rejected() {
+    let rule = r#"SecRule ARGS "@rx (?:\w{100}){100}" "id:1,phase:2,deny""#;
+    assert!(matches!(
+        SecRule::try_from(rule.to_string()),
+        Err(ValidationErrors::RegexTooComplex { .. })
+    ));
+
+    // the limit is configurable
+    let rule = r#"SecRule ARGS "@rx ^\w{20}$" "id:1,phase:2,deny""#;
+    assert!(SignatureBasedDetectionEngine::from_conf_str(rule).is_ok());
+    let options = ParseOptions {
+        regex_size_limit: 1024,
+        ..ParseOptions::default()
+    };
+    assert!(matches!(
+        SignatureBasedDetectionEngine::from_conf_str_with_options(rule, options),
+        Err(LoadErrors::InvalidRule {
+            line: 1,
+            error: ValidationErrors::RegexTooComplex { limit: 1024, .. }
+        })
+    ));
+}
+
+#[test]
+fn regexes_with_backreferences_are_rejected() {
+    let rule = r#"SecRule ARGS "@rx (['\"]).*\1" "id:1,phase:2,deny""#;
+    assert!(matches!(
+        SecRule::try_from(rule.to_string()),
+        Err(ValidationErrors::InvalidRegex { .. })
+    ));
+}
+
+#[test]
+fn none_clears_the_transformations_before_it() {
+    let sec_rule =
+        rule(r#"SecRule ARGS "@contains x" "id:1,phase:2,deny,t:lowercase,t:none,t:urlDecode""#);
+    assert_eq!(sec_rule.transformations, vec!["urlDecode".to_string()]);
+
+    let sec_rule = rule(r#"SecRule ARGS "@contains x" "id:1,phase:2,deny,t:lowercase,t:None""#);
+    assert!(sec_rule.transformations.is_empty());
+}
+
+#[test]
+fn rules_inherit_the_default_actions_of_their_phase() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"
+SecDefaultAction "phase:1,log,t:lowercase,deny,status:418"
+SecDefaultAction "phase:2,log,pass"
+SecRule REQUEST_HEADERS:User-Agent "@contains bot" "id:1,phase:1"
+SecRule REQUEST_HEADERS:User-Agent "@contains crawler" "id:2,phase:1,t:none,status:403"
+"#,
+    )
+    .unwrap();
+
+    let rule = header_match(&engine, "user-agent", "EvilBot").unwrap().rule;
+    assert_eq!(rule.id, 1);
+    assert_eq!(rule.action, Some(DisruptiveAction::Deny));
+    assert_eq!(rule.status, Some(418));
+    assert_eq!(rule.transformations, vec!["lowercase".to_string()]);
+
+    // the rule's own actions override the defaults
+    let rule = header_match(&engine, "user-agent", "crawler").unwrap().rule;
+    assert_eq!(rule.id, 2);
+    assert_eq!(rule.action, Some(DisruptiveAction::Deny));
+    assert_eq!(rule.status, Some(403));
+    assert!(rule.transformations.is_empty());
+}
+
+#[test]
+fn default_actions_apply_until_the_next_for_their_phase() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"
+SecRule REQUEST_HEADERS:User-Agent "@contains curl" "id:1,phase:1,deny"
+SecDefaultAction "phase:1,deny,status:418"
+SecRule REQUEST_HEADERS:User-Agent "@contains bot" "id:2,phase:1"
+SecDefaultAction "phase:1,deny,status:429"
+SecRule REQUEST_HEADERS:User-Agent "@contains crawler" "id:3,phase:1"
+"#,
+    )
+    .unwrap();
+
+    let status =
+        |user_agent| header_match(&engine, "user-agent", user_agent).map(|m| m.rule.status);
+    assert_eq!(status("curl"), Some(None));
+    assert_eq!(status("bot"), Some(Some(418)));
+    assert_eq!(status("crawler"), Some(Some(429)));
+}
+
+#[test]
+fn rules_without_a_phase_take_the_latest_default_phase() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"
+SecDefaultAction "phase:1,log,pass"
+SecDefaultAction "phase:2,log,deny,status:418"
+SecRule ARGS "@contains evil" "id:5"
+SecRule REQUEST_HEADERS:User-Agent "@contains bot" "id:6,phase:1"
+"#,
+    )
+    .unwrap();
+
+    let sec_rule = engine.get_rule_by_id(5).unwrap();
+    assert_eq!(sec_rule.phase, Phase::RequestBody);
+    assert_eq!(sec_rule.action, Some(DisruptiveAction::Deny));
+    assert_eq!(sec_rule.status, Some(418));
+    let match_result = engine
+        .run_args_phase(&mut Transaction::default(), "q=evil")
+        .unwrap();
+    assert_eq!(match_result.map(|m| m.rule.id), Some(5));
+
+    // a rule with a phase still takes the defaults of its own phase
+    let sec_rule = engine.get_rule_by_id(6).unwrap();
+    assert_eq!(sec_rule.phase, Phase::RequestHeaders);
+    assert_eq!(sec_rule.action, Some(DisruptiveAction::Pass));
+}
+
+#[test]
+fn invalid_default_actions_are_rejected() {
+    for (default_action, expected) in [
+        (
+            r#"SecDefaultAction "log,deny""#,
+            ValidationErrors::MissingDefaultPhase,
+        ),
+        (
+            r#"SecDefaultAction "phase:2,id:10,deny""#,
+            ValidationErrors::InvalidDefaultAction {
+                action: "id:10".to_string(),
+            },
+        ),
+        (
+            r#"SecDefaultAction "phase:2,t:bogus,deny""#,
+            ValidationErrors::InvalidTransformation {
+                value: "bogus".to_string(),
+            },
+        ),
+    ] {
+        assert_eq!(
+            SignatureBasedDetectionEngine::from_conf_str(default_action).err(),
+            Some(LoadErrors::InvalidRule {
+                line: 1,
+                error: expected,
+            }),
+        );
+    }
+}
+
+#[test]
+fn chain_continuations_take_the_phase_of_their_chain() {
+    let rules = r#"
+SecRule REQUEST_METHOD "@streq POST" "id:1,phase:2,deny,chain"
+SecRule REQUEST_HEADERS:User-Agent "@contains curl"
+SecRule ARGS "@contains evil" "id:2,phase:2,deny"
+"#;
+    let engine = SignatureBasedDetectionEngine::from_conf_str(rules).unwrap();
+    let phases: Vec<_> = engine
+        .iter_rules()
+        .map(|(phase, sec_rule)| (phase, sec_rule.id))
+        .collect();
+    assert_eq!(
+        phases,
+        vec![
+            (Phase::RequestBody, 1),
+            (Phase::RequestBody, 0),
+            (Phase::RequestBody, 2)
+        ]
+    );
+
+    // removing the rule starting the chain removes its continuation too,
+    // rather than leaving it to match on its own
+    let engine =
+        SignatureBasedDetectionEngine::from_conf_str(&format!("{}SecRuleRemoveById 1\n", rules))
+            .unwrap();
+    assert_eq!(engine.rule_count(), 1);
+    assert_eq!(header_match(&engine, "user-agent", "curl/8.5.0"), None);
+}
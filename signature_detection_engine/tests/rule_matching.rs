@@ -0,0 +1,85 @@
+mod common;
+
+use common::{headers, rule};
+use signature_detection_engine::SignatureBasedDetectionEngine;
+use signature_detection_engine::transaction::Transaction;
+
+#[test]
+fn rules_match_headers_without_an_engine() {
+    let sec_rule =
+        rule(r#"SecRule REQUEST_HEADERS:User-Agent "@contains sqlmap" "id:1,phase:1,deny""#);
+    assert!(
+        sec_rule
+            .matches_headers(&headers(&[("user-agent", "sqlmap/1.7")]))
+            .unwrap()
+    );
+    assert!(
+        !sec_rule
+            .matches_headers(&headers(&[("user-agent", "curl/8.0")]))
+            .unwrap()
+    );
+    assert!(
+        !sec_rule
+            .matches_headers(&headers(&[("referer", "sqlmap")]))
+            .unwrap()
+    );
+}
+
+#[test]
+fn rules_match_args_without_an_engine() {
+    let sec_rule = rule(r#"SecRule ARGS:q "@detectSQLi" "id:2,phase:2,deny""#);
+    assert!(
+        sec_rule
+            .matches_args("q=1%27%20OR%20%271%27%3D%271")
+            .unwrap()
+    );
+    assert!(!sec_rule.matches_args("q=shoes").unwrap());
+    assert!(!sec_rule.matches_args("other=1' OR '1'='1").unwrap());
+}
+
+#[test]
+fn rules_match_bodies_without_an_engine() {
+    let sec_rule =
+        rule(r#"SecRule REQUEST_BODY "@contains drop table" "id:3,phase:2,deny,t:lowercase""#);
+    assert!(sec_rule.matches_body(b"DROP TABLE users").unwrap());
+    assert!(!sec_rule.matches_body(b"SELECT 1").unwrap());
+}
+
+#[test]
+fn variables_of_other_inputs_are_ignored() {
+    let sec_rule = rule(r#"SecRule REQUEST_BODY "@contains bot" "id:4,phase:2,deny""#);
+    assert!(
+        !sec_rule
+            .matches_headers(&headers(&[("user-agent", "bot")]))
+            .unwrap()
+    );
+    assert!(!sec_rule.matches_args("a=bot").unwrap());
+    assert!(sec_rule.matches_body(b"bot").unwrap());
+}
+
+#[test]
+fn rules_match_the_first_value_in_the_order_of_their_variables() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule QUERY_STRING|ARGS "@rx (\d+)" "id:1,phase:2,deny,capture""#,
+    )
+    .unwrap();
+    let mut transaction = Transaction::default();
+    let match_result = engine
+        .run_args_phase(&mut transaction, "a=12&b=34")
+        .unwrap()
+        .unwrap();
+    assert_eq!(match_result.matched_var, "QUERY_STRING");
+    assert_eq!(match_result.captures, vec!["12", "12"]);
+
+    let engine = SignatureBasedDetectionEngine::from_conf_str(
+        r#"SecRule ARGS:b|ARGS:a "@rx (\d+)" "id:1,phase:2,deny,capture""#,
+    )
+    .unwrap();
+    let mut transaction = Transaction::default();
+    let match_result = engine
+        .run_args_phase(&mut transaction, "a=12&b=34")
+        .unwrap()
+        .unwrap();
+    assert_eq!(match_result.matched_var, "ARGS:b");
+    assert_eq!(match_result.captures, vec!["34", "34"]);
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use common::header_match;
+use signature_detection_engine::{Severity, SignatureBasedDetectionEngine};
+
+const RULES: &str = r#"
+SecRule REQUEST_HEADERS:User-Agent "@contains scanner" "id:1,phase:1,deny,severity:4"
+SecRule REQUEST_HEADERS:User-Agent "@contains scanner" "id:2,phase:1,deny,severity:2"
+SecRule REQUEST_HEADERS:User-Agent "@contains curl" "id:3,phase:1,deny"
+"#;
+
+#[test]
+fn severities_are_ordered_by_how_severe_they_are() {
+    assert!(Severity::Emergency > Severity::Alert);
+    assert!(Severity::Critical > Severity::Warning);
+    assert!(Severity::Info > Severity::Debug);
+    assert_eq!(Severity::Critical.max(Severity::Notice), Severity::Critical);
+
+    // while their numbers are the reverse
+    assert!(u8::from(Severity::Critical) < u8::from(Severity::Warning));
+}
+
+#[test]
+fn rules_less_severe_than_the_minimum_are_skipped() {
+    let mut engine = SignatureBasedDetectionEngine::from_conf_str(RULES).unwrap();
+    assert_eq!(
+        header_match(&engine, "user-agent", "scanner").map(|m| m.rule.id),
+        Some(1)
+    );
+
+    engine.set_min_severity(Severity::Critical);
+    assert_eq!(
+        header_match(&engine, "user-agent", "scanner").map(|m| m.rule.id),
+        Some(2)
+    );
+    // rules without a severity always run
+    assert_eq!(
+        header_match(&engine, "user-agent", "curl").map(|m| m.rule.id),
+        Some(3)
+    );
+
+    engine.set_min_severity(Severity::Emergency);
+    assert_eq!(
+        header_match(&engine, "user-agent", "scanner").map(|m| m.rule.id),
+        None
+    );
+}
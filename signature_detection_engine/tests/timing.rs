@@ -0,0 +1,69 @@
+mod common;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use common::headers;
+use signature_detection_engine::SignatureBasedDetectionEngine;
+use signature_detection_engine::timing::monotonic_clock;
+use signature_detection_engine::transaction::Transaction;
+
+const RULES: &str = r#"SecRule REQUEST_HEADERS:User-Agent "@contains scanner" "id:1,phase:1,pass"
+SecRule REQUEST_HEADERS "@rx (?:a+)+b" "id:2,phase:1,pass""#;
+
+// A clock which advances a millisecond every time it's read, so that every
+// evaluation takes a millisecond.
+fn ticking_clock() -> Duration {
+    static TICKS: AtomicU64 = AtomicU64::new(0);
+    Duration::from_millis(TICKS.fetch_add(1, Ordering::Relaxed))
+}
+
+const HEADERS: &[(&str, &str)] = &[
+    ("user-agent", "Mozilla/5.0"),
+    ("accept", "*/*"),
+    ("x-padding", "aaaaaaaaaa"),
+];
+
+#[test]
+fn slowest_rules_are_taken_per_window() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(RULES)
+        .unwrap()
+        .with_rule_timings(ticking_clock);
+
+    engine
+        .run_header_phase(&mut Transaction::default(), headers(HEADERS))
+        .unwrap();
+
+    engine
+        .run_header_phase(&mut Transaction::default(), headers(HEADERS))
+        .unwrap();
+
+    // rules which took the same time are ordered by id
+    let slowest = engine.take_slowest_rules(10);
+    let rule_ids: Vec<u32> = slowest.iter().map(|timing| timing.rule_id).collect();
+    assert_eq!(rule_ids, vec![1, 2]);
+    assert_eq!(slowest[0].evaluations, 2);
+    assert_eq!(slowest[0].total, Duration::from_millis(2));
+    assert_eq!(slowest[0].max, Duration::from_millis(1));
+
+    assert_eq!(engine.take_slowest_rules(1).len(), 0);
+    engine
+        .run_header_phase(&mut Transaction::default(), headers(HEADERS))
+        .unwrap();
+    assert_eq!(engine.take_slowest_rules(1).len(), 1);
+}
+
+#[test]
+fn rule_timings_are_disabled_by_default() {
+    let engine = SignatureBasedDetectionEngine::from_conf_str(RULES).unwrap();
+    engine
+        .run_header_phase(&mut Transaction::default(), headers(HEADERS))
+        .unwrap();
+    assert!(engine.take_slowest_rules(10).is_empty());
+
+    let engine = engine.with_rule_timings(monotonic_clock);
+    engine
+        .run_header_phase(&mut Transaction::default(), headers(HEADERS))
+        .unwrap();
+    assert_eq!(engine.take_slowest_rules(10).len(), 2);
+}
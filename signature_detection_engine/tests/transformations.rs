@@ -0,0 +1,198 @@
+use signature_detection_engine::SignatureBasedDetectionEngine;
+use signature_detection_engine::transaction::Transaction;
+
+fn body_rule_engine(transformations: &str) -> SignatureBasedDetectionEngine {
+    let rule = format!(
+        r#"SecRule REQUEST_BODY "@contains DROP TABLE" "id:2001,phase:2,deny,{}""#,
+        transformations
+    );
+    SignatureBasedDetectionEngine::from_conf_str(&rule).unwrap()
+}
+
+fn matched_id(engine: &SignatureBasedDetectionEngine, body: &str) -> Option<u32> {
+    engine
+        .run_body_phase(&mut Transaction::default(), body)
+        .unwrap()
+        .map(|match_result| match_result.rule.id)
+}
+
+#[test]
+fn remove_comments_normalizes_inline_comments() {
+    let engine = body_rule_engine("t:removeComments");
+    assert_eq!(matched_id(&engine, "DR/*x*/OP TABLE users"), Some(2001));
+    assert_eq!(
+        matched_id(&engine, "D<!-- x -->ROP TABLE users"),
+        Some(2001)
+    );
+}
+
+#[test]
+fn remove_comments_drops_line_comments_to_the_end() {
+    let engine = body_rule_engine("t:removeComments");
+    assert_eq!(matched_id(&engine, "x -- DROP TABLE users"), None);
+    assert_eq!(matched_id(&engine, "x # DROP TABLE users"), None);
+    assert_eq!(
+        matched_id(&engine, "DROP TABLE users -- trailing"),
+        Some(2001)
+    );
+}
+
+#[test]
+fn comments_evade_rules_without_remove_comments() {
+    let engine = body_rule_engine("t:lowercase");
+    assert_eq!(matched_id(&engine, "DR/*x*/OP TABLE users"), None);
+}
+
+#[test]
+fn remove_nulls_strips_null_bytes() {
+    let engine = body_rule_engine("t:removeNulls");
+    assert_eq!(matched_id(&engine, "DR\0OP TA\0BLE users"), Some(2001));
+}
+
+#[test]
+fn replace_nulls_replaces_null_bytes_with_spaces() {
+    let engine = body_rule_engine("t:replaceNulls");
+    assert_eq!(matched_id(&engine, "DROP\0TABLE users"), Some(2001));
+    assert_eq!(matched_id(&engine, "DR\0OP TABLE users"), None);
+}
+
+#[test]
+fn transformations_apply_in_order() {
+    let engine = body_rule_engine("t:urlDecode,t:removeNulls,t:removeComments");
+    assert_eq!(matched_id(&engine, "DR%00/**/OP%20TABLE"), Some(2001));
+}
+
+#[test]
+fn unknown_transformations_are_rejected() {
+    let rule = r#"SecRule REQUEST_BODY "@contains x" "id:2002,phase:2,deny,t:bogus""#;
+    assert!(SignatureBasedDetectionEngine::from_conf_str(rule).is_err());
+}
+
+fn filename_matches(transformations: &str, pattern: &str, path: &str) -> bool {
+    let rule = format!(
+        r#"SecRule REQUEST_FILENAME "@rx {}" "id:2003,phase:1,deny,{}""#,
+        pattern, transformations
+    );
+    let engine = SignatureBasedDetectionEngine::from_conf_str(&rule).unwrap();
+    engine
+        .run_header_phase(
+            &mut Transaction::default(),
+            vec![(":path".to_string(), path.to_string())],
+        )
+        .unwrap()
+        .is_some()
+}
+
+#[test]
+fn normalize_path_resolves_traversal() {
+    let admin = |path: &str| filename_matches("t:normalizePath", "^/admin/?$", path);
+    assert!(admin("/foo/../admin"));
+    assert!(admin("/./admin/."));
+    assert!(admin("//admin//"));
+    assert!(admin("/../../admin"));
+    assert!(admin("/foo/bar/../../admin"));
+    assert!(!admin("/foo/../../bar/admin"));
+    assert!(!filename_matches("", "^/admin/?$", "/foo/../admin"));
+}
+
+#[test]
+fn normalize_path_keeps_relative_traversal_and_trailing_slashes() {
+    let normalized = |path: &str, expected: &str| {
+        filename_matches("t:normalizePath", &format!("^{}$", expected), path)
+    };
+    assert!(normalized("a/b/../c", "a/c"));
+    assert!(normalized("../a/./b", "\\.\\./a/b"));
+    assert!(normalized("/a/b/..", "/a/"));
+    assert!(normalized("/", "/"));
+}
+
+#[test]
+fn normalize_path_win_treats_backslashes_as_separators() {
+    let admin =
+        |transformation: &str| filename_matches(transformation, "^/admin$", "/static\\..\\admin");
+    assert!(admin("t:normalizePathWin"));
+    assert!(!admin("t:normalizePath"));
+}
+
+#[test]
+fn hex_decode_decodes_pairs_of_hex_digits() {
+    let engine = body_rule_engine("t:hexDecode");
+    // "DROP TABLE" in hex
+    assert_eq!(matched_id(&engine, "44524f50205441424c45"), Some(2001));
+    // an odd trailing digit is kept as it is
+    assert_eq!(matched_id(&engine, "44524f50205441424c457"), Some(2001));
+    assert_eq!(matched_id(&engine, "44524f50205441424c4"), None);
+    // as are bytes outside of pairs of hex digits
+    assert_eq!(matched_id(&engine, "zz44524f50205441424c45"), Some(2001));
+}
+
+#[test]
+fn hex_encode_encodes_every_byte() {
+    let rule = r#"SecRule REQUEST_BODY "@streq 00410aff" "id:2004,phase:2,deny,t:hexEncode""#;
+    let engine = SignatureBasedDetectionEngine::from_conf_str(rule).unwrap();
+    let match_result = engine
+        .run_body_phase_bytes(&mut Transaction::default(), b"\0A\n\xff")
+        .unwrap();
+    assert_eq!(match_result.map(|m| m.rule.id), Some(2004));
+
+    let rule =
+        r#"SecRule REQUEST_BODY "@streq DROP" "id:2005,phase:2,deny,t:hexEncode,t:hexDecode""#;
+    let engine = SignatureBasedDetectionEngine::from_conf_str(rule).unwrap();
+    assert_eq!(matched_id(&engine, "DROP"), Some(2005));
+}
+
+fn script_rule_engine(transformations: &str) -> SignatureBasedDetectionEngine {
+    let rule = format!(
+        r#"SecRule REQUEST_BODY "@contains <script>" "id:2006,phase:2,deny,{}""#,
+        transformations
+    );
+    SignatureBasedDetectionEngine::from_conf_str(&rule).unwrap()
+}
+
+#[test]
+fn css_decode_decodes_hex_escapes() {
+    let engine = script_rule_engine("t:cssDecode,t:lowercase");
+    for body in [
+        r"\3c script\3e",
+        r"\3cscript\3e",
+        r"\00003Cscript>",
+        r"\3C \73 \63 ript\3E",
+        // a backslash before any other character is dropped
+        r"<sc\r\ipt>",
+        "<scr\\\nipt>",
+    ] {
+        assert_eq!(matched_id(&engine, body), Some(2006), "{}", body);
+    }
+    assert_eq!(
+        matched_id(&body_rule_engine("t:cssDecode"), r"\44ROP TABLE"),
+        Some(2001)
+    );
+    assert_eq!(
+        matched_id(&script_rule_engine("t:lowercase"), r"\3c script\3e"),
+        None
+    );
+}
+
+#[test]
+fn js_decode_decodes_string_escapes() {
+    let engine = script_rule_engine("t:jsDecode,t:lowercase");
+    for body in [
+        r"\x3cscript\x3e",
+        r"\u003cscript\u003E",
+        r"\u{3c}script\u{00003e}",
+        r"\74script\076",
+        r"\<\scri\pt\>",
+    ] {
+        assert_eq!(matched_id(&engine, body), Some(2006), "{}", body);
+    }
+    // invalid hex escapes are left as they are
+    assert_eq!(matched_id(&engine, r"\x3gscript>"), None);
+    assert_eq!(
+        matched_id(&body_rule_engine("t:jsDecode"), r"DROP\tTABLE"),
+        None
+    );
+    assert_eq!(
+        matched_id(&body_rule_engine("t:jsDecode"), r"DROP\x20TABLE"),
+        Some(2001)
+    );
+}
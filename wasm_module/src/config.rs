@@ -0,0 +1,359 @@
+use serde::Deserialize;
+use signature_detection_engine::{EngineMode, IpMatchSet};
+
+// -----------------------------------------------------------------------------
+// Firewall Configuration
+// -----------------------------------------------------------------------------
+
+// The plugin configuration, provided as a JSON object in the filter's
+// "configuration" field, e.g.:
+//
+//   {
+//     "mode": "detect_only",
+//     "engine_mode": {"anomaly_score": {"threshold": 5}},
+//     "log_level": "warn",
+//     "trusted_proxies": ["10.0.0.0/8", "2001:db8::/32"],
+//     "request_id_header": "x-request-id",
+//     "anomaly_detection_failure_policy": "fail_closed",
+//     "engine_failure_policy": "fail_closed",
+//     "anomaly_block_score": 0.6,
+//     "expose_rule_details": false,
+//     "debug_headers": false,
+//     "rule_timings": false,
+//     "slowest_rules_logged": 10,
+//     "block_status": 403,
+//     "block_response_content_type": "application/json",
+//     "allowed_content_types": ["application/json", "application/x-www-form-urlencoded"],
+//     "content_type_block_status": 415,
+//     "request_body_limit": 1048576,
+//     "request_body_limit_action": "process_partial",
+//     "max_body_inspect_bytes": 131072,
+//     "body_inspect_limit_action": "inspect_prefix",
+//     "max_args": 255,
+//     "max_args_action": "reject",
+//     "inspect_response_body": true,
+//     "response_body_limit": 524288,
+//     "response_body_limit_action": "process_partial",
+//     "rules": "SecRule REQUEST_HEADERS:User-Agent \"@contains bot\" \"id:1,deny\""
+//   }
+//
+// Every field is optional, and an empty configuration uses the defaults.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct FirewallConfig {
+    // Whether requests which match are blocked, or only logged and counted.
+    pub mode: FirewallMode,
+    // How the signature-based engine decides to block: on the first matching
+    // rule, or once the summed severity scores of the matching rules reach a
+    // threshold, e.g. {"anomaly_score": {"threshold": 5}}.
+    pub engine_mode: EngineModeConfig,
+    // The most verbose messages which are logged. Per-request traces are
+    // logged at debug, blocked requests and audit records at info, and
+    // failures at warn or error.
+    pub log_level: LogLevel,
+    // The addresses and networks (e.g. "10.0.0.0/8") of the proxies in front
+    // of this one, whose x-forwarded-for entries are trusted when deriving
+    // the client address for REMOTE_ADDR. Without any, the client address is
+    // the peer's address, as x-forwarded-for can be forged by the client.
+    pub trusted_proxies: Vec<String>,
+    // trusted_proxies, parsed when the configuration is loaded
+    #[serde(skip)]
+    pub trusted_proxy_set: IpMatchSet,
+    // The header holding the ID of a request, which is included in the logs
+    // and audit records of the request, and sent to the anomaly detection
+    // service, so that the logs of both can be correlated. Requests without
+    // one are given one, which is set on the request for the backend.
+    pub request_id_header: String,
+    // What to do with a request when the anomaly detection service can't be
+    // reached or returns an error.
+    pub anomaly_detection_failure_policy: FailurePolicy,
+    // What to do with a request when the signature-based engine fails to
+    // evaluate it. Unlike anomaly detection this fails closed by default, as
+    // an engine error usually means a broken ruleset rather than an outage.
+    pub engine_failure_policy: FailurePolicy,
+    // Requests the anomaly detection service flags are only blocked when their
+    // similarity score is below this, and are logged otherwise, so that a
+    // deployment can be stricter than the service's own threshold about what
+    // it blocks. When not provided every flagged request is blocked.
+    pub anomaly_block_score: Option<f32>,
+    // Whether the tags and severity of the rule which blocked a request are
+    // included in the blocked response. They're always logged and set as
+    // request metadata, but are off by default in the response, as they tell
+    // an attacker which rules to evade.
+    pub expose_rule_details: bool,
+    // Whether blocked responses carry headers describing what blocked them,
+    // e.g. "X-Portkullis-Rule-Id", "X-Portkullis-Severity" and
+    // "X-Portkullis-Phase" for rules, or the score and threshold for anomaly
+    // detection. Meant for debugging in staging, not for production.
+    pub debug_headers: bool,
+    // Whether the time each rule takes to evaluate is recorded, and the
+    // slowest rules logged every few seconds, to find the rules (e.g. regexes
+    // prone to catastrophic backtracking) which dominate latency. Off by
+    // default, as it reads the proxy's clock twice for every rule evaluated.
+    pub rule_timings: bool,
+    // How many of the slowest rules are logged when rule_timings is enabled.
+    pub slowest_rules_logged: usize,
+    // The status of the response to a blocked request, unless the rule which
+    // blocked it sets its own with the status action (e.g. "status:429").
+    pub block_status: u16,
+    // The format of the body of a blocked response, either a line of text, or
+    // for clients which only understand JSON a JSON object such as
+    // {"blocked": true, "rule_id": 1001, "message": "..."}.
+    pub block_response_content_type: BlockResponseContentType,
+    // The ruleset as SecRule directives. When not provided the example rules
+    // are used.
+    pub rules: Option<String>,
+    // The ruleset as compiled by "cargo xtask compile-rules", which loads
+    // faster than parsing the directives. Only one of rules and compiled_rules
+    // can be provided.
+    pub compiled_rules: Option<serde_json::Value>,
+    // The media types (e.g. "application/json") a request's content-type may
+    // have, compared case-insensitively and ignoring parameters such as the
+    // charset. When not provided any content type is allowed.
+    pub allowed_content_types: Option<Vec<String>>,
+    // The status of the response to a request whose content type isn't
+    // allowed.
+    pub content_type_block_status: u16,
+    // The request body is buffered until it has been received in full, so
+    // that it's scanned once as a whole, up to this many bytes.
+    pub request_body_limit: usize,
+    // What to do with a request whose body is larger than the limit.
+    pub request_body_limit_action: RequestBodyLimitAction,
+    // The most bytes of a request body which are inspected by the detection
+    // engines, as scanning is the expensive part of handling a large body.
+    pub max_body_inspect_bytes: usize,
+    // What to do with a request body which is larger than the inspection size.
+    pub body_inspect_limit_action: BodyInspectLimitAction,
+    // The most arguments of a request, of its query string and body together,
+    // which are evaluated, so that a flood of arguments can't make every ARGS
+    // rule expensive. Every argument is evaluated when not provided.
+    pub max_args: Option<usize>,
+    // What to do with a request which has more arguments than max_args.
+    pub max_args_action: MaxArgsAction,
+    // Whether the response body is buffered and evaluated by the phase 4
+    // rules, e.g. to catch leaked secrets. Off by default, as the response's
+    // headers are held back until its body has been received in full.
+    pub inspect_response_body: bool,
+    // The response body is buffered up to this many bytes.
+    pub response_body_limit: usize,
+    // What to do with a response whose body is larger than the limit.
+    pub response_body_limit_action: ResponseBodyLimitAction,
+}
+
+impl Default for FirewallConfig {
+    fn default() -> Self {
+        Self {
+            mode: FirewallMode::default(),
+            engine_mode: EngineModeConfig::default(),
+            log_level: LogLevel::default(),
+            trusted_proxies: Vec::new(),
+            trusted_proxy_set: IpMatchSet::default(),
+            request_id_header: DEFAULT_REQUEST_ID_HEADER.to_string(),
+            anomaly_detection_failure_policy: FailurePolicy::default(),
+            engine_failure_policy: FailurePolicy::FailClosed,
+            anomaly_block_score: None,
+            expose_rule_details: false,
+            debug_headers: false,
+            rule_timings: false,
+            slowest_rules_logged: DEFAULT_SLOWEST_RULES_LOGGED,
+            block_status: DEFAULT_BLOCK_STATUS,
+            block_response_content_type: BlockResponseContentType::default(),
+            rules: None,
+            compiled_rules: None,
+            allowed_content_types: None,
+            content_type_block_status: DEFAULT_CONTENT_TYPE_BLOCK_STATUS,
+            request_body_limit: DEFAULT_REQUEST_BODY_LIMIT,
+            request_body_limit_action: RequestBodyLimitAction::default(),
+            max_body_inspect_bytes: DEFAULT_MAX_BODY_INSPECT_BYTES,
+            body_inspect_limit_action: BodyInspectLimitAction::default(),
+            max_args: None,
+            max_args_action: MaxArgsAction::default(),
+            inspect_response_body: false,
+            response_body_limit: DEFAULT_RESPONSE_BODY_LIMIT,
+            response_body_limit_action: ResponseBodyLimitAction::default(),
+        }
+    }
+}
+
+const DEFAULT_REQUEST_ID_HEADER: &str = "x-request-id";
+// 403 Forbidden
+const DEFAULT_BLOCK_STATUS: u16 = 403;
+// 415 Unsupported Media Type
+const DEFAULT_CONTENT_TYPE_BLOCK_STATUS: u16 = 415;
+const DEFAULT_SLOWEST_RULES_LOGGED: usize = 10;
+const DEFAULT_REQUEST_BODY_LIMIT: usize = 1024 * 1024;
+const DEFAULT_MAX_BODY_INSPECT_BYTES: usize = 128 * 1024;
+const DEFAULT_RESPONSE_BODY_LIMIT: usize = 512 * 1024;
+
+impl FirewallConfig {
+    pub(crate) fn from_bytes(configuration: &[u8]) -> Result<Self, String> {
+        if configuration.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Self::default());
+        }
+        let mut config: Self = serde_json::from_slice(configuration).map_err(|e| e.to_string())?;
+        if config.rules.is_some() && config.compiled_rules.is_some() {
+            return Err("only one of rules and compiled_rules can be provided".to_string());
+        }
+        config.trusted_proxy_set = IpMatchSet::try_from(config.trusted_proxies.join(",").as_str())
+            .map_err(|e| format!("invalid trusted_proxies: {}", e))?;
+        if !(400..=599).contains(&config.block_status) {
+            return Err(format!(
+                "block_status must be an error status (400-599), got {}",
+                config.block_status
+            ));
+        }
+        if !(400..=599).contains(&config.content_type_block_status) {
+            return Err(format!(
+                "content_type_block_status must be an error status (400-599), got {}",
+                config.content_type_block_status
+            ));
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FirewallMode {
+    // Block requests which match a rule or are detected as anomalous.
+    #[default]
+    Enforce,
+    // Let every request through, logging and counting the ones which would
+    // have been blocked, so that false positives can be measured before
+    // enforcing. Limits on body sizes and engine errors are still enforced,
+    // as they aren't detections.
+    DetectOnly,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum EngineModeConfig {
+    // Block the request on the first matching rule.
+    #[default]
+    FirstMatch,
+    // Only block once the scores of the matching rules reach the threshold.
+    AnomalyScore {
+        threshold: u32,
+    },
+}
+
+impl From<EngineModeConfig> for EngineMode {
+    fn from(engine_mode: EngineModeConfig) -> Self {
+        match engine_mode {
+            EngineModeConfig::FirstMatch => EngineMode::FirstMatch,
+            EngineModeConfig::AnomalyScore { threshold } => EngineMode::AnomalyScore { threshold },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LogLevel {
+    Trace,
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+    Critical,
+}
+
+impl From<LogLevel> for proxy_wasm::types::LogLevel {
+    fn from(log_level: LogLevel) -> Self {
+        match log_level {
+            LogLevel::Trace => proxy_wasm::types::LogLevel::Trace,
+            LogLevel::Debug => proxy_wasm::types::LogLevel::Debug,
+            LogLevel::Info => proxy_wasm::types::LogLevel::Info,
+            LogLevel::Warn => proxy_wasm::types::LogLevel::Warn,
+            LogLevel::Error => proxy_wasm::types::LogLevel::Error,
+            LogLevel::Critical => proxy_wasm::types::LogLevel::Critical,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FailurePolicy {
+    // Let the request through, so that an outage of a detection service
+    // doesn't become an outage of everything behind the proxy.
+    #[default]
+    FailOpen,
+    // Block the request, for deployments which would rather lose traffic
+    // than let it through uninspected.
+    FailClosed,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+pub(crate) enum BlockResponseContentType {
+    #[default]
+    #[serde(rename = "text/plain")]
+    Text,
+    #[serde(rename = "application/json")]
+    Json,
+}
+
+impl BlockResponseContentType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlockResponseContentType::Text => "text/plain",
+            BlockResponseContentType::Json => "application/json",
+        }
+    }
+}
+
+// Named after ModSecurity's SecRequestBodyLimitAction.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RequestBodyLimitAction {
+    // Reject the request with a 413, as its body can't be fully inspected.
+    #[default]
+    Reject,
+    // Scan the body up to the limit, and let the rest through uninspected.
+    ProcessPartial,
+}
+
+// Like the CRS's tx.max_num_args, which blocks requests with too many
+// arguments rather than evaluating some of them.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MaxArgsAction {
+    // Reject the request with a 400, as its arguments can't all be inspected.
+    #[default]
+    Reject,
+    // Evaluate the arguments up to the maximum, and let the rest through
+    // uninspected.
+    ProcessPartial,
+}
+
+// Named after ModSecurity's SecResponseBodyLimitAction. Unlike the request
+// body, a large response (e.g. a download) is usually legitimate, so by
+// default it's let through.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ResponseBodyLimitAction {
+    // Replace the response with a 500, as its body can't be fully inspected.
+    Reject,
+    // Scan the body up to the limit, and let the rest through uninspected.
+    #[default]
+    ProcessPartial,
+}
+
+// Every option is a tradeoff between security and cost: anything past the
+// inspected prefix is never seen by the detection engines, so an attacker can
+// pad a body to push a payload beyond it, while inspecting everything lets
+// large bodies consume CPU on every request.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BodyInspectLimitAction {
+    // Let the body through without inspecting any of it, which is only safe
+    // when large bodies are trusted (e.g. file uploads from authenticated
+    // clients).
+    Skip,
+    // Inspect the body up to the inspection size, which catches payloads at
+    // the start of a body at a bounded cost.
+    #[default]
+    InspectPrefix,
+    // Block the request, for deployments which would rather reject large
+    // bodies than let any part of them through uninspected.
+    Block,
+}
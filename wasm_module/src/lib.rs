@@ -1,9 +1,26 @@
-use std::sync::{Arc, OnceLock};
-use std::time::Duration;
+mod config;
 
-use signature_detection_engine::SignatureBasedDetectionEngine as FirewallEngine;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock, PoisonError};
+use std::time::{Duration, UNIX_EPOCH};
 
-use log::info;
+use signature_detection_engine::audit::AuditRecord;
+use signature_detection_engine::body::process_body;
+use signature_detection_engine::macros::expand_macros;
+use signature_detection_engine::scoring::AnomalyScorer;
+use signature_detection_engine::transaction::Transaction;
+use signature_detection_engine::{
+    DisruptiveAction, EngineMode, IpMatchSet, MatchResult, RuleEngineMode, SecRule,
+    SignatureBasedDetectionEngine as FirewallEngine,
+};
+
+use log::{debug, error, info, warn};
+
+use crate::config::{
+    BlockResponseContentType, BodyInspectLimitAction, EngineModeConfig, FailurePolicy,
+    FirewallConfig, FirewallMode, MaxArgsAction, RequestBodyLimitAction, ResponseBodyLimitAction,
+};
+use proxy_wasm::hostcalls;
 use proxy_wasm::traits::*;
 use proxy_wasm::types::*;
 
@@ -13,10 +30,21 @@ use proxy_wasm::types::*;
 
 static FIREWALL_ENGINE: OnceLock<Arc<FirewallEngine>> = OnceLock::new();
 
+// The example rules are used until the plugin configuration provides a
+// ruleset, see on_configure. Should they fail to load, there are no rules
+// rather than a panic, which would take the proxy's traffic down with it.
 fn initialize(_context_id: u32) -> Box<dyn RootContext> {
-    let engine = FIREWALL_ENGINE.get_or_init(|| Arc::new(FirewallEngine::new_example()));
-    let firewall = Firewall::new(engine.clone()).expect("Failed to initialize firewall");
-    Box::new(firewall)
+    let engine = FIREWALL_ENGINE.get_or_init(|| {
+        let engine = FirewallEngine::new_example().unwrap_or_else(|e| {
+            error!(
+                "failed to load the example rules, starting without rules: {}",
+                e
+            );
+            FirewallEngine::new(Default::default())
+        });
+        Arc::new(engine.with_audit_hook(log_audit_record))
+    });
+    Box::new(Firewall::new(engine.clone()))
 }
 
 #[cfg(feature = "anomaly_detection_engine")]
@@ -32,7 +60,8 @@ pub mod anomaly {
 // -----------------------------------------------------------------------------
 
 proxy_wasm::main! {{
-    proxy_wasm::set_log_level(LogLevel::Debug);
+    // until the plugin configuration sets the level, see on_configure
+    proxy_wasm::set_log_level(LogLevel::Info);
     proxy_wasm::set_root_context(initialize);
 }}
 
@@ -40,111 +69,520 @@ proxy_wasm::main! {{
 // Firewall
 // -----------------------------------------------------------------------------
 
+// Counts the requests which would have been blocked in detect-only mode.
+const WOULD_BLOCK_METRIC: &str = "portkullis_would_block_total";
+
 #[derive(Clone, Debug)]
 struct Firewall {
+    config: FirewallConfig,
+    would_block_metric: Option<u32>,
     engine: Arc<FirewallEngine>,
+    anomaly_scorer: AnomalyScorer,
+    transaction: Transaction,
+    request_body_processed: bool,
+    response_body_processed: bool,
 }
 
 impl Firewall {
-    fn new(engine: Arc<FirewallEngine>) -> Result<Self, String> {
-        Ok(Firewall { engine })
+    fn new(engine: Arc<FirewallEngine>) -> Self {
+        Firewall {
+            config: FirewallConfig::default(),
+            would_block_metric: None,
+            engine,
+            anomaly_scorer: AnomalyScorer::new(),
+            transaction: Transaction::default(),
+            request_body_processed: false,
+            response_body_processed: false,
+        }
+    }
+
+    // A panic aborts the VM and takes the proxy's traffic down with it, so a
+    // lock poisoned by an earlier panic is recovered rather than unwrapped;
+    // the counter is only informational.
+    fn count_request(&self) -> u64 {
+        let mut counter = self
+            .engine
+            .counter
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *counter += 1;
+        *counter
+    }
+
+    // Applies the configured failure policy when the signature-based engine
+    // couldn't evaluate the request (e.g. a rule with an unknown
+    // transformation).
+    fn signature_detection_failed(&mut self, error: &str) -> Action {
+        match self.config.engine_failure_policy {
+            FailurePolicy::FailOpen => {
+                error!(
+                    "signature-based firewall engine error, failing open: {}",
+                    error
+                );
+                Action::Continue
+            }
+            FailurePolicy::FailClosed => {
+                error!(
+                    "signature-based firewall engine error, failing closed: {}",
+                    error
+                );
+                self.send_blocked_response("(signature-based detection): engine error");
+                Action::Pause
+            }
+        }
     }
 
     fn run_signature_based_header_detection(&mut self, headers: Vec<(String, String)>) -> Action {
-        match self.engine.run_header_phase(headers) {
+        if let EngineMode::AnomalyScore { threshold } = self.engine.mode {
+            let score = self
+                .engine
+                .run_header_phase_all(&mut self.transaction, headers)
+                .map(|matched_rules| self.anomaly_scorer.record(&matched_rules));
+            return self.enforce_anomaly_score(score, threshold);
+        }
+
+        match self.engine.run_header_phase(&mut self.transaction, headers) {
             Ok(detection_result) => {
-                if let Some(blocked_rule) = detection_result {
-                    info!(
-                        "request blocked by signature-based firewall rule: {:?}",
-                        blocked_rule
-                    );
-                    self.send_blocked_response(&format!(
-                        "(signature-based detection): {}",
-                        blocked_rule.message.as_deref().unwrap_or("no message")
-                    ));
-                    return Action::Pause;
+                if let Some(match_result) = detection_result {
+                    log_blocked("request", self.request_id(), &match_result);
+                    return self.block_request(&match_result);
                 }
-                info!("request headers passed signature-based firewall checks");
-            }
-            Err(e) => {
-                info!("(signature-based detection): engine error: {:?}", e);
-                self.send_blocked_response("(signature-based detection): engine error");
-                return Action::Pause;
+                debug!("request headers passed signature-based firewall checks");
             }
+            Err(e) => return self.signature_detection_failed(&e),
         }
         Action::Continue
     }
 
-    fn run_signature_based_body_detection(&mut self, body: &str) -> Action {
-        match self.engine.run_body_phase(body) {
+    // The body is parsed by the body processor for the request's
+    // content-type, so that rules selecting ARGS see the arguments of form and
+    // JSON bodies. A body which fails to parse is still evaluated, and it's
+    // up to the rules to block it, through REQBODY_ERROR.
+    fn run_signature_based_body_detection(&mut self, body: &[u8]) -> Action {
+        let content_type = self.get_http_request_header("content-type");
+        let body = process_body(content_type.as_deref(), body);
+        debug!(
+            "request body processed as {:?} with {} arguments and {} files",
+            body.processor,
+            body.args.len(),
+            body.files.len()
+        );
+        if let Some(error) = &body.error {
+            debug!("request body could only be processed in part: {}", error);
+        }
+
+        if let EngineMode::AnomalyScore { threshold } = self.engine.mode {
+            let score = self
+                .engine
+                .run_processed_body_phase_all(&mut self.transaction, &body)
+                .map(|matched_rules| self.anomaly_scorer.record(&matched_rules));
+            return self.enforce_anomaly_score(score, threshold);
+        }
+
+        match self
+            .engine
+            .run_processed_body_phase(&mut self.transaction, &body)
+        {
             Ok(detection_result) => {
-                if let Some(blocked_rule) = detection_result {
-                    info!(
-                        "request blocked by signature-based firewall rule: {:?}",
-                        blocked_rule
-                    );
-                    self.send_blocked_response(&format!(
-                        "(signature-based detection): {}",
-                        blocked_rule.message.as_deref().unwrap_or("No message")
-                    ));
-                    return Action::Pause;
+                if let Some(match_result) = detection_result {
+                    log_blocked("request", self.request_id(), &match_result);
+                    return self.block_request(&match_result);
                 }
-                info!("request body passed signature-based firewall checks");
-            }
-            Err(e) => {
-                info!("signature-based firewall engine error: {:?}", e);
-                self.send_blocked_response("(signature-based detection): engine error");
-                return Action::Pause;
+                debug!("request body passed signature-based firewall checks");
             }
+            Err(e) => return self.signature_detection_failed(&e),
         }
         Action::Continue
     }
 
     fn run_signature_based_args_detection(&mut self, query_string: &str) -> Action {
-        match self.engine.run_args_phase(query_string) {
+        if let EngineMode::AnomalyScore { threshold } = self.engine.mode {
+            let score = self
+                .engine
+                .run_args_phase_all(&mut self.transaction, query_string)
+                .map(|matched_rules| self.anomaly_scorer.record(&matched_rules));
+            return self.enforce_anomaly_score(score, threshold);
+        }
+
+        match self
+            .engine
+            .run_args_phase(&mut self.transaction, query_string)
+        {
             Ok(detection_result) => {
-                if let Some(blocked_rule) = detection_result {
-                    info!(
-                        "request blocked by signature-based firewall rule: {:?}",
-                        blocked_rule
-                    );
-                    self.send_blocked_response(&format!(
-                        "(signature-based) detection: {}",
-                        blocked_rule.message.as_deref().unwrap_or("no message")
-                    ));
-                    return Action::Pause;
+                if let Some(match_result) = detection_result {
+                    log_blocked("request", self.request_id(), &match_result);
+                    return self.block_request(&match_result);
                 }
-                info!("query arguments passed signature-based firewall checks");
+                debug!("query arguments passed signature-based firewall checks");
             }
-            Err(e) => {
-                info!("signature-based firewall engine error: {:?}", e);
-                self.send_blocked_response("(signature-based detection): engine error");
-                return Action::Pause;
+            Err(e) => return self.signature_detection_failed(&e),
+        }
+        Action::Continue
+    }
+
+    // By the time the response headers arrive the request has been forwarded,
+    // so a blocked response replaces the backend's response.
+    fn run_signature_based_response_header_detection(
+        &mut self,
+        status: u32,
+        headers: Vec<(String, String)>,
+    ) -> Action {
+        if let EngineMode::AnomalyScore { threshold } = self.engine.mode {
+            let score = self
+                .engine
+                .run_response_header_phase_all(&mut self.transaction, status, headers)
+                .map(|matched_rules| self.anomaly_scorer.record(&matched_rules));
+            return self.enforce_anomaly_score(score, threshold);
+        }
+
+        match self
+            .engine
+            .run_response_header_phase(&mut self.transaction, status, headers)
+        {
+            Ok(detection_result) => {
+                if let Some(match_result) = detection_result {
+                    log_blocked("response", self.request_id(), &match_result);
+                    return self.block_request(&match_result);
+                }
+                debug!("response headers passed signature-based firewall checks");
             }
+            Err(e) => return self.signature_detection_failed(&e),
         }
         Action::Continue
     }
 
-    fn send_blocked_response(&self, reason: &str) {
-        self.send_http_response(
-            403,
-            vec![("content-type", "text/plain")],
-            Some(format!("the firewall was very displeased with you {}\n", reason).as_bytes()),
+    fn run_signature_based_response_body_detection(&mut self, body: &[u8]) -> Action {
+        if let EngineMode::AnomalyScore { threshold } = self.engine.mode {
+            let score = self
+                .engine
+                .run_response_body_phase_all(&mut self.transaction, body)
+                .map(|matched_rules| self.anomaly_scorer.record(&matched_rules));
+            return self.enforce_anomaly_score(score, threshold);
+        }
+
+        match self
+            .engine
+            .run_response_body_phase(&mut self.transaction, body)
+        {
+            Ok(detection_result) => {
+                if let Some(match_result) = detection_result {
+                    log_blocked("response", self.request_id(), &match_result);
+                    return self.block_request(&match_result);
+                }
+                debug!("response body passed signature-based firewall checks");
+            }
+            Err(e) => return self.signature_detection_failed(&e),
+        }
+        Action::Continue
+    }
+
+    // The anomaly scorer lives on the per-request http context, so the score
+    // accumulates across the header, args, body and response phases of a
+    // request.
+    fn enforce_anomaly_score(&mut self, score: Result<u32, String>, threshold: u32) -> Action {
+        match score {
+            Ok(score) if self.anomaly_scorer.threshold_reached(threshold) => {
+                info!(
+                    "request {} blocked by signature-based anomaly score {} (threshold {}), matched rules: {:?}",
+                    self.request_id(),
+                    score,
+                    threshold,
+                    self.anomaly_scorer.matched_rule_ids
+                );
+                let rule_ids = self
+                    .anomaly_scorer
+                    .matched_rule_ids
+                    .iter()
+                    .map(u32::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",");
+                self.deny(
+                    &format!(
+                        "(signature-based detection): anomaly score {} reached threshold {}",
+                        score, threshold
+                    ),
+                    None,
+                    self.config.block_status,
+                    vec![
+                        ("x-portkullis-rule-id", rule_ids),
+                        ("x-portkullis-anomaly-score", score.to_string()),
+                        ("x-portkullis-anomaly-threshold", threshold.to_string()),
+                    ],
+                )
+            }
+            Ok(score) => {
+                debug!(
+                    "signature-based anomaly score {} is below threshold {}",
+                    score, threshold
+                );
+                Action::Continue
+            }
+            Err(e) => self.signature_detection_failed(&e),
+        }
+    }
+
+    // Rules with a redirect action send the client elsewhere (e.g. a honeypot
+    // or captcha) instead of a blocked response, unless they also explicitly
+    // deny, and rules with the drop action don't respond at all. A rule's
+    // status action (e.g. "status:429") overrides the configured block status.
+    //
+    // The blocked response names the matched variable, but not its value, so
+    // that the client's payload isn't reflected back to it.
+    fn block_request(&mut self, match_result: &MatchResult) -> Action {
+        let blocked_rule = &match_result.rule;
+        self.set_rule_metadata(blocked_rule);
+        let mut message = match &blocked_rule.message {
+            Some(message) => expand_macros(message, &self.transaction, match_result),
+            None => "no message".to_string(),
+        };
+        if self.config.expose_rule_details {
+            message.push_str(&rule_details(blocked_rule));
+        }
+        let reason = format!(
+            "(signature-based detection): {} (matched {})",
+            message, match_result.matched_var
+        );
+        match blocked_rule.redirect.as_deref() {
+            _ if blocked_rule.action == Some(DisruptiveAction::Drop) => self.drop_request(&reason),
+            Some(location) if blocked_rule.action != Some(DisruptiveAction::Deny) => {
+                if self.would_block(&format!("redirect to {}: {}", location, message)) {
+                    return Action::Continue;
+                }
+                let status = blocked_rule
+                    .status
+                    .filter(|status| (300..=399).contains(status))
+                    .unwrap_or(302);
+                info!(
+                    "redirecting blocked request {} to {} ({}): {}",
+                    self.request_id(),
+                    location,
+                    status,
+                    message
+                );
+                self.transaction.blocked = true;
+                self.send_http_response(status as u32, vec![("location", location)], None);
+                Action::Pause
+            }
+            _ => {
+                let status = blocked_rule.status.unwrap_or(self.config.block_status);
+                self.deny(
+                    &reason,
+                    Some(blocked_rule.id),
+                    status,
+                    rule_debug_headers(blocked_rule),
+                )
+            }
+        }
+    }
+
+    // Records which rule blocked the request as filter state (e.g.
+    // "portkullis.tags"), so that the proxy's access logs and later filters
+    // can categorize blocked requests.
+    fn set_rule_metadata(&self, rule: &SecRule) {
+        let tags = serde_json::to_string(&rule.tags).unwrap_or_default();
+        let severity = rule
+            .severity
+            .map(|severity| severity.to_string())
+            .unwrap_or_default();
+        self.set_property(
+            vec!["portkullis.rule_id"],
+            Some(rule.id.to_string().as_bytes()),
         );
+        self.set_property(vec!["portkullis.tags"], Some(tags.as_bytes()));
+        self.set_property(vec!["portkullis.severity"], Some(severity.as_bytes()));
+    }
+
+    // Blocks the request for a rule with the drop action, unless the firewall
+    // is in detect-only mode. proxy-wasm can't close the client's connection
+    // from an http filter, so the request's stream is reset instead, which
+    // the proxy turns into a closed connection for HTTP/1 and a RST_STREAM
+    // for HTTP/2. Either way the client gets no response, unlike deny.
+    fn drop_request(&mut self, reason: &str) -> Action {
+        if self.would_block(reason) {
+            return Action::Continue;
+        }
+        info!("dropping request {} {}", self.request_id(), reason);
+        self.transaction.blocked = true;
+        self.reset_http_request();
+        Action::Pause
+    }
+
+    // Blocks the request with the given status, unless the firewall is in
+    // detect-only mode. The rule ID is that of the rule which blocked the
+    // request, if a single rule did, and the debug headers describe what
+    // blocked it, see send_blocked_response_with.
+    fn deny(
+        &mut self,
+        reason: &str,
+        rule_id: Option<u32>,
+        status: u16,
+        debug_headers: Vec<(&str, String)>,
+    ) -> Action {
+        if self.would_block(reason) {
+            return Action::Continue;
+        }
+        self.send_blocked_response_with(reason, rule_id, status, debug_headers);
+        Action::Pause
+    }
+
+    // In detect-only mode a request which would have been blocked is logged
+    // and counted instead, and this returns true so it's let through. A rule
+    // with ctl:ruleEngine=DetectionOnly puts the rest of its request in
+    // detect-only mode.
+    fn would_block(&self, reason: &str) -> bool {
+        if self.config.mode != FirewallMode::DetectOnly
+            && self.transaction.rule_engine != Some(RuleEngineMode::DetectionOnly)
+        {
+            return false;
+        }
+        info!(
+            "detect-only mode, request {} would have been blocked: {}",
+            self.request_id(),
+            reason
+        );
+        if let Some(metric_id) = self.would_block_metric
+            && let Err(e) = hostcalls::increment_metric(metric_id, 1)
+        {
+            warn!("failed to increment {}: {:?}", WOULD_BLOCK_METRIC, e);
+        }
+        true
+    }
+
+    fn send_blocked_response(&mut self, reason: &str) {
+        self.send_blocked_response_with(reason, None, self.config.block_status, Vec::new());
+    }
+
+    // The debug headers (e.g. "x-portkullis-rule-id") are only added to the
+    // response when enabled, as they're meant for debugging rules in staging
+    // and tell an attacker which rules to evade.
+    fn send_blocked_response_with(
+        &mut self,
+        reason: &str,
+        rule_id: Option<u32>,
+        status: u16,
+        debug_headers: Vec<(&str, String)>,
+    ) {
+        self.transaction.blocked = true;
+        let content_type = self.config.block_response_content_type;
+        let body = match content_type {
+            BlockResponseContentType::Text => {
+                format!("the firewall was very displeased with you {}\n", reason)
+            }
+            BlockResponseContentType::Json => serde_json::json!({
+                "blocked": true,
+                "rule_id": rule_id,
+                "message": reason,
+            })
+            .to_string(),
+        };
+        let mut headers = vec![("content-type", content_type.as_str())];
+        if self.config.debug_headers {
+            headers.extend(
+                debug_headers
+                    .iter()
+                    .map(|(name, value)| (*name, value.as_str())),
+            );
+        }
+        self.send_http_response(status as u32, headers, Some(body.as_bytes()));
     }
 
     fn process_query_string(&mut self) -> Action {
-        if let Some(path) = self.get_http_request_header(":path") {
-            if let Some(query_start) = path.find('?') {
-                let query_string = &path[query_start + 1..];
-                info!("processing query string: {}", query_string);
-                return self.run_signature_based_args_detection(query_string);
+        if let Some(path) = self.get_http_request_header(":path")
+            && let Some(query_start) = path.find('?')
+        {
+            let query_string = &path[query_start + 1..];
+            debug!("processing query string: {}", query_string);
+            let args_result = self.run_signature_based_args_detection(query_string);
+            if args_result != Action::Continue {
+                return args_result;
             }
+            return self.enforce_max_args();
         }
         Action::Continue
     }
 
+    // The engine only evaluates the request's arguments up to max_args, and
+    // flags the transaction when there were more. Like the body limits this is
+    // enforced in detect-only mode too, as it isn't a detection.
+    fn enforce_max_args(&mut self) -> Action {
+        if !self.transaction.too_many_args {
+            return Action::Continue;
+        }
+        let max_args = self.config.max_args.unwrap_or_default();
+        match self.config.max_args_action {
+            MaxArgsAction::Reject => {
+                info!(
+                    "request {} has more than {} arguments",
+                    self.request_id(),
+                    max_args
+                );
+                self.transaction.blocked = true;
+                self.send_http_response(
+                    400,
+                    vec![("content-type", "text/plain")],
+                    Some(b"too many arguments\n"),
+                );
+                Action::Pause
+            }
+            MaxArgsAction::ProcessPartial => {
+                debug!(
+                    "request has more than {} arguments, the rest aren't evaluated",
+                    max_args
+                );
+                Action::Continue
+            }
+        }
+    }
+
+    // The request's ID from the configured header, or a new one which is set
+    // on the request, so that the backend's logs can be correlated too. The
+    // proxy's clock and the request counter make it unique to this VM.
+    fn read_or_set_request_id(&self, headers: &[(String, String)]) -> String {
+        let request_id_header = &self.config.request_id_header;
+        if let Some((_, request_id)) = headers
+            .iter()
+            .find(|(name, value)| name.eq_ignore_ascii_case(request_id_header) && !value.is_empty())
+        {
+            return request_id.clone();
+        }
+        let request_id = format!(
+            "portkullis-{:x}-{:x}",
+            proxy_clock().as_nanos(),
+            self.count_request()
+        );
+        self.set_http_request_header(request_id_header, Some(&request_id));
+        request_id
+    }
+
+    fn request_id(&self) -> &str {
+        self.transaction.request_id.as_deref().unwrap_or("-")
+    }
+
+    // The client address, which is the peer's address unless the peer is a
+    // trusted proxy, in which case it's taken from x-forwarded-for (see
+    // forwarded_client_addr). Falls back to the peer's address when
+    // x-forwarded-for doesn't name a client.
+    fn remote_addr(&self, headers: &[(String, String)]) -> Option<IpAddr> {
+        let trusted_proxies = &self.config.trusted_proxy_set;
+        let peer = self
+            .get_property(vec!["source", "address"])
+            .and_then(|address| String::from_utf8(address).ok())
+            .and_then(|address| parse_ip_addr(&address));
+        match peer {
+            Some(peer) if !trusted_proxies.contains(&peer) => Some(peer),
+            _ => forwarded_client_addr(headers, trusted_proxies).or(peer),
+        }
+    }
+
     fn run_header_detection(&mut self, headers: Vec<(String, String)>) -> Action {
+        self.transaction.request_id = Some(self.read_or_set_request_id(&headers));
+        self.transaction.remote_addr = self.remote_addr(&headers);
+        self.transaction.request_uri = self.get_http_request_header(":path");
+        let content_type_result = self.enforce_content_type(&headers);
+        if content_type_result != Action::Continue {
+            return content_type_result;
+        }
+
         let signature_result = self.run_signature_based_header_detection(headers.clone());
         if signature_result != Action::Continue {
             return signature_result;
@@ -166,17 +604,196 @@ impl Firewall {
         }
     }
 
-    fn run_body_detecion(&mut self, body: &str) -> Action {
+    // Rejects requests whose media type isn't allowlisted before any of their
+    // body is processed, which narrows the parsers an attacker can reach.
+    // Requests without a content type have no body to process, so they're
+    // let through.
+    fn enforce_content_type(&mut self, headers: &[(String, String)]) -> Action {
+        let allowed_content_types = match &self.config.allowed_content_types {
+            Some(allowed_content_types) => allowed_content_types,
+            None => return Action::Continue,
+        };
+        let media_type = match headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        {
+            Some((_, content_type)) => media_type(content_type),
+            None => return Action::Continue,
+        };
+        if allowed_content_types
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(media_type))
+        {
+            return Action::Continue;
+        }
+
+        let reason = format!("(content type): '{}' is not allowed", media_type);
+        if self.would_block(&reason) {
+            return Action::Continue;
+        }
+        info!("request {} blocked {}", self.request_id(), reason);
+        self.transaction.blocked = true;
+        self.send_http_response(
+            self.config.content_type_block_status as u32,
+            vec![("content-type", "text/plain")],
+            Some(b"content type not allowed\n"),
+        );
+        Action::Pause
+    }
+
+    // The inspection limit applies to every body detection engine.
+    fn run_body_detecion(&mut self, body: &[u8]) -> Action {
+        let max_inspect_bytes = self.config.max_body_inspect_bytes;
+        let body = match self.config.body_inspect_limit_action {
+            _ if body.len() <= max_inspect_bytes => body,
+            BodyInspectLimitAction::Skip => {
+                debug!(
+                    "request body exceeds the inspection size of {} bytes, skipping inspection",
+                    max_inspect_bytes
+                );
+                return Action::Continue;
+            }
+            BodyInspectLimitAction::InspectPrefix => {
+                debug!(
+                    "request body exceeds the inspection size of {} bytes, inspecting a prefix",
+                    max_inspect_bytes
+                );
+                &body[..max_inspect_bytes]
+            }
+            BodyInspectLimitAction::Block => {
+                info!(
+                    "body of request {} exceeds the inspection size of {} bytes, blocking",
+                    self.request_id(),
+                    max_inspect_bytes
+                );
+                self.send_blocked_response("(body inspection): request body too large to inspect");
+                return Action::Pause;
+            }
+        };
+
         let signature_result = self.run_signature_based_body_detection(body);
         if signature_result != Action::Continue {
             return signature_result;
         }
+        let args_result = self.enforce_max_args();
+        if args_result != Action::Continue {
+            return args_result;
+        }
 
         // TODO: implement anomaly detection for body
         Action::Continue
     }
 }
 
+// The clock for rule timings. std::time::Instant isn't available to WASM
+// modules, so the proxy's clock is used instead.
+fn proxy_clock() -> Duration {
+    hostcalls::get_current_time()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default()
+}
+
+// Rules with the nolog action match silently, e.g. high-frequency rules whose
+// matches would flood the proxy's logs.
+fn log_blocked(what: &str, request_id: &str, match_result: &MatchResult) {
+    if !match_result.rule.logs() {
+        return;
+    }
+    info!(
+        "{} blocked by signature-based firewall rule {} (request {}, matched {}: {:?}): {:?}",
+        what,
+        match_result.rule.id,
+        request_id,
+        match_result.matched_var,
+        match_result.matched_value,
+        match_result.rule
+    );
+}
+
+// e.g. " [tags: attack-xss, paranoia-level/1; severity: CRITICAL]"
+fn rule_details(rule: &SecRule) -> String {
+    let severity = match rule.severity {
+        Some(severity) => severity.to_string(),
+        None => "none".to_string(),
+    };
+    format!(" [tags: {}; severity: {}]", rule.tags.join(", "), severity)
+}
+
+fn rule_debug_headers(rule: &SecRule) -> Vec<(&'static str, String)> {
+    let mut headers = vec![
+        ("x-portkullis-rule-id", rule.id.to_string()),
+        ("x-portkullis-phase", u8::from(rule.phase).to_string()),
+    ];
+    if let Some(severity) = rule.severity {
+        headers.push(("x-portkullis-severity", severity.to_string()));
+    }
+    headers
+}
+
+// The media type without its parameters, e.g. "application/json" for
+// "application/json; charset=utf-8".
+fn media_type(content_type: &str) -> &str {
+    content_type
+        .split_once(';')
+        .map_or(content_type, |(media_type, _)| media_type)
+        .trim()
+}
+
+// Each proxy appends the address it received the request from to
+// x-forwarded-for, so the rightmost entry which isn't a trusted proxy is the
+// client, while anything left of it could have been forged by the client.
+// Every instance of the header is considered, in order. An entry which isn't
+// an address ends the search, as the entries before it can't be trusted.
+fn forwarded_client_addr(
+    headers: &[(String, String)],
+    trusted_proxies: &IpMatchSet,
+) -> Option<IpAddr> {
+    let entries: Vec<&str> = headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("x-forwarded-for"))
+        .flat_map(|(_, forwarded_for)| forwarded_for.split(','))
+        .collect();
+    for entry in entries.into_iter().rev() {
+        let address = parse_ip_addr(entry)?;
+        if !trusted_proxies.contains(&address) {
+            return Some(address);
+        }
+    }
+    None
+}
+
+// envoy reports addresses with their port (e.g. "10.0.0.1:51234")
+fn parse_ip_addr(address: &str) -> Option<IpAddr> {
+    let address = address.trim();
+    address
+        .parse::<SocketAddr>()
+        .map(|socket_addr| socket_addr.ip())
+        .or_else(|_| address.parse::<IpAddr>())
+        .ok()
+}
+
+// -----------------------------------------------------------------------------
+// Audit Log
+// -----------------------------------------------------------------------------
+
+// Audit records are logged as a single line of JSON behind a fixed prefix, so
+// that they can be grepped out of the proxy's logs and parsed.
+fn log_audit_record(record: &AuditRecord) {
+    let audit_record = serde_json::json!({
+        "request_id": record.request_id,
+        "rule_id": record.rule_id,
+        "msg": record.message,
+        "logdata": record.log_data,
+        "matched_var_name": record.matched_variable,
+        "matched_var": record.matched_value,
+        "tags": record.tags,
+        "severity": record.severity.map(|severity| severity.to_string()),
+        "blocked": record.blocked,
+    });
+    info!("portkullis audit: {}", audit_record);
+}
+
 // -----------------------------------------------------------------------------
 // Anomaly Detection
 // -----------------------------------------------------------------------------
@@ -196,6 +813,7 @@ impl Firewall {
 
         let request = HeaderDetectionRequest {
             headers: grpc_headers,
+            request_id: self.request_id().to_string(),
         };
 
         let encoded_request = request.encode_to_vec();
@@ -209,14 +827,36 @@ impl Firewall {
             Duration::from_secs(5),
         ) {
             Ok(call_id) => {
-                info!(
-                    "header anomaly detection gRPC call dispatched with ID: {}",
-                    call_id
+                debug!(
+                    "header anomaly detection gRPC call dispatched with ID {} for request {}",
+                    call_id,
+                    self.request_id()
                 );
-                return Action::Pause;
+                Action::Pause
             }
             Err(e) => {
-                panic!("failed to dispatch: {:?}", e);
+                warn!("failed to dispatch header anomaly detection: {:?}", e);
+                self.anomaly_detection_failed(false)
+            }
+        }
+    }
+
+    // Applies the configured failure policy when anomaly detection couldn't
+    // produce a result. If the request was already paused waiting on the gRPC
+    // call, failing open has to resume it.
+    fn anomaly_detection_failed(&mut self, paused: bool) -> Action {
+        match self.config.anomaly_detection_failure_policy {
+            FailurePolicy::FailOpen => {
+                warn!("anomaly detection unavailable, failing open");
+                if paused {
+                    self.resume_http_request();
+                }
+                Action::Continue
+            }
+            FailurePolicy::FailClosed => {
+                warn!("anomaly detection unavailable, failing closed");
+                self.send_blocked_response("(anomaly detection): detection unavailable");
+                Action::Pause
             }
         }
     }
@@ -225,33 +865,64 @@ impl Firewall {
         match anomaly::HeaderDetectionResponse::decode(response_data) {
             Ok(response) => {
                 if let Some(detection) = response.detection {
-                    info!(
-                        "header detection: anomaly {}, message {}",
-                        detection.anomaly_detected, detection.message
+                    debug!(
+                        "header detection: anomaly {}, score {:.4} (threshold {:.4}), message {}",
+                        detection.anomaly_detected,
+                        detection.score,
+                        detection.threshold,
+                        detection.message
                     );
 
-                    if detection.anomaly_detected {
-                        info!("ANOMALY DETECTED: {}", detection.message);
-                        self.send_blocked_response(&format!(
-                            "(anomaly detection): {}",
-                            detection.message
-                        ));
-                        return Action::Pause;
-                    } else {
-                        info!("no anomalies detected in headers");
-                        self.resume_http_request();
-                        return Action::Continue;
+                    match self.config.anomaly_block_score {
+                        _ if !detection.anomaly_detected => {
+                            debug!("no anomalies detected in headers");
+                            self.resume_http_request();
+                            Action::Continue
+                        }
+                        Some(block_score) if detection.score >= block_score => {
+                            info!(
+                                "ANOMALY DETECTED in request {} (score {:.4} not below block score {:.4}, allowing): {}",
+                                self.request_id(),
+                                detection.score,
+                                block_score,
+                                detection.message
+                            );
+                            self.resume_http_request();
+                            Action::Continue
+                        }
+                        _ => {
+                            info!(
+                                "ANOMALY DETECTED in request {}: {}",
+                                self.request_id(),
+                                detection.message
+                            );
+                            let action = self.deny(
+                                &format!("(anomaly detection): {}", detection.message),
+                                None,
+                                self.config.block_status,
+                                vec![
+                                    ("x-portkullis-phase", "1".to_string()),
+                                    ("x-portkullis-anomaly-score", detection.score.to_string()),
+                                    (
+                                        "x-portkullis-anomaly-threshold",
+                                        detection.threshold.to_string(),
+                                    ),
+                                ],
+                            );
+                            if action == Action::Continue {
+                                self.resume_http_request();
+                            }
+                            action
+                        }
                     }
                 } else {
-                    info!("no detection data in anomaly response");
-                    self.resume_http_request();
-                    return Action::Continue;
+                    warn!("no detection data in anomaly response");
+                    self.anomaly_detection_failed(true)
                 }
             }
             Err(e) => {
-                info!("failed to decode HeaderDetectionResponse: {:?}", e);
-                self.resume_http_request();
-                return Action::Continue;
+                warn!("failed to decode HeaderDetectionResponse: {:?}", e);
+                self.anomaly_detection_failed(true)
             }
         }
     }
@@ -264,20 +935,20 @@ impl Firewall {
 impl Context for Firewall {
     #[cfg(feature = "anomaly_detection_engine")]
     fn on_grpc_call_response(&mut self, token_id: u32, status_code: u32, response_size: usize) {
-        info!("gRPC response: id {}, status {}", token_id, status_code);
+        debug!("gRPC response: id {}, status {}", token_id, status_code);
 
         if status_code == 0 {
             if let Some(response_data) = self.get_grpc_call_response_body(0, response_size) {
                 self.handle_anomaly_detection_response(&response_data);
                 return;
             } else {
-                info!("no response body received from gRPC call");
+                warn!("no response body received from gRPC call");
             }
         } else {
-            info!("gRPC call failed with status code: {}", status_code);
+            warn!("gRPC call failed with status code: {}", status_code);
         }
 
-        self.resume_http_request();
+        self.anomaly_detection_failed(true);
     }
 }
 
@@ -289,9 +960,85 @@ impl RootContext for Firewall {
             info!("anomaly detection engine is enabled");
         }
         self.set_tick_period(Duration::from_secs(5));
+        match hostcalls::define_metric(MetricType::Counter, WOULD_BLOCK_METRIC) {
+            Ok(metric_id) => self.would_block_metric = Some(metric_id),
+            Err(e) => warn!("failed to define {}: {:?}", WOULD_BLOCK_METRIC, e),
+        }
         true
     }
 
+    fn on_configure(&mut self, _plugin_configuration_size: usize) -> bool {
+        let configuration = self.get_plugin_configuration().unwrap_or_default();
+        let config = match FirewallConfig::from_bytes(&configuration) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("invalid firewall configuration: {}", e);
+                return false;
+            }
+        };
+
+        let engine = match (&config.rules, &config.compiled_rules) {
+            (Some(rules), _) => Some(FirewallEngine::from_conf_str(rules)),
+            (None, Some(compiled_rules)) => {
+                Some(FirewallEngine::from_json(&compiled_rules.to_string()))
+            }
+            // the shared example engine can't have rule timings, max_args or
+            // another engine mode
+            (None, None)
+                if config.rule_timings
+                    || config.max_args.is_some()
+                    || config.engine_mode != EngineModeConfig::FirstMatch =>
+            {
+                Some(FirewallEngine::new_example())
+            }
+            (None, None) => None,
+        };
+        match engine {
+            Some(Ok(engine)) => {
+                for skipped in engine.skipped_directives() {
+                    info!(
+                        "skipped {} on line {} of the rules, it has no effect",
+                        skipped.name, skipped.line
+                    );
+                }
+                let mut engine = engine
+                    .with_audit_hook(log_audit_record)
+                    .with_mode(config.engine_mode.into());
+                if config.rule_timings {
+                    engine = engine.with_rule_timings(proxy_clock);
+                }
+                if let Some(max_args) = config.max_args {
+                    engine.set_max_args(max_args);
+                }
+                self.engine = Arc::new(engine);
+            }
+            Some(Err(e)) => {
+                error!("invalid firewall rules: {}", e);
+                return false;
+            }
+            None => {}
+        }
+
+        proxy_wasm::set_log_level(config.log_level.into());
+        info!("firewall configured: {:?}", config);
+        self.config = config;
+        true
+    }
+
+    // Each tick logs the rules which took the longest to evaluate since the
+    // last one, when rule timings are enabled.
+    fn on_tick(&mut self) {
+        for timing in self
+            .engine
+            .take_slowest_rules(self.config.slowest_rules_logged)
+        {
+            info!(
+                "rule {} took {:?} over {} evaluations (at most {:?})",
+                timing.rule_id, timing.total, timing.evaluations, timing.max
+            );
+        }
+    }
+
     fn get_type(&self) -> Option<ContextType> {
         Some(ContextType::HttpContext)
     }
@@ -303,36 +1050,167 @@ impl RootContext for Firewall {
 
 impl HttpContext for Firewall {
     fn on_http_request_headers(&mut self, num_headers: usize, _end_of_stream: bool) -> Action {
-        {
-            let mut counter = self.engine.counter.lock().unwrap();
-            *counter += 1;
-            info!(
-                "firewall processing request headers (request counter {})",
-                *counter
-            );
-        }
+        let counter = self.count_request();
+        debug!(
+            "firewall processing request headers (request counter {})",
+            counter
+        );
 
         let headers = self.get_http_request_headers();
 
-        info!("processing {} request headers", num_headers);
-        info!("request headers: {:?}", headers);
+        debug!("processing {} request headers", num_headers);
+        debug!("request headers: {:?}", headers);
 
         self.run_header_detection(headers)
     }
 
-    fn on_http_request_body(&mut self, body_size: usize, _end_of_stream: bool) -> Action {
+    // The body arrives in chunks, and pausing has the proxy buffer them, so
+    // that the body is scanned once as a whole when the last chunk arrives
+    // and signatures split across chunks are still matched. body_size is the
+    // size of everything buffered so far.
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        if self.request_body_processed {
+            return Action::Continue;
+        }
+
+        let limit = self.config.request_body_limit;
+        if body_size > limit {
+            match self.config.request_body_limit_action {
+                RequestBodyLimitAction::Reject => {
+                    info!(
+                        "body of request {} of at least {} bytes exceeds the limit of {} bytes",
+                        self.request_id(),
+                        body_size,
+                        limit
+                    );
+                    self.transaction.blocked = true;
+                    self.send_http_response(
+                        413,
+                        vec![("content-type", "text/plain")],
+                        Some(b"request body too large\n"),
+                    );
+                    return Action::Pause;
+                }
+                RequestBodyLimitAction::ProcessPartial => {
+                    debug!(
+                        "request body exceeds the limit of {} bytes, only scanning up to the limit",
+                        limit
+                    );
+                }
+            }
+        } else if !end_of_stream {
+            return Action::Pause;
+        }
+        self.request_body_processed = true;
+
+        let counter = self.count_request();
+        debug!("firewall processing request body (counter {})", counter);
+
+        // the body is only decoded for logging, the engine gets the raw bytes
+        if let Some(body_bytes) = self.get_http_request_body(0, body_size.min(limit)) {
+            debug!(
+                "processing request body: {}",
+                String::from_utf8_lossy(&body_bytes)
+            );
+            return self.run_body_detecion(&body_bytes);
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_response_headers(&mut self, num_headers: usize, end_of_stream: bool) -> Action {
+        let headers = self.get_http_response_headers();
+
+        debug!("processing {} response headers", num_headers);
+        debug!("response headers: {:?}", headers);
+
+        // the response status is provided by the proxy as the ":status"
+        // pseudo-header
+        let status = headers
+            .iter()
+            .find(|(name, _)| name == ":status")
+            .and_then(|(_, status)| status.parse::<u32>().ok())
+            .unwrap_or_default();
+
+        let signature_result = self.run_signature_based_response_header_detection(status, headers);
+        if signature_result != Action::Continue {
+            return signature_result;
+        }
+
+        // the headers are held back until the body has been inspected, so
+        // that a blocked response can still be replaced, which isn't needed
+        // for the firewall's own responses
+        if self.config.inspect_response_body && !end_of_stream && !self.transaction.blocked {
+            return Action::Pause;
+        }
+        Action::Continue
+    }
+
+    // Buffered the same way as the request body, see on_http_request_body.
+    fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        if !self.config.inspect_response_body
+            || self.response_body_processed
+            || self.transaction.blocked
         {
-            let mut counter = self.engine.counter.lock().unwrap();
-            *counter += 1;
-            info!("firewall processing request body (counter {})", *counter);
+            return Action::Continue;
+        }
+
+        let limit = self.config.response_body_limit;
+        if body_size > limit {
+            match self.config.response_body_limit_action {
+                ResponseBodyLimitAction::Reject => {
+                    info!(
+                        "response body of request {} of at least {} bytes exceeds the limit of {} bytes",
+                        self.request_id(),
+                        body_size,
+                        limit
+                    );
+                    self.transaction.blocked = true;
+                    self.send_http_response(
+                        500,
+                        vec![("content-type", "text/plain")],
+                        Some(b"response body too large\n"),
+                    );
+                    return Action::Pause;
+                }
+                ResponseBodyLimitAction::ProcessPartial => {
+                    debug!(
+                        "response body exceeds the limit of {} bytes, only scanning up to the limit",
+                        limit
+                    );
+                }
+            }
+        } else if !end_of_stream {
+            return Action::Pause;
         }
+        self.response_body_processed = true;
 
-        if let Some(body_bytes) = self.get_http_request_body(0, body_size) {
-            let body = String::from_utf8_lossy(&body_bytes);
-            info!("processing request body: {}", body);
-            return self.run_body_detecion(&body);
+        if let Some(body_bytes) = self.get_http_response_body(0, body_size.min(limit)) {
+            debug!(
+                "processing response body: {}",
+                String::from_utf8_lossy(&body_bytes)
+            );
+            return self.run_signature_based_response_body_detection(&body_bytes);
         }
 
         Action::Continue
     }
+
+    // Called once the transaction is complete, including when it was blocked.
+    fn on_log(&mut self) {
+        match self.engine.run_logging_phase(&mut self.transaction) {
+            Ok(matched_rules) => {
+                for match_result in matched_rules.iter().filter(|m| m.rule.logs()) {
+                    info!(
+                        "logging phase rule {} matched {}: {:?}",
+                        match_result.rule.id, match_result.matched_var, match_result.matched_value
+                    );
+                }
+            }
+            Err(e) => error!(
+                "signature-based firewall engine error in logging phase: {:?}",
+                e
+            ),
+        }
+    }
 }
@@ -0,0 +1,327 @@
+mod host;
+
+// links the module's callbacks, which the host calls through the ABI
+extern crate portkullis_firewall_wasm_module;
+
+use host::Plugin;
+use proxy_wasm::types::Action;
+
+// Drive the module through a proxy-wasm host (see host/mod.rs), with the
+// example rules unless the configuration provides others.
+
+fn request_headers<'a>(path: &'a str, user_agent: &'a str) -> Vec<(&'a str, &'a str)> {
+    vec![
+        (":method", "GET"),
+        (":path", path),
+        (":authority", "example.com"),
+        ("user-agent", user_agent),
+    ]
+}
+
+#[test]
+fn benign_request_passes() {
+    let plugin = Plugin::start("").unwrap();
+
+    let action = plugin.request_headers(&request_headers("/search?q=shoes", "curl/8.5.0"));
+    assert_eq!(action, Action::Continue);
+    let action = plugin.request_body(br#"{"user": "robert"}"#);
+    assert_eq!(action, Action::Continue);
+    assert_eq!(plugin.local_response(), None);
+}
+
+#[test]
+fn bot_user_agent_is_blocked_in_request_headers() {
+    let plugin = Plugin::start("").unwrap();
+
+    let action = plugin.request_headers(&request_headers("/", "malicious-bot"));
+    assert_eq!(action, Action::Pause);
+    let response = plugin.local_response().expect("blocked response");
+    assert_eq!(response.status, 403);
+    assert_eq!(response.header("content-type"), Some("text/plain"));
+    assert!(String::from_utf8_lossy(&response.body).contains("bot detected"));
+    assert_eq!(
+        plugin.property("portkullis.rule_id").as_deref(),
+        Some("1001")
+    );
+}
+
+#[test]
+fn script_in_query_arguments_is_blocked() {
+    let plugin = Plugin::start("").unwrap();
+
+    let action = plugin.request_headers(&request_headers(
+        "/test?search=%3Cscript%3Ealert('xss')%3C/script%3E",
+        "Mozilla/5.0",
+    ));
+    assert_eq!(action, Action::Pause);
+    let response = plugin.local_response().expect("blocked response");
+    assert_eq!(response.status, 403);
+    assert_eq!(
+        plugin.property("portkullis.rule_id").as_deref(),
+        Some("1002")
+    );
+}
+
+#[test]
+fn injection_in_request_body_is_blocked() {
+    let plugin = Plugin::start("").unwrap();
+
+    let action = plugin.request_headers(&request_headers("/users", "Mozilla/5.0"));
+    assert_eq!(action, Action::Continue);
+    let action = plugin.request_body(br#"{"user": "Robert');DROP TABLE users;--"}"#);
+    assert_eq!(action, Action::Pause);
+    assert_eq!(plugin.local_response().map(|r| r.status), Some(403));
+}
+
+#[test]
+fn detect_only_mode_lets_matching_requests_through() {
+    let plugin = Plugin::start(r#"{"mode": "detect_only"}"#).unwrap();
+
+    let action = plugin.request_headers(&request_headers("/", "malicious-bot"));
+    assert_eq!(action, Action::Continue);
+    assert_eq!(plugin.local_response(), None);
+}
+
+#[test]
+fn configured_rules_replace_the_example_rules() {
+    let plugin = Plugin::start(
+        r#"{
+            "debug_headers": true,
+            "rules": "SecRule REQUEST_HEADERS:User-Agent \"@contains scanner\" \"id:42,phase:1,deny\""
+        }"#,
+    )
+    .unwrap();
+
+    let action = plugin.request_headers(&request_headers("/", "vuln-scanner"));
+    assert_eq!(action, Action::Pause);
+    let response = plugin.local_response().expect("blocked response");
+    assert_eq!(response.header("x-portkullis-rule-id"), Some("42"));
+    assert_eq!(response.header("x-portkullis-phase"), Some("1"));
+}
+
+#[test]
+fn drop_action_resets_the_request() {
+    let plugin = Plugin::start(
+        r#"{"rules": "SecRule REQUEST_HEADERS:X-Action \"@streq drop\" \"id:43,phase:1,drop\""}"#,
+    )
+    .unwrap();
+
+    let mut headers = request_headers("/", "Mozilla/5.0");
+    headers.push(("x-action", "drop"));
+    assert_eq!(plugin.request_headers(&headers), Action::Pause);
+    assert!(plugin.request_reset());
+    assert_eq!(plugin.local_response(), None);
+}
+
+#[test]
+fn invalid_configuration_is_rejected() {
+    assert!(Plugin::start(r#"{"mode": "nonsense"}"#).is_err());
+    assert!(Plugin::start(r#"{"rules": "SecRule ARGS"}"#).is_err());
+    assert!(Plugin::start(r#"{"block_status": 200}"#).is_err());
+}
+
+#[test]
+fn block_status_is_configurable() {
+    let plugin = Plugin::start(
+        r#"{
+            "block_status": 406,
+            "rules": "SecRule REQUEST_HEADERS:User-Agent \"@contains scanner\" \"id:1,phase:1,deny\""
+        }"#,
+    )
+    .unwrap();
+
+    plugin.request_headers(&request_headers("/", "vuln-scanner"));
+    assert_eq!(plugin.local_response().map(|r| r.status), Some(406));
+}
+
+#[test]
+fn rule_status_overrides_the_block_status() {
+    let plugin = Plugin::start(
+        r#"{
+            "block_status": 406,
+            "rules": "SecRule REQUEST_HEADERS:X-Rate \"@streq exceeded\" \"id:2,phase:1,deny,status:429\""
+        }"#,
+    )
+    .unwrap();
+
+    let mut headers = request_headers("/", "Mozilla/5.0");
+    headers.push(("x-rate", "exceeded"));
+    assert_eq!(plugin.request_headers(&headers), Action::Pause);
+    assert_eq!(plugin.local_response().map(|r| r.status), Some(429));
+}
+
+#[test]
+fn blocked_response_can_be_json() {
+    let plugin = Plugin::start(r#"{"block_response_content_type": "application/json"}"#).unwrap();
+
+    plugin.request_headers(&request_headers("/", "malicious-bot"));
+    let response = plugin.local_response().expect("blocked response");
+    assert_eq!(response.status, 403);
+    assert_eq!(response.header("content-type"), Some("application/json"));
+    let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(body["blocked"], true);
+    assert_eq!(body["rule_id"], 1001);
+    assert!(body["message"].as_str().unwrap().contains("bot detected"));
+}
+
+#[test]
+fn requests_without_an_id_are_given_one() {
+    let plugin = Plugin::start("").unwrap();
+
+    plugin.request_headers(&request_headers("/", "curl/8.5.0"));
+    let request_id = plugin.request_header("x-request-id").unwrap();
+    assert!(request_id.starts_with("portkullis-"), "{}", request_id);
+}
+
+#[test]
+fn request_ids_are_read_from_the_configured_header() {
+    let plugin = Plugin::start(r#"{"request_id_header": "x-trace-id"}"#).unwrap();
+
+    let mut headers = request_headers("/", "curl/8.5.0");
+    headers.push(("x-trace-id", "0af7651916cd43dd"));
+    plugin.request_headers(&headers);
+    assert_eq!(
+        plugin.request_header("x-trace-id").as_deref(),
+        Some("0af7651916cd43dd")
+    );
+    assert_eq!(plugin.request_header("x-request-id"), None);
+}
+
+#[test]
+fn form_body_arguments_are_matched_as_args() {
+    let plugin = Plugin::start(
+        r#"{
+            "rules": "SecRule ARGS:comment \"@contains <script>\" \"id:43,phase:2,deny\""
+        }"#,
+    )
+    .unwrap();
+
+    let mut headers = request_headers("/comments", "Mozilla/5.0");
+    headers[0] = (":method", "POST");
+    headers.push(("content-type", "application/x-www-form-urlencoded"));
+    let action = plugin.request_headers(&headers);
+    assert_eq!(action, Action::Continue);
+    let action = plugin.request_body(b"author=robert&comment=%3Cscript%3Ealert(1)%3C%2Fscript%3E");
+    assert_eq!(action, Action::Pause);
+    assert_eq!(plugin.property("portkullis.rule_id").as_deref(), Some("43"));
+}
+
+#[test]
+fn uploaded_file_names_are_matched_as_files() {
+    let plugin = Plugin::start(
+        r#"{
+            "rules": "SecRule FILES \"@rx \\.php$\" \"id:44,phase:2,deny\""
+        }"#,
+    )
+    .unwrap();
+
+    let mut headers = request_headers("/upload", "Mozilla/5.0");
+    headers[0] = (":method", "POST");
+    headers.push(("content-type", "multipart/form-data; boundary=upload"));
+    plugin.request_headers(&headers);
+    let action = plugin.request_body(
+        b"--upload\r\n\
+Content-Disposition: form-data; name=\"avatar\"; filename=\"shell.php\"\r\n\
+\r\n\
+<?php ?>\r\n\
+--upload--\r\n",
+    );
+    assert_eq!(action, Action::Pause);
+    assert_eq!(plugin.property("portkullis.rule_id").as_deref(), Some("44"));
+}
+
+#[test]
+fn requests_up_to_the_maximum_args_pass() {
+    let plugin = Plugin::start(r#"{"max_args": 3}"#).unwrap();
+
+    let action = plugin.request_headers(&request_headers("/search?a=1&b=2&c=3", "curl/8.5.0"));
+    assert_eq!(action, Action::Continue);
+    let action = plugin.request_body(b"");
+    assert_eq!(action, Action::Continue);
+    assert_eq!(plugin.local_response(), None);
+}
+
+#[test]
+fn args_past_the_maximum_are_rejected() {
+    let plugin = Plugin::start(r#"{"max_args": 3}"#).unwrap();
+
+    let action = plugin.request_headers(&request_headers("/search?a=1&b=2&c=3&d=4", "curl/8.5.0"));
+    assert_eq!(action, Action::Pause);
+    assert_eq!(plugin.local_response().map(|r| r.status), Some(400));
+}
+
+#[test]
+fn args_past_the_maximum_are_not_evaluated_when_processing_partially() {
+    let plugin = Plugin::start(
+        r#"{
+            "max_args": 2,
+            "max_args_action": "process_partial",
+            "rules": "SecRule ARGS \"@contains <script>\" \"id:45,phase:2,deny\""
+        }"#,
+    )
+    .unwrap();
+
+    let mut headers = request_headers("/comments?a=1", "Mozilla/5.0");
+    headers[0] = (":method", "POST");
+    headers.push(("content-type", "application/x-www-form-urlencoded"));
+    let action = plugin.request_headers(&headers);
+    assert_eq!(action, Action::Continue);
+    let action = plugin.request_body(b"b=2&c=%3Cscript%3E");
+    assert_eq!(action, Action::Continue);
+    assert_eq!(plugin.local_response(), None);
+}
+
+#[test]
+fn ctl_rule_engine_detection_only_lets_matched_requests_through() {
+    let plugin = Plugin::start(
+        r#"{
+            "rules": "SecRule REQUEST_FILENAME \"@rx ^/legacy\" \"id:46,phase:1,pass,nolog,ctl:ruleEngine=DetectionOnly\"\nSecRule ARGS \"@contains <script>\" \"id:47,phase:2,deny\""
+        }"#,
+    )
+    .unwrap();
+
+    let action = plugin.request_headers(&request_headers("/legacy?q=%3Cscript%3E", "curl/8.5.0"));
+    assert_eq!(action, Action::Continue);
+    assert_eq!(plugin.local_response(), None);
+}
+
+#[test]
+fn requests_without_ctl_rule_engine_are_still_blocked() {
+    let plugin = Plugin::start(
+        r#"{
+            "rules": "SecRule REQUEST_FILENAME \"@rx ^/legacy\" \"id:46,phase:1,pass,nolog,ctl:ruleEngine=DetectionOnly\"\nSecRule ARGS \"@contains <script>\" \"id:47,phase:2,deny\""
+        }"#,
+    )
+    .unwrap();
+
+    let action = plugin.request_headers(&request_headers("/search?q=%3Cscript%3E", "curl/8.5.0"));
+    assert_eq!(action, Action::Pause);
+    assert_eq!(plugin.local_response().map(|r| r.status), Some(403));
+}
+
+const ANOMALY_SCORE_CONFIG: &str = r#"{
+    "debug_headers": true,
+    "engine_mode": {"anomaly_score": {"threshold": 5}},
+    "rules": "SecRule REQUEST_HEADERS:User-Agent \"@contains scanner\" \"id:48,phase:1,pass,severity:4\"\nSecRule REQUEST_FILENAME \"@rx ^/admin\" \"id:49,phase:1,pass,severity:5\""
+}"#;
+
+#[test]
+fn anomaly_score_mode_lets_requests_under_the_threshold_through() {
+    let plugin = Plugin::start(ANOMALY_SCORE_CONFIG).unwrap();
+
+    let action = plugin.request_headers(&request_headers("/", "vuln-scanner"));
+    assert_eq!(action, Action::Continue);
+    assert_eq!(plugin.local_response(), None);
+}
+
+#[test]
+fn anomaly_score_mode_blocks_requests_reaching_the_threshold() {
+    let plugin = Plugin::start(ANOMALY_SCORE_CONFIG).unwrap();
+
+    let action = plugin.request_headers(&request_headers("/admin", "vuln-scanner"));
+    assert_eq!(action, Action::Pause);
+    let response = plugin.local_response().expect("blocked response");
+    assert_eq!(response.header("x-portkullis-rule-id"), Some("48,49"));
+    assert_eq!(response.header("x-portkullis-anomaly-score"), Some("5"));
+    assert_eq!(response.header("x-portkullis-anomaly-threshold"), Some("5"));
+}
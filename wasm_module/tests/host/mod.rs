@@ -0,0 +1,466 @@
+// shared by the test binaries, each of which only uses some of the host
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use proxy_wasm::types::*;
+
+// A minimal proxy-wasm host standing in for the proxy. It implements the
+// hostcalls the module imports and drives the callbacks it exports, the same
+// ABI the proxy uses when the module is compiled to WASM, so that the tests
+// exercise the module's callbacks and the SDK's dispatch between them.
+//
+// The host's state is per thread, as is the SDK's, so tests can run in
+// parallel.
+
+// -----------------------------------------------------------------------------
+// Host State
+// -----------------------------------------------------------------------------
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalResponse {
+    pub status: u32,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl LocalResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Default)]
+struct Host {
+    plugin_configuration: Vec<u8>,
+    request_headers: Vec<(String, String)>,
+    request_body: Vec<u8>,
+    response_headers: Vec<(String, String)>,
+    response_body: Vec<u8>,
+    properties: HashMap<String, Vec<u8>>,
+    local_response: Option<LocalResponse>,
+    request_reset: bool,
+}
+
+thread_local! {
+    static HOST: RefCell<Host> = RefCell::new(Host::default());
+}
+
+// -----------------------------------------------------------------------------
+// Module Callbacks
+// -----------------------------------------------------------------------------
+
+unsafe extern "C" {
+    fn _initialize();
+    fn proxy_on_context_create(context_id: u32, root_context_id: u32);
+    fn proxy_on_vm_start(context_id: u32, vm_configuration_size: usize) -> bool;
+    fn proxy_on_configure(context_id: u32, plugin_configuration_size: usize) -> bool;
+    fn proxy_on_request_headers(context_id: u32, num_headers: usize, end_of_stream: bool)
+    -> Action;
+    fn proxy_on_request_body(context_id: u32, body_size: usize, end_of_stream: bool) -> Action;
+    fn proxy_on_response_headers(
+        context_id: u32,
+        num_headers: usize,
+        end_of_stream: bool,
+    ) -> Action;
+    fn proxy_on_response_body(context_id: u32, body_size: usize, end_of_stream: bool) -> Action;
+    fn proxy_on_log(context_id: u32);
+    fn proxy_on_delete(context_id: u32);
+}
+
+const ROOT_CONTEXT_ID: u32 = 1;
+const HTTP_CONTEXT_ID: u32 = 2;
+
+// A plugin instance with a single request, configured with the given plugin
+// configuration (see FirewallConfig).
+pub struct Plugin;
+
+impl Plugin {
+    pub fn start(configuration: &str) -> Result<Self, String> {
+        HOST.with(|host| {
+            *host.borrow_mut() = Host {
+                plugin_configuration: configuration.as_bytes().to_vec(),
+                ..Host::default()
+            }
+        });
+        unsafe {
+            _initialize();
+            proxy_on_context_create(ROOT_CONTEXT_ID, 0);
+            if !proxy_on_vm_start(ROOT_CONTEXT_ID, 0) {
+                return Err("on_vm_start failed".to_string());
+            }
+            if !proxy_on_configure(ROOT_CONTEXT_ID, configuration.len()) {
+                return Err("on_configure rejected the configuration".to_string());
+            }
+            proxy_on_context_create(HTTP_CONTEXT_ID, ROOT_CONTEXT_ID);
+        }
+        Ok(Plugin)
+    }
+
+    pub fn request_headers(&self, headers: &[(&str, &str)]) -> Action {
+        HOST.with(|host| {
+            host.borrow_mut().request_headers = headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        });
+        unsafe { proxy_on_request_headers(HTTP_CONTEXT_ID, headers.len(), false) }
+    }
+
+    // Delivers the body as a single chunk which ends the stream.
+    pub fn request_body(&self, body: &[u8]) -> Action {
+        HOST.with(|host| host.borrow_mut().request_body = body.to_vec());
+        unsafe { proxy_on_request_body(HTTP_CONTEXT_ID, body.len(), true) }
+    }
+
+    // The backend's response headers, including the ":status" pseudo-header,
+    // which are followed by a body unless end_of_stream is set.
+    pub fn response_headers(&self, headers: &[(&str, &str)], end_of_stream: bool) -> Action {
+        HOST.with(|host| {
+            host.borrow_mut().response_headers = headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        });
+        unsafe { proxy_on_response_headers(HTTP_CONTEXT_ID, headers.len(), end_of_stream) }
+    }
+
+    // Delivers a chunk of the response body, which is appended to the chunks
+    // delivered before it, as the proxy does while the module buffers them.
+    pub fn response_body(&self, chunk: &[u8], end_of_stream: bool) -> Action {
+        let body_size = HOST.with(|host| {
+            let mut host = host.borrow_mut();
+            host.response_body.extend_from_slice(chunk);
+            host.response_body.len()
+        });
+        unsafe { proxy_on_response_body(HTTP_CONTEXT_ID, body_size, end_of_stream) }
+    }
+
+    // A request header as the module left it, e.g. one it set.
+    pub fn request_header(&self, name: &str) -> Option<String> {
+        HOST.with(|host| {
+            host.borrow()
+                .request_headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        })
+    }
+
+    pub fn local_response(&self) -> Option<LocalResponse> {
+        HOST.with(|host| host.borrow().local_response.clone())
+    }
+
+    pub fn request_reset(&self) -> bool {
+        HOST.with(|host| host.borrow().request_reset)
+    }
+
+    // A property set by the module, e.g. "portkullis.rule_id".
+    pub fn property(&self, path: &str) -> Option<String> {
+        HOST.with(|host| host.borrow().properties.get(path).cloned())
+            .map(|value| String::from_utf8_lossy(&value).into_owned())
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        unsafe {
+            proxy_on_log(HTTP_CONTEXT_ID);
+            proxy_on_delete(HTTP_CONTEXT_ID);
+            proxy_on_delete(ROOT_CONTEXT_ID);
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Hostcalls
+// -----------------------------------------------------------------------------
+
+// Serializes a header map the way the proxy does: the number of pairs, the
+// size of each key and value, then each key and value followed by a NUL.
+fn serialize_map(map: &[(String, String)]) -> Vec<u8> {
+    let mut bytes = (map.len() as u32).to_le_bytes().to_vec();
+    for (name, value) in map {
+        bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    }
+    for (name, value) in map {
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(value.as_bytes());
+        bytes.push(0);
+    }
+    bytes
+}
+
+fn deserialize_map(bytes: &[u8]) -> Vec<(String, String)> {
+    let read_u32 =
+        |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+    if bytes.is_empty() {
+        return Vec::new();
+    }
+    let pairs = read_u32(0);
+    let mut position = 4 + pairs * 8;
+    let mut read_string = |size: usize| {
+        let string = String::from_utf8_lossy(&bytes[position..position + size]).into_owned();
+        position += size + 1;
+        string
+    };
+    (0..pairs)
+        .map(|pair| {
+            let name = read_string(read_u32(4 + pair * 8));
+            let value = read_string(read_u32(8 + pair * 8));
+            (name, value)
+        })
+        .collect()
+}
+
+// The module takes ownership of the data it's returned, so it's allocated as
+// the module would allocate it.
+fn return_bytes(bytes: &[u8], return_data: *mut *mut u8, return_size: *mut usize) -> Status {
+    let bytes = bytes.to_vec().into_boxed_slice();
+    unsafe {
+        *return_size = bytes.len();
+        *return_data = Box::into_raw(bytes) as *mut u8;
+    }
+    Status::Ok
+}
+
+fn slice<'a>(data: *const u8, size: usize) -> &'a [u8] {
+    match size {
+        0 => &[],
+        _ => unsafe { std::slice::from_raw_parts(data, size) },
+    }
+}
+
+// Property paths are NUL separated, e.g. "source\0address", and are keyed
+// here joined by dots, e.g. "source.address".
+fn property_path(data: *const u8, size: usize) -> String {
+    String::from_utf8_lossy(slice(data, size)).replace('\0', ".")
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_log(level: LogLevel, message_data: *const u8, message_size: usize) -> Status {
+    let message = String::from_utf8_lossy(slice(message_data, message_size));
+    eprintln!("[{:?}] {}", level, message);
+    Status::Ok
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_get_current_time_nanoseconds(return_time: *mut u64) -> Status {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    unsafe { *return_time = now.as_nanos() as u64 };
+    Status::Ok
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_set_tick_period_milliseconds(_period: u32) -> Status {
+    Status::Ok
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_get_buffer_bytes(
+    buffer_type: BufferType,
+    start: usize,
+    max_size: usize,
+    return_buffer_data: *mut *mut u8,
+    return_buffer_size: *mut usize,
+) -> Status {
+    HOST.with(|host| {
+        let host = host.borrow();
+        let buffer = match buffer_type {
+            BufferType::PluginConfiguration => &host.plugin_configuration,
+            BufferType::HttpRequestBody => &host.request_body,
+            BufferType::HttpResponseBody => &host.response_body,
+            _ => return Status::NotFound,
+        };
+        if buffer.is_empty() {
+            return Status::NotFound;
+        }
+        let start = start.min(buffer.len());
+        let end = start.saturating_add(max_size).min(buffer.len());
+        return_bytes(&buffer[start..end], return_buffer_data, return_buffer_size)
+    })
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_get_header_map_pairs(
+    map_type: MapType,
+    return_map_data: *mut *mut u8,
+    return_map_size: *mut usize,
+) -> Status {
+    HOST.with(|host| match map_type {
+        MapType::HttpRequestHeaders => return_bytes(
+            &serialize_map(&host.borrow().request_headers),
+            return_map_data,
+            return_map_size,
+        ),
+        MapType::HttpResponseHeaders => return_bytes(
+            &serialize_map(&host.borrow().response_headers),
+            return_map_data,
+            return_map_size,
+        ),
+        _ => return_bytes(&serialize_map(&[]), return_map_data, return_map_size),
+    })
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_get_header_map_value(
+    map_type: MapType,
+    key_data: *const u8,
+    key_size: usize,
+    return_value_data: *mut *mut u8,
+    return_value_size: *mut usize,
+) -> Status {
+    let key = String::from_utf8_lossy(slice(key_data, key_size));
+    HOST.with(|host| {
+        let host = host.borrow();
+        let value = match map_type {
+            MapType::HttpRequestHeaders => host
+                .request_headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(&key)),
+            MapType::HttpResponseHeaders => host
+                .response_headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(&key)),
+            _ => None,
+        };
+        match value {
+            Some((_, value)) => {
+                return_bytes(value.as_bytes(), return_value_data, return_value_size)
+            }
+            None => Status::NotFound,
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_replace_header_map_value(
+    map_type: MapType,
+    key_data: *const u8,
+    key_size: usize,
+    value_data: *const u8,
+    value_size: usize,
+) -> Status {
+    let key = String::from_utf8_lossy(slice(key_data, key_size)).into_owned();
+    let value = String::from_utf8_lossy(slice(value_data, value_size)).into_owned();
+    HOST.with(|host| {
+        let mut host = host.borrow_mut();
+        let headers = match map_type {
+            MapType::HttpRequestHeaders => &mut host.request_headers,
+            MapType::HttpResponseHeaders => &mut host.response_headers,
+            _ => return Status::BadArgument,
+        };
+        headers.retain(|(name, _)| !name.eq_ignore_ascii_case(&key));
+        headers.push((key, value));
+        Status::Ok
+    })
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_remove_header_map_value(
+    map_type: MapType,
+    key_data: *const u8,
+    key_size: usize,
+) -> Status {
+    let key = String::from_utf8_lossy(slice(key_data, key_size)).into_owned();
+    HOST.with(|host| {
+        let mut host = host.borrow_mut();
+        let headers = match map_type {
+            MapType::HttpRequestHeaders => &mut host.request_headers,
+            MapType::HttpResponseHeaders => &mut host.response_headers,
+            _ => return Status::BadArgument,
+        };
+        headers.retain(|(name, _)| !name.eq_ignore_ascii_case(&key));
+        Status::Ok
+    })
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_get_property(
+    path_data: *const u8,
+    path_size: usize,
+    return_value_data: *mut *mut u8,
+    return_value_size: *mut usize,
+) -> Status {
+    let path = property_path(path_data, path_size);
+    HOST.with(|host| match host.borrow().properties.get(&path) {
+        Some(value) => return_bytes(value, return_value_data, return_value_size),
+        None => Status::NotFound,
+    })
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_set_property(
+    path_data: *const u8,
+    path_size: usize,
+    value_data: *const u8,
+    value_size: usize,
+) -> Status {
+    let path = property_path(path_data, path_size);
+    let value = slice(value_data, value_size).to_vec();
+    HOST.with(|host| host.borrow_mut().properties.insert(path, value));
+    Status::Ok
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_continue_stream(_stream_type: StreamType) -> Status {
+    Status::Ok
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_close_stream(stream_type: StreamType) -> Status {
+    if stream_type == StreamType::HttpRequest {
+        HOST.with(|host| host.borrow_mut().request_reset = true);
+    }
+    Status::Ok
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+extern "C" fn proxy_send_local_response(
+    status_code: u32,
+    _status_code_details_data: *const u8,
+    _status_code_details_size: usize,
+    body_data: *const u8,
+    body_size: usize,
+    headers_data: *const u8,
+    headers_size: usize,
+    _grpc_status: i32,
+) -> Status {
+    let response = LocalResponse {
+        status: status_code,
+        headers: deserialize_map(slice(headers_data, headers_size)),
+        body: slice(body_data, body_size).to_vec(),
+    };
+    HOST.with(|host| host.borrow_mut().local_response = Some(response));
+    Status::Ok
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_set_effective_context(_context_id: u32) -> Status {
+    Status::Ok
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_define_metric(
+    _metric_type: MetricType,
+    _name_data: *const u8,
+    _name_size: usize,
+    return_id: *mut u32,
+) -> Status {
+    unsafe { *return_id = 1 };
+    Status::Ok
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proxy_increment_metric(_metric_id: u32, _offset: i64) -> Status {
+    Status::Ok
+}
@@ -1,6 +1,18 @@
+use std::collections::HashMap;
 use std::env;
 use std::fs;
 
+use anomaly_detection_engine::collection::{
+    COLLECTION_NAME, SCORE_THRESHOLD, VECTOR_DATABASE_URL, anomaly_search,
+    format_headers_for_embedding, ignored_headers_from_env,
+};
+use anomaly_detection_engine::embeddings::{
+    DIMENSIONS, EmbeddingsConfig, generate_embeddings, generate_embeddings_batch,
+    validate_dimensions,
+};
+use signature_detection_engine::transaction::Transaction;
+use signature_detection_engine::{DisruptiveAction, MatchResult, SignatureBasedDetectionEngine};
+
 use qdrant_client::{
     Qdrant,
     qdrant::{
@@ -8,7 +20,6 @@ use qdrant_client::{
         vectors_config::Config,
     },
 };
-use serde_json;
 
 // ----------------------------------------------------------------------------
 // gRPC Client
@@ -28,17 +39,70 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     if args.len() < 2 {
         eprintln!("Usage: cargo xtask <task>");
-        eprintln!("Available tasks:");
-        eprintln!("  setup-qdrant        create collection and populate");
+        print_tasks();
         std::process::exit(1);
     }
 
+    let (target, positional) = match QdrantTarget::from_args(&args[2..]) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            print_tasks();
+            std::process::exit(1);
+        }
+    };
+
     match args[1].as_str() {
-        "setup-qdrant" => setup_qdrant_collection().await?,
+        "setup-qdrant" => setup_qdrant_collection(&target).await?,
+        "query-qdrant" => match positional.first() {
+            Some(header_text) => query_qdrant_collection(&target, header_text).await?,
+            None => {
+                eprintln!("Usage: cargo xtask query-qdrant [options] \"<header text>\"");
+                std::process::exit(1);
+            }
+        },
+        "collection-stats" => print_collection_stats(&target).await?,
+        "calibrate-threshold" => match positional.as_slice() {
+            [] => calibrate_threshold(&target, None, DEFAULT_FALSE_POSITIVE_RATE).await?,
+            [held_out] => {
+                calibrate_threshold(&target, Some(held_out), DEFAULT_FALSE_POSITIVE_RATE).await?
+            }
+            [held_out, rate] => match rate.parse::<f64>() {
+                Ok(rate) if rate > 0.0 && rate < 1.0 => {
+                    calibrate_threshold(&target, Some(held_out), rate).await?
+                }
+                _ => {
+                    eprintln!(
+                        "Invalid false positive rate: {} (expected 0 < rate < 1)",
+                        rate
+                    );
+                    std::process::exit(1);
+                }
+            },
+            _ => {
+                eprintln!(
+                    "Usage: cargo xtask calibrate-threshold [options] [<held-out.json> [<false-positive-rate>]]"
+                );
+                std::process::exit(1);
+            }
+        },
+        "compile-rules" => match positional.as_slice() {
+            [input, output] => compile_rules(input, output)?,
+            _ => {
+                eprintln!("Usage: cargo xtask compile-rules <in.conf> <out.json>");
+                std::process::exit(1);
+            }
+        },
+        "run-corpus" => match positional.as_slice() {
+            [rules, requests] => run_corpus(rules, requests)?,
+            _ => {
+                eprintln!("Usage: cargo xtask run-corpus <rules.conf> <requests.jsonl>");
+                std::process::exit(1);
+            }
+        },
         _ => {
             eprintln!("Unknown task: {}", args[1]);
-            eprintln!("Available tasks:");
-            eprintln!("  setup-qdrant        create collection and populate");
+            print_tasks();
             std::process::exit(1);
         }
     }
@@ -46,19 +110,88 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn print_tasks() {
+    eprintln!("Available tasks:");
+    eprintln!("  setup-qdrant        (re)create collection and populate");
+    eprintln!("  query-qdrant <text> search the collection like the engine does");
+    eprintln!("  collection-stats    print the collection's point count and dimensions");
+    eprintln!("  calibrate-threshold [<held-out.json> [<false-positive-rate>]]");
+    eprintln!("                      suggest a score threshold from normal traffic samples");
+    eprintln!("  compile-rules <in.conf> <out.json>");
+    eprintln!("                      check a rules file and write it as a JSON ruleset");
+    eprintln!("  run-corpus <rules.conf> <requests.jsonl>");
+    eprintln!("                      report the rules a file of recorded requests fires");
+    eprintln!("Options:");
+    eprintln!(
+        "  --url <url>         Qdrant URL (default {})",
+        VECTOR_DATABASE_URL
+    );
+    eprintln!(
+        "  --collection <name> collection name (default {})",
+        COLLECTION_NAME
+    );
+}
+
+// The Qdrant instance and collection a task operates on.
+struct QdrantTarget {
+    url: String,
+    collection: String,
+}
+
+impl QdrantTarget {
+    // Parses the --url and --collection options out of the task arguments,
+    // returning the remaining positional arguments.
+    fn from_args(args: &[String]) -> Result<(Self, Vec<String>), String> {
+        let mut target = QdrantTarget {
+            url: VECTOR_DATABASE_URL.to_string(),
+            collection: COLLECTION_NAME.to_string(),
+        };
+        let mut positional = Vec::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let option = match arg.as_str() {
+                "--url" => &mut target.url,
+                "--collection" => &mut target.collection,
+                _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+                _ => {
+                    positional.push(arg.clone());
+                    continue;
+                }
+            };
+            *option = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", arg))?
+                .clone();
+        }
+
+        Ok((target, positional))
+    }
+}
+
 // ----------------------------------------------------------------------------
 // xtasks
 // ----------------------------------------------------------------------------
 
-async fn setup_qdrant_collection() -> Result<(), Box<dyn std::error::Error>> {
-    let client = Qdrant::from_url("http://localhost:6334").build()?;
-    let collection_name = "normal_headers";
+const EMBEDDING_BATCH_SIZE: usize = 64;
+
+// Creates the collection and populates it with the normal traffic samples. An
+// existing collection is dropped first, so this can be re-run to start over.
+async fn setup_qdrant_collection(target: &QdrantTarget) -> Result<(), Box<dyn std::error::Error>> {
+    validate_dimensions(&EmbeddingsConfig::from_env()?, DIMENSIONS)?;
+
+    let client = Qdrant::from_url(&target.url).build()?;
+    let collection_name = target.collection.as_str();
+    if client.collection_exists(collection_name).await? {
+        client.delete_collection(collection_name).await?;
+        println!("existing collection '{}' deleted", collection_name);
+    }
     client
         .create_collection(CreateCollection {
             collection_name: collection_name.to_string(),
             vectors_config: Some(VectorsConfig {
                 config: Some(Config::Params(VectorParams {
-                    size: 386,
+                    size: DIMENSIONS as u64,
                     distance: Distance::Cosine.into(),
                     ..Default::default()
                 })),
@@ -70,26 +203,32 @@ async fn setup_qdrant_collection() -> Result<(), Box<dyn std::error::Error>> {
 
     let normal_headers = get_test_headers("config/test_headers.json")?;
     println!("populating {}", collection_name);
+    let ignored_headers = ignored_headers_from_env();
+
+    let header_texts: Vec<String> = normal_headers
+        .iter()
+        .map(|h| format_headers_for_embedding(h, &ignored_headers))
+        .collect();
 
     let mut points = Vec::new();
-    for (i, headers) in normal_headers.iter().enumerate() {
-        let header_text = fmt_headers(headers);
-        println!("processing header {}: {}", i + 1, header_text);
-
-        match anomaly_detection_engine::embeddings::generate_embeddings(&header_text, Some(386)) {
-            Ok(embedding) => {
-                println!("generated embedding ({} dimensions)", embedding.len());
-
-                let point = PointStruct::new(
-                    (i + 1) as u64,
-                    embedding,
-                    [("headers".to_string(), header_text.into())],
-                );
-                points.push(point);
-            }
-            Err(e) => {
-                return Err(e.into());
-            }
+    for (batch_index, batch) in header_texts.chunks(EMBEDDING_BATCH_SIZE).enumerate() {
+        println!(
+            "processing headers {}-{}",
+            batch_index * EMBEDDING_BATCH_SIZE + 1,
+            batch_index * EMBEDDING_BATCH_SIZE + batch.len()
+        );
+
+        let texts: Vec<&str> = batch.iter().map(String::as_str).collect();
+        let embeddings = generate_embeddings_batch(&texts, None)?;
+
+        for (offset, (header_text, embedding)) in batch.iter().zip(embeddings).enumerate() {
+            let id = batch_index * EMBEDDING_BATCH_SIZE + offset + 1;
+            let point = PointStruct::new(
+                id as u64,
+                embedding,
+                [("headers".to_string(), header_text.clone().into())],
+            );
+            points.push(point);
         }
     }
 
@@ -111,22 +250,362 @@ async fn setup_qdrant_collection() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+// Number of nearest neighbours printed by query-qdrant.
+const QUERY_TOP_K: u64 = 10;
+
+// Runs the engine's search for the given header text (formatted like
+// "name: value | name: value", with the names lowercased and sorted, see
+// format_headers_for_embedding), but for the top QUERY_TOP_K neighbours
+// instead of only the nearest, marking the ones which count as a match.
+async fn query_qdrant_collection(
+    target: &QdrantTarget,
+    header_text: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = Qdrant::from_url(&target.url).build()?;
+
+    let embedding = generate_embeddings(header_text, None)?;
+    let mut search_points = anomaly_search(&target.collection, embedding);
+    search_points.limit = QUERY_TOP_K;
+    search_points.with_payload = Some(true.into());
+
+    let search_result = client.search_points(search_points).await?;
+
+    println!("top {} matches in {}:", QUERY_TOP_K, target.collection);
+    for point in &search_result.result {
+        let headers = point
+            .payload
+            .get("headers")
+            .map(|headers| headers.to_string())
+            .unwrap_or_default();
+        let marker = if point.score >= SCORE_THRESHOLD {
+            "match"
+        } else {
+            "     "
+        };
+        println!("  {:.4} {} {}", point.score, marker, headers);
+    }
+
+    let top_score = search_result.result.first().map(|point| point.score);
+    match top_score {
+        Some(score) if score >= SCORE_THRESHOLD => {
+            println!(
+                "normal traffic (top score {:.4} >= {})",
+                score, SCORE_THRESHOLD
+            )
+        }
+        Some(score) => println!("anomaly (top score {:.4} < {})", score, SCORE_THRESHOLD),
+        None => println!("anomaly (collection is empty)"),
+    }
+
+    Ok(())
+}
+
+async fn print_collection_stats(target: &QdrantTarget) -> Result<(), Box<dyn std::error::Error>> {
+    let client = Qdrant::from_url(&target.url).build()?;
+    let info = client
+        .collection_info(&target.collection)
+        .await?
+        .result
+        .ok_or("no collection info returned")?;
+
+    let dimensions = info
+        .config
+        .and_then(|config| config.params)
+        .and_then(|params| params.vectors_config)
+        .and_then(|vectors_config| vectors_config.config)
+        .and_then(|config| match config {
+            Config::Params(params) => Some(params.size),
+            Config::ParamsMap(_) => None,
+        });
+
+    println!("collection: {}", target.collection);
+    println!("points: {}", info.points_count.unwrap_or_default());
+    match dimensions {
+        Some(dimensions) => println!("vector dimensions: {}", dimensions),
+        None => println!("vector dimensions: unknown (named vectors)"),
+    }
+
+    Ok(())
+}
+
+// The share of normal traffic calibrate-threshold lets be flagged as anomalous
+// by default.
+const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+// Suggests a score threshold from the scores normal traffic gets: each
+// held-out sample (in the format of config/test_headers.json) is searched for
+// like the engine does, and the threshold is the score below which the given
+// share of the samples falls, so that about that share of normal traffic
+// would be flagged. The server is configured with the threshold through
+// PORTKULLIS_SCORE_THRESHOLD.
+//
+// Without held-out samples the ingested samples are used, with each one's own
+// point left out of its search, as it would otherwise be its own nearest
+// neighbour.
+async fn calibrate_threshold(
+    target: &QdrantTarget,
+    held_out: Option<&String>,
+    false_positive_rate: f64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = Qdrant::from_url(&target.url).build()?;
+    let samples = get_test_headers(held_out.map_or("config/test_headers.json", String::as_str))?;
+    let ignored_headers = ignored_headers_from_env();
+    let header_texts: Vec<String> = samples
+        .iter()
+        .map(|h| format_headers_for_embedding(h, &ignored_headers))
+        .collect();
+
+    let mut scores = Vec::with_capacity(header_texts.len());
+    for batch in header_texts.chunks(EMBEDDING_BATCH_SIZE) {
+        let texts: Vec<&str> = batch.iter().map(String::as_str).collect();
+        let embeddings = generate_embeddings_batch(&texts, None)?;
+
+        for (header_text, embedding) in batch.iter().zip(embeddings) {
+            let mut search_points = anomaly_search(&target.collection, embedding);
+            search_points.limit = 2;
+            search_points.with_payload = Some(true.into());
+
+            let mut points = client.search_points(search_points).await?.result;
+            let own_point = points.iter().position(|point| {
+                point
+                    .payload
+                    .get("headers")
+                    .and_then(|headers| headers.as_str())
+                    == Some(header_text)
+            });
+            if let (None, Some(own_point)) = (held_out, own_point) {
+                points.remove(own_point);
+            }
+            let score = points.first().map(|point| point.score);
+            // a sample with nothing in the collection to compare with would
+            // be flagged whatever the threshold
+            scores.push(score.unwrap_or(f32::NEG_INFINITY));
+        }
+    }
+
+    if scores.is_empty() {
+        return Err("no samples to calibrate with".into());
+    }
+    scores.sort_by(f32::total_cmp);
+    let percentile = |share: f64| scores[((scores.len() - 1) as f64 * share).floor() as usize];
+
+    println!(
+        "nearest neighbour scores of {} samples in {}:",
+        scores.len(),
+        target.collection
+    );
+    for (label, share) in [
+        ("min", 0.0),
+        ("p1", 0.01),
+        ("p5", 0.05),
+        ("median", 0.5),
+        ("max", 1.0),
+    ] {
+        println!("  {:<6} {:.4}", label, percentile(share));
+    }
+    let threshold = percentile(false_positive_rate);
+    let flagged = scores.iter().filter(|&&score| score < threshold).count();
+    println!(
+        "recommended threshold: {:.4} ({} of {} samples below it, current default {})",
+        threshold,
+        flagged,
+        scores.len(),
+        SCORE_THRESHOLD
+    );
+    println!("PORTKULLIS_SCORE_THRESHOLD={:.4}", threshold);
+
+    Ok(())
+}
+
+// Parses a ModSecurity rules file, failing with the line of the first invalid
+// rule, and writes the rules in the JSON format the engine loads with
+// from_json, which is faster to load than parsing the rules at startup.
+fn compile_rules(input: &str, output: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let engine = match SignatureBasedDetectionEngine::from_conf_file(input) {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            std::process::exit(1);
+        }
+    };
+
+    for skipped in engine.skipped_directives() {
+        println!(
+            "{}:{}: skipped {}, it has no effect",
+            skipped.file.as_deref().unwrap_or(input),
+            skipped.line,
+            skipped.name
+        );
+    }
+
+    let rule_count: usize = engine
+        .rule_group
+        .values()
+        .flatten()
+        .map(|ruleset| ruleset.directives.len())
+        .sum();
+    fs::write(output, engine.to_json()?)?;
+    println!(
+        "compiled {} rules from {} into {}",
+        rule_count, input, output
+    );
+
+    Ok(())
+}
+
+// Runs a file of recorded requests through the engine and reports how often
+// each rule fired and how many of the requests would have been blocked, to
+// measure the false positive rate of a ruleset against real traffic before
+// enforcing it. Each line of the file is a JSON request, e.g.:
+//
+//   {"method": "POST", "path": "/login?next=/", "headers": {"host": "example.com"}, "body": "user=bob"}
+//
+// where the headers may also be a list of [name, value] pairs, so that
+// repeated headers can be recorded, and the method and body are optional.
+//
+// Every rule of every phase is evaluated, as in anomaly scoring mode, so that
+// rules which would only be reached once an earlier rule is excluded are
+// reported too. A request counts as blocked when any rule which fired for it
+// blocks (deny or drop).
+fn run_corpus(rules: &str, requests: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let engine = match SignatureBasedDetectionEngine::from_conf_file(rules) {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("{}: {}", rules, e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut request_count = 0;
+    let mut blocked_count = 0;
+    let mut rule_counts: HashMap<u32, usize> = HashMap::new();
+    for (index, line) in fs::read_to_string(requests)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = match CorpusRequest::from_json(line) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("{}:{}: {}", requests, index + 1, e);
+                std::process::exit(1);
+            }
+        };
+
+        let matched_rules = request.run(&engine)?;
+        request_count += 1;
+        if matched_rules.iter().any(|match_result| {
+            matches!(
+                match_result.rule.disruptive_action(),
+                DisruptiveAction::Deny | DisruptiveAction::Drop
+            )
+        }) {
+            blocked_count += 1;
+        }
+        for match_result in &matched_rules {
+            *rule_counts.entry(match_result.rule.id).or_default() += 1;
+        }
+    }
+
+    let mut rule_counts: Vec<(u32, usize)> = rule_counts.into_iter().collect();
+    rule_counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    println!("rule      fired");
+    for (rule_id, count) in &rule_counts {
+        println!("{:<9} {}", rule_id, count);
+    }
+    let block_rate = match request_count {
+        0 => 0.0,
+        _ => blocked_count as f64 * 100.0 / request_count as f64,
+    };
+    println!(
+        "{} of {} requests blocked ({:.2}%)",
+        blocked_count, request_count, block_rate
+    );
+
+    Ok(())
+}
+
 // ----------------------------------------------------------------------------
 // xtasks - helper functions
 // ----------------------------------------------------------------------------
 
-fn fmt_headers(headers: &[(String, String)]) -> String {
-    headers
-        .iter()
-        .map(|(name, value)| format!("{}: {}", name, value))
-        .collect::<Vec<_>>()
-        .join(" | ")
+// A request recorded for run-corpus.
+struct CorpusRequest {
+    method: String,
+    path: String,
+    headers: HeaderSet,
+    body: String,
 }
 
-fn get_test_headers(
-    filename: &str,
-) -> Result<Vec<Vec<(String, String)>>, Box<dyn std::error::Error>> {
+impl CorpusRequest {
+    fn from_json(line: &str) -> Result<Self, String> {
+        let request: serde_json::Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+        let string = |value: &serde_json::Value| value.as_str().map(str::to_string);
+
+        let path = request
+            .get("path")
+            .and_then(string)
+            .ok_or("missing the request's path")?;
+        let method = request
+            .get("method")
+            .and_then(string)
+            .unwrap_or_else(|| "GET".to_string());
+        let body = request.get("body").and_then(string).unwrap_or_default();
+        let headers = match request.get("headers") {
+            Some(serde_json::Value::Object(headers)) => headers
+                .iter()
+                .map(|(name, value)| Some((name.clone(), string(value)?)))
+                .collect::<Option<HeaderSet>>(),
+            Some(serde_json::Value::Array(headers)) => headers
+                .iter()
+                .map(|header| match header.as_array().map(Vec::as_slice) {
+                    Some([name, value]) => Some((string(name)?, string(value)?)),
+                    _ => None,
+                })
+                .collect::<Option<HeaderSet>>(),
+            None => Some(Vec::new()),
+            Some(_) => None,
+        }
+        .ok_or("headers must be an object or a list of [name, value] pairs of strings")?;
+
+        Ok(Self {
+            method,
+            path,
+            headers,
+            body,
+        })
+    }
+
+    // Evaluates the request the way the proxy-wasm plugin does, with the
+    // method and path as pseudo-headers alongside the request's headers.
+    fn run(&self, engine: &SignatureBasedDetectionEngine) -> Result<Vec<MatchResult>, String> {
+        let mut headers = vec![
+            (":method".to_string(), self.method.clone()),
+            (":path".to_string(), self.path.clone()),
+        ];
+        headers.extend(self.headers.iter().cloned());
+        let query_string = self
+            .path
+            .split_once('?')
+            .map(|(_, query_string)| query_string)
+            .unwrap_or_default();
+
+        let mut transaction = Transaction {
+            request_uri: Some(self.path.clone()),
+            ..Transaction::default()
+        };
+        let mut matched_rules = engine.run_header_phase_all(&mut transaction, headers)?;
+        matched_rules.extend(engine.run_args_phase_all(&mut transaction, query_string)?);
+        if !self.body.is_empty() {
+            matched_rules.extend(engine.run_body_phase_all(&mut transaction, &self.body)?);
+        }
+        Ok(matched_rules)
+    }
+}
+
+type HeaderSet = Vec<(String, String)>;
+
+fn get_test_headers(filename: &str) -> Result<Vec<HeaderSet>, Box<dyn std::error::Error>> {
     let file_content = fs::read_to_string(filename)?;
-    let headers_data: Vec<Vec<(String, String)>> = serde_json::from_str(&file_content)?;
+    let headers_data: Vec<HeaderSet> = serde_json::from_str(&file_content)?;
     Ok(headers_data)
 }